@@ -0,0 +1,134 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Persists the retry state of the targets across sessions, such that restarting
+//! k8sfwd doesn't give targets that are known to be down a fresh set of retries.
+
+use crate::config::PortForwardConfig;
+use crate::session::state_dir;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The persisted retry state of a target.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backoff {
+    /// The number of consecutive failed restarts.
+    pub retries: usize,
+    /// Whether the target was given up after exceeding its maximum number of retries.
+    pub failed: bool,
+}
+
+/// The retry state of all targets, backed by `backoff.json` in the state directory.
+#[derive(Debug, Clone, Default)]
+pub struct BackoffStore {
+    path: Option<PathBuf>,
+    targets: Arc<Mutex<HashMap<String, Backoff>>>,
+}
+
+impl BackoffStore {
+    /// Loads the retry state of the previous sessions.
+    pub fn load() -> Self {
+        let path = Self::path();
+        let targets = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            targets: Arc::new(Mutex::new(targets)),
+        }
+    }
+
+    /// Removes the retry state of all targets.
+    pub fn reset() -> Result<(), io::Error> {
+        match Self::path().map(fs::remove_file) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the retry state of a target.
+    pub fn get(&self, target: &PortForwardConfig) -> Backoff {
+        let targets = self.targets.lock().expect("lock is not poisoned");
+        targets.get(&Self::key(target)).copied().unwrap_or_default()
+    }
+
+    /// Records the retry state of a target; targets without failures are forgotten.
+    pub fn record(&self, target: &PortForwardConfig, backoff: Backoff) {
+        let mut targets = self.targets.lock().expect("lock is not poisoned");
+        let key = Self::key(target);
+        let changed = if backoff == Backoff::default() {
+            targets.remove(&key).is_some()
+        } else {
+            targets.insert(key, backoff) != Some(backoff)
+        };
+
+        if changed {
+            Self::write(self.path.as_ref(), &targets).ok();
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("backoff.json"))
+    }
+
+    fn write(path: Option<&PathBuf>, targets: &HashMap<String, Backoff>) -> Result<(), io::Error> {
+        let Some(path) = path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let contents = serde_json::to_string_pretty(targets)?;
+        fs::write(path, contents)
+    }
+
+    /// Identifies a target across sessions by the resource it forwards to.
+    fn key(target: &PortForwardConfig) -> String {
+        format!(
+            "{context}/{cluster}/{namespace}/{resource}/{name}",
+            context = target.context.as_deref().unwrap_or_default(),
+            cluster = target.cluster.as_deref().unwrap_or_default(),
+            namespace = target.namespace,
+            resource = target.r#type.as_arg(),
+            name = target.target
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let store = BackoffStore::default();
+        let target = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let backoff = Backoff {
+            retries: 3,
+            failed: true,
+        };
+        store.record(&target, backoff);
+        assert_eq!(store.get(&target), backoff);
+
+        store.record(&target, Backoff::default());
+        assert!(store.targets.lock().unwrap().is_empty());
+    }
+}
@@ -2,8 +2,11 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
+use crate::config::{
+    ConfigName, Port, RemoteConfig, RemoteConfigError, ResourceType, SpawnPolicy, Theme,
+};
 use crate::target_filter::TargetFilter;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use just_a_tag::TagUnion;
 use std::fs::File;
 use std::ops::Deref;
@@ -14,31 +17,332 @@ use which::which;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// Sets a custom config file to load instead of .k8sfwd.
-    #[arg(short = 'f', long = "file", value_name = "FILE", value_parser = config_file_exists)]
-    pub config: Vec<PathBuf>,
+    /// Sets a custom config file to load instead of .k8sfwd; an HTTPS URL is downloaded,
+    /// optionally pinned to a checksum with #sha256=<HEX>.
+    #[arg(short = 'f', long = "file", value_name = "FILE", value_parser = config_file_exists, global = true)]
+    pub config: Vec<ConfigFileArg>,
 
-    /// Specifies the prefixes of the target configurations to select.
+    /// Loads only the files given with --file, without searching the directory hierarchy,
+    /// the home and the config directory, e.g. for reproducible runs in CI.
+    #[arg(long, env = "K8SFWD_NO_HIERARCHY", global = true)]
+    pub no_hierarchy: bool,
+
+    /// Skips config files that can't be read or loaded, e.g. because of an unsupported version,
+    /// and continues with the remaining files.
+    #[arg(long, env = "K8SFWD_IGNORE_ERRORS", global = true)]
+    pub ignore_errors: bool,
+
+    /// Sets the name of the config files to search the directory hierarchy for, e.g. k8sfwd.yaml.
+    #[arg(long, value_name = "NAME", env = "K8SFWD_CONFIG_NAME", default_value_t = ConfigName::default(), global = true)]
+    pub config_name: ConfigName,
+
+    /// Specifies the prefixes, or patterns like *payments*, of the target configurations to select.
     #[arg(value_name = "FILTER", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false)]
     pub filters: Vec<TargetFilter>,
 
+    /// Specifies regular expressions selecting the target configurations by resource or name,
+    /// e.g. '^api-(staging|prod)$'.
+    #[arg(long = "filter-regex", value_name = "PATTERN", value_parser = TargetFilter::regex, global = true)]
+    pub regex_filters: Vec<TargetFilter>,
+
+    /// Specifies the prefixes, or patterns like *kafka*, of the target configurations to leave out.
+    #[arg(short = 'x', long = "exclude", value_name = "FILTER", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false, global = true)]
+    pub excludes: Vec<TargetFilter>,
+
+    /// Selects a profile of the configuration, i.e. a set of targets and settings.
+    #[arg(
+        short,
+        long,
+        value_name = "PROFILE",
+        env = "K8SFWD_PROFILE",
+        global = true
+    )]
+    pub profile: Option<String>,
+
     /// Specifies the tags of the targets to forward to.
-    #[arg(short, long, value_name = "TAGS", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false)]
+    #[arg(short, long, value_name = "TAGS", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false, global = true)]
     pub tags: Vec<TagUnion>,
 
+    /// Specifies the groups of targets to forward to.
+    #[arg(short, long = "group", value_name = "GROUPS", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false, global = true)]
+    pub groups: Vec<String>,
+
     /// Sets a custom path to the kubectl binary.
-    #[arg(long, value_name = "FILE", env = "KUBECTL_PATH")]
+    #[arg(long, value_name = "FILE", env = "KUBECTL_PATH", global = true)]
     pub kubectl: Option<KubectlPathBuf>,
 
+    /// Sets the number of seconds to wait for in-flight connections to finish when stopping a target.
+    #[arg(long, value_name = "SECONDS", global = true)]
+    pub drain_timeout: Option<f64>,
+
+    /// Sets the number of seconds kubectl waits for a response of the Kubernetes API before giving up.
+    #[arg(long, value_name = "SECONDS", global = true)]
+    pub request_timeout: Option<f64>,
+
+    /// Sets the number of seconds without any connection after which kubectl is restarted.
+    #[arg(long, value_name = "SECONDS", global = true)]
+    pub idle_timeout: Option<f64>,
+
+    /// Sets whether a target failing to spawn stops all targets or only itself.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        env = "K8SFWD_ON_SPAWN_ERROR",
+        global = true
+    )]
+    pub on_spawn_error: Option<SpawnPolicy>,
+
+    /// Forgets the retries of targets that failed in previous sessions.
+    #[arg(long, global = true)]
+    pub reset_backoff: bool,
+
+    /// Verifies that the resources of the targets exist before forwarding to them.
+    #[arg(long, global = true)]
+    pub check_targets: bool,
+
+    /// Uses the shared (multi-user) session, e.g. on a shared development machine.
+    #[arg(long, env = "K8SFWD_SHARED", global = true)]
+    pub shared: bool,
+
+    /// The user running the shared session to connect to; defaults to the current user.
+    #[arg(long, value_name = "USER", env = "K8SFWD_SHARED_USER", global = true)]
+    pub shared_user: Option<String>,
+
+    /// Loads environment variables from this file instead of the nearest .env file.
+    #[arg(long, value_name = "FILE", conflicts_with = "no_dotenv", global = true)]
+    pub env_file: Option<PathBuf>,
+
+    /// Does not load environment variables from a .env file.
+    #[arg(long, global = true)]
+    pub no_dotenv: bool,
+
+    /// Asks for confirmation before a configuration reload stops or restarts running targets.
+    #[arg(long, global = true)]
+    pub confirm_reload: bool,
+
+    /// Prints tables at full width instead of truncating them to the width of the terminal.
+    #[arg(long, global = true)]
+    pub wide: bool,
+
+    /// Marks the states of the targets with ASCII symbols, Unicode symbols or emoji.
+    #[arg(long, value_name = "THEME", env = "K8SFWD_THEME", global = true)]
+    pub theme: Option<Theme>,
+
     /// Enables verbose log outputs.
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub verbose: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Starts forwarding the configured targets, optionally in the background.
+    Up(UpArgs),
+    /// Shuts down the running session, or releases your targets in a shared session.
+    Down,
+    /// Starts the selected targets of your configuration in the running (shared) session.
+    Attach(AttachArgs),
+    /// Shows the state of each target of the running session.
+    Status(StatusArgs),
+    /// Stops a target of the running session.
+    Stop(TargetArgs),
+    /// Restarts the kubectl process of a target of the running session.
+    Restart(TargetArgs),
+    /// Prints the recent output of a target of the running session.
+    Logs(LogsArgs),
+    /// Manages the targets of the running session.
+    Target {
+        #[command(subcommand)]
+        command: TargetCommand,
+    },
+    /// Prints the context, cluster, namespace and pod each target would use, without forwarding.
+    Resolve(ResolveArgs),
+    /// Removes in-cluster helper resources (e.g. gateway pods) left behind by crashed sessions.
+    Cleanup(CleanupArgs),
+    /// Deploys a temporary echo pod and verifies that forwarding to it works end to end.
+    Selftest(SelftestArgs),
+    /// Clones or updates the team's repository of shared configuration fragments.
+    Sync(SyncArgs),
+    /// Creates a starter configuration file in the current directory.
+    Init(InitArgs),
+    /// Adds a target to the nearest configuration file, asking for the values not given.
+    Add(AddArgs),
+    /// Upgrades a configuration file to the highest supported version, renaming older fields.
+    Migrate(MigrateArgs),
+    /// Prints the JSON Schema of the configuration files, e.g. for editor validation.
+    Schema,
+}
+
+#[derive(Args)]
+pub struct UpArgs {
+    /// Runs k8sfwd in the background, controllable through its control socket.
+    #[arg(short, long)]
+    pub detach: bool,
+
+    /// Specifies the prefixes, or patterns like *payments*, of the target configurations to select.
+    #[arg(value_name = "FILTER", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false)]
+    pub filters: Vec<TargetFilter>,
+}
+
+#[derive(Args)]
+pub struct StatusArgs {
+    /// Prints the status as JSON instead of a table.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct TargetArgs {
+    /// The ID (e.g. `#3fa2`), name or target of the running target.
+    #[arg(value_name = "TARGET")]
+    pub target: String,
+}
+
+#[derive(Args)]
+pub struct LogsArgs {
+    /// The ID (e.g. `#3fa2`), name or target of the running target.
+    #[arg(value_name = "TARGET")]
+    pub target: String,
+
+    /// The number of recent lines to print.
+    #[arg(short = 'n', long, value_name = "LINES", default_value_t = 100)]
+    pub lines: usize,
+
+    /// Keeps printing new output until the session ends.
+    #[arg(long)]
+    pub follow: bool,
+}
+
+#[derive(Subcommand)]
+pub enum TargetCommand {
+    /// Exposes a remote port of a running target on a different local port.
+    Remap(RemapArgs),
+}
+
+#[derive(Args)]
+pub struct RemapArgs {
+    /// The ID (e.g. `#3fa2`), name or target of the running target.
+    #[arg(value_name = "TARGET")]
+    pub target: String,
+
+    /// The new port mapping, e.g. `15432:5432`.
+    #[arg(value_name = "LOCAL:REMOTE")]
+    pub port: Port,
+}
+
+#[derive(Args)]
+pub struct AttachArgs {
+    /// Specifies the prefixes, or patterns like *payments*, of the target configurations to select.
+    #[arg(value_name = "FILTER", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false)]
+    pub filters: Vec<TargetFilter>,
+}
+
+#[derive(Args)]
+pub struct ResolveArgs {
+    /// Specifies the prefixes, or patterns like *payments*, of the target configurations to select.
+    #[arg(value_name = "FILTER", num_args = 1.., value_delimiter = ' ', allow_hyphen_values = false)]
+    pub filters: Vec<TargetFilter>,
+}
+
+#[derive(Args)]
+pub struct CleanupArgs {
+    /// The cluster to clean up; defaults to the cluster of the current context.
+    #[arg(long, value_name = "CLUSTER", conflicts_with = "context")]
+    pub cluster: Option<String>,
+
+    /// The context to clean up; defaults to the current context.
+    #[arg(long, value_name = "CONTEXT")]
+    pub context: Option<String>,
+}
+
+#[derive(Args)]
+pub struct SelftestArgs {
+    /// The context to test; defaults to the current context.
+    #[arg(long, value_name = "CONTEXT")]
+    pub context: Option<String>,
+
+    /// The namespace to deploy the echo pod to.
+    #[arg(short, long, value_name = "NAMESPACE", default_value = "default")]
+    pub namespace: String,
+
+    /// The image of the echo pod; must provide `socat`.
+    #[arg(long, value_name = "IMAGE", default_value = "alpine/socat:latest")]
+    pub image: String,
+
+    /// Deploys the pod without asking for confirmation.
+    #[arg(short, long)]
+    pub yes: bool,
 }
 
-fn config_file_exists(s: &str) -> Result<PathBuf, String> {
+#[derive(Args)]
+pub struct SyncArgs {
+    /// The URL of the repository; defaults to the `sync.repository` setting.
+    #[arg(value_name = "REPOSITORY")]
+    pub repository: Option<String>,
+
+    /// The branch to use; defaults to the `sync.branch` setting or the default branch.
+    #[arg(long, value_name = "BRANCH")]
+    pub branch: Option<String>,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// Overwrites an existing configuration file.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// The name of the resource to forward to, e.g. `api`.
+    #[arg(value_name = "RESOURCE")]
+    pub target: Option<String>,
+
+    /// The type of the resource.
+    #[arg(long = "type", value_name = "TYPE")]
+    pub kind: Option<ResourceType>,
+
+    /// The namespace of the resource.
+    #[arg(short, long, value_name = "NAMESPACE")]
+    pub namespace: Option<String>,
+
+    /// The context of the resource; defaults to the current context when forwarding.
+    #[arg(long, value_name = "CONTEXT")]
+    pub context: Option<String>,
+
+    /// A display name of the target.
+    #[arg(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// A port to forward, e.g. `8080:80`; can be repeated.
+    #[arg(long = "port", value_name = "LOCAL:REMOTE")]
+    pub ports: Vec<Port>,
+}
+
+#[derive(Args)]
+pub struct MigrateArgs {
+    /// The file to migrate; defaults to the nearest configuration file.
+    #[arg(value_name = "FILE")]
+    pub file: Option<PathBuf>,
+
+    /// Prints the migrated file instead of writing it.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+fn config_file_exists(s: &str) -> Result<ConfigFileArg, String> {
+    if RemoteConfig::is_url(s) {
+        return s
+            .parse()
+            .map(ConfigFileArg::Url)
+            .map_err(|e: RemoteConfigError| e.to_string());
+    }
+
     let path = PathBuf::from(s);
     if File::open(&path).is_ok() {
-        Ok(path)
+        Ok(ConfigFileArg::Path(path))
     } else {
         Err(format!(
             "The config file `{s}` does not exist or is not a valid file"
@@ -46,6 +350,15 @@ fn config_file_exists(s: &str) -> Result<PathBuf, String> {
     }
 }
 
+/// A config file given with `--file`.
+#[derive(Debug, Clone)]
+pub enum ConfigFileArg {
+    /// A local file.
+    Path(PathBuf),
+    /// A file downloaded from an HTTPS server.
+    Url(RemoteConfig),
+}
+
 #[derive(Debug, Clone)]
 pub struct KubectlPathBuf(PathBuf);
 
@@ -76,3 +389,14 @@ impl FromStr for KubectlPathBuf {
         Ok(Self(PathBuf::from_str(s)?))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,33 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+mod add;
+mod attach;
+mod cleanup;
+mod down;
+mod init;
+mod logs;
+mod migrate;
+mod resolve;
+mod schema;
+mod selftest;
+mod status;
+mod sync;
+mod target;
+mod up;
+
+pub use add::add;
+pub use attach::attach;
+pub use cleanup::cleanup;
+pub use down::down;
+pub use init::init;
+pub use logs::logs;
+pub use migrate::migrate;
+pub use resolve::resolve;
+pub use schema::schema;
+pub use selftest::selftest;
+pub use status::status;
+pub use sync::sync;
+pub use target::{remap, restart, stop};
+pub use up::detach;
@@ -0,0 +1,264 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::{AddArgs, Cli};
+use crate::config::{nearest_config_file, ConfigFormat, Port, PortForwardConfigs, ResourceType};
+use crate::wizard;
+use clap::ValueEnum;
+use std::fs;
+use std::process::ExitCode;
+
+/// Appends a target to the nearest configuration file, asking for the values not given as
+/// arguments. The file is edited as text, such that its formatting and comments are kept.
+pub fn add(cli: &Cli, args: AddArgs) -> anyhow::Result<ExitCode> {
+    let Some(path) = nearest_config_file(&cli.config_name)? else {
+        eprintln!("No config file was found - run `k8sfwd init` to create one");
+        return crate::exitcode(exitcode::NOINPUT);
+    };
+
+    let contents = fs::read_to_string(&path)?;
+    if ConfigFormat::detect(&path, &contents) != ConfigFormat::Yaml {
+        eprintln!(
+            "Only YAML files can be edited, but {path} is not one",
+            path = path.display()
+        );
+        return crate::exitcode(exitcode::DATAERR);
+    }
+
+    let interactive = wizard::is_interactive();
+    if !interactive && (args.target.is_none() || args.ports.is_empty()) {
+        eprintln!("Pass the resource and --port when not running in a terminal");
+        return crate::exitcode(exitcode::USAGE);
+    }
+
+    let target = match args.target {
+        Some(target) => target,
+        None => ask_until("Resource name", "", |answer| {
+            (!answer.is_empty()).then(|| answer.to_string())
+        })?,
+    };
+    let kind = match args.kind {
+        Some(kind) => kind,
+        None if interactive => ask_until("Resource type", "service", |answer| {
+            <ResourceType as ValueEnum>::from_str(answer, true).ok()
+        })?,
+        None => ResourceType::default(),
+    };
+    let namespace = match args.namespace {
+        Some(namespace) => namespace,
+        None if interactive => wizard::ask("Namespace", "default")?,
+        None => "default".to_string(),
+    };
+    let ports = match args.ports {
+        ports if !ports.is_empty() => ports,
+        _ => ask_until("Ports, e.g. 8080:80", "", |answer| {
+            let ports: Result<Vec<Port>, _> = answer
+                .split([',', ' '])
+                .filter(|port| !port.is_empty())
+                .map(str::parse)
+                .collect();
+            ports.ok().filter(|ports| !ports.is_empty())
+        })?,
+    };
+
+    let entry = render(&Entry {
+        name: args.name.as_deref(),
+        target: &target,
+        kind,
+        namespace: &namespace,
+        context: args.context.as_deref(),
+        ports: &ports,
+    });
+    let edited = match append_target(&contents, &entry) {
+        Ok(edited) => edited,
+        Err(e) => {
+            eprintln!(
+                "Unable to add the target to {path}: {e}",
+                path = path.display()
+            );
+            return crate::exitcode(exitcode::DATAERR);
+        }
+    };
+
+    // Never break a working file.
+    if let Err(e) = serde_yaml::from_str::<PortForwardConfigs>(&edited) {
+        eprintln!(
+            "Unable to add the target to {path}: the result would be invalid: {e}",
+            path = path.display()
+        );
+        return crate::exitcode(exitcode::DATAERR);
+    }
+
+    fs::write(&path, edited)?;
+    println!(
+        "Added {kind}/{target} to {path}",
+        kind = kind.as_arg(),
+        path = path.display()
+    );
+    crate::exitcode(exitcode::OK)
+}
+
+/// Asks the question until the answer is accepted.
+fn ask_until<T>(
+    question: &str,
+    default: &str,
+    accept: impl Fn(&str) -> Option<T>,
+) -> std::io::Result<T> {
+    loop {
+        let answer = wizard::ask(question, default)?;
+        if let Some(value) = accept(answer.trim()) {
+            return Ok(value);
+        }
+    }
+}
+
+/// The values of the target to add.
+struct Entry<'a> {
+    name: Option<&'a str>,
+    target: &'a str,
+    kind: ResourceType,
+    namespace: &'a str,
+    context: Option<&'a str>,
+    ports: &'a [Port],
+}
+
+/// Renders the target as an item of a YAML sequence, without indentation.
+fn render(entry: &Entry) -> String {
+    let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+    let mut fields = Vec::new();
+    if let Some(name) = entry.name {
+        fields.push(("name", quote(name)));
+    }
+    fields.push(("target", quote(entry.target)));
+    fields.push(("type", entry.kind.as_arg().to_string()));
+    fields.push(("namespace", quote(entry.namespace)));
+    if let Some(context) = entry.context {
+        fields.push(("context", quote(context)));
+    }
+
+    let mut yaml = String::new();
+    for (index, (key, value)) in fields.iter().enumerate() {
+        let prefix = if index == 0 { "- " } else { "  " };
+        yaml.push_str(&format!("{prefix}{key}: {value}\n"));
+    }
+    yaml.push_str("  ports:\n");
+    for port in entry.ports {
+        yaml.push_str(&format!("    - \"{port}\"\n"));
+    }
+    yaml
+}
+
+/// Appends the rendered target to the `targets` of the YAML text, after the last target,
+/// keeping all other lines as they are.
+fn append_target(contents: &str, entry: &str) -> Result<String, AppendError> {
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let Some(start) = lines.iter().position(|line| line.starts_with("targets:")) else {
+        // Without targets, the list is added at the end.
+        lines.push("targets:".to_string());
+        lines.extend(indent(entry, 2));
+        return Ok(join(lines));
+    };
+
+    let value = lines[start]["targets:".len()..]
+        .split('#')
+        .next()
+        .unwrap_or_default()
+        .trim();
+    match value {
+        "" => {}
+        "[]" => lines[start] = "targets:".to_string(),
+        _ => return Err(AppendError::FlowSequence),
+    }
+
+    // The block ends before the next top-level key or document marker; comments and empty
+    // lines after the last target are kept after the new one.
+    let mut last = start;
+    let mut item_indent = None;
+    for (index, line) in lines.iter().enumerate().skip(start + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if line.starts_with("---") || line.starts_with("...") {
+            break;
+        }
+        if !line.starts_with([' ', '-']) {
+            break;
+        }
+        if item_indent.is_none() && trimmed.starts_with('-') {
+            item_indent = Some(line.len() - trimmed.len());
+        }
+        last = index;
+    }
+
+    let entry = indent(entry, item_indent.unwrap_or(2));
+    lines.splice(last + 1..last + 1, entry);
+    Ok(join(lines))
+}
+
+/// Indents each line of the text.
+fn indent(text: &str, width: usize) -> Vec<String> {
+    text.lines()
+        .map(|line| format!("{:width$}{line}", ""))
+        .collect()
+}
+
+/// Joins the lines, ending with a newline.
+fn join(lines: Vec<String>) -> String {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+#[derive(Debug, thiserror::Error)]
+enum AppendError {
+    #[error("the targets are written as a flow sequence, e.g. `targets: [...]`")]
+    FlowSequence,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_target() {
+        let entry = render(&Entry {
+            name: None,
+            target: "db",
+            kind: ResourceType::Service,
+            namespace: "data",
+            context: Some("staging"),
+            ports: &["5432".parse().unwrap()],
+        });
+
+        let contents = r#"---
+version: 0.3.0
+# Targets of the team
+targets:
+    - target: api # the API
+      ports: [80]
+
+    # more to come
+config:
+  retry_delay_sec: 1
+"#;
+        let edited = append_target(contents, &entry).unwrap();
+        assert!(edited.starts_with(
+            "---\nversion: 0.3.0\n# Targets of the team\ntargets:\n    - target: api # the API\n      ports: [80]\n    - target: \"db\"\n"
+        ));
+        assert!(edited.ends_with("\n\n    # more to come\nconfig:\n  retry_delay_sec: 1\n"));
+
+        let config: PortForwardConfigs = serde_yaml::from_str(&edited).unwrap();
+        assert_eq!(config.targets.len(), 2);
+        assert_eq!(config.targets[1].namespace, "data");
+        assert_eq!(config.targets[1].context.as_deref(), Some("staging"));
+        assert_eq!(config.targets[1].ports[0].remote, 5432);
+
+        let edited = append_target("version: 0.3.0\ntargets: []\n", &entry).unwrap();
+        let config: PortForwardConfigs = serde_yaml::from_str(&edited).unwrap();
+        assert_eq!(config.targets.len(), 1);
+
+        assert!(append_target("targets: [{ target: api }]\n", &entry).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::Cli;
+use crate::config::PortForwardConfig;
+use crate::control::{AttachTarget, ControlClient, ControlRequest, ControlResponse};
+use crate::kubectl::Kubectl;
+use std::process::ExitCode;
+
+/// Requests the selected targets from the running session via its control socket.
+///
+/// The targets are resolved against the session's kubeconfig; local ports are
+/// assigned by the session.
+pub fn attach(cli: &Cli, kubectl: &Kubectl) -> anyhow::Result<ExitCode> {
+    let mut config = match crate::load_config(cli, kubectl) {
+        Ok(config) => config,
+        Err(code) => return crate::exitcode(code),
+    };
+
+    // The contexts are resolved by the session; only clusters set explicitly are known here.
+    if config.config.as_ref().and_then(|config| config.auto_tags) == Some(true) {
+        for target in &mut config.targets {
+            target.add_auto_tags();
+        }
+    }
+
+    let targets = crate::select_targets(&config.targets, &cli.tags, &cli.filters, &cli.excludes);
+    if targets.is_empty() {
+        eprintln!("No targets selected.");
+        crate::print_selection_hints(&config.targets, &cli.filters);
+        return crate::exitcode(exitcode::DATAERR);
+    }
+
+    // The session only accepts what to forward; anything else would run as the session's user.
+    let targets: Vec<_> = targets
+        .iter()
+        .map(|config| {
+            let target = AttachTarget::from(config);
+            let ignored: Vec<_> = config
+                .changed_fields(&PortForwardConfig::from(target.clone()))
+                .into_iter()
+                .filter(|field| *field != "tags")
+                .collect();
+            if !ignored.is_empty() {
+                eprintln!(
+                    "Ignoring the settings {fields} of {name}, which attached targets cannot use",
+                    fields = ignored.join(", "),
+                    name = config.name.as_ref().unwrap_or(&config.target)
+                );
+            }
+            target
+        })
+        .collect();
+
+    match ControlClient::request(&ControlRequest::Attach { targets }) {
+        Ok(ControlResponse::Attached { targets }) => {
+            println!("Forwarding to the following targets:");
+            for target in targets {
+                let ports = target
+                    .ports
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("{id} {name}: {ports}", id = target.id, name = target.name);
+            }
+            crate::exitcode(exitcode::OK)
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Unable to attach the targets: {message}");
+            crate::exitcode(exitcode::SOFTWARE)
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from k8sfwd: {response:?}");
+            crate::exitcode(exitcode::PROTOCOL)
+        }
+        Err(e) => {
+            eprintln!("Unable to attach the targets: {e}");
+            crate::exitcode(exitcode::UNAVAILABLE)
+        }
+    }
+}
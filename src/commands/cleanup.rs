@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::CleanupArgs;
+use crate::kubectl::Kubectl;
+use crate::session::Session;
+use std::process::ExitCode;
+
+/// Removes the in-cluster helper resources of sessions that did not shut down properly.
+///
+/// Only helper resources of sessions started on this machine are removed; ones of sessions
+/// on other machines can't be told apart from running ones and are kept.
+pub fn cleanup(kubectl: &Kubectl, args: CleanupArgs) -> anyhow::Result<ExitCode> {
+    let context = match (args.context, args.cluster) {
+        (Some(context), _) => context,
+        (None, Some(cluster)) => match kubectl.contexts_of_cluster(&cluster)?.as_slice() {
+            [context] => context.clone(),
+            _ => {
+                eprintln!("Unable to determine a unique context for cluster {cluster}");
+                return crate::exitcode(exitcode::CONFIG);
+            }
+        },
+        (None, None) => kubectl.current_context()?,
+    };
+
+    // Determined before collecting the garbage, which removes the manifests of these sessions.
+    let stale_sessions = Session::stale_session_ids();
+
+    // Resources recorded on this machine.
+    let mut deleted = 0;
+    for resource in Session::collect_garbage(kubectl) {
+        println!("Deleted {resource} left over from a previous session");
+        deleted += 1;
+    }
+
+    // Resources of stale sessions on this machine that are no longer recorded, e.g.
+    // because deleting them failed before.
+    for (resource, sessions) in kubectl.list_helper_pods(Some(&context))? {
+        if sessions.is_empty() {
+            println!("Keeping {resource}, which is not assigned to a session");
+            continue;
+        }
+        if let Some(session) = sessions.iter().find(|id| !stale_sessions.contains(id)) {
+            println!("Keeping {resource} of session {session}, which may still be running");
+            continue;
+        }
+
+        kubectl.delete_helper_resource(&resource)?;
+        println!("Deleted {resource}");
+        deleted += 1;
+    }
+
+    if deleted == 0 {
+        println!("No helper resources to clean up in context {context}");
+    }
+
+    crate::exitcode(exitcode::OK)
+}
@@ -0,0 +1,32 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::control::{ControlClient, ControlRequest, ControlResponse};
+use std::process::ExitCode;
+
+/// Shuts down the running session via its control socket.
+pub fn down() -> anyhow::Result<ExitCode> {
+    match ControlClient::request(&ControlRequest::Shutdown) {
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Unable to shut down k8sfwd: {message}");
+            crate::exitcode(exitcode::SOFTWARE)
+        }
+        Ok(ControlResponse::Released { ids }) if ids.is_empty() => {
+            println!("You have no targets in the shared session");
+            crate::exitcode(exitcode::OK)
+        }
+        Ok(ControlResponse::Released { ids }) => {
+            println!("Stopping {ids}", ids = ids.join(", "));
+            crate::exitcode(exitcode::OK)
+        }
+        Ok(_) => {
+            println!("Shutting down k8sfwd");
+            crate::exitcode(exitcode::OK)
+        }
+        Err(e) => {
+            eprintln!("Unable to shut down k8sfwd: {e}");
+            crate::exitcode(exitcode::UNAVAILABLE)
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::{Cli, InitArgs};
+use crate::config::HIGHEST_SUPPORTED_VERSION;
+use crate::kubectl::Kubectl;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Writes a starter configuration to the current directory, with an example target in the
+/// current context and cluster.
+pub fn init(cli: &Cli, kubectl: &Kubectl, args: InitArgs) -> anyhow::Result<ExitCode> {
+    let Some(file_name) = cli.config_name.yaml_file_name() else {
+        eprintln!(
+            "Starter configurations are YAML files - pass a YAML name with --config-name instead of {name}",
+            name = cli.config_name
+        );
+        return crate::exitcode(exitcode::USAGE);
+    };
+
+    let path = PathBuf::from(file_name);
+    if path.exists() && !args.force {
+        eprintln!(
+            "{path} already exists - pass --force to overwrite it",
+            path = path.display()
+        );
+        return crate::exitcode(exitcode::CANTCREAT);
+    }
+
+    // Without a kubeconfig, the example target uses the current context at run time.
+    let context = kubectl.current_context().ok();
+    let cluster = kubectl.current_cluster().ok().flatten();
+    fs::write(&path, render(context.as_deref(), cluster.as_deref()))?;
+
+    println!("Created {path}", path = path.display());
+    println!("Replace the example target with your services, then run k8sfwd to start forwarding.");
+    crate::exitcode(exitcode::OK)
+}
+
+/// Renders the starter configuration.
+fn render(context: Option<&str>, cluster: Option<&str>) -> String {
+    let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+    let mut yaml = format!(
+        r#"---
+# See https://github.com/sunsided/k8sfwd for all settings.
+version: {version}
+config:
+  # The number of seconds to wait before restarting kubectl after it exited.
+  retry_delay_sec: 5
+targets:
+  # An example target; replace the service, namespace and ports with your own.
+  - name: Example API
+    target: example-api
+    type: service
+    namespace: default
+"#,
+        version = *HIGHEST_SUPPORTED_VERSION
+    );
+    if let Some(context) = context {
+        yaml.push_str(&format!(
+            "    context: {context}\n",
+            context = quote(context)
+        ));
+    }
+    if let Some(cluster) = cluster {
+        yaml.push_str(&format!(
+            "    cluster: {cluster}\n",
+            cluster = quote(cluster)
+        ));
+    }
+    yaml.push_str("    ports:\n      - \"8080:80\" # local:remote\n");
+    yaml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PortForwardConfigs;
+
+    #[test]
+    fn test_render() {
+        let yaml = render(Some("kind-kind"), Some("kind-kind"));
+        let config: PortForwardConfigs = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.is_supported_version());
+        assert_eq!(config.targets.len(), 1);
+        assert_eq!(config.targets[0].context.as_deref(), Some("kind-kind"));
+        assert_eq!(config.targets[0].ports[0].local, Some(8080));
+
+        let config: PortForwardConfigs = serde_yaml::from_str(&render(None, None)).unwrap();
+        assert_eq!(config.targets[0].context, None);
+    }
+}
@@ -0,0 +1,55 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::LogsArgs;
+use crate::control::{ControlClient, ControlRequest, ControlResponse};
+use crate::kubectl::StreamSource;
+use std::process::ExitCode;
+
+/// Prints the output of a target of the running session via its control socket.
+pub fn logs(args: LogsArgs) -> anyhow::Result<ExitCode> {
+    let request = ControlRequest::Logs {
+        target: args.target,
+        lines: args.lines,
+        follow: args.follow,
+    };
+
+    let mut client = match ControlClient::connect().and_then(|mut client| {
+        client.send(&request)?;
+        Ok(client)
+    }) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Unable to read the logs: {e}");
+            return crate::exitcode(exitcode::UNAVAILABLE);
+        }
+    };
+
+    // The session closes the connection once all lines were sent, unless following.
+    loop {
+        match client.receive() {
+            Ok(Some(ControlResponse::Log {
+                stream,
+                line,
+                attempt,
+            })) => match stream {
+                StreamSource::StdOut => println!("[{attempt}] {line}"),
+                StreamSource::StdErr => eprintln!("[{attempt}] {line}"),
+            },
+            Ok(Some(ControlResponse::Error { message })) => {
+                eprintln!("Unable to read the logs: {message}");
+                return crate::exitcode(exitcode::DATAERR);
+            }
+            Ok(Some(response)) => {
+                eprintln!("Unexpected response from k8sfwd: {response:?}");
+                return crate::exitcode(exitcode::PROTOCOL);
+            }
+            Ok(None) => return crate::exitcode(exitcode::OK),
+            Err(e) => {
+                eprintln!("Unable to read the logs: {e}");
+                return crate::exitcode(exitcode::UNAVAILABLE);
+            }
+        }
+    }
+}
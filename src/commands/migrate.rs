@@ -0,0 +1,365 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::{Cli, MigrateArgs};
+use crate::config::{
+    nearest_config_file, ConfigFormat, PortForwardConfigs, HIGHEST_SUPPORTED_VERSION,
+};
+use semver::Version;
+use serde_yaml::Value;
+use std::fs;
+use std::process::ExitCode;
+
+/// The fields renamed since the first schema version, as `(parent, old name, new name)`.
+/// Fields without a parent are renamed wherever they occur.
+const RENAMED_FIELDS: &[(Option<&str>, &str, &str)] = &[
+    (None, "drain_timeout", "drain_timeout_sec"),
+    (None, "drift_check_interval", "drift_check_interval_sec"),
+    (None, "request_timeout", "request_timeout_sec"),
+    (None, "idle_timeout", "idle_timeout_sec"),
+    (None, "on_start", "on_ready"),
+    (None, "ready_timeout", "ready_timeout_sec"),
+    (None, "hold_timeout", "hold_timeout_sec"),
+    (Some("health_check"), "interval", "interval_sec"),
+    (Some("keepalive"), "interval", "interval_sec"),
+    (Some("keepalive"), "tcp_keepalive", "tcp_keepalive_sec"),
+];
+
+/// The values renamed since the first schema version, as `(field, old value, new value)`.
+const RENAMED_VALUES: &[(&str, &str, &str)] = &[
+    ("merge_order", "cli_first", "cli-first"),
+    ("merge_order", "nearest_first", "nearest-first"),
+    ("restart", "on_failure", "on-failure"),
+];
+
+/// Upgrades a configuration file to the highest supported schema version, renaming the fields
+/// of older versions. The file is edited as text, such that its formatting and comments are kept.
+pub fn migrate(cli: &Cli, args: MigrateArgs) -> anyhow::Result<ExitCode> {
+    let path = match args.file {
+        Some(path) => path,
+        None => match nearest_config_file(&cli.config_name)? {
+            Some(path) => path,
+            None => {
+                eprintln!("No config file was found to migrate");
+                return crate::exitcode(exitcode::NOINPUT);
+            }
+        },
+    };
+
+    let contents = fs::read_to_string(&path)?;
+    if ConfigFormat::detect(&path, &contents) != ConfigFormat::Yaml {
+        eprintln!(
+            "Only YAML files can be migrated, but {path} is not one",
+            path = path.display()
+        );
+        return crate::exitcode(exitcode::DATAERR);
+    }
+
+    let migration = match migrate_yaml(&contents) {
+        Ok(migration) => migration,
+        Err(e) => {
+            eprintln!("Unable to migrate {path}: {e}", path = path.display());
+            return crate::exitcode(exitcode::DATAERR);
+        }
+    };
+
+    if migration.contents == contents {
+        println!(
+            "{path} already uses version {version}",
+            path = path.display(),
+            version = migration.from
+        );
+        return crate::exitcode(exitcode::OK);
+    }
+
+    println!(
+        "Migrating {path} from version {from} to {to}",
+        path = path.display(),
+        from = migration.from,
+        to = *HIGHEST_SUPPORTED_VERSION
+    );
+    for change in &migration.changes {
+        println!("- {change}");
+    }
+    if !migration.kept_formatting {
+        println!("The file could not be edited in place; comments and formatting are not kept");
+    }
+
+    if args.dry_run {
+        print!("{contents}", contents = migration.contents);
+    } else {
+        fs::write(&path, migration.contents)?;
+    }
+    crate::exitcode(exitcode::OK)
+}
+
+/// The result of migrating a file.
+#[derive(Debug)]
+struct Migration {
+    /// The version the file was written for.
+    from: Version,
+    /// The migrated contents.
+    contents: String,
+    /// The renamed fields and values.
+    changes: Vec<String>,
+    /// Whether the contents were edited in place, keeping comments and formatting.
+    kept_formatting: bool,
+}
+
+/// Migrates the YAML text to the highest supported version.
+///
+/// The lines are rewritten in place; if the result differs from renaming the fields of the parsed
+/// document, e.g. because of flow mappings, the migrated document is written instead.
+fn migrate_yaml(contents: &str) -> Result<Migration, MigrateError> {
+    let mut document: Value = serde_yaml::from_str(contents)?;
+    let from = match document.get("version") {
+        Some(Value::String(version)) => version.parse()?,
+        Some(_) => return Err(MigrateError::InvalidVersion),
+        None => return Err(MigrateError::MissingVersion),
+    };
+    if from > *HIGHEST_SUPPORTED_VERSION {
+        return Err(MigrateError::NewerVersion(from));
+    }
+
+    let mut changes = Vec::new();
+    let edited = migrate_lines(contents, &mut changes);
+
+    migrate_value(&mut document, None);
+    document["version"] = Value::String(HIGHEST_SUPPORTED_VERSION.to_string());
+
+    let kept_formatting = serde_yaml::from_str::<Value>(&edited).ok().as_ref() == Some(&document);
+    let contents = match kept_formatting {
+        true => edited,
+        false => format!("---\n{}", serde_yaml::to_string(&document)?),
+    };
+
+    // Never write a broken file.
+    serde_yaml::from_str::<PortForwardConfigs>(&contents).map_err(MigrateError::Invalid)?;
+
+    Ok(Migration {
+        from,
+        contents,
+        changes,
+        kept_formatting,
+    })
+}
+
+/// Rewrites the version and the renamed fields and values of the YAML lines.
+fn migrate_lines(contents: &str, changes: &mut Vec<String>) -> String {
+    // The keys of the enclosing mappings, with the indentation of their values.
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut lines = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let Some((indent, key, rest)) = split_key(line) else {
+            lines.push(line.to_string());
+            continue;
+        };
+
+        while parents.last().is_some_and(|(level, _)| *level >= indent) {
+            parents.pop();
+        }
+        let parent = parents.last().map(|(_, key)| key.as_str());
+        let prefix = &line[..line.len() - key.len() - rest.len() - 1];
+
+        let mut key = key.to_string();
+        let mut rest = rest.to_string();
+        if indent == 0 && key == "version" {
+            let comment = rest.find(" #").map(|index| &rest[index..]).unwrap_or("");
+            rest = format!(" {version}{comment}", version = *HIGHEST_SUPPORTED_VERSION);
+        }
+        if let Some(new) = renamed_field(parent, &key) {
+            changes.push(format!(
+                "Renamed `{key}` to `{new}` in line {line}",
+                line = number + 1
+            ));
+            key = new.to_string();
+        }
+        if let Some((old, new)) = renamed_value(&key, &rest) {
+            changes.push(format!(
+                "Changed `{key}: {old}` to `{key}: {new}` in line {line}",
+                line = number + 1
+            ));
+            rest = rest.replacen(old, new, 1);
+        }
+
+        lines.push(format!("{prefix}{key}:{rest}"));
+        parents.push((indent, key));
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+/// Splits a line of a block mapping into the indentation of the key, counting the dashes of
+/// sequence items, the key and the text after the colon.
+fn split_key(line: &str) -> Option<(usize, &str, &str)> {
+    let mut indent = 0;
+    let mut remaining = line;
+    loop {
+        let trimmed = remaining.trim_start_matches(' ');
+        indent += remaining.len() - trimmed.len();
+        match trimmed.strip_prefix("- ") {
+            Some(item) => {
+                indent += 2;
+                remaining = item;
+            }
+            None => {
+                remaining = trimmed;
+                break;
+            }
+        }
+    }
+
+    let (key, rest) = remaining.split_once(':')?;
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let ends_key = rest.is_empty() || rest.starts_with([' ', '\t']);
+    (is_key && ends_key).then_some((indent, key, rest))
+}
+
+/// Gets the new name of the field, if it was renamed.
+fn renamed_field(parent: Option<&str>, key: &str) -> Option<&'static str> {
+    RENAMED_FIELDS
+        .iter()
+        .find(|(scope, old, _)| *old == key && (scope.is_none() || *scope == parent))
+        .map(|(_, _, new)| *new)
+}
+
+/// Gets the old and new spelling of the value after the colon, if it was renamed.
+fn renamed_value(key: &str, rest: &str) -> Option<(&'static str, &'static str)> {
+    let value = rest.split(" #").next().unwrap_or_default().trim();
+    let value = value.trim_matches(|c| c == '"' || c == '\'');
+    RENAMED_VALUES
+        .iter()
+        .find(|(field, old, _)| *field == key && *old == value)
+        .map(|(_, old, new)| (*old, *new))
+}
+
+/// Renames the fields and values of the parsed document; the items of a sequence share the
+/// parent of the sequence.
+fn migrate_value(value: &mut Value, parent: Option<&str>) {
+    match value {
+        Value::Mapping(mapping) => {
+            let entries = std::mem::take(mapping);
+            for (key, mut value) in entries {
+                let key = match key {
+                    Value::String(key) => {
+                        let key = renamed_field(parent, &key).map_or(key, String::from);
+                        if let Value::String(text) = &mut value {
+                            if let Some((_, new)) = renamed_value(&key, text) {
+                                *text = new.to_string();
+                            }
+                        }
+                        migrate_value(&mut value, Some(&key));
+                        Value::String(key)
+                    }
+                    key => key,
+                };
+                mapping.insert(key, value);
+            }
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                migrate_value(item, parent);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum MigrateError {
+    #[error("the file is not valid YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("the file has no `version`")]
+    MissingVersion,
+    #[error("the `version` is not a string like `0.1.0`")]
+    InvalidVersion,
+    #[error("the `version` is invalid: {0}")]
+    Version(#[from] semver::Error),
+    #[error("version {0} is newer than this application supports")]
+    NewerVersion(Version),
+    #[error("the result would be invalid: {0}")]
+    Invalid(serde_yaml::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_yaml() {
+        let contents = r#"---
+version: 0.1.0 # the first version
+config:
+  drain_timeout: 5
+  merge_order: cli_first
+  keepalive:
+    interval: 10
+targets:
+  - target: api
+    on_start: echo ready
+    restart: "on_failure"
+    health_check:
+      interval: 3
+    selector:
+      interval: kept
+    ports: [80]
+"#;
+        let migration = migrate_yaml(contents).unwrap();
+        assert!(migration.kept_formatting);
+        assert_eq!(migration.from, Version::new(0, 1, 0));
+        assert_eq!(migration.changes.len(), 6);
+        assert_eq!(
+            migration.contents,
+            format!(
+                r#"---
+version: {version} # the first version
+config:
+  drain_timeout_sec: 5
+  merge_order: cli-first
+  keepalive:
+    interval_sec: 10
+targets:
+  - target: api
+    on_ready: echo ready
+    restart: "on-failure"
+    health_check:
+      interval_sec: 3
+    selector:
+      interval: kept
+    ports: [80]
+"#,
+                version = *HIGHEST_SUPPORTED_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_migrate_yaml_flow_mapping() {
+        let contents =
+            "version: 0.2.0\ntargets:\n  - { target: api, drain_timeout: 5, ports: [80] }\n";
+        let migration = migrate_yaml(contents).unwrap();
+        assert!(!migration.kept_formatting);
+        let config: PortForwardConfigs = serde_yaml::from_str(&migration.contents).unwrap();
+        assert!(config.is_supported_version());
+        assert!(migration.contents.contains("drain_timeout_sec: 5"));
+    }
+
+    #[test]
+    fn test_migrate_yaml_newer_version() {
+        assert!(matches!(
+            migrate_yaml("version: 99.0.0\n"),
+            Err(MigrateError::NewerVersion(_))
+        ));
+        assert!(matches!(
+            migrate_yaml("targets: []\n"),
+            Err(MigrateError::MissingVersion)
+        ));
+    }
+}
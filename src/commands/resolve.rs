@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::Cli;
+use crate::config::sanitize_config;
+use crate::kubectl::Kubectl;
+use crate::pod_watch::BackingPod;
+use std::process::ExitCode;
+
+/// Prints the context, cluster, namespace and pod each selected target would use,
+/// without forwarding anything.
+pub fn resolve(cli: &Cli, kubectl: &Kubectl) -> anyhow::Result<ExitCode> {
+    let mut config = match crate::load_config(cli, kubectl) {
+        Ok(config) => config,
+        Err(code) => return crate::exitcode(code),
+    };
+    println!();
+
+    if let Err(code) = crate::use_kubeconfigs(kubectl, &config) {
+        return crate::exitcode(code);
+    }
+    let current_context = kubectl.current_context()?;
+    let current_cluster = kubectl.current_cluster()?;
+    match sanitize_config(&mut config, current_context, current_cluster, kubectl) {
+        Ok(warnings) => warnings.iter().for_each(|warning| eprintln!("{warning}")),
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            return crate::exitcode(exitcode::CONFIG);
+        }
+    }
+
+    let targets = crate::select_targets(&config.targets, &cli.tags, &cli.filters, &cli.excludes);
+    if targets.is_empty() {
+        eprintln!("No targets selected.");
+        crate::print_selection_hints(&config.targets, &cli.filters);
+        return crate::exitcode(exitcode::DATAERR);
+    }
+
+    let mut unresolved = 0;
+    for target in &targets {
+        let id = target.id.expect("IDs are assigned when sanitizing");
+        let padding = " ".repeat(id.to_string().len());
+        let resource = format!(
+            "{resource}/{name}",
+            resource = target.r#type.as_arg(),
+            name = target.target
+        );
+
+        match &target.name {
+            Some(name) => println!("{id} {name}"),
+            None => println!("{id} {resource}"),
+        }
+        println!(
+            "{padding} context:   {}",
+            target.context.as_deref().unwrap_or("(implicit)")
+        );
+        println!(
+            "{padding} cluster:   {}",
+            target.cluster.as_deref().unwrap_or("(implicit)")
+        );
+        println!("{padding} namespace: {}", target.namespace);
+        println!("{padding} resource:  {resource}");
+        if let Some(via) = &target.via {
+            println!(
+                "{padding} via:       pod/{pod}.{namespace}",
+                pod = via.pod,
+                namespace = via.namespace
+            );
+        }
+
+        match BackingPod::resolve(kubectl, target) {
+            Ok(Some(backing)) => println!(
+                "{padding} pod:       {pod}.{namespace}",
+                pod = backing.pod,
+                namespace = backing.namespace
+            ),
+            Ok(None) => {
+                unresolved += 1;
+                println!("{padding} pod:       (not found)");
+            }
+            Err(e) => {
+                unresolved += 1;
+                println!("{padding} pod:       (unknown: {e})");
+            }
+        }
+    }
+
+    if unresolved > 0 {
+        eprintln!();
+        eprintln!(
+            "No pod was found for {unresolved} of {} targets.",
+            targets.len()
+        );
+        return crate::exitcode(exitcode::UNAVAILABLE);
+    }
+
+    crate::exitcode(exitcode::OK)
+}
@@ -0,0 +1,40 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::PortForwardConfigs;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use std::process::ExitCode;
+
+/// Prints the JSON Schema of the configuration files, e.g. for editors to validate and
+/// complete `.k8sfwd` files.
+pub fn schema() -> anyhow::Result<ExitCode> {
+    println!("{}", serde_json::to_string_pretty(&config_schema())?);
+    crate::exitcode(exitcode::OK)
+}
+
+/// Generates the JSON Schema of the configuration files from their types.
+fn config_schema() -> RootSchema {
+    let mut schema = schema_for!(PortForwardConfigs);
+    let metadata = schema.schema.metadata();
+    metadata.title = Some("k8sfwd configuration".to_string());
+    metadata.description =
+        Some("The targets and settings of a k8sfwd configuration file.".to_string());
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_schema() {
+        let schema = serde_json::to_value(config_schema()).unwrap();
+        assert_eq!(schema["required"], serde_json::json!(["version"]));
+        assert_eq!(schema["properties"]["version"]["type"], "string");
+        assert!(schema["definitions"]["PortForwardConfig"]["properties"]["ports"].is_object());
+        assert!(schema["definitions"]["Port"]["anyOf"].is_array());
+        assert!(schema["definitions"]["Seconds"]["anyOf"].is_array());
+    }
+}
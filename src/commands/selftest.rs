@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::SelftestArgs;
+use crate::kubectl::{read_line_lossy, Kubectl};
+use crate::session::{HelperResource, Session};
+use crate::status::parse_forwarding_line;
+use crate::wizard;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpStream};
+use std::process::{Child, ExitCode};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The port the echo server of the pod listens on.
+const ECHO_PORT: u16 = 8080;
+
+/// How long to wait for the pod to be ready, including pulling the image.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait for kubectl to listen, and for the echo to arrive.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Deploys an echo pod, forwards a port to it and verifies that data makes the round trip,
+/// then deletes the pod again.
+pub fn selftest(kubectl: &Kubectl, args: SelftestArgs) -> anyhow::Result<ExitCode> {
+    let context = match args.context {
+        Some(context) => context,
+        None => kubectl.current_context()?,
+    };
+    let namespace = args.namespace;
+    let pod = format!("k8sfwd-selftest-{id}", id = std::process::id());
+
+    println!(
+        "The self-test deploys the pod {pod} ({image}) to namespace {namespace} of context {context}, forwards a port to it and deletes it afterwards.",
+        image = args.image
+    );
+    if !args.yes && !confirm() {
+        eprintln!("Cancelled; pass --yes to confirm without a terminal.");
+        return crate::exitcode(exitcode::NOPERM);
+    }
+    println!();
+
+    let selected = Some(context.clone());
+    let session = Session::new();
+    let mut passed = check_permissions(kubectl, selected.as_ref(), &namespace);
+    if passed {
+        let started = Instant::now();
+        passed = match kubectl.run_helper_pod(
+            selected.as_ref(),
+            &namespace,
+            &pod,
+            &args.image,
+            &[
+                "socat",
+                &format!("TCP-LISTEN:{ECHO_PORT},fork,reuseaddr"),
+                "EXEC:cat",
+            ],
+            &session,
+        ) {
+            Ok(()) => {
+                let mut pod = PodGuard {
+                    kubectl,
+                    session: &session,
+                    resource: HelperResource {
+                        kind: "pod".into(),
+                        name: pod.clone(),
+                        namespace: namespace.clone(),
+                        context: selected.clone(),
+                        shared: false,
+                    },
+                    deleted: false,
+                };
+                report(Ok(format!("Started pod {pod}", pod = pod.resource.name)));
+                let passed = run_pod_checks(kubectl, selected.as_ref(), &namespace, &pod, started);
+                let deleted = pod.delete();
+                passed && deleted
+            }
+            Err(e) => report(Err(format!("Unable to start pod {pod}: {e}"))),
+        };
+    }
+
+    println!();
+    if passed {
+        println!("All checks passed - k8sfwd can forward to context {context}");
+        crate::exitcode(exitcode::OK)
+    } else {
+        eprintln!("The self-test failed; see above for the failed check.");
+        crate::exitcode(exitcode::UNAVAILABLE)
+    }
+}
+
+/// Asks whether to deploy the pod.
+fn confirm() -> bool {
+    if !wizard::is_interactive() {
+        return false;
+    }
+
+    print!("Continue? (y/N) ");
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Checks the permissions needed to deploy and forward to the pod.
+fn check_permissions(kubectl: &Kubectl, context: Option<&String>, namespace: &str) -> bool {
+    ["pods", "pods/portforward"].into_iter().all(|resource| {
+        let result = match kubectl.can_i(context, namespace, "create", resource) {
+            Ok(true) => Ok(format!("Allowed to create {resource}")),
+            Ok(false) => Err(format!(
+                "Not allowed to create {resource} in namespace {namespace}"
+            )),
+            Err(e) => Err(format!(
+                "Unable to check the permission to create {resource}: {e}"
+            )),
+        };
+        report(result)
+    })
+}
+
+/// Waits for the pod, forwards a port to it and sends data through the tunnel.
+fn run_pod_checks(
+    kubectl: &Kubectl,
+    context: Option<&String>,
+    namespace: &str,
+    pod: &PodGuard,
+    started: Instant,
+) -> bool {
+    let name = &pod.resource.name;
+    let ready = match kubectl.wait_pod_ready(context, namespace, name, READY_TIMEOUT) {
+        Ok(()) => Ok(format!(
+            "The pod became ready after {secs:.1} sec",
+            secs = started.elapsed().as_secs_f64()
+        )),
+        Err(e) => Err(format!("The pod did not become ready: {e}")),
+    };
+    if !report(ready) {
+        return false;
+    }
+
+    let mut child = match kubectl.port_forward_pod(context, namespace, name, ECHO_PORT) {
+        Ok(child) => ChildGuard(child),
+        Err(e) => return report(Err(format!("Unable to start kubectl: {e}"))),
+    };
+
+    let port = match wait_forwarding(&mut child.0) {
+        Ok(port) => port,
+        Err(e) => return report(Err(format!("kubectl did not forward a port: {e}"))),
+    };
+    report(Ok(format!("Forwarding from local port {port}")));
+
+    let result = echo(port).map(|(bytes, elapsed)| {
+        format!(
+            "Received the {bytes} bytes sent through the tunnel after {ms} ms",
+            ms = elapsed.as_millis()
+        )
+    });
+    report(result.map_err(|e| format!("The data did not make the round trip: {e}")))
+}
+
+/// Waits for kubectl to report the local port it listens on.
+fn wait_forwarding(child: &mut Child) -> Result<u16, String> {
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Ok(Some(line)) = read_line_lossy(&mut reader, 4096) {
+                if let Some(port) = parse_forwarding_line(&line) {
+                    tx.send(Ok(port)).ok();
+                }
+            }
+        });
+    }
+    if let Some(mut stderr) = child.stderr.take() {
+        thread::spawn(move || {
+            let mut error = String::new();
+            stderr.read_to_string(&mut error).ok();
+            tx.send(Err(error.trim().to_string())).ok();
+        });
+    }
+
+    match rx.recv_timeout(FORWARD_TIMEOUT) {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "no port within {secs} sec",
+            secs = FORWARD_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+/// Sends a line through the tunnel and waits for the echo.
+///
+/// Returns the number of bytes along with the round-trip time.
+fn echo(port: u16) -> io::Result<(usize, Duration)> {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let payload = format!("k8sfwd self-test {nonce}\n");
+
+    let started = Instant::now();
+    let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))?;
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+    stream.write_all(payload.as_bytes())?;
+
+    let mut echoed = vec![0; payload.len()];
+    stream.read_exact(&mut echoed)?;
+    if echoed != payload.as_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the echo differs from the data sent",
+        ));
+    }
+    Ok((payload.len(), started.elapsed()))
+}
+
+/// Prints the outcome of a check; returns whether it passed.
+fn report(result: Result<String, String>) -> bool {
+    match result {
+        Ok(message) => {
+            println!("  ok      {message}");
+            true
+        }
+        Err(message) => {
+            println!("  FAILED  {message}");
+            false
+        }
+    }
+}
+
+/// The pod of the self-test; deleted along with the session when dropped, unless deleted before.
+struct PodGuard<'a> {
+    kubectl: &'a Kubectl,
+    /// The session the pod is recorded in.
+    session: &'a Session,
+    resource: HelperResource,
+    deleted: bool,
+}
+
+impl PodGuard<'_> {
+    /// Deletes the pod and reports the outcome; returns whether it was deleted.
+    fn delete(&mut self) -> bool {
+        self.deleted = true;
+        let name = &self.resource.name;
+        let deleted = self.session.teardown(self.kubectl);
+        report(match deleted.contains(&self.resource) {
+            true => Ok(format!("Deleted pod {name}")),
+            false => Err(format!(
+                "Unable to delete pod {name} - run `k8sfwd cleanup` to remove it"
+            )),
+        })
+    }
+}
+
+impl Drop for PodGuard<'_> {
+    fn drop(&mut self) {
+        if !self.deleted {
+            self.session.teardown(self.kubectl);
+        }
+    }
+}
+
+/// Terminates kubectl when dropped.
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
@@ -0,0 +1,139 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::StatusArgs;
+use crate::config::Theme;
+use crate::control::{ControlClient, ControlRequest, ControlResponse};
+use crate::status::{ForwardState, TargetStatus};
+use crate::table::Table;
+use std::process::ExitCode;
+
+/// Prints the status of the targets of the running session.
+pub fn status(args: StatusArgs, wide: bool, theme: Theme) -> anyhow::Result<ExitCode> {
+    let targets = match ControlClient::request(&ControlRequest::Status) {
+        Ok(ControlResponse::Status { targets }) => targets,
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Unable to query the status: {message}");
+            return crate::exitcode(exitcode::SOFTWARE);
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from k8sfwd: {response:?}");
+            return crate::exitcode(exitcode::PROTOCOL);
+        }
+        Err(e) => {
+            eprintln!("Unable to query the status: {e}");
+            return crate::exitcode(exitcode::UNAVAILABLE);
+        }
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&targets)?);
+    } else {
+        print_table(&targets, wide, theme);
+    }
+
+    crate::exitcode(exitcode::OK)
+}
+
+fn print_table(targets: &[TargetStatus], wide: bool, theme: Theme) {
+    if targets.is_empty() {
+        println!("No targets are running.");
+        return;
+    }
+
+    // Only shared sessions have targets owned by individual users.
+    let show_owner = targets.iter().any(|target| target.owner.is_some());
+    let show_health = targets.iter().any(|target| target.healthy.is_some());
+    let show_traffic = targets.iter().any(|target| target.traffic.is_some());
+
+    let mut header = vec![
+        "ID",
+        "TARGET",
+        "STATE",
+        "POD",
+        "PORTS",
+        "RESTARTS",
+        "LATENCY",
+        "LAST ERROR",
+    ];
+    if show_traffic {
+        header.splice(5..5, ["CONNS", "SENT", "RECEIVED"]);
+    }
+    if show_health {
+        header.insert(4, "HEALTH");
+    }
+    if show_owner {
+        header.insert(2, "OWNER");
+    }
+    let mut table = Table::new(header);
+    for target in targets {
+        let ports = target
+            .local_ports
+            .iter()
+            .map(
+                |port| match target.encryption.iter().find(|known| known.port == *port) {
+                    Some(known) => format!("{port} ({encryption})", encryption = known.encryption),
+                    None => port.to_string(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut row = vec![
+            target.id.clone(),
+            target.name.clone().unwrap_or_else(|| target.target.clone()),
+            theme.mark(target.state, target.state),
+            target.pod.clone().unwrap_or_else(|| "-".to_string()),
+            if ports.is_empty() {
+                "-".to_string()
+            } else {
+                ports
+            },
+            target.restarts.to_string(),
+            target
+                .latency_ms
+                .map(|ms| format!("{:.2}s", ms as f64 / 1000.0))
+                .unwrap_or_else(|| "-".to_string()),
+            target.last_error.clone().unwrap_or_else(|| "-".to_string()),
+        ];
+        if show_traffic {
+            let traffic = match &target.traffic {
+                Some(traffic) => vec![
+                    match traffic.active {
+                        0 => traffic.connections.to_string(),
+                        active => format!("{} ({active} open)", traffic.connections),
+                    },
+                    crate::format_bytes(traffic.bytes_sent),
+                    crate::format_bytes(traffic.bytes_received),
+                ],
+                None => vec!["-".to_string(); 3],
+            };
+            row.splice(5..5, traffic);
+        }
+        if show_health {
+            let health = match target.healthy {
+                Some(true) => theme.mark(ForwardState::Forwarding, "ok"),
+                Some(false) => theme.mark(ForwardState::Failed, "failing"),
+                None => "-".to_string(),
+            };
+            row.insert(4, health);
+        }
+        if show_owner {
+            row.insert(2, target.owner.clone().unwrap_or_else(|| "-".to_string()));
+        }
+        table.row(row);
+    }
+    table.print(wide);
+
+    // Drift is reported below the table, as it doesn't fit into a column.
+    let drifted: Vec<_> = targets.iter().filter(|t| !t.drift.is_empty()).collect();
+    if !drifted.is_empty() {
+        println!();
+        println!("Configuration drift:");
+        for target in drifted {
+            for drift in &target.drift {
+                println!("{id}: {drift}", id = target.id);
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::{Cli, SyncArgs};
+use crate::config::SyncConfig;
+use crate::kubectl::Kubectl;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
+
+/// Clones the team's repository of configuration fragments to the user's config directory,
+/// or updates the copy to the latest commit of the branch.
+///
+/// The repository and branch are taken from the arguments, or else from the `sync` setting.
+pub fn sync(cli: &Cli, kubectl: &Kubectl, args: SyncArgs) -> anyhow::Result<ExitCode> {
+    let Some(dir) = SyncConfig::dir() else {
+        eprintln!("Unable to determine the config directory to synchronize to");
+        return crate::exitcode(exitcode::UNAVAILABLE);
+    };
+
+    let configured = match &args.repository {
+        Some(_) => None,
+        None => crate::load_config(cli, kubectl)
+            .ok()
+            .and_then(|config| config.config)
+            .and_then(|config| config.sync),
+    };
+    let repository = args
+        .repository
+        .or_else(|| configured.as_ref().map(|sync| sync.repository.clone()));
+    let branch = args
+        .branch
+        .or_else(|| configured.and_then(|sync| sync.branch));
+
+    // Values starting with a dash would be taken for options of git.
+    if let Some(value) = repository
+        .iter()
+        .chain(&branch)
+        .find(|value| value.starts_with('-'))
+    {
+        eprintln!("Invalid repository or branch `{value}`: must not start with `-`");
+        return crate::exitcode(exitcode::CONFIG);
+    }
+
+    let result = if dir.join(".git").is_dir() {
+        update(&dir, repository.as_deref(), branch.as_deref())
+    } else if let Some(repository) = &repository {
+        clone(&dir, repository, branch.as_deref())
+    } else {
+        eprintln!("No team repository is configured - pass its URL or set `sync.repository`");
+        return crate::exitcode(exitcode::CONFIG);
+    };
+
+    match result {
+        Ok(()) => println!("Synchronized {dir}", dir = dir.display()),
+        Err(e) => {
+            eprintln!("Unable to synchronize {dir}: {e}", dir = dir.display());
+            return crate::exitcode(exitcode::UNAVAILABLE);
+        }
+    }
+
+    let fragments = SyncConfig::fragments(&dir, &cli.config_name);
+    if fragments.is_empty() {
+        println!("The repository holds no configuration fragments");
+    }
+    for fragment in fragments {
+        println!("- {fragment}", fragment = fragment.display());
+    }
+
+    crate::exitcode(exitcode::OK)
+}
+
+/// Clones the branch of the repository to the directory.
+fn clone(dir: &Path, repository: &str, branch: Option<&str>) -> Result<(), SyncError> {
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent).map_err(SyncError::Spawn)?;
+    }
+
+    let mut command = git();
+    command.args(["clone", "--quiet", "--depth", "1"]);
+    if let Some(branch) = branch {
+        command.args(["--branch", branch]);
+    }
+    run(command.arg("--").arg(repository).arg(dir))
+}
+
+/// Points the copy at the repository, if given, and resets it to the latest commit of the branch.
+///
+/// The copy is managed by k8sfwd; local changes are discarded.
+fn update(dir: &Path, repository: Option<&str>, branch: Option<&str>) -> Result<(), SyncError> {
+    if let Some(repository) = repository {
+        run(git()
+            .arg("-C")
+            .arg(dir)
+            .args(["remote", "set-url", "--", "origin", repository]))?;
+    }
+
+    run(git().arg("-C").arg(dir).args([
+        "fetch",
+        "--quiet",
+        "--depth",
+        "1",
+        "--",
+        "origin",
+        branch.unwrap_or("HEAD"),
+    ]))?;
+    run(git()
+        .arg("-C")
+        .arg(dir)
+        .args(["reset", "--quiet", "--hard", "FETCH_HEAD"]))
+}
+
+/// Creates a git command without a terminal for prompts.
+fn git() -> Command {
+    let mut command = Command::new("git");
+    command.env("GIT_TERMINAL_PROMPT", "0").stdin(Stdio::null());
+    command
+}
+
+/// Runs the git command, failing with its output if it fails.
+fn run(command: &mut Command) -> Result<(), SyncError> {
+    let output = command.output().map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => SyncError::GitNotFound,
+        _ => SyncError::Spawn(e),
+    })?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(SyncError::Git(stderr.trim().to_string()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum SyncError {
+    #[error("Synchronizing requires git, which was not found")]
+    GitNotFound,
+    #[error("Unable to run git: {0}")]
+    Spawn(io::Error),
+    #[error("{0}")]
+    Git(String),
+}
@@ -0,0 +1,79 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::cli::{RemapArgs, TargetArgs};
+use crate::control::{ControlClient, ControlRequest, ControlResponse};
+use std::process::ExitCode;
+
+/// Stops a target of the running session via its control socket.
+pub fn stop(args: TargetArgs) -> anyhow::Result<ExitCode> {
+    let request = ControlRequest::Stop {
+        target: args.target,
+    };
+    send_target_request(&request, "Stopping", "stop")
+}
+
+/// Restarts a target of the running session via its control socket.
+pub fn restart(args: TargetArgs) -> anyhow::Result<ExitCode> {
+    let request = ControlRequest::Restart {
+        target: args.target,
+    };
+    send_target_request(&request, "Restarting", "restart")
+}
+
+fn send_target_request(
+    request: &ControlRequest,
+    progress: &str,
+    verb: &str,
+) -> anyhow::Result<ExitCode> {
+    match ControlClient::request(request) {
+        Ok(ControlResponse::Target { id }) => {
+            println!("{progress} {id}");
+            crate::exitcode(exitcode::OK)
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Unable to {verb} the target: {message}");
+            crate::exitcode(exitcode::DATAERR)
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from k8sfwd: {response:?}");
+            crate::exitcode(exitcode::PROTOCOL)
+        }
+        Err(e) => {
+            eprintln!("Unable to {verb} the target: {e}");
+            crate::exitcode(exitcode::UNAVAILABLE)
+        }
+    }
+}
+
+/// Changes the local port of a target in the running session via its control socket.
+pub fn remap(args: RemapArgs) -> anyhow::Result<ExitCode> {
+    let request = ControlRequest::Remap {
+        target: args.target,
+        port: args.port.to_string(),
+    };
+
+    match ControlClient::request(&request) {
+        Ok(ControlResponse::Remapped {
+            id,
+            previous,
+            current,
+        }) => {
+            println!("{id}: Remapped {previous} to {current}");
+            crate::exitcode(exitcode::OK)
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Unable to remap the target: {message}");
+            crate::exitcode(exitcode::DATAERR)
+        }
+        Ok(response) => {
+            eprintln!("Unexpected response from k8sfwd: {response:?}");
+            crate::exitcode(exitcode::PROTOCOL)
+        }
+        Err(e) => {
+            eprintln!("Unable to remap the target: {e}");
+            crate::exitcode(exitcode::UNAVAILABLE)
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::daemon;
+use std::process::ExitCode;
+
+/// Re-spawns k8sfwd in the background.
+pub fn detach() -> anyhow::Result<ExitCode> {
+    match daemon::detach() {
+        Ok((pid, log)) => {
+            println!("Started k8sfwd in the background (PID {pid})");
+            println!("Logs are written to {log}", log = log.display());
+            crate::exitcode(exitcode::OK)
+        }
+        Err(e) => {
+            eprintln!("Unable to start k8sfwd in the background: {e}");
+            crate::exitcode(exitcode::UNAVAILABLE)
+        }
+    }
+}
@@ -2,32 +2,87 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
+mod cluster_config;
+mod config_format;
 mod config_id;
+mod config_name;
+mod dns_config;
+mod event_hooks_config;
+mod file_defaults;
+mod gateway_config;
+mod health_check_config;
+mod hook_command;
+mod http_router_config;
+mod interpolation;
+mod ip_family;
+mod keepalive_config;
+mod kubeconfig_names;
+mod loopback_aliases;
+mod merge_order;
+mod merge_strategy;
 mod merge_with;
 mod operational_config;
 mod port;
+mod port_conflict;
 mod port_forward_config;
 mod port_forward_configs;
+mod profile_config;
+mod protocol;
+mod remote_config;
+mod remote_ports;
 mod resource_type;
-mod retry_delay;
+mod restart_mode;
+mod seconds;
+mod secrets;
+mod shared_config;
+mod spawn_policy;
+mod sync_config;
+mod theme;
+mod tls_config;
 mod visit_tracker;
 
 use lazy_static::lazy_static;
 use semver::Version;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{env, io};
 
+use crate::config::kubeconfig_names::KubeconfigNames;
+use crate::config::loopback_aliases::assign_loopback_aliases;
+use crate::config::remote_ports::{discover_ports, resolve_port_names, RemotePortError};
 use crate::config::visit_tracker::VisitTracker;
-use crate::kubectl::Kubectl;
+use crate::kubectl::{ContextError, Kubectl};
+pub use cluster_config::ClusterConfig;
+pub use config_format::ConfigFormat;
 pub use config_id::ConfigId;
+pub use config_name::ConfigName;
+pub use dns_config::DnsConfig;
+pub use event_hooks_config::EventHooksConfig;
+pub use gateway_config::GatewayConfig;
+pub use health_check_config::HealthCheckConfig;
+pub use hook_command::HookCommand;
+pub use http_router_config::HttpRouterConfig;
+pub use ip_family::IpFamily;
+pub use keepalive_config::KeepaliveConfig;
+pub use merge_order::MergeOrder;
+pub use merge_strategy::MergeStrategy;
 pub use merge_with::MergeWith;
 pub use operational_config::OperationalConfig;
 pub use port::Port;
-pub use port_forward_config::PortForwardConfig;
-pub use port_forward_configs::{FromYaml, FromYamlError, PortForwardConfigs};
+pub use port_conflict::PortConflict;
+pub use port_forward_config::{ListenAddrError, ListenAddrWarning, PortForwardConfig};
+pub use port_forward_configs::{FromConfigFile, FromConfigFileError, PortForwardConfigs};
+pub use profile_config::ProfileConfig;
+pub use protocol::Protocol;
+pub use remote_config::{RemoteConfig, RemoteConfigError};
 pub use resource_type::ResourceType;
-pub use retry_delay::RetryDelay;
+pub use restart_mode::RestartMode;
+pub use seconds::Seconds;
+pub use shared_config::SharedConfig;
+pub use spawn_policy::SpawnPolicy;
+pub use sync_config::SyncConfig;
+pub use theme::Theme;
+pub use tls_config::TlsConfig;
 
 lazy_static! {
     pub static ref LOWEST_SUPPORTED_VERSION: Version = Version::new(0, 1, 0);
@@ -48,6 +103,9 @@ pub struct ConfigMeta {
     /// (if `true`, e.g. when automatically detected in presence of an explicitly
     /// specified file), or to load everything (if `false`).
     pub load_config_only: bool,
+    /// Whether the file was fetched from a remote source (or is included by such a file),
+    /// such that it may not run commands or read files on this machine.
+    pub remote: bool,
 }
 
 /// This method also unifies the "current" context/cluster configuration with the
@@ -57,16 +115,119 @@ pub fn sanitize_config(
     current_context: String,
     current_cluster: Option<String>,
     kubectl: &Kubectl,
-) {
+) -> Result<Vec<SanitizeWarning>, SanitizeError> {
     if let Some(operational) = &mut config.config {
         operational.sanitize();
     } else {
         config.config = Some(OperationalConfig::default());
     }
 
-    for config in config.targets.iter_mut() {
-        autofill_context_and_cluster(config, kubectl, &current_context, &current_cluster);
+    let operational = config.config.as_ref().expect("operational config exists");
+    let warnings = sanitize_targets(
+        &mut config.targets,
+        operational,
+        &current_context,
+        &current_cluster,
+        kubectl,
+    )?;
+
+    // Derive the IDs from all configured targets, such that selecting some doesn't change them.
+    let ids = ConfigId::assign(&config.targets);
+    for (target, id) in config.targets.iter_mut().zip(ids) {
+        target.id = Some(id);
+    }
+
+    Ok(warnings)
+}
+
+/// Fills in the missing values of the targets from the operational configuration
+/// and the current context/cluster.
+///
+/// Returns the warnings about listen addresses that were dropped while normalizing them.
+pub fn sanitize_targets(
+    targets: &mut [PortForwardConfig],
+    operational: &OperationalConfig,
+    current_context: &str,
+    current_cluster: &Option<String>,
+    kubectl: &Kubectl,
+) -> Result<Vec<SanitizeWarning>, SanitizeError> {
+    let ip_family = operational.ip_family.unwrap_or_default();
+    if operational.loopback_aliases == Some(true) && ip_family != IpFamily::Ipv6 {
+        assign_loopback_aliases(targets);
+    }
+
+    let mut warnings = Vec::new();
+    for target in targets.iter_mut() {
+        if let Some(context) = &mut target.context {
+            *context = operational.resolve_context(context).to_string();
+        }
     }
+
+    let mut kubeconfig = KubeconfigNames::load(targets, kubectl);
+    for target in targets.iter_mut() {
+        if let Some(kubeconfig) = &mut kubeconfig {
+            warnings.extend(kubeconfig.check(target).into_iter().map(|unknown| {
+                SanitizeWarning::UnknownName {
+                    kind: unknown.kind,
+                    name: unknown.name,
+                    similar: unknown.similar,
+                }
+            }));
+        }
+        autofill_context_and_cluster(target, kubectl, current_context, current_cluster)?;
+        resolve_port_names(target, kubectl)?;
+        if let Some(resource) = discover_ports(target, kubectl)? {
+            warnings.push(SanitizeWarning::DiscoveredPorts {
+                resource,
+                ports: target
+                    .ports
+                    .iter()
+                    .map(|port| port.remote.to_string())
+                    .collect(),
+            });
+        }
+        target.drain_timeout_sec = target
+            .drain_timeout_sec
+            .or(operational.drain_timeout_sec)
+            .map(Seconds::sanitized);
+        target.max_retries = target.max_retries.or(operational.max_retries);
+        target.detect_tls = target.detect_tls.or(operational.detect_tls);
+        target.request_timeout_sec = target
+            .request_timeout_sec
+            .or(operational.request_timeout_sec)
+            .map(Seconds::sanitized);
+        target.idle_timeout_sec = target
+            .idle_timeout_sec
+            .or(operational.idle_timeout_sec)
+            .map(Seconds::sanitized);
+        if let Some(keepalive) = &operational.keepalive {
+            target
+                .keepalive
+                .get_or_insert_with(Default::default)
+                .merge_with(keepalive);
+        }
+        target.ready_timeout_sec = target.ready_timeout_sec.map(Seconds::sanitized);
+        // Only the proxy knows about the in-flight connections to drain.
+        if target.drain_timeout_sec > Some(Seconds::ZERO) {
+            target.proxy.get_or_insert(true);
+        }
+        if target.failover == Some(true) {
+            target.proxy.get_or_insert(true);
+            target.watch.get_or_insert(true);
+            target.wait_ready.get_or_insert(true);
+        }
+        warnings.extend(
+            target
+                .normalize_listen_addrs(ip_family)?
+                .into_iter()
+                .map(SanitizeWarning::from),
+        );
+        if operational.auto_tags == Some(true) {
+            target.add_auto_tags();
+        }
+    }
+
+    Ok(warnings)
 }
 
 /// Fills the context and cluster name depending on which values are missing.
@@ -75,7 +236,7 @@ fn autofill_context_and_cluster(
     kubectl: &Kubectl,
     current_context: &str,
     current_cluster: &Option<String>,
-) {
+) -> Result<(), SanitizeError> {
     match (&mut config.context, &mut config.cluster) {
         (Some(_context), Some(_cluster)) => { /* nothing to do */ }
         (Some(context), None) => match kubectl.cluster_from_context(Some(context)) {
@@ -85,26 +246,55 @@ fn autofill_context_and_cluster(
             Ok(None) => {}
             Err(_) => {}
         },
-        (None, Some(cluster)) => match kubectl.context_from_cluster(Some(cluster)) {
-            Ok(Some(context)) => {
-                config.context = Some(context);
-            }
-            Ok(None) => {}
-            Err(_) => {}
-        },
+        (None, Some(cluster)) => {
+            let contexts = kubectl.contexts_of_cluster(cluster)?;
+            config.context = Some(context_of_cluster(
+                cluster,
+                contexts,
+                current_context,
+                current_cluster,
+            )?);
+        }
         (None, None) => {
             config.context = Some(current_context.to_owned());
             config.cluster = current_cluster.clone();
         }
     }
+
+    Ok(())
+}
+
+/// Picks the context to use for a cluster among the contexts using it, preferring the
+/// current context; `kubectl port-forward` only accepts a context.
+fn context_of_cluster(
+    cluster: &str,
+    contexts: Vec<String>,
+    current_context: &str,
+    current_cluster: &Option<String>,
+) -> Result<String, SanitizeError> {
+    match contexts.as_slice() {
+        [] => Err(SanitizeError::NoContext(cluster.to_string())),
+        [context] => Ok(context.clone()),
+        _ if current_cluster.as_deref() == Some(cluster) => Ok(current_context.to_string()),
+        _ => Err(SanitizeError::AmbiguousCluster {
+            cluster: cluster.to_string(),
+            contexts,
+        }),
+    }
 }
 
 /// Enumerates all configuration files along the path hierarchy,
-/// in the user's home directory and the user's config directory, in that order.
+/// in the user's home directory, the user's config directory and the fragments
+/// synchronized from the team's repository, in that order.
+///
+/// The files are looked for by the `name`, e.g. `.k8sfwd`. With `no_hierarchy`, only the
+/// files given on the command line are enumerated.
 pub fn collect_config_files(
     // TODO: Allow more than file
     cli_file: Vec<PathBuf>,
-) -> Result<Vec<(ConfigMeta, File)>, FindConfigFileError> {
+    name: &ConfigName,
+    no_hierarchy: bool,
+) -> Result<Vec<(ConfigMeta, io::Result<File>)>, FindConfigFileError> {
     let mut files = Vec::new();
     let mut visited_paths = VisitTracker::default();
 
@@ -112,24 +302,33 @@ pub fn collect_config_files(
 
     // Try file from the CLI arguments.
     for path in cli_file.into_iter() {
-        let file = File::open(&path)?;
-        // Ensure we don't specify the same file multiple times.
-        // We also return any errors since these files are explicitly specified.
-        if !visited_paths.track_file_path(&path)? {
-            // TODO: Attach file name to the error
-            files.push((
-                ConfigMeta {
-                    path,
-                    auto_detected: false,
-                    load_config_only: false,
-                },
-                file,
-            ));
-        }
+        // Ensure we don't specify the same file multiple times; files that can't be read
+        // are reported like any other file of the hierarchy.
+        let file = match visited_paths.track_file_path(&path) {
+            Ok(true) => continue,
+            Ok(false) => File::open(&path),
+            Err(e) => Err(e),
+        };
+        files.push((
+            ConfigMeta {
+                remote: RemoteConfig::is_fetched(&path),
+                path,
+                auto_detected: false,
+                load_config_only: false,
+            },
+            file,
+        ));
+    }
+
+    if no_hierarchy {
+        return if files.is_empty() {
+            Err(FindConfigFileError::NoFileSpecified)
+        } else {
+            Ok(files)
+        };
     }
 
     // Look for config file in current_dir + it's parents -> $HOME -> $HOME/.config
-    let config = PathBuf::from(DEFAULT_CONFIG_FILE);
     let working_dir = env::current_dir()?;
 
     let mut current_dir = working_dir.clone();
@@ -138,8 +337,7 @@ pub fn collect_config_files(
         levels_deep += 1;
         // Ignore the path if it was already specified by explicit arguments.
         if let Ok(false) = visited_paths.track_directory(&current_dir) {
-            let path = current_dir.join(&config);
-            if let Ok(file) = File::open(&path) {
+            if let Some((path, file)) = open_config_file(&current_dir, name) {
                 // Provide an easier to read path by keeping it relative if we
                 // are close to the current working directory.
                 let path = if levels_deep <= 4 {
@@ -148,16 +346,7 @@ pub fn collect_config_files(
                     path.canonicalize()?
                 };
 
-                files.push((
-                    ConfigMeta {
-                        path,
-                        auto_detected: true,
-                        load_config_only,
-                    },
-                    file,
-                ));
-            } else {
-                // TODO: Log error about invalid file
+                push_detected(&mut files, path, file, name, load_config_only);
             }
         }
 
@@ -171,10 +360,10 @@ pub fn collect_config_files(
     // $HOME
     handle_special_path(
         dirs::home_dir(),
+        name,
         &mut files,
         &mut visited_paths,
         load_config_only,
-        &config,
     )
     .ok();
 
@@ -182,13 +371,31 @@ pub fn collect_config_files(
     // Or just $HOME/.config if the above is not present
     handle_special_path(
         dirs::config_dir(),
+        name,
         &mut files,
         &mut visited_paths,
         load_config_only,
-        &config,
     )
     .ok();
 
+    // The fragments shared by the team, see `k8sfwd sync`.
+    if let Some(dir) = SyncConfig::dir().filter(|dir| dir.is_dir()) {
+        if let Ok(false) = visited_paths.track_directory(&dir) {
+            for path in SyncConfig::fragments(&dir, name) {
+                let file = File::open(&path);
+                files.push((
+                    ConfigMeta {
+                        path,
+                        auto_detected: true,
+                        load_config_only,
+                        remote: false,
+                    },
+                    file,
+                ));
+            }
+        }
+    }
+
     if files.is_empty() {
         Err(FindConfigFileError::FileNotFound)
     } else {
@@ -200,10 +407,10 @@ pub fn collect_config_files(
 /// These paths already have canonical names.
 fn handle_special_path(
     dir: Option<PathBuf>,
-    files: &mut Vec<(ConfigMeta, File)>,
+    name: &ConfigName,
+    files: &mut Vec<(ConfigMeta, io::Result<File>)>,
     visited_paths: &mut VisitTracker,
     load_config_only: bool,
-    config: &PathBuf,
 ) -> Result<bool, std::io::Error> {
     let path = match dir {
         Some(path) => path,
@@ -211,18 +418,8 @@ fn handle_special_path(
     };
 
     if !visited_paths.track_directory(&path)? {
-        let path = path.join(config);
-        if let Ok(file) = File::open(&path) {
-            files.push((
-                ConfigMeta {
-                    path,
-                    auto_detected: true,
-                    load_config_only,
-                },
-                file,
-            ));
-        } else {
-            // TODO: Log error about invalid file
+        if let Some((path, file)) = open_config_file(&path, name) {
+            push_detected(files, path, file, name, load_config_only);
         }
 
         Ok(false)
@@ -231,10 +428,135 @@ fn handle_special_path(
     }
 }
 
+/// Adds an automatically detected configuration file, preceded by the `.k8sfwd.local` file
+/// next to it, if any, such that personal overrides take precedence over the shared file.
+fn push_detected(
+    files: &mut Vec<(ConfigMeta, io::Result<File>)>,
+    path: PathBuf,
+    file: io::Result<File>,
+    name: &ConfigName,
+    load_config_only: bool,
+) {
+    let local = path.with_file_name(name.local_file_name());
+    if local.is_file() {
+        let local_file = File::open(&local);
+        files.push((
+            ConfigMeta {
+                path: local,
+                auto_detected: true,
+                load_config_only,
+                remote: false,
+            },
+            local_file,
+        ));
+    }
+
+    files.push((
+        ConfigMeta {
+            path,
+            auto_detected: true,
+            load_config_only,
+            remote: false,
+        },
+        file,
+    ));
+}
+
+/// Finds the configuration file closest to the current directory, e.g. to edit it.
+pub fn nearest_config_file(name: &ConfigName) -> Result<Option<PathBuf>, FindConfigFileError> {
+    let working_dir = env::current_dir()?;
+    Ok(working_dir.ancestors().find_map(|dir| {
+        open_config_file(dir, name)
+            .map(|(path, _)| pathdiff::diff_paths(&path, &working_dir).unwrap_or(path))
+    }))
+}
+
+/// Opens the first configuration file in the directory, trying the names of all formats.
+///
+/// A file that exists but can't be opened is returned along with the error.
+fn open_config_file(dir: &Path, name: &ConfigName) -> Option<(PathBuf, io::Result<File>)> {
+    name.file_names().into_iter().find_map(|name| {
+        let path = dir.join(name);
+        if !path.is_file() {
+            return None;
+        }
+        let file = File::open(&path);
+        Some((path, file))
+    })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum FindConfigFileError {
     #[error("No config file could be found in the path hierarchy")]
     FileNotFound,
+    #[error(
+        "No config file was specified with --file, and --no-hierarchy skips the search for one"
+    )]
+    NoFileSpecified,
     #[error(transparent)]
     InvalidWorkingDirectory(#[from] io::Error),
 }
+
+#[derive(Debug, thiserror::Error)]
+pub enum SanitizeError {
+    #[error(transparent)]
+    ListenAddr(#[from] ListenAddrError),
+    #[error(transparent)]
+    RemotePort(#[from] RemotePortError),
+    #[error("Unable to determine the context of a cluster: {0}")]
+    Context(#[from] ContextError),
+    #[error("No context of the kubeconfig uses the cluster {0} - set the `context` instead")]
+    NoContext(String),
+    #[error("The contexts {} all use the cluster {cluster} - set the `context` instead", .contexts.join(", "))]
+    AmbiguousCluster {
+        cluster: String,
+        contexts: Vec<String>,
+    },
+}
+
+/// A notice about a target that was changed while sanitizing it.
+#[derive(Debug, thiserror::Error)]
+pub enum SanitizeWarning {
+    #[error(transparent)]
+    ListenAddr(#[from] ListenAddrWarning),
+    #[error(
+        "The {kind} {name} is not in the kubeconfig{}",
+        .similar.as_ref().map(|similar| format!(" - did you mean {similar}?")).unwrap_or_default()
+    )]
+    UnknownName {
+        kind: &'static str,
+        name: String,
+        similar: Option<String>,
+    },
+    #[error("No ports are configured for {resource} - forwarding all of its ports ({}) to random local ports", .ports.join(", "))]
+    DiscoveredPorts {
+        resource: String,
+        ports: Vec<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_of_cluster() {
+        let current = Some("staging".to_string());
+        let contexts = vec!["admin@staging".to_string(), "dev@staging".to_string()];
+
+        let context = context_of_cluster("staging", contexts[..1].to_vec(), "other", &None);
+        assert_eq!(context.unwrap(), "admin@staging");
+
+        let context = context_of_cluster("staging", contexts.clone(), "dev@staging", &current);
+        assert_eq!(context.unwrap(), "dev@staging");
+
+        assert!(matches!(
+            context_of_cluster("staging", contexts, "other", &None),
+            Err(SanitizeError::AmbiguousCluster { .. })
+        ));
+        assert!(matches!(
+            context_of_cluster("staging", Vec::new(), "other", &None),
+            Err(SanitizeError::NoContext(_))
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::MergeWith;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Settings applying to all targets of a cluster.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+pub struct ClusterConfig {
+    /// A shell command verifying that the user is allowed to port-forward,
+    /// e.g. `kubectl auth can-i create pods/portforward`. The command is run
+    /// once per context and namespace before any target is started.
+    pub auth_check: Option<String>,
+}
+
+impl MergeWith for ClusterConfig {
+    fn merge_with(&mut self, other: &Self) {
+        self.auth_check.merge_with(&other.auth_check);
+    }
+}
+
+impl MergeWith for HashMap<String, ClusterConfig> {
+    fn merge_with(&mut self, other: &Self) {
+        for (name, config) in other {
+            self.entry(name.clone())
+                .and_modify(|existing| existing.merge_with(config))
+                .or_insert_with(|| config.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster() {
+        let config = serde_yaml::from_str::<ClusterConfig>(
+            r#"auth_check: kubectl auth can-i create pods/portforward"#,
+        )
+        .expect("configuration is valid");
+        assert_eq!(
+            config.auth_check.as_deref(),
+            Some("kubectl auth can-i create pods/portforward")
+        );
+    }
+
+    #[test]
+    fn test_merge_clusters() {
+        let mut clusters = HashMap::from([(
+            "production".to_string(),
+            ClusterConfig {
+                auth_check: Some("true".to_string()),
+            },
+        )]);
+        let other = HashMap::from([
+            (
+                "production".to_string(),
+                ClusterConfig {
+                    auth_check: Some("false".to_string()),
+                },
+            ),
+            ("staging".to_string(), ClusterConfig::default()),
+        ]);
+
+        clusters.merge_with(&other);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters["production"].auth_check.as_deref(), Some("true"));
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// The format of a configuration file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Determines the format by the extension of the file, or by its contents if the extension
+    /// is unknown, e.g. for `.k8sfwd`.
+    pub fn detect(path: &Path, contents: &str) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            Some("toml") => ConfigFormat::Toml,
+            _ => Self::sniff(contents),
+        }
+    }
+
+    /// Guesses the format from the start of the contents.
+    ///
+    /// JSON starts with an object, while the first line of TOML that isn't empty or a comment
+    /// is a table header or a `key = value` pair; anything else is read as YAML.
+    fn sniff(contents: &str) -> Self {
+        if contents.trim_start().starts_with('{') {
+            return ConfigFormat::Json;
+        }
+
+        let Some(line) = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+        else {
+            return ConfigFormat::Yaml;
+        };
+
+        let is_table = line.starts_with('[') && line.ends_with(']') && !line.contains(',');
+        let is_pair = line
+            .split_once('=')
+            .is_some_and(|(key, _)| is_bare_key(key.trim()));
+        if is_table || is_pair {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+}
+
+/// Determines whether the text is a key TOML accepts without quotes.
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigFormat::Yaml => write!(f, "YAML"),
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let detect = |path: &str, contents: &str| ConfigFormat::detect(Path::new(path), contents);
+        assert_eq!(detect(".k8sfwd.json", ""), ConfigFormat::Json);
+        assert_eq!(detect(".k8sfwd.TOML", ""), ConfigFormat::Toml);
+        assert_eq!(detect(".k8sfwd", "version: 0.3.0\n"), ConfigFormat::Yaml);
+        assert_eq!(
+            detect(".k8sfwd", "# comment\nversion = \"0.3.0\"\n"),
+            ConfigFormat::Toml
+        );
+        assert_eq!(detect(".k8sfwd", "[[targets]]\n"), ConfigFormat::Toml);
+        assert_eq!(
+            detect(".k8sfwd", "{\n  \"version\": \"0.3.0\"\n}\n"),
+            ConfigFormat::Json
+        );
+        assert_eq!(detect(".k8sfwd", "- [a, b]\n"), ConfigFormat::Yaml);
+    }
+}
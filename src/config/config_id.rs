@@ -2,25 +2,95 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
+use crate::config::PortForwardConfig;
+use crate::fnv::fnv1a;
 use std::fmt::{Display, Formatter};
+use std::num::ParseIntError;
+use std::str::FromStr;
 
+/// Identifies a target, e.g. `#3fa2`, in the output and in control commands.
+///
+/// The ID is derived from the target itself rather than its position, such that a target
+/// keeps its ID across runs, reloads and selections of the targets.
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Ord, Eq, Hash)]
-pub struct ConfigId(usize);
+pub struct ConfigId(u16);
 
 impl ConfigId {
-    pub fn new(id: usize) -> Self {
-        Self(id)
+    /// Derives the ID of the target from its identity, see [`PortForwardConfig::identity`].
+    /// IDs already in use by other targets are skipped.
+    pub fn of(config: &PortForwardConfig, in_use: impl Fn(ConfigId) -> bool) -> Self {
+        let hash = fnv1a(config.identity().to_string().as_bytes());
+        let start = (hash ^ (hash >> 16)) as u16;
+        (0..=u16::MAX)
+            .map(|offset| Self(start.wrapping_add(offset)))
+            .find(|id| !in_use(*id))
+            .unwrap_or(Self(start))
+    }
+
+    /// Derives the IDs of all configured targets, in the order they are configured in.
+    pub fn assign<'a>(targets: impl IntoIterator<Item = &'a PortForwardConfig>) -> Vec<Self> {
+        let mut ids: Vec<Self> = Vec::new();
+        for target in targets {
+            let id = Self::of(target, |id| ids.contains(&id));
+            ids.push(id);
+        }
+        ids
     }
 }
 
-impl From<usize> for ConfigId {
-    fn from(value: usize) -> Self {
-        ConfigId::new(value)
+impl From<u16> for ConfigId {
+    fn from(value: u16) -> Self {
+        Self(value)
     }
 }
 
 impl Display for ConfigId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "#{}", self.0)
+        write!(f, "#{:04x}", self.0)
+    }
+}
+
+impl FromStr for ConfigId {
+    type Err = ParseIntError;
+
+    /// Parses an ID from its display form (e.g. `#3fa2`) or its plain hexadecimal digits.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = s.strip_prefix('#').unwrap_or(s);
+        Ok(Self(u16::from_str_radix(id, 16)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign() {
+        let targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: api
+              context: staging
+              ports: [80]
+            - target: api
+              context: production
+              ports: [80]
+            - target: api
+              context: production
+              ports: [8080]
+            - target: api
+              context: production
+              cluster: eu
+              ports: [80]
+        "#,
+        )
+        .unwrap();
+
+        let ids = ConfigId::assign(&targets);
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+        assert_ne!(ids[1], ids[3]);
+        assert_eq!(ConfigId::assign(&targets[1..2]), vec![ids[1]]);
+        assert_eq!(ids[0].to_string().parse::<ConfigId>().unwrap(), ids[0]);
+        assert_eq!("#00ff".parse::<ConfigId>().unwrap(), ConfigId::from(255));
     }
 }
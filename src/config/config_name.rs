@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::{ConfigFormat, DEFAULT_CONFIG_FILE};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The extensions of the configuration formats, in order of precedence.
+const EXTENSIONS: [&str; 4] = ["yaml", "yml", "json", "toml"];
+
+/// The name of the configuration files looked for in the directory hierarchy, `.k8sfwd` by default.
+///
+/// Without an extension, the name is tried as is and with the extension of each format, e.g.
+/// `.k8sfwd.yaml`; a name with an extension, e.g. `k8sfwd.yaml`, only matches that file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigName(String);
+
+impl ConfigName {
+    /// Gets the names of the files looked for in each directory, in order of precedence.
+    pub fn file_names(&self) -> Vec<String> {
+        if self.extension().is_some() {
+            return vec![self.0.clone()];
+        }
+
+        let mut names = vec![self.0.clone()];
+        names.extend(EXTENSIONS.map(|extension| format!("{name}.{extension}", name = self.0)));
+        names
+    }
+
+    /// Gets the name of the personal, usually not versioned, file overriding the configuration
+    /// file next to it, e.g. `.k8sfwd.local` or `k8sfwd.local.yaml`.
+    pub fn local_file_name(&self) -> String {
+        match self.extension() {
+            Some(extension) => {
+                let stem = &self.0[..self.0.len() - extension.len() - 1];
+                format!("{stem}.local.{extension}")
+            }
+            None => format!("{name}.local", name = self.0),
+        }
+    }
+
+    /// Gets the name of the file to create a YAML configuration in, unless the name
+    /// requires another format.
+    pub fn yaml_file_name(&self) -> Option<&str> {
+        let format = ConfigFormat::detect(Path::new(&self.0), "");
+        (format == ConfigFormat::Yaml).then_some(self.0.as_str())
+    }
+
+    /// Gets the extension of the name, if it is the one of a configuration format.
+    fn extension(&self) -> Option<&str> {
+        let extension = Path::new(&self.0).extension()?.to_str()?;
+        EXTENSIONS
+            .iter()
+            .any(|known| extension.eq_ignore_ascii_case(known))
+            .then_some(extension)
+    }
+}
+
+impl Default for ConfigName {
+    fn default() -> Self {
+        Self(DEFAULT_CONFIG_FILE.to_string())
+    }
+}
+
+impl Display for ConfigName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ConfigName {
+    type Err = ConfigNameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s == "." || s == ".." {
+            return Err(ConfigNameError::Invalid(s.to_string()));
+        }
+        if s.contains(['/', '\\']) {
+            return Err(ConfigNameError::ContainsPath(s.to_string()));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigNameError {
+    #[error("Invalid configuration file name: {0:?}")]
+    Invalid(String),
+    #[error("The configuration file name {0} must not contain a path - use --file for other directories")]
+    ContainsPath(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        let name = ConfigName::default();
+        assert_eq!(
+            name.file_names(),
+            vec![
+                ".k8sfwd",
+                ".k8sfwd.yaml",
+                ".k8sfwd.yml",
+                ".k8sfwd.json",
+                ".k8sfwd.toml"
+            ]
+        );
+        assert_eq!(name.local_file_name(), ".k8sfwd.local");
+        assert_eq!(name.yaml_file_name(), Some(".k8sfwd"));
+
+        let name: ConfigName = "k8sfwd.yaml".parse().unwrap();
+        assert_eq!(name.file_names(), vec!["k8sfwd.yaml"]);
+        assert_eq!(name.local_file_name(), "k8sfwd.local.yaml");
+
+        let name: ConfigName = "k8sfwd.json".parse().unwrap();
+        assert_eq!(name.yaml_file_name(), None);
+
+        assert!("".parse::<ConfigName>().is_err());
+        assert!("config/k8sfwd.yaml".parse::<ConfigName>().is_err());
+    }
+}
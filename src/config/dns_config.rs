@@ -0,0 +1,68 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// The domain the names of the forwarded services are answered under by default.
+const DEFAULT_DOMAIN: &str = "fwd";
+
+/// A local DNS server answering names like `my-service.default.svc.fwd` with the local
+/// address the service is forwarded to.
+///
+/// ```yaml
+/// dns:
+///   port: 5353
+///   listen_addr: 127.0.0.1
+///   domain: fwd
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct DnsConfig {
+    /// The local UDP port to answer queries on.
+    pub port: u16,
+    /// The address to listen on; defaults to `127.0.0.1`.
+    pub listen_addr: Option<IpAddr>,
+    /// The domain the names are answered under; defaults to `fwd`.
+    pub domain: Option<String>,
+}
+
+impl DnsConfig {
+    /// Gets the address to listen on.
+    pub fn address(&self) -> SocketAddr {
+        let ip = self.listen_addr.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        SocketAddr::new(ip, self.port)
+    }
+
+    /// Gets the domain the names are answered under, without leading or trailing dots.
+    pub fn domain(&self) -> String {
+        self.domain
+            .as_deref()
+            .unwrap_or(DEFAULT_DOMAIN)
+            .trim_matches('.')
+            .to_ascii_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns() {
+        let config =
+            serde_yaml::from_str::<DnsConfig>(r#"port: 5353"#).expect("configuration is valid");
+        assert_eq!(config.address(), "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(config.domain(), "fwd");
+
+        let config = serde_yaml::from_str::<DnsConfig>(
+            r#"
+            port: 5353
+            domain: .Local.Test.
+        "#,
+        )
+        .expect("configuration is valid");
+        assert_eq!(config.domain(), "local.test");
+    }
+}
@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::{HookCommand, MergeWith};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// The commands run on events of any target, e.g. to send notifications.
+///
+/// ```yaml
+/// hooks:
+///   on_restart: notify-send "k8sfwd" "$K8SFWD_TARGET restarted"
+///   on_permanent_failure: ./page-me.sh
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+pub struct EventHooksConfig {
+    /// Run once a target listens on all of its ports; with `repeat`, each time it does.
+    pub on_ready: Option<HookCommand>,
+    /// Run each time kubectl exited and is about to be restarted.
+    pub on_restart: Option<HookCommand>,
+    /// Run once a target is given up, be it for a permanent error or after exceeding
+    /// its retries.
+    pub on_permanent_failure: Option<HookCommand>,
+}
+
+impl MergeWith for EventHooksConfig {
+    fn merge_with(&mut self, other: &Self) {
+        self.on_ready.merge_with(&other.on_ready);
+        self.on_restart.merge_with(&other.on_restart);
+        self.on_permanent_failure
+            .merge_with(&other.on_permanent_failure);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut hooks =
+            serde_yaml::from_str::<EventHooksConfig>(r#"on_restart: ./restarted.sh"#).unwrap();
+        let other = serde_yaml::from_str::<EventHooksConfig>(
+            r#"
+            on_restart: ./other.sh
+            on_permanent_failure:
+              command: ./failed.sh
+        "#,
+        )
+        .unwrap();
+
+        hooks.merge_with(&other);
+        assert_eq!(hooks.on_restart.unwrap().command, "./restarted.sh");
+        assert_eq!(hooks.on_permanent_failure.unwrap().command, "./failed.sh");
+        assert!(hooks.on_ready.is_none());
+    }
+}
@@ -0,0 +1,92 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Fills in the `default_context`, `default_cluster` and `default_namespace` of the `config`
+//! section for the targets of the same file, such that e.g. a staging-only file doesn't need
+//! to repeat the context on every target.
+
+use crate::config::OperationalConfig;
+use serde_yaml::Value;
+
+/// Applies the file-level defaults to the targets of the file that don't set the values.
+///
+/// Since the context and cluster select the kubeconfig entry together, neither default
+/// applies to targets setting one of them. Returns whether any target was changed.
+pub fn apply_file_defaults(config: &mut Value) -> bool {
+    // Invalid settings are reported when deserializing the entire file.
+    let Some(Ok(operational)) = config
+        .get("config")
+        .map(|operational| serde_yaml::from_value::<OperationalConfig>(operational.clone()))
+    else {
+        return false;
+    };
+
+    let context = operational.default_context.map(Value::from);
+    let cluster = operational.default_cluster.map(Value::from);
+    let namespace = operational.default_namespace.map(Value::from);
+    if context.is_none() && cluster.is_none() && namespace.is_none() {
+        return false;
+    }
+
+    let Some(targets) = config.get_mut("targets").and_then(Value::as_sequence_mut) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for target in targets {
+        let Some(target) = target.as_mapping_mut() else {
+            continue;
+        };
+
+        let is_unset = |key: &str| matches!(target.get(key), None | Some(Value::Null));
+        let mut defaults = Vec::new();
+        if is_unset("context") && is_unset("cluster") {
+            defaults.extend([("context", &context), ("cluster", &cluster)]);
+        }
+        if is_unset("namespace") {
+            defaults.push(("namespace", &namespace));
+        }
+
+        for (key, value) in defaults {
+            if let Some(value) = value {
+                target.insert(Value::from(key), value.clone());
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_file_defaults() {
+        let mut config: Value = serde_yaml::from_str(
+            r#"
+            config:
+              default_context: staging
+              default_namespace: payments
+            targets:
+              - target: api
+              - target: db
+                namespace: databases
+                cluster: production
+        "#,
+        )
+        .unwrap();
+
+        assert!(apply_file_defaults(&mut config));
+        let targets = &config["targets"];
+        assert_eq!(targets[0]["context"], Value::from("staging"));
+        assert_eq!(targets[0]["namespace"], Value::from("payments"));
+        assert_eq!(targets[1]["context"], Value::Null);
+        assert_eq!(targets[1]["namespace"], Value::from("databases"));
+
+        let mut config: Value = serde_yaml::from_str("targets: [{ target: api }]").unwrap();
+        assert!(!apply_file_defaults(&mut config));
+    }
+}
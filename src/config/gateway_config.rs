@@ -0,0 +1,49 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::port_forward_config::default_namespace;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A gateway pod through which a forward is chained, e.g. for namespaces
+/// whose pods cannot be port-forwarded to directly due to network policies.
+///
+/// The gateway relays the traffic to the actual target using `socat`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayConfig {
+    /// The name of the gateway pod.
+    pub pod: String,
+    /// The namespace of the gateway pod.
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    /// An optional image (e.g. `alpine/socat`) used to launch the gateway pod
+    /// if it does not exist yet.
+    pub image: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway() {
+        let config = serde_yaml::from_str::<GatewayConfig>(
+            r#"
+            pod: gateway
+            namespace: tools
+        "#,
+        )
+        .expect("configuration is valid");
+        assert_eq!(config.pod, "gateway");
+        assert_eq!(config.namespace, "tools");
+        assert_eq!(config.image, None);
+    }
+
+    #[test]
+    fn test_gateway_default_namespace() {
+        let config = serde_yaml::from_str::<GatewayConfig>(r#"pod: gateway"#)
+            .expect("configuration is valid");
+        assert_eq!(config.namespace, "default");
+    }
+}
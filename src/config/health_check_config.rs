@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::Seconds;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The default time between two health checks.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The shortest time between two health checks.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The default number of consecutive failed checks after which kubectl is restarted.
+const DEFAULT_FAILURE_THRESHOLD: usize = 3;
+
+/// Periodic checks that the forwarded local ports still accept connections
+/// or, if a path is configured, respond to HTTP requests.
+///
+/// ```yaml
+/// healthcheck:
+///   type: http
+///   path: /healthz
+///   port: 8080
+///   interval: 30s
+///   failure_threshold: 3
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HealthCheckConfig {
+    /// The kind of check; defaults to `http` if a path is configured, or `tcp` otherwise.
+    pub r#type: Option<HealthCheckType>,
+    /// The path to request via HTTP, e.g. `/healthz`; defaults to `/` for HTTP checks.
+    pub path: Option<String>,
+    /// The path to request via HTTP; the shorthand for `type: http` along with `path`.
+    pub http: Option<String>,
+    /// The remote port to check; defaults to all ports, or the first one for HTTP checks.
+    pub port: Option<u16>,
+    /// The number of seconds between two checks; defaults to 10.
+    #[serde(alias = "interval")]
+    pub interval_sec: Option<Seconds>,
+    /// The number of consecutive failed checks after which kubectl is restarted; defaults to 3.
+    pub failure_threshold: Option<usize>,
+}
+
+/// The kind of a health check.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheckType {
+    /// Only connects to the local ports.
+    Tcp,
+    /// Requests a path via HTTP and expects a successful or redirecting status.
+    Http,
+}
+
+impl HealthCheckConfig {
+    /// Gets the path to request via HTTP, or `None` if only TCP connections are made.
+    pub fn http_path(&self) -> Option<&str> {
+        let path = self.path.as_deref().or(self.http.as_deref());
+        match self.r#type {
+            Some(HealthCheckType::Tcp) => None,
+            Some(HealthCheckType::Http) => Some(path.unwrap_or("/")),
+            None => path,
+        }
+    }
+
+    /// Gets the time between two checks.
+    pub fn interval(&self) -> Duration {
+        self.interval_sec
+            .map_or(DEFAULT_INTERVAL, Duration::from)
+            .max(MIN_INTERVAL)
+    }
+
+    /// Gets the number of consecutive failed checks after which kubectl is restarted.
+    pub fn failure_threshold(&self) -> usize {
+        self.failure_threshold
+            .unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+            .max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = serde_yaml::from_str::<HealthCheckConfig>("{}").unwrap();
+        assert_eq!(config.interval(), DEFAULT_INTERVAL);
+        assert_eq!(config.failure_threshold(), DEFAULT_FAILURE_THRESHOLD);
+
+        let config =
+            serde_yaml::from_str::<HealthCheckConfig>("interval: 0\nfailure_threshold: 0").unwrap();
+        assert_eq!(config.interval(), MIN_INTERVAL);
+        assert_eq!(config.failure_threshold(), 1);
+    }
+
+    #[test]
+    fn test_http() {
+        let config = serde_yaml::from_str::<HealthCheckConfig>(
+            "{ http: /healthz, port: 8080, interval: 30s }",
+        )
+        .unwrap();
+        assert_eq!(config.http_path(), Some("/healthz"));
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.interval(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_type() {
+        let config = serde_yaml::from_str::<HealthCheckConfig>("{ type: http }").unwrap();
+        assert_eq!(config.http_path(), Some("/"));
+
+        let config = serde_yaml::from_str::<HealthCheckConfig>("{ path: /ready }").unwrap();
+        assert_eq!(config.http_path(), Some("/ready"));
+
+        let config =
+            serde_yaml::from_str::<HealthCheckConfig>("{ type: tcp, path: /ready }").unwrap();
+        assert_eq!(config.http_path(), None);
+    }
+}
@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A shell command run on an event of a target, e.g. once it is ready.
+///
+/// Either specified as a plain command line or with additional settings:
+///
+/// ```yaml
+/// on_ready:
+///   command: ./seed-cache.sh
+///   repeat: true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "HookCommandRepr")]
+pub struct HookCommand {
+    /// The command line, run through the system shell.
+    pub command: String,
+    /// Whether to run the command again each time the forward is re-established.
+    pub repeat: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum HookCommandRepr {
+    Command(String),
+    Detailed {
+        command: String,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+
+impl From<HookCommandRepr> for HookCommand {
+    fn from(value: HookCommandRepr) -> Self {
+        match value {
+            HookCommandRepr::Command(command) => Self {
+                command,
+                repeat: false,
+            },
+            HookCommandRepr::Detailed { command, repeat } => Self { command, repeat },
+        }
+    }
+}
+
+impl JsonSchema for HookCommand {
+    fn schema_name() -> String {
+        "HookCommand".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        HookCommandRepr::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_command() {
+        let hook: HookCommand = serde_yaml::from_str("pgcli -p $K8SFWD_PORT").unwrap();
+        assert_eq!(hook.command, "pgcli -p $K8SFWD_PORT");
+        assert!(!hook.repeat);
+
+        let hook: HookCommand = serde_yaml::from_str(
+            r#"
+            command: ./seed-cache.sh
+            repeat: true
+        "#,
+        )
+        .unwrap();
+        assert_eq!(hook.command, "./seed-cache.sh");
+        assert!(hook.repeat);
+    }
+}
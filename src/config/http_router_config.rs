@@ -0,0 +1,52 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// A local HTTP port that routes requests to the targets by their `Host` header,
+/// e.g. `api.staging.localhost` to the target with that `host`.
+///
+/// ```yaml
+/// http_router:
+///   port: 8080
+///   listen_addr: 127.0.0.1
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct HttpRouterConfig {
+    /// The local port to accept HTTP requests on.
+    pub port: u16,
+    /// The address to listen on; defaults to `127.0.0.1`.
+    pub listen_addr: Option<IpAddr>,
+}
+
+impl HttpRouterConfig {
+    /// Gets the address to listen on.
+    pub fn address(&self) -> SocketAddr {
+        let ip = self.listen_addr.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        SocketAddr::new(ip, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_router() {
+        let config = serde_yaml::from_str::<HttpRouterConfig>(r#"port: 8080"#)
+            .expect("configuration is valid");
+        assert_eq!(config.address(), "127.0.0.1:8080".parse().unwrap());
+
+        let config = serde_yaml::from_str::<HttpRouterConfig>(
+            r#"
+            port: 8080
+            listen_addr: "::1"
+        "#,
+        )
+        .expect("configuration is valid");
+        assert_eq!(config.address(), "[::1]:8080".parse().unwrap());
+    }
+}
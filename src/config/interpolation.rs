@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Expands environment variables like `${VAR}` or `${VAR:-default}` in the values of targets
+//! that commonly differ between developers, such that configurations can be checked in once.
+
+use serde_yaml::Value;
+
+/// The settings of a target in which environment variables are expanded.
+const INTERPOLATED_KEYS: &[&str] = &["target", "namespace", "context", "cluster", "listen_addrs"];
+
+/// Expands the environment variables in the interpolated settings of each target.
+///
+/// Returns whether any value was changed.
+pub fn interpolate_targets(config: &mut Value) -> Result<bool, InterpolationError> {
+    interpolate_targets_with(config, |name| std::env::var(name).ok())
+}
+
+fn interpolate_targets_with(
+    config: &mut Value,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<bool, InterpolationError> {
+    let Some(targets) = config.get_mut("targets").and_then(Value::as_sequence_mut) else {
+        return Ok(false);
+    };
+
+    let mut changed = false;
+    for target in targets {
+        let Some(target) = target.as_mapping_mut() else {
+            continue;
+        };
+
+        for key in INTERPOLATED_KEYS {
+            match target.get_mut(*key) {
+                Some(Value::String(value)) => changed |= interpolate(value, &lookup)?,
+                Some(Value::Sequence(values)) => {
+                    for value in values {
+                        if let Value::String(value) = value {
+                            changed |= interpolate(value, &lookup)?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` in the text; `$${` is kept as a literal `${`.
+///
+/// Returns whether the text was changed.
+fn interpolate(
+    text: &mut String,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<bool, InterpolationError> {
+    if !text.contains("${") {
+        return Ok(false);
+    }
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("${") {
+        // An escaped reference.
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        expanded.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            return Err(InterpolationError::Unterminated(text.clone()));
+        };
+
+        let reference = &rest[start + 2..start + length];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() {
+            return Err(InterpolationError::Unterminated(text.clone()));
+        }
+
+        // Like in shells, empty variables fall back to the default as well.
+        match (lookup(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => match lookup(name) {
+                Some(value) => expanded.push_str(&value),
+                None => return Err(InterpolationError::NotSet(name.into())),
+            },
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+
+    // Remove the escapes.
+    let expanded = expanded.replace("$${", "${");
+    let changed = expanded != *text;
+    *text = expanded;
+    Ok(changed)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InterpolationError {
+    #[error("The environment variable {0} is not set and has no default; use ${{{0}:-default}} to provide one")]
+    NotSet(String),
+    #[error("Invalid variable reference in {0:?}; expected e.g. ${{VAR}} or ${{VAR:-default}}")]
+    Unterminated(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "USER" => Some("jane".into()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let expand = |text: &str| {
+            let mut text = text.to_string();
+            interpolate(&mut text, lookup).map(|_| text)
+        };
+
+        assert_eq!(expand("dev-${USER}").unwrap(), "dev-jane");
+        assert_eq!(expand("${CONTEXT:-kind-kind}").unwrap(), "kind-kind");
+        assert_eq!(expand("${EMPTY:-default}").unwrap(), "default");
+        assert_eq!(expand("${EMPTY}").unwrap(), "");
+        assert_eq!(expand("$${USER}").unwrap(), "${USER}");
+        assert!(matches!(
+            expand("${CONTEXT}"),
+            Err(InterpolationError::NotSet(_))
+        ));
+        assert!(matches!(
+            expand("${USER"),
+            Err(InterpolationError::Unterminated(_))
+        ));
+    }
+
+    #[test]
+    fn test_interpolate_targets() {
+        let mut config: Value = serde_yaml::from_str(
+            r#"
+            targets:
+              - target: api
+                namespace: dev-${USER}
+                listen_addrs: ["${LISTEN:-127.0.0.1}"]
+                on_ready: echo ${K8SFWD_PORT}
+        "#,
+        )
+        .unwrap();
+
+        assert!(interpolate_targets_with(&mut config, lookup).unwrap());
+        let target = &config["targets"][0];
+        assert_eq!(target["namespace"], Value::String("dev-jane".into()));
+        assert_eq!(target["listen_addrs"][0], Value::String("127.0.0.1".into()));
+        assert_eq!(
+            target["on_ready"],
+            Value::String("echo ${K8SFWD_PORT}".into())
+        );
+    }
+}
@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+/// The IP family to listen on locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum IpFamily {
+    /// Listen on both IPv4 and IPv6, as `kubectl` does for `localhost`.
+    #[serde(rename = "dual")]
+    Dual,
+    /// Listen on IPv4 addresses only.
+    #[serde(rename = "ipv4")]
+    Ipv4,
+    /// Listen on IPv6 addresses only.
+    #[serde(rename = "ipv6")]
+    Ipv6,
+}
+
+impl Default for IpFamily {
+    fn default() -> Self {
+        Self::Dual
+    }
+}
+
+impl Display for IpFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpFamily::Dual => write!(f, "dual-stack"),
+            IpFamily::Ipv4 => write!(f, "IPv4"),
+            IpFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+impl IpFamily {
+    /// Gets the address `localhost` stands for, or `None` if `kubectl` should resolve it.
+    pub fn loopback(&self) -> Option<&'static str> {
+        match self {
+            IpFamily::Dual => None,
+            IpFamily::Ipv4 => Some("127.0.0.1"),
+            IpFamily::Ipv6 => Some("::1"),
+        }
+    }
+
+    /// Determines whether an address belongs to this family.
+    pub fn accepts(&self, address: &IpAddr) -> bool {
+        match self {
+            IpFamily::Dual => true,
+            IpFamily::Ipv4 => address.is_ipv4(),
+            IpFamily::Ipv6 => address.is_ipv6(),
+        }
+    }
+}
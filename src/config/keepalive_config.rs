@@ -0,0 +1,95 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::{MergeWith, Seconds};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The default time between two checks of the keepalive connections.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The shortest time between two checks of the keepalive connections.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The connections held open through the forwarded ports such that idle tunnels aren't closed.
+///
+/// ```yaml
+/// keepalive:
+///   enabled: true
+///   interval: 30s
+///   tcp_keepalive: 60s
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct KeepaliveConfig {
+    /// Whether to hold keepalive connections; defaults to `true`. Disable this for services
+    /// that log or bill every connection.
+    pub enabled: Option<bool>,
+    /// The number of seconds between checks that re-establish closed connections; defaults to 10.
+    #[serde(alias = "interval")]
+    pub interval_sec: Option<Seconds>,
+    /// The number of seconds a keepalive connection may be idle before TCP keepalive probes
+    /// are sent over it; uses the system's settings if unset.
+    #[serde(alias = "tcp_keepalive")]
+    pub tcp_keepalive_sec: Option<Seconds>,
+}
+
+impl KeepaliveConfig {
+    /// Determines whether keepalive connections are held.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Gets the time between two checks of the connections.
+    pub fn interval(&self) -> Duration {
+        self.interval_sec
+            .map_or(DEFAULT_INTERVAL, Duration::from)
+            .max(MIN_INTERVAL)
+    }
+
+    /// Gets the idle time after which TCP keepalive probes are sent, if configured.
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        self.tcp_keepalive_sec
+            .map(Duration::from)
+            .map(|idle| idle.max(MIN_INTERVAL))
+    }
+}
+
+impl MergeWith for KeepaliveConfig {
+    /// Fills the values not set in this configuration, e.g. those of a target
+    /// from the global settings.
+    fn merge_with(&mut self, other: &Self) {
+        self.enabled.merge_with(&other.enabled);
+        self.interval_sec.merge_with(&other.interval_sec);
+        self.tcp_keepalive_sec.merge_with(&other.tcp_keepalive_sec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = KeepaliveConfig::default();
+        assert!(config.is_enabled());
+        assert_eq!(config.interval(), DEFAULT_INTERVAL);
+        assert_eq!(config.tcp_keepalive(), None);
+
+        let config =
+            serde_yaml::from_str::<KeepaliveConfig>("{ interval: 0, tcp_keepalive: 1m }").unwrap();
+        assert_eq!(config.interval(), MIN_INTERVAL);
+        assert_eq!(config.tcp_keepalive(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut target = serde_yaml::from_str::<KeepaliveConfig>("enabled: false").unwrap();
+        let global =
+            serde_yaml::from_str::<KeepaliveConfig>("{ enabled: true, interval: 30 }").unwrap();
+        target.merge_with(&global);
+        assert!(!target.is_enabled());
+        assert_eq!(target.interval(), Duration::from_secs(30));
+    }
+}
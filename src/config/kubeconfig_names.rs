@@ -0,0 +1,120 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Validates the `context` and `cluster` of targets against the kubeconfig, such that typos
+//! are pointed out when loading the configuration rather than failing every retry of kubectl.
+
+use crate::config::PortForwardConfig;
+use crate::kubectl::Kubectl;
+use crate::target_filter::most_similar;
+use std::collections::HashSet;
+
+/// The contexts and clusters of the kubeconfig.
+pub struct KubeconfigNames {
+    contexts: Vec<String>,
+    clusters: Vec<String>,
+    /// The unknown names already reported, such that each is reported once.
+    reported: HashSet<(&'static str, String)>,
+}
+
+impl KubeconfigNames {
+    /// Gets the contexts and clusters of the kubeconfig if any of the targets refers to one,
+    /// or `None` if none does or kubectl can't list them.
+    pub fn load(targets: &[PortForwardConfig], kubectl: &Kubectl) -> Option<Self> {
+        if targets
+            .iter()
+            .all(|target| target.context.is_none() && target.cluster.is_none())
+        {
+            return None;
+        }
+
+        Some(Self::new(
+            kubectl.contexts().ok()?,
+            kubectl.clusters().ok()?,
+        ))
+    }
+
+    fn new(contexts: Vec<String>, clusters: Vec<String>) -> Self {
+        Self {
+            contexts,
+            clusters,
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Checks the context and cluster of the target, returning the ones not yet reported
+    /// that are not in the kubeconfig.
+    pub fn check(&mut self, target: &PortForwardConfig) -> Vec<UnknownName> {
+        let configured = [
+            ("context", &target.context, &self.contexts),
+            ("cluster", &target.cluster, &self.clusters),
+        ];
+
+        let mut unknown = Vec::new();
+        for (kind, name, known) in configured {
+            let Some(name) = name else {
+                continue;
+            };
+
+            if known.contains(name) || !self.reported.insert((kind, name.clone())) {
+                continue;
+            }
+
+            unknown.push(UnknownName {
+                kind,
+                name: name.clone(),
+                similar: most_similar(name, known).map(String::from),
+            });
+        }
+
+        unknown
+    }
+}
+
+/// A context or cluster that is not in the kubeconfig.
+#[derive(Debug)]
+pub struct UnknownName {
+    /// Either `context` or `cluster`.
+    pub kind: &'static str,
+    pub name: String,
+    /// The most similar name of the kubeconfig, if any.
+    pub similar: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: api
+              context: stagin
+              ports: [80]
+            - target: db
+              context: stagin
+              cluster: production
+              ports: [5432]
+            - target: web
+              context: qa
+              ports: [80]
+        "#,
+        )
+        .unwrap();
+
+        let mut names = KubeconfigNames::new(
+            vec!["staging".into(), "production".into()],
+            vec!["production".into()],
+        );
+        let unknown = names.check(&targets[0]);
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].kind, "context");
+        assert_eq!(unknown[0].similar.as_deref(), Some("staging"));
+
+        assert!(names.check(&targets[1]).is_empty());
+        let unknown = names.check(&targets[2]);
+        assert_eq!(unknown[0].similar, None);
+    }
+}
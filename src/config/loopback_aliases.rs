@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::PortForwardConfig;
+use crate::fnv::fnv1a;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+
+/// The number of aliases in `127.1.0.0/16`, leaving out the addresses ending in `.0` and `.255`.
+const CAPACITY: u32 = 256 * 254;
+
+/// Assigns a loopback address of its own to each target without listen addresses.
+///
+/// The address is derived from the name of the target, or its namespace, type and resource
+/// if it has none, such that a target keeps its address across reloads and sessions. Addresses
+/// already used by other targets are skipped.
+pub fn assign_loopback_aliases(targets: &mut [PortForwardConfig]) {
+    let mut used: HashSet<Ipv4Addr> = targets
+        .iter()
+        .flat_map(|target| &target.listen_addrs)
+        .filter_map(|address| address.parse().ok())
+        .collect();
+
+    // Targets claim their addresses in a fixed order, independent of the configuration files.
+    let mut pending: Vec<_> = targets
+        .iter_mut()
+        .filter(|target| target.listen_addrs.is_empty())
+        .collect();
+    pending.sort_by_cached_key(|target| key(target));
+
+    for target in pending {
+        let start = fnv1a(key(target).as_bytes()) % CAPACITY;
+        let Some(alias) = (0..CAPACITY)
+            .map(|offset| alias((start + offset) % CAPACITY))
+            .find(|alias| used.insert(*alias))
+        else {
+            return;
+        };
+
+        target.loopback_alias = Some(alias);
+        target.listen_addrs = vec![alias.to_string()];
+    }
+}
+
+/// Identifies the target independently of the current context.
+fn key(target: &PortForwardConfig) -> String {
+    match &target.name {
+        Some(name) => name.clone(),
+        None => format!(
+            "{namespace}/{kind}/{target}",
+            namespace = target.namespace,
+            kind = target.r#type.as_arg(),
+            target = target.target
+        ),
+    }
+}
+
+/// Gets the alias with the given index in `127.1.0.0/16`.
+fn alias(index: u32) -> Ipv4Addr {
+    Ipv4Addr::new(127, 1, (index / 254) as u8, (index % 254 + 1) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_loopback_aliases() {
+        let mut targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - name: api
+              target: api
+              ports: ["8080:80"]
+            - target: web
+              ports: ["8080:80"]
+            - target: db
+              listen_addrs: ["127.0.0.1"]
+              ports: ["5432"]
+        "#,
+        )
+        .unwrap();
+
+        assign_loopback_aliases(&mut targets);
+        let api = targets[0].loopback_alias.unwrap();
+        let web = targets[1].loopback_alias.unwrap();
+        assert_ne!(api, web);
+        assert_eq!(api.octets()[..2], [127, 1]);
+        assert_eq!(targets[0].listen_addrs, vec![api.to_string()]);
+        assert_eq!(targets[2].loopback_alias, None);
+
+        // The aliases don't depend on the order of the targets.
+        targets.reverse();
+        for target in &mut targets {
+            target.loopback_alias = None;
+            if target.target != "db" {
+                target.listen_addrs.clear();
+            }
+        }
+        assign_loopback_aliases(&mut targets);
+        assert_eq!(targets[1].loopback_alias, Some(web));
+        assert_eq!(targets[2].loopback_alias, Some(api));
+    }
+}
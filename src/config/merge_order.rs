@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::ConfigMeta;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Determines which configuration file wins when merged files set the same value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MergeOrder {
+    /// Files passed on the command line win over automatically detected ones,
+    /// which in turn win over files further up the directory hierarchy.
+    #[serde(rename = "cli-first", alias = "cli_first")]
+    CliFirst,
+    /// The automatically detected file closest to the working directory wins,
+    /// and files passed on the command line only provide defaults.
+    #[serde(rename = "nearest-first", alias = "nearest_first")]
+    NearestFirst,
+}
+
+impl Default for MergeOrder {
+    fn default() -> Self {
+        Self::CliFirst
+    }
+}
+
+impl Display for MergeOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeOrder::CliFirst => write!(f, "cli-first"),
+            MergeOrder::NearestFirst => write!(f, "nearest-first"),
+        }
+    }
+}
+
+impl MergeOrder {
+    /// Sorts the configurations by descending precedence.
+    ///
+    /// The configurations are expected in the order they were found in, i.e. the files
+    /// passed on the command line first, followed by the automatically detected files
+    /// from the working directory upwards.
+    pub fn prioritize<T>(&self, configs: &mut [(ConfigMeta, T)]) {
+        match self {
+            MergeOrder::CliFirst => {}
+            MergeOrder::NearestFirst => configs.sort_by_key(|(meta, _)| !meta.auto_detected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn meta(path: &str, auto_detected: bool) -> (ConfigMeta, ()) {
+        let meta = ConfigMeta {
+            path: PathBuf::from(path),
+            auto_detected,
+            load_config_only: false,
+            remote: false,
+        };
+        (meta, ())
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let found = || {
+            vec![
+                meta("cli.yaml", false),
+                meta(".k8sfwd", true),
+                meta("../.k8sfwd", true),
+            ]
+        };
+        let paths = |configs: Vec<(ConfigMeta, ())>| -> Vec<PathBuf> {
+            configs.into_iter().map(|(meta, _)| meta.path).collect()
+        };
+
+        let mut configs = found();
+        MergeOrder::CliFirst.prioritize(&mut configs);
+        assert_eq!(
+            paths(configs),
+            ["cli.yaml", ".k8sfwd", "../.k8sfwd"].map(PathBuf::from)
+        );
+
+        let mut configs = found();
+        MergeOrder::NearestFirst.prioritize(&mut configs);
+        assert_eq!(
+            paths(configs),
+            [".k8sfwd", "../.k8sfwd", "cli.yaml"].map(PathBuf::from)
+        );
+    }
+}
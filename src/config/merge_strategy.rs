@@ -0,0 +1,35 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Determines whether a configuration file inherits the targets of the files of lower precedence,
+/// e.g. of the home directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MergeStrategy {
+    /// The targets of the files of lower precedence are added to the ones of this file.
+    #[serde(rename = "extend")]
+    Extend,
+    /// Only the targets of this file and the files of higher precedence are used; the
+    /// operational settings of the other files still apply.
+    #[serde(rename = "replace")]
+    Replace,
+}
+
+impl Default for MergeStrategy {
+    fn default() -> Self {
+        Self::Extend
+    }
+}
+
+impl Display for MergeStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeStrategy::Extend => write!(f, "extend"),
+            MergeStrategy::Replace => write!(f, "replace"),
+        }
+    }
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 
 /// Trait for merging configuration instances.
@@ -47,3 +47,12 @@ where
         }
     }
 }
+
+impl MergeWith for HashMap<String, String> {
+    /// Adds the entries of the other map whose keys are not set in this one.
+    fn merge_with(&mut self, other: &Self) {
+        for (key, value) in other {
+            self.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+}
@@ -2,13 +2,110 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
-use crate::config::{MergeWith, RetryDelay};
+use crate::config::{
+    ClusterConfig, DnsConfig, EventHooksConfig, HttpRouterConfig, IpFamily, KeepaliveConfig,
+    MergeOrder, MergeWith, PortForwardConfig, Seconds, SharedConfig, SpawnPolicy, SyncConfig,
+    Theme,
+};
+use schemars::JsonSchema;
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Deserialize)]
+/// The lowest scheduling priority processes can be given.
+const MAX_NICENESS: u8 = 19;
+
+/// The number of seconds to delay retries for, unless configured.
+const DEFAULT_RETRY_DELAY_SEC: f64 = 5.0;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct OperationalConfig {
     /// The number of seconds to delay retries for.
-    pub retry_delay_sec: Option<RetryDelay>,
+    pub retry_delay_sec: Option<Seconds>,
+    /// The number of seconds to wait for in-flight connections to finish
+    /// when stopping a target.
+    #[serde(alias = "drain_timeout")]
+    pub drain_timeout_sec: Option<Seconds>,
+    /// The number of seconds between comparisons of the targets against the cluster;
+    /// disabled if unset.
+    #[serde(alias = "drift_check_interval")]
+    pub drift_check_interval_sec: Option<Seconds>,
+    /// The number of consecutive failed restarts after which a target is given up;
+    /// retries indefinitely if unset.
+    pub max_retries: Option<usize>,
+    /// The IP family to listen on; defaults to both IPv4 and IPv6.
+    pub ip_family: Option<IpFamily>,
+    /// The keepalive connections held through the forwarded ports, unless a target overrides them.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Settings for individual clusters, keyed by cluster or context name.
+    #[serde(default)]
+    pub clusters: HashMap<String, ClusterConfig>,
+    /// Short names for kubeconfig contexts, e.g. `staging`, mapped to the actual context names;
+    /// targets may refer to contexts by these names.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Settings of the shared (multi-user) mode.
+    #[serde(default)]
+    pub shared: SharedConfig,
+    /// The commands run on events of any target, e.g. to send notifications.
+    #[serde(default)]
+    pub hooks: EventHooksConfig,
+    /// Which configuration file wins when merged files set the same value;
+    /// taken from the file with the highest default precedence that sets it.
+    pub merge_order: Option<MergeOrder>,
+    /// Whether to tag each target with its namespace and cluster, e.g. `ns-payments`
+    /// and `cluster-staging`; defaults to `false`.
+    pub auto_tags: Option<bool>,
+    /// The glyphs marking the states of the targets in the output; defaults to `plain`.
+    pub theme: Option<Theme>,
+    /// How much to lower the scheduling priority of the spawned kubectl processes, from `0`
+    /// (unchanged) to `19` (lowest), as with `nice`; on Windows, `1` to `14` map to the
+    /// below-normal and higher values to the idle priority class.
+    pub child_niceness: Option<u8>,
+    /// The local HTTP port routing requests to the targets by their `host`; disabled if unset.
+    pub http_router: Option<HttpRouterConfig>,
+    /// The local DNS server answering the names of the forwarded services; disabled if unset.
+    pub dns: Option<DnsConfig>,
+    /// Whether to map the `hostnames` of the targets to their local addresses in the hosts file;
+    /// defaults to `false`.
+    pub manage_hosts: Option<bool>,
+    /// The hosts file to manage; defaults to the hosts file of the system, e.g. `/etc/hosts`.
+    pub hosts_file: Option<PathBuf>,
+    /// Whether to give each target without listen addresses a loopback address of its own,
+    /// e.g. `127.1.23.45`, such that all targets can use their natural ports; defaults to `false`.
+    pub loopback_aliases: Option<bool>,
+    /// Whether to probe the forwarded ports with a TLS handshake to tell whether they speak TLS,
+    /// unless a target overrides it; defaults to `false`.
+    pub detect_tls: Option<bool>,
+    /// Whether to verify that the resources of the targets exist before forwarding to them;
+    /// defaults to `false`.
+    pub check_targets: Option<bool>,
+    /// The number of seconds kubectl waits for a response of the Kubernetes API before giving up,
+    /// unless a target overrides it; kubectl waits indefinitely if unset.
+    #[serde(alias = "request_timeout")]
+    pub request_timeout_sec: Option<Seconds>,
+    /// The number of seconds without any connection through a tunnel after which kubectl is
+    /// restarted, unless a target overrides it; kubectl keeps running if unset.
+    #[serde(alias = "idle_timeout")]
+    pub idle_timeout_sec: Option<Seconds>,
+    /// The kubeconfig files of all kubectl invocations, joined into `KUBECONFIG`; relative paths
+    /// are relative to the configuration file. The inherited `KUBECONFIG` is used if empty.
+    #[serde(default)]
+    pub kubeconfigs: Vec<PathBuf>,
+    /// The context of the targets of this file that set neither a context nor a cluster;
+    /// applies to this file only and is not merged.
+    pub default_context: Option<String>,
+    /// The cluster of the targets of this file that set neither a context nor a cluster;
+    /// applies to this file only and is not merged.
+    pub default_cluster: Option<String>,
+    /// The namespace of the targets of this file that don't set one;
+    /// applies to this file only and is not merged.
+    pub default_namespace: Option<String>,
+    /// The git repository of configuration fragments shared by the team, see `k8sfwd sync`.
+    pub sync: Option<SyncConfig>,
+    /// Whether a target failing to spawn its processes stops all targets (`abort`) or only
+    /// itself (`continue`); defaults to `abort`.
+    pub on_spawn_error: Option<SpawnPolicy>,
     // TODO: Add mappings of cluster names; useful for merged hierarchical configs
 }
 
@@ -17,6 +114,42 @@ impl MergeWith for OperationalConfig {
         if self.retry_delay_sec.is_none() {
             self.retry_delay_sec = other.retry_delay_sec;
         }
+
+        self.drain_timeout_sec.merge_with(&other.drain_timeout_sec);
+        self.drift_check_interval_sec
+            .merge_with(&other.drift_check_interval_sec);
+        self.max_retries.merge_with(&other.max_retries);
+        self.ip_family.merge_with(&other.ip_family);
+        self.keepalive.merge_with(&other.keepalive);
+        self.clusters.merge_with(&other.clusters);
+        self.aliases.merge_with(&other.aliases);
+        self.shared.merge_with(&other.shared);
+        self.hooks.merge_with(&other.hooks);
+        self.merge_order.merge_with(&other.merge_order);
+        self.auto_tags.merge_with(&other.auto_tags);
+        self.theme.merge_with(&other.theme);
+        self.child_niceness.merge_with(&other.child_niceness);
+        self.http_router.merge_with(&other.http_router);
+        self.dns.merge_with(&other.dns);
+        self.manage_hosts.merge_with(&other.manage_hosts);
+        self.hosts_file.merge_with(&other.hosts_file);
+        self.loopback_aliases.merge_with(&other.loopback_aliases);
+        self.detect_tls.merge_with(&other.detect_tls);
+        self.check_targets.merge_with(&other.check_targets);
+        self.request_timeout_sec
+            .merge_with(&other.request_timeout_sec);
+        self.idle_timeout_sec.merge_with(&other.idle_timeout_sec);
+        self.sync.merge_with(&other.sync);
+        self.on_spawn_error.merge_with(&other.on_spawn_error);
+
+        // The file-level defaults were applied to the targets of their files when loading them.
+
+        // The files of all configurations are used, those of this one first.
+        for kubeconfig in &other.kubeconfigs {
+            if !self.kubeconfigs.contains(kubeconfig) {
+                self.kubeconfigs.push(kubeconfig.clone());
+            }
+        }
     }
 }
 
@@ -31,21 +164,96 @@ impl MergeWith<Option<OperationalConfig>> for OperationalConfig {
 impl Default for OperationalConfig {
     fn default() -> Self {
         Self {
-            retry_delay_sec: Some(RetryDelay::default()),
+            retry_delay_sec: Some(Seconds::from_secs(DEFAULT_RETRY_DELAY_SEC)),
+            drain_timeout_sec: None,
+            drift_check_interval_sec: None,
+            max_retries: None,
+            ip_family: None,
+            keepalive: None,
+            clusters: HashMap::new(),
+            aliases: HashMap::new(),
+            shared: SharedConfig::default(),
+            hooks: EventHooksConfig::default(),
+            merge_order: None,
+            auto_tags: None,
+            theme: None,
+            child_niceness: None,
+            http_router: None,
+            dns: None,
+            manage_hosts: None,
+            hosts_file: None,
+            loopback_aliases: None,
+            detect_tls: None,
+            check_targets: None,
+            request_timeout_sec: None,
+            idle_timeout_sec: None,
+            kubeconfigs: Vec::new(),
+            default_context: None,
+            default_cluster: None,
+            default_namespace: None,
+            sync: None,
+            on_spawn_error: None,
         }
     }
 }
 
 impl OperationalConfig {
+    /// Gets the settings of the target's cluster, looked up by cluster name first, then by context name.
+    pub fn cluster_config(&self, target: &PortForwardConfig) -> Option<&ClusterConfig> {
+        target
+            .cluster
+            .as_ref()
+            .and_then(|cluster| self.clusters.get(cluster))
+            .or_else(|| {
+                target
+                    .context
+                    .as_ref()
+                    .and_then(|context| self.clusters.get(context))
+            })
+    }
+
+    /// Gets the actual name of a context that may be referred to by an alias.
+    pub fn resolve_context<'a>(&'a self, context: &'a str) -> &'a str {
+        self.aliases.get(context).map_or(context, String::as_str)
+    }
+
+    /// Makes the paths of the kubeconfig files absolute, expanding `~` to the home directory
+    /// and resolving relative paths against the directory of the configuration file.
+    pub fn resolve_kubeconfigs(&mut self, base_dir: &Path) {
+        // kubectl runs in a directory of its own.
+        let base_dir = std::env::current_dir()
+            .map(|dir| dir.join(base_dir))
+            .unwrap_or_else(|_| base_dir.to_path_buf());
+        for kubeconfig in &mut self.kubeconfigs {
+            let home = kubeconfig
+                .strip_prefix("~")
+                .ok()
+                .zip(dirs::home_dir())
+                .map(|(relative, home)| home.join(relative));
+            *kubeconfig = match home {
+                Some(path) => path,
+                None => base_dir.join(&kubeconfig),
+            };
+        }
+    }
+
     /// Ensures that values, if set, are valid (or sanitized such that they are valid).
     pub fn sanitize(&mut self) {
         if self.retry_delay_sec.is_some()
-            && self.retry_delay_sec.expect("value exists") < RetryDelay::NONE
+            && self.retry_delay_sec.expect("value exists") < Seconds::ZERO
         {
-            self.retry_delay_sec = Some(RetryDelay::NONE);
+            self.retry_delay_sec = Some(Seconds::ZERO);
         } else {
-            self.retry_delay_sec = Some(RetryDelay::default())
+            self.retry_delay_sec = Some(Seconds::from_secs(DEFAULT_RETRY_DELAY_SEC))
         }
+
+        self.drain_timeout_sec = self.drain_timeout_sec.map(Seconds::sanitized);
+        self.drift_check_interval_sec = self.drift_check_interval_sec.map(Seconds::sanitized);
+        self.request_timeout_sec = self.request_timeout_sec.map(Seconds::sanitized);
+        self.idle_timeout_sec = self.idle_timeout_sec.map(Seconds::sanitized);
+        self.child_niceness = self
+            .child_niceness
+            .map(|niceness| niceness.min(MAX_NICENESS));
     }
 }
 
@@ -60,13 +268,117 @@ mod tests {
         assert_eq!(config.retry_delay_sec, None);
 
         config.sanitize();
-        assert_eq!(config.retry_delay_sec, Some(RetryDelay::default()));
+        assert_eq!(
+            config.retry_delay_sec,
+            Some(Seconds::from_secs(DEFAULT_RETRY_DELAY_SEC))
+        );
     }
 
     #[test]
     fn test_operational() {
         let config = serde_yaml::from_str::<OperationalConfig>(r#"retry_delay_sec: 3.14"#)
             .expect("configuration is valid");
-        assert_eq!(config.retry_delay_sec, Some(RetryDelay::from_secs(3.14)))
+        assert_eq!(config.retry_delay_sec, Some(Seconds::from_secs(3.14)))
+    }
+
+    #[test]
+    fn test_clusters() {
+        let config = serde_yaml::from_str::<OperationalConfig>(
+            r#"
+            clusters:
+              production:
+                auth_check: kubectl auth can-i create pods/portforward
+        "#,
+        )
+        .expect("configuration is valid");
+        assert!(config.clusters.contains_key("production"));
+    }
+
+    #[test]
+    fn test_aliases() {
+        let config = serde_yaml::from_str::<OperationalConfig>(
+            r#"
+            aliases:
+              staging: arn:aws:eks:eu-west-1:123456789012:cluster/staging
+        "#,
+        )
+        .expect("configuration is valid");
+        assert_eq!(
+            config.resolve_context("staging"),
+            "arn:aws:eks:eu-west-1:123456789012:cluster/staging"
+        );
+        assert_eq!(config.resolve_context("kind-kind"), "kind-kind");
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let mut config = serde_yaml::from_str::<OperationalConfig>(r#"drain_timeout_sec: -1"#)
+            .expect("configuration is valid");
+        config.sanitize();
+        assert_eq!(config.drain_timeout_sec, Some(Seconds::ZERO));
+
+        let config = serde_yaml::from_str::<OperationalConfig>(r#"drain_timeout: 10"#)
+            .expect("configuration is valid");
+        assert_eq!(config.drain_timeout_sec, Some(Seconds::from_secs(10.0)));
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let mut config = serde_yaml::from_str::<OperationalConfig>(
+            r#"{ request_timeout: 30s, idle_timeout_sec: -1 }"#,
+        )
+        .expect("configuration is valid");
+        config.sanitize();
+        assert_eq!(config.request_timeout_sec, Some(Seconds::from_secs(30.0)));
+        assert_eq!(config.idle_timeout_sec, Some(Seconds::ZERO));
+    }
+
+    #[test]
+    fn test_ip_family() {
+        let config = serde_yaml::from_str::<OperationalConfig>(r#"ip_family: ipv6"#)
+            .expect("configuration is valid");
+        assert_eq!(config.ip_family, Some(IpFamily::Ipv6));
+
+        serde_yaml::from_str::<OperationalConfig>(r#"ip_family: ipv5"#)
+            .expect_err("the IP family is invalid");
+    }
+
+    #[test]
+    fn test_child_niceness() {
+        let mut config = serde_yaml::from_str::<OperationalConfig>(r#"child_niceness: 40"#)
+            .expect("configuration is valid");
+        config.sanitize();
+        assert_eq!(config.child_niceness, Some(19));
+
+        serde_yaml::from_str::<OperationalConfig>(r#"child_niceness: -5"#)
+            .expect_err("raising the priority is not supported");
+    }
+
+    #[test]
+    fn test_kubeconfigs() {
+        let mut config = serde_yaml::from_str::<OperationalConfig>(
+            r#"kubeconfigs: [staging.yaml, /etc/kube/prod.yaml]"#,
+        )
+        .expect("configuration is valid");
+        config.resolve_kubeconfigs(Path::new("/home/user/project"));
+        config.merge_with(&OperationalConfig {
+            kubeconfigs: vec![PathBuf::from("/etc/kube/prod.yaml"), PathBuf::from("/a")],
+            ..OperationalConfig::default()
+        });
+        assert_eq!(
+            config.kubeconfigs,
+            vec![
+                PathBuf::from("/home/user/project/staging.yaml"),
+                PathBuf::from("/etc/kube/prod.yaml"),
+                PathBuf::from("/a")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_retries() {
+        let config = serde_yaml::from_str::<OperationalConfig>(r#"max_retries: 3"#)
+            .expect("configuration is valid");
+        assert_eq!(config.max_retries, Some(3));
     }
 }
@@ -3,28 +3,137 @@
 // SPDX-FileType: SOURCE
 
 use crate::config::MergeWith;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
-use std::collections::HashSet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::num::ParseIntError;
+use std::str::FromStr;
 
 /// A port to forward.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Port {
     /// The local port to forward to.
     pub local: Option<u16>,
-    /// The remote port to forward to.
+    /// The remote port to forward to; `0` until the name of the port is resolved.
     pub remote: u16,
+    /// The name of the remote port as declared by the resource, e.g. `postgres`.
+    pub name: Option<String>,
+}
+
+impl Port {
+    /// Creates a port forwarding the remote port by its number.
+    pub fn new(local: Option<u16>, remote: u16) -> Self {
+        Self {
+            local,
+            remote,
+            name: None,
+        }
+    }
+
+    /// Determines whether the remote port is known by its number, rather than only by its name.
+    pub fn is_resolved(&self) -> bool {
+        self.remote != 0
+    }
+
+    /// Determines whether both ports forward the same remote port, by its number or name.
+    fn is_same_remote(&self, other: &Port) -> bool {
+        if self.is_resolved() || other.is_resolved() {
+            self.remote == other.remote
+        } else {
+            self.name == other.name
+        }
+    }
 }
 
 impl MergeWith for Vec<Port> {
+    /// Adds the ports of the other list forwarding remote ports not in this list, keeping the
+    /// order of both; for the same remote port, the local port of this list wins if it sets one.
     fn merge_with(&mut self, other: &Self) {
-        if other.is_empty() {
-            return;
+        for port in other {
+            match self
+                .iter_mut()
+                .find(|existing| existing.is_same_remote(port))
+            {
+                Some(existing) => existing.local.merge_with(&port.local),
+                None => self.push(port.clone()),
+            }
+        }
+    }
+}
+
+impl FromStr for Port {
+    type Err = PortParseError;
+
+    /// Parses a port from a `local:remote`, `:remote` or `remote` string,
+    /// where the remote port may be given by its name, e.g. `5432:postgres`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split the string by ':' and parse the numbers
+        let parts: Vec<&str> = s.split(':').collect();
+        let (local, remote) = match parts[..] {
+            [local, remote] => {
+                let local = match local {
+                    "" => None,
+                    value => Some(value.parse::<u16>()?),
+                };
+                (local, remote)
+            }
+            [remote] => (None, remote),
+            _ => return Err(PortParseError::InvalidFormat),
+        };
+
+        if is_port_name(remote) {
+            return Ok(Port {
+                local,
+                remote: 0,
+                name: Some(remote.to_string()),
+            });
         }
+        Ok(Port::new(local, remote.parse::<u16>()?))
+    }
+}
+
+/// Determines whether the remote part of a port is the name of a port, which, like in
+/// Kubernetes, consists of lowercase letters, digits and hyphens and contains a letter.
+fn is_port_name(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && s.chars().any(|c| c.is_ascii_lowercase())
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let remote = match (&self.name, self.is_resolved()) {
+            (Some(name), false) => name.clone(),
+            _ => self.remote.to_string(),
+        };
+        match self.local {
+            Some(local) => write!(f, "{local}:{remote}"),
+            None => write!(f, ":{remote}"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortParseError {
+    #[error("Invalid string format")]
+    InvalidFormat,
+    #[error(transparent)]
+    InvalidNumber(#[from] ParseIntError),
+    #[error("Invalid port range: the first port must be positive and not exceed the last one")]
+    InvalidRange,
+    #[error("Invalid port range: the local and remote ranges differ in length")]
+    RangeMismatch,
+}
 
-        let set: HashSet<Port> = HashSet::from_iter(self.iter().cloned());
-        let other_set = HashSet::from_iter(other.iter().cloned());
-        *self = Vec::from_iter(&mut set.union(&other_set).cloned());
+impl Serialize for Port {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
     }
 }
 
@@ -33,131 +142,267 @@ impl<'de> Deserialize<'de> for Port {
     where
         D: Deserializer<'de>,
     {
-        struct PortVisitor;
+        deserializer.deserialize_any(PortVisitor)
+    }
+}
 
-        impl<'de> serde::de::Visitor<'de> for PortVisitor {
-            type Value = Port;
+impl JsonSchema for Port {
+    fn schema_name() -> String {
+        "Port".to_string()
+    }
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("a string or an object")
-            }
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        PortSchema::json_schema(gen)
+    }
+}
 
-            fn visit_i16<E>(self, remote: i16) -> Result<Self::Value, E>
-            where
-                E: Error,
-            {
-                if remote <= 0 {
-                    return Err(E::custom("Invalid port number: value must be positive"));
-                }
+/// The forms a port is accepted in; only describes the schema of [`Port`].
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum PortSchema {
+    /// The remote port, forwarded from the same local port.
+    Number(u16),
+    /// The remote port or a range of them, e.g. `80`, `8080:80`, `:80`, `5432:postgres`
+    /// or `18000-18010:8000-8010`.
+    Text(String),
+    Object {
+        /// The local port; defaults to the remote port.
+        local: Option<u16>,
+        /// The remote port.
+        remote: u16,
+    },
+}
 
-                Ok(Port {
-                    local: None,
-                    remote: remote as _,
-                })
-            }
+/// Deserializes a single port from a number, a string or an object.
+struct PortVisitor;
 
-            fn visit_u16<E>(self, remote: u16) -> Result<Self::Value, E>
-            where
-                E: Error,
-            {
-                if remote == 0 {
-                    return Err(E::custom("Invalid port number: value must be positive"));
-                }
+impl<'de> serde::de::Visitor<'de> for PortVisitor {
+    type Value = Port;
 
-                Ok(Port {
-                    local: None,
-                    remote,
-                })
-            }
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a string or an object")
+    }
 
-            fn visit_u64<E>(self, remote: u64) -> Result<Self::Value, E>
-            where
-                E: Error,
-            {
-                if remote == 0 {
-                    return Err(E::custom("Invalid port number: value must be positive"));
-                }
+    fn visit_i16<E>(self, remote: i16) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        if remote <= 0 {
+            return Err(E::custom("Invalid port number: value must be positive"));
+        }
 
-                if remote > u16::MAX as _ {
-                    return Err(E::custom(
-                        "Invalid port number: value must be smaller than or equal to 65535",
-                    ));
-                }
+        Ok(Port::new(None, remote as _))
+    }
 
-                Ok(Port {
-                    local: None,
-                    remote: remote as _,
-                })
-            }
+    fn visit_u16<E>(self, remote: u16) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        if remote == 0 {
+            return Err(E::custom("Invalid port number: value must be positive"));
+        }
 
-            fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
-            where
-                E: Error,
-            {
-                // Split the string by ':' and parse the numbers
-                let parts: Vec<&str> = s.split(':').collect();
-                match parts[..] {
-                    [local, remote] => {
-                        let local = match local {
-                            "" => None,
-                            value => Some(value.parse::<u16>().map_err(E::custom)?),
-                        };
-                        let remote = remote.parse::<u16>().map_err(E::custom)?;
-
-                        Ok(Port { local, remote })
+        Ok(Port::new(None, remote))
+    }
+
+    fn visit_u64<E>(self, remote: u64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        if remote == 0 {
+            return Err(E::custom("Invalid port number: value must be positive"));
+        }
+
+        if remote > u16::MAX as _ {
+            return Err(E::custom(
+                "Invalid port number: value must be smaller than or equal to 65535",
+            ));
+        }
+
+        Ok(Port::new(None, remote as _))
+    }
+
+    fn visit_i64<E>(self, remote: i64) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        // TOML only knows signed integers.
+        if remote <= 0 {
+            return Err(E::custom("Invalid port number: value must be positive"));
+        }
+
+        self.visit_u64(remote as _)
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        s.parse().map_err(E::custom)
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: serde::de::MapAccess<'de>,
+    {
+        // Deserialize the JSON object
+        let mut local = None;
+        let mut remote = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "local" => {
+                    if local.is_some() {
+                        return Err(Error::duplicate_field("local"));
                     }
-                    [remote] => {
-                        let remote = remote.parse::<u16>().map_err(E::custom)?;
-                        Ok(Port {
-                            local: None,
-                            remote,
-                        })
+                    local = Some(map.next_value()?);
+                }
+                "remote" => {
+                    if remote.is_some() {
+                        return Err(Error::duplicate_field("remote"));
                     }
-                    _ => Err(E::custom("Invalid string format")),
+                    remote = Some(map.next_value()?);
                 }
+                _ => return Err(Error::unknown_field(&key, &["local", "remote"])),
+            }
+        }
+
+        Ok(Port::new(
+            local,
+            remote.ok_or_else(|| Error::missing_field("remote"))?,
+        ))
+    }
+}
+
+/// Deserializes the ports of a target, expanding ranges like `8000-8010` or
+/// `18000-18010:8000-8010` into one port each.
+pub fn deserialize_ports<'de, D>(deserializer: D) -> Result<Vec<Port>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<PortEntry>::deserialize(deserializer)?;
+    Ok(entries.into_iter().flat_map(|entry| entry.0).collect())
+}
+
+/// An entry of the ports of a target, which is either a single port or a range of them.
+struct PortEntry(Vec<Port>);
+
+impl<'de> Deserialize<'de> for PortEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PortEntryVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PortEntryVisitor {
+            type Value = PortEntry;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a port, a range of ports or an object")
+            }
+
+            fn visit_i16<E: Error>(self, remote: i16) -> Result<Self::Value, E> {
+                PortVisitor
+                    .visit_i16(remote)
+                    .map(|port| PortEntry(vec![port]))
+            }
+
+            fn visit_u16<E: Error>(self, remote: u16) -> Result<Self::Value, E> {
+                PortVisitor
+                    .visit_u16(remote)
+                    .map(|port| PortEntry(vec![port]))
+            }
+
+            fn visit_u64<E: Error>(self, remote: u64) -> Result<Self::Value, E> {
+                PortVisitor
+                    .visit_u64(remote)
+                    .map(|port| PortEntry(vec![port]))
             }
 
-            fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+            fn visit_i64<E: Error>(self, remote: i64) -> Result<Self::Value, E> {
+                PortVisitor
+                    .visit_i64(remote)
+                    .map(|port| PortEntry(vec![port]))
+            }
+
+            fn visit_str<E: Error>(self, s: &str) -> Result<Self::Value, E> {
+                parse_range(s).map(PortEntry).map_err(E::custom)
+            }
+
+            fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
             where
                 M: serde::de::MapAccess<'de>,
             {
-                // Deserialize the JSON object
-                let mut local = None;
-                let mut remote = None;
-
-                while let Some(key) = map.next_key::<String>()? {
-                    match key.as_str() {
-                        "local" => {
-                            if local.is_some() {
-                                return Err(Error::duplicate_field("local"));
-                            }
-                            local = Some(map.next_value()?);
-                        }
-                        "remote" => {
-                            if remote.is_some() {
-                                return Err(Error::duplicate_field("remote"));
-                            }
-                            remote = Some(map.next_value()?);
-                        }
-                        _ => return Err(Error::unknown_field(&key, &["local", "remote"])),
-                    }
-                }
-
-                Ok(Port {
-                    local,
-                    remote: remote.ok_or_else(|| Error::missing_field("remote"))?,
-                })
+                PortVisitor.visit_map(map).map(|port| PortEntry(vec![port]))
             }
         }
 
-        deserializer.deserialize_any(PortVisitor)
+        deserializer.deserialize_any(PortEntryVisitor)
+    }
+}
+
+/// Parses a port or a range of ports, e.g. `8000-8010`, `:8000-8010` or `18000-18010:8000-8010`.
+fn parse_range(s: &str) -> Result<Vec<Port>, PortParseError> {
+    let remote = s.rsplit(':').next().unwrap_or(s);
+    if !remote.contains('-') || is_port_name(remote) {
+        return s.parse().map(|port| vec![port]);
+    }
+
+    let (local, remote) = match s.split_once(':') {
+        Some(("", remote)) => (None, remote),
+        Some((local, remote)) => (Some(parse_bounds(local)?), remote),
+        None => (None, s),
+    };
+
+    let remote = parse_bounds(remote)?;
+    if let Some(local) = &local {
+        if local.len() != remote.len() {
+            return Err(PortParseError::RangeMismatch);
+        }
+    }
+
+    let ports = remote.enumerate().map(|(index, remote)| {
+        Port::new(
+            local.as_ref().map(|local| local.start() + index as u16),
+            remote,
+        )
+    });
+    Ok(ports.collect())
+}
+
+/// Parses the bounds of a range of ports, e.g. `8000-8010`, or a single port.
+fn parse_bounds(s: &str) -> Result<std::ops::RangeInclusive<u16>, PortParseError> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let (start, end) = (start.parse::<u16>()?, end.parse::<u16>()?);
+    if start == 0 || start > end {
+        return Err(PortParseError::InvalidRange);
     }
+    Ok(start..=end)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_merge_ports() {
+        let parse = |ports: &[&str]| -> Vec<Port> {
+            ports.iter().map(|port| port.parse().unwrap()).collect()
+        };
+
+        let mut ports = parse(&["5012:80", "8080", "15432:postgres"]);
+        ports.merge_with(&parse(&["6000:80", "9000:8080", "9090", "5433:postgres"]));
+        assert_eq!(
+            ports,
+            parse(&["5012:80", "9000:8080", "15432:postgres", "9090"])
+        );
+
+        let mut ports = Vec::new();
+        ports.merge_with(&parse(&["5012:80"]));
+        assert_eq!(ports, parse(&["5012:80"]));
+    }
+
     #[test]
     fn test_port_from_object() {
         let input = r"
@@ -206,6 +451,61 @@ mod tests {
         assert_eq!(port.remote, 80);
     }
 
+    #[test]
+    fn test_port_from_str() {
+        let port: Port = "15432:5432".parse().unwrap();
+        assert_eq!(port.local, Some(15432));
+        assert_eq!(port.remote, 5432);
+        assert_eq!(port.to_string(), "15432:5432");
+        assert!("1:2:3".parse::<Port>().is_err());
+    }
+
+    #[test]
+    fn test_named_port_from_str() {
+        let port: Port = "15432:postgres".parse().unwrap();
+        assert_eq!(port.local, Some(15432));
+        assert_eq!(port.name.as_deref(), Some("postgres"));
+        assert!(!port.is_resolved());
+        assert_eq!(port.to_string(), "15432:postgres");
+        assert!("15432:Postgres".parse::<Port>().is_err());
+    }
+
+    #[test]
+    fn test_port_ranges() {
+        #[derive(Deserialize)]
+        struct Ports {
+            #[serde(deserialize_with = "deserialize_ports")]
+            ports: Vec<Port>,
+        }
+
+        let ports = serde_yaml::from_str::<Ports>(
+            r#"ports: ["8000-8002", "18000-18001:9000-9001", 80, { remote: 443 }]"#,
+        )
+        .unwrap()
+        .ports;
+        let ports: Vec<_> = ports.iter().map(Port::to_string).collect();
+        assert_eq!(
+            ports,
+            vec![
+                ":8000",
+                ":8001",
+                ":8002",
+                "18000:9000",
+                "18001:9001",
+                ":80",
+                ":443"
+            ]
+        );
+
+        assert_eq!(
+            parse_range("8443:https-alt").unwrap()[0].to_string(),
+            "8443:https-alt"
+        );
+        assert!(parse_range("8010-8000").is_err());
+        assert!(parse_range("18000-18002:8000-8001").is_err());
+        assert!(parse_range("18000:8000-8001").is_err());
+    }
+
     #[test]
     fn test_auto_port_from_string_4() {
         let port: Port = serde_yaml::from_str("80").unwrap();
@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Determines how a port-forward handles local ports that are already in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum PortConflict {
+    /// Stop the port-forward until it is restarted.
+    #[serde(rename = "fail")]
+    Fail,
+    /// Listen on the next free local port instead.
+    #[serde(rename = "remap")]
+    Remap,
+    /// Terminate kubectl processes left over from a k8sfwd session that did not shut down
+    /// and take over their port; fail for ports in use by other processes.
+    #[serde(rename = "reclaim")]
+    Reclaim,
+}
+
+impl Default for PortConflict {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+impl Display for PortConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortConflict::Fail => write!(f, "fail"),
+            PortConflict::Remap => write!(f, "remap"),
+            PortConflict::Reclaim => write!(f, "reclaim"),
+        }
+    }
+}
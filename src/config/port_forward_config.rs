@@ -2,25 +2,38 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
-use crate::config::{MergeWith, Port, ResourceType};
+use crate::config::port::deserialize_ports;
+use crate::config::{
+    ConfigId, GatewayConfig, HealthCheckConfig, HookCommand, IpFamily, KeepaliveConfig, MergeWith,
+    Port, PortConflict, Protocol, ResourceType, RestartMode, Seconds, TlsConfig,
+};
+use crate::hosts;
 use just_a_tag::Tag;
+use schemars::JsonSchema;
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::net::IpAddr;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PortForwardConfig {
     /// Designates the file from which this configuration was loaded.
     #[serde(skip_serializing, skip_deserializing)]
     pub source_file: Option<PathBuf>,
+    /// The ID assigned to the target when sanitizing the configuration, see [`ConfigId::assign`].
+    #[serde(skip_serializing, skip_deserializing)]
+    pub id: Option<ConfigId>,
     /// An optional name used to refer to this configuration.
     pub name: Option<String>,
-    // TODO: Add alias for filtering
+    /// Further names to select the target by, e.g. `pg` for `postgres-staging`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
     // TODO: Add explicit/implicit configurations
     /// An optional set of tags to apply to the configuration.
     #[serde(default)]
+    #[schemars(with = "HashSet<String>")]
     pub tags: HashSet<Tag>,
     /// The name of the kubeconfig context to use.
     pub context: Option<String>,
@@ -29,6 +42,10 @@ pub struct PortForwardConfig {
     /// The addresses or host names to listen on; must be an IP address or `localhost`.
     #[serde(default, deserialize_with = "deserialize_listen_addrs")]
     pub listen_addrs: Vec<String>, // TODO: Make HashSet
+    /// The loopback address assigned to the target by the `loopback_aliases` setting,
+    /// which is also its only listen address.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub loopback_alias: Option<Ipv4Addr>,
     /// The namespace to forward to, e.g. `default`.
     #[serde(default = "default_namespace")]
     pub namespace: String,
@@ -37,8 +54,87 @@ pub struct PortForwardConfig {
     pub r#type: ResourceType,
     /// The name of the resource to forward to.
     pub target: String,
-    /// The port to forward.
+    /// The port to forward; ranges like `8000-8010` are expanded into one port each.
+    /// If none are configured, all ports declared by the resource are forwarded.
+    #[serde(default, deserialize_with = "deserialize_ports")]
     pub ports: Vec<Port>, // TODO: Make HashSet
+    /// An optional gateway pod to chain the forward through.
+    pub via: Option<GatewayConfig>,
+    /// The number of seconds to wait for in-flight connections to finish when stopping
+    /// the target; defaults to the operational configuration.
+    #[serde(alias = "drain_timeout")]
+    pub drain_timeout_sec: Option<Seconds>,
+    /// The number of consecutive failed restarts after which the target is given up;
+    /// defaults to the operational configuration.
+    pub max_retries: Option<usize>,
+    /// Whether to restart the kubectl process when it exits; defaults to `always`.
+    pub restart: Option<RestartMode>,
+    /// What to do when a local port is already in use; defaults to `fail`.
+    pub on_port_conflict: Option<PortConflict>,
+    /// A command to run once kubectl listens on all ports of the target.
+    #[serde(alias = "on_start")]
+    pub on_ready: Option<HookCommand>,
+    /// A command to run once the target is stopped, if it was forwarding before.
+    pub on_stop: Option<HookCommand>,
+    /// Whether to watch the pod backing the target and restart as soon as it is terminated.
+    pub watch: Option<bool>,
+    /// Whether to wait for a pod backing the target to be ready before starting kubectl.
+    pub wait_ready: Option<bool>,
+    /// The number of seconds to wait for a ready pod before counting the attempt as failed.
+    #[serde(alias = "ready_timeout")]
+    pub ready_timeout_sec: Option<Seconds>,
+    /// Periodic checks that the local ports still accept connections; kubectl is restarted
+    /// if they keep failing.
+    pub healthcheck: Option<HealthCheckConfig>,
+    /// The keepalive connections held through the local ports; overrides the global settings.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Whether k8sfwd listens on the local ports itself and relays connections to kubectl,
+    /// such that clients don't see refused connections while kubectl is restarted.
+    pub proxy: Option<bool>,
+    /// The number of seconds the proxy holds new connections while kubectl is restarted;
+    /// defaults to 30.
+    #[serde(alias = "hold_timeout")]
+    pub hold_timeout_sec: Option<Seconds>,
+    /// Whether to forward to every ready pod of a deployment and take turns between them
+    /// for new connections; implies `proxy`.
+    pub load_balance: Option<bool>,
+    /// Whether to switch to another ready pod right away when the forwarded pod dies,
+    /// keeping the local ports; implies `proxy`, `watch` and `wait_ready` unless disabled.
+    pub failover: Option<bool>,
+    /// The host name, e.g. `api.staging.localhost`, under which the HTTP router of the
+    /// `http_router` setting passes requests to the first port of the target.
+    pub host: Option<String>,
+    /// The host names mapped to the local address of the target in the hosts file,
+    /// if the `manage_hosts` setting is enabled.
+    #[serde(default, deserialize_with = "deserialize_hostnames")]
+    pub hostnames: HashSet<String>,
+    /// Terminates TLS on the local ports with the given, or a self-signed, certificate,
+    /// relaying the plain connections through the tunnel.
+    pub tls: Option<TlsConfig>,
+    /// Whether kubectl and the hook commands inherit the environment of k8sfwd; defaults to `true`.
+    /// If `false`, only the variables needed to run kubectl (e.g. `HOME` and `KUBECONFIG`) are kept.
+    pub inherit_env: Option<bool>,
+    /// Additional environment variables for kubectl and the hook commands.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// The protocol spoken on the first port, e.g. `grpc`, which is verified to pass through
+    /// the tunnel unmodified whenever the target starts forwarding.
+    pub protocol: Option<Protocol>,
+    /// Whether to probe the local ports with a TLS handshake whenever the target starts
+    /// forwarding; defaults to the operational configuration.
+    pub detect_tls: Option<bool>,
+    /// The number of seconds kubectl waits for a response of the Kubernetes API before
+    /// giving up; defaults to the operational configuration.
+    #[serde(alias = "request_timeout")]
+    pub request_timeout_sec: Option<Seconds>,
+    /// The number of seconds without any connection through the tunnel after which kubectl
+    /// is restarted; defaults to the operational configuration.
+    #[serde(alias = "idle_timeout")]
+    pub idle_timeout_sec: Option<Seconds>,
+    /// The names or resources of the targets whose first local port needs to accept
+    /// connections before this target is started.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
 }
 
 impl PartialEq for PortForwardConfig {
@@ -48,40 +144,112 @@ impl PartialEq for PortForwardConfig {
 }
 
 impl MergeWith for PortForwardConfig {
+    /// Merges the other configuration of the same target into this one;
+    /// values set in this configuration take precedence.
     fn merge_with(&mut self, other: &Self) {
-        self.source_file = other.source_file.clone();
         self.name.merge_with(&other.name);
         self.tags.merge_with(&other.tags);
         self.context.merge_with(&other.context);
         self.cluster.merge_with(&other.cluster);
         self.merge_listen_addrs(&other.listen_addrs);
-        self.namespace = other.namespace.clone();
-        self.r#type = other.r#type;
-        self.target = other.target.clone();
         self.ports.merge_with(&other.ports);
+        self.via.merge_with(&other.via);
+        self.drain_timeout_sec.merge_with(&other.drain_timeout_sec);
+        self.max_retries.merge_with(&other.max_retries);
+        self.restart.merge_with(&other.restart);
+        self.on_port_conflict.merge_with(&other.on_port_conflict);
+        self.on_ready.merge_with(&other.on_ready);
+        self.on_stop.merge_with(&other.on_stop);
+        self.watch.merge_with(&other.watch);
+        self.wait_ready.merge_with(&other.wait_ready);
+        self.ready_timeout_sec.merge_with(&other.ready_timeout_sec);
+        self.healthcheck.merge_with(&other.healthcheck);
+        self.keepalive.merge_with(&other.keepalive);
+        self.proxy.merge_with(&other.proxy);
+        self.hold_timeout_sec.merge_with(&other.hold_timeout_sec);
+        self.load_balance.merge_with(&other.load_balance);
+        self.failover.merge_with(&other.failover);
+        self.host.merge_with(&other.host);
+        self.hostnames.merge_with(&other.hostnames);
+        self.tls.merge_with(&other.tls);
+        self.inherit_env.merge_with(&other.inherit_env);
+        self.env.merge_with(&other.env);
+        self.protocol.merge_with(&other.protocol);
+        self.detect_tls.merge_with(&other.detect_tls);
+        self.request_timeout_sec
+            .merge_with(&other.request_timeout_sec);
+        self.idle_timeout_sec.merge_with(&other.idle_timeout_sec);
+        if self.depends_on.is_empty() {
+            self.depends_on = other.depends_on.clone();
+        }
+        if self.aliases.is_empty() {
+            self.aliases = other.aliases.clone();
+        }
+    }
+}
+
+/// The resource a target forwards to: its type and name in a namespace of a cluster.
+///
+/// This identifies the target when merging the configuration files, when comparing the targets
+/// on reload, and when deriving its ID, such that all of them agree on what the same target is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetIdentity<'a> {
+    context: Option<&'a str>,
+    cluster: Option<&'a str>,
+    namespace: &'a str,
+    r#type: ResourceType,
+    target: &'a str,
+}
+
+impl TargetIdentity<'_> {
+    /// Determines whether both identify the same resource.
+    ///
+    /// A context or cluster set in only one of them is inherited by the other,
+    /// such that overrides in other files don't need to repeat it.
+    pub fn matches(&self, other: &TargetIdentity) -> bool {
+        let compatible = |a: Option<&str>, b: Option<&str>| a.is_none() || b.is_none() || a == b;
+
+        self.target == other.target
+            && self.r#type == other.r#type
+            && self.namespace == other.namespace
+            && compatible(self.context, other.context)
+            && compatible(self.cluster, other.cluster)
+    }
+}
+
+impl Display for TargetIdentity<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{context}/{cluster}/{namespace}/{kind}/{target}",
+            context = self.context.unwrap_or_default(),
+            cluster = self.cluster.unwrap_or_default(),
+            namespace = self.namespace,
+            kind = self.r#type.as_arg(),
+            target = self.target
+        )
     }
 }
 
 impl MergeWith for Vec<PortForwardConfig> {
+    /// Merges the targets of the other file into the ones of this file.
+    ///
+    /// The targets keep their order, followed by the targets only configured in the other file
+    /// in their order, such that the IDs of the targets are the same on every run.
     fn merge_with(&mut self, other: &Self) {
         if other.is_empty() {
             return;
         }
 
-        // TODO: Ensure sort order is stable.
-
-        let mut map = HashMap::<String, PortForwardConfig>::new();
-        for cfg in self.drain(0..) {
-            map.insert(cfg.target.clone(), cfg);
-        }
-
         for cfg in other {
-            map.entry(cfg.target.clone())
-                .and_modify(|current| current.merge_with(cfg))
-                .or_insert(cfg.clone());
+            match self
+                .iter_mut()
+                .find(|current| current.is_same_identity(cfg))
+            {
+                Some(current) => current.merge_with(cfg),
+                None => self.push(cfg.clone()),
+            }
         }
-
-        *self = Vec::from_iter(map.into_values());
     }
 }
 
@@ -90,14 +258,264 @@ impl PortForwardConfig {
         self.source_file = Some(file);
     }
 
+    /// Determines whether the other configuration describes exactly the same forward,
+    /// e.g. to determine whether a running forward needs to be restarted after a reload.
+    ///
+    /// Unlike [`PartialEq`], which only considers the target, this compares all fields
+    /// except for the source file.
+    pub fn is_equivalent(&self, other: &Self) -> bool {
+        self.changed_fields(other).is_empty()
+    }
+
+    /// Gets the names of the fields that differ from the other configuration,
+    /// as spelled in the configuration file; the source file is not considered.
+    pub fn changed_fields(&self, other: &Self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        macro_rules! changed {
+            ($field:ident, $name:literal) => {
+                if self.$field != other.$field {
+                    changed.push($name);
+                }
+            };
+        }
+
+        changed!(name, "name");
+        changed!(aliases, "aliases");
+        changed!(tags, "tags");
+        changed!(context, "context");
+        changed!(cluster, "cluster");
+        changed!(listen_addrs, "listen_addrs");
+        changed!(namespace, "namespace");
+        changed!(r#type, "type");
+        changed!(target, "target");
+        changed!(ports, "ports");
+        changed!(via, "via");
+        changed!(drain_timeout_sec, "drain_timeout_sec");
+        changed!(max_retries, "max_retries");
+        changed!(restart, "restart");
+        changed!(on_port_conflict, "on_port_conflict");
+        changed!(on_ready, "on_ready");
+        changed!(on_stop, "on_stop");
+        changed!(watch, "watch");
+        changed!(wait_ready, "wait_ready");
+        changed!(ready_timeout_sec, "ready_timeout_sec");
+        changed!(healthcheck, "healthcheck");
+        changed!(keepalive, "keepalive");
+        changed!(tls, "tls");
+        changed!(proxy, "proxy");
+        changed!(hold_timeout_sec, "hold_timeout_sec");
+        changed!(load_balance, "load_balance");
+        changed!(failover, "failover");
+        changed!(host, "host");
+        changed!(hostnames, "hostnames");
+        changed!(inherit_env, "inherit_env");
+        changed!(env, "env");
+        changed!(protocol, "protocol");
+        changed!(detect_tls, "detect_tls");
+        changed!(request_timeout_sec, "request_timeout_sec");
+        changed!(idle_timeout_sec, "idle_timeout_sec");
+        changed!(depends_on, "depends_on");
+        changed
+    }
+
+    /// Gets the resource the target forwards to, which identifies it e.g. when merging files.
+    pub fn identity(&self) -> TargetIdentity<'_> {
+        TargetIdentity {
+            context: self.context.as_deref(),
+            cluster: self.cluster.as_deref(),
+            namespace: &self.namespace,
+            r#type: self.r#type,
+            target: &self.target,
+        }
+    }
+
+    /// Determines whether the other configuration refers to the same target,
+    /// see [`TargetIdentity::matches`].
+    pub fn is_same_identity(&self, other: &Self) -> bool {
+        self.identity().matches(&other.identity())
+    }
+
+    /// Determines whether connections are balanced across the pods of the target,
+    /// which requires a deployment that isn't forwarded through a gateway.
+    pub fn is_load_balanced(&self) -> bool {
+        self.load_balance == Some(true)
+            && self.r#type == ResourceType::Deployment
+            && self.via.is_none()
+    }
+
+    /// Converts the listen addresses into the form `kubectl` expects, for the given IP family.
+    ///
+    /// Brackets around IPv6 addresses are removed and, unless listening on both families,
+    /// `localhost` is replaced with the family's loopback address. This also applies to
+    /// targets without listen addresses, for which `kubectl` would listen on `localhost`.
+    ///
+    /// Addresses written differently are only kept once. Addresses that are already covered
+    /// by another one, e.g. `127.0.0.1` by `localhost` or `0.0.0.0`, are removed as `kubectl`
+    /// would fail to listen on them twice; a warning is returned for each of them.
+    pub fn normalize_listen_addrs(
+        &mut self,
+        family: IpFamily,
+    ) -> Result<Vec<ListenAddrWarning>, ListenAddrError> {
+        if self.listen_addrs.is_empty() && family.loopback().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let configured = if self.listen_addrs.is_empty() {
+            vec!["localhost".to_string()]
+        } else {
+            std::mem::take(&mut self.listen_addrs)
+        };
+
+        let mut addresses = Vec::with_capacity(configured.len());
+        for address in configured {
+            let normalized = if address == "localhost" {
+                family.loopback().unwrap_or("localhost").to_string()
+            } else {
+                let ip = parse_listen_addr(&address)
+                    .ok_or_else(|| ListenAddrError::InvalidAddress(address.clone()))?;
+                if !family.accepts(&ip) {
+                    return Err(ListenAddrError::FamilyMismatch {
+                        target: self.target.clone(),
+                        address,
+                        family,
+                    });
+                }
+                ip.to_string()
+            };
+
+            if !addresses.contains(&normalized) {
+                addresses.push(normalized);
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let covering = addresses.clone();
+        addresses.retain(
+            |address| match covering.iter().find(|by| covers(by, address)) {
+                Some(by) => {
+                    warnings.push(ListenAddrWarning::Covered {
+                        target: self.target.clone(),
+                        address: address.clone(),
+                        by: by.clone(),
+                    });
+                    false
+                }
+                None => true,
+            },
+        );
+
+        self.listen_addrs = addresses;
+        Ok(warnings)
+    }
+
+    /// Tags the target with its namespace and cluster, e.g. `ns-payments` and `cluster-staging`,
+    /// such that targets can be selected by these without tagging them explicitly.
+    pub fn add_auto_tags(&mut self) {
+        let cluster = self.cluster.as_ref().or(self.context.as_ref());
+        let derived = [("ns", Some(&self.namespace)), ("cluster", cluster)];
+        for (prefix, value) in derived {
+            if let Some(tag) = value.and_then(|value| auto_tag(prefix, value)) {
+                self.tags.insert(tag);
+            }
+        }
+    }
+
+    /// Adds the other listen addresses, skipping those that are only written differently,
+    /// e.g. `[::1]` and `::1`.
     fn merge_listen_addrs(&mut self, other: &[String]) {
-        let set: HashSet<String> = HashSet::from_iter(self.listen_addrs.drain(0..));
-        let other_set = HashSet::from_iter(other.iter().cloned());
-        self.listen_addrs = Vec::from_iter(&mut set.union(&other_set).cloned());
+        for address in other {
+            let canonical = canonical_listen_addr(address);
+            if !self
+                .listen_addrs
+                .iter()
+                .any(|existing| canonical_listen_addr(existing) == canonical)
+            {
+                self.listen_addrs.push(address.clone());
+            }
+        }
     }
 }
 
-fn default_namespace() -> String {
+/// Parses an IP address, with or without brackets around IPv6 addresses.
+fn parse_listen_addr(address: &str) -> Option<IpAddr> {
+    address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Writes the listen address the way `kubectl` expects it, e.g. `::1` for `[0:0:0:0:0:0:0:1]`.
+fn canonical_listen_addr(address: &str) -> String {
+    match parse_listen_addr(address) {
+        Some(ip) => ip.to_string(),
+        None => address.to_string(),
+    }
+}
+
+/// Determines whether `kubectl` listening on `by` already listens on `address`, in which case
+/// listening on both fails.
+///
+/// `localhost` covers both loopback addresses and the unspecified address of a family,
+/// e.g. `0.0.0.0`, covers all addresses of the family. `localhost` itself is kept, since
+/// `kubectl` is content with listening on either of its addresses.
+fn covers(by: &str, address: &str) -> bool {
+    let Some(ip) = parse_listen_addr(address) else {
+        return false;
+    };
+
+    match parse_listen_addr(by) {
+        None => by == "localhost" && ip.is_loopback(),
+        Some(by) => by.is_unspecified() && !ip.is_unspecified() && by.is_ipv4() == ip.is_ipv4(),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListenAddrWarning {
+    #[error(
+        "The listen address {address} of target {target} is already covered by {by} and is ignored"
+    )]
+    Covered {
+        target: String,
+        address: String,
+        by: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListenAddrError {
+    #[error("Invalid listen address: {0}")]
+    InvalidAddress(String),
+    #[error("The listen address {address} of target {target} is not an {family} address")]
+    FamilyMismatch {
+        target: String,
+        address: String,
+        family: IpFamily,
+    },
+}
+
+/// Builds a tag from a prefix and a name, replacing characters other than letters and digits
+/// with hyphens, e.g. `cluster-arn-aws-eks-eu-west-1` for an EKS cluster ARN.
+fn auto_tag(prefix: &str, value: &str) -> Option<Tag> {
+    let mut tag = format!("{prefix}-");
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() {
+            tag.push(c.to_ascii_lowercase());
+        } else if !tag.ends_with('-') {
+            tag.push('-');
+        }
+    }
+
+    // Tags are limited to the length of a DNS label.
+    tag.truncate(63);
+    let tag = tag.trim_end_matches('-');
+    if tag.len() <= prefix.len() + 1 {
+        return None;
+    }
+    tag.parse().ok()
+}
+
+pub(crate) fn default_namespace() -> String {
     "default".to_string()
 }
 
@@ -113,6 +531,23 @@ where
     Ok(v.into_iter().map(|Wrapper(a)| a).collect())
 }
 
+/// Parses a set of host names, which are written to the hosts file verbatim.
+fn deserialize_hostnames<'de, D>(deserializer: D) -> Result<HashSet<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hostnames = HashSet::<String>::deserialize(deserializer)?;
+    match hostnames
+        .iter()
+        .find(|name| !hosts::is_valid_hostname(name))
+    {
+        Some(name) => Err(Error::custom(format!(
+            "An invalid host name was specified: {name:?}"
+        ))),
+        None => Ok(hostnames),
+    }
+}
+
 /// Parses an IPv4 or IPv6 address or the literal `localhost`.
 fn deserialize_listen_addr<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
@@ -168,6 +603,147 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_auto_tags() {
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            namespace: payments
+            cluster: "arn:aws:eks:eu-west-1:123:cluster/Staging"
+            tags: [foo]
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+        config.add_auto_tags();
+
+        assert_eq!(
+            config.tags,
+            HashSet::from([
+                Tag::new("foo"),
+                Tag::new("ns-payments"),
+                Tag::new("cluster-arn-aws-eks-eu-west-1-123-cluster-staging")
+            ])
+        );
+        assert_eq!(auto_tag("ns", "--"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            type: pod
+            ports:
+              - "1234:5678"
+              - 8080
+        "#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_string(&config).unwrap();
+        let parsed = serde_json::from_str::<PortForwardConfig>(&json).unwrap();
+        assert!(parsed.is_equivalent(&config));
+    }
+
+    #[test]
+    fn test_via() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            namespace: restricted
+            via:
+              pod: gateway
+              namespace: tools
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let via = config.via.expect("gateway is configured");
+        assert_eq!(via.pod, "gateway");
+        assert_eq!(via.namespace, "tools");
+    }
+
+    #[test]
+    fn test_is_equivalent() {
+        let config = r#"
+            target: foo
+            ports:
+              - "1234:5678"
+        "#;
+        let mut a = serde_yaml::from_str::<PortForwardConfig>(config).unwrap();
+        let mut b = serde_yaml::from_str::<PortForwardConfig>(config).unwrap();
+        b.set_source_file(PathBuf::from(".k8sfwd"));
+        assert!(a.is_equivalent(&b));
+
+        b.namespace = "bar".into();
+        assert!(!a.is_equivalent(&b));
+        assert_eq!(a.changed_fields(&b), vec!["namespace"]);
+        assert!(!a.is_same_identity(&b));
+
+        // Targets are identified by their resource rather than their name.
+        a.namespace = "bar".into();
+        b.name = Some("api".into());
+        assert_eq!(a.changed_fields(&b), vec!["name"]);
+        assert!(a.is_same_identity(&b));
+    }
+
+    #[test]
+    fn test_merge_by_identity() {
+        let mut targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: foo
+              context: staging
+              ports: ["5012:80"]
+            - target: foo
+              context: production
+              ports: ["6012:80"]
+        "#,
+        )
+        .unwrap();
+        let overrides: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: foo
+              context: staging
+              ports: ["7012:80"]
+            - target: foo
+              context: staging
+              namespace: bar
+              ports: ["8012:80"]
+        "#,
+        )
+        .unwrap();
+
+        targets.merge_with(&overrides);
+        assert_eq!(targets.len(), 3);
+        let staging = targets
+            .iter()
+            .find(|target| {
+                target.context.as_deref() == Some("staging") && target.namespace == "default"
+            })
+            .unwrap();
+        assert_eq!(staging.ports[0].local, Some(5012));
+    }
+
+    #[test]
+    fn test_start_and_stop_hooks() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: postgres
+            on_start: ./migrate.sh
+            on_stop: ./flush-dns.sh
+            ports:
+              - 5432
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.on_ready.unwrap().command, "./migrate.sh");
+        assert_eq!(config.on_stop.unwrap().command, "./flush-dns.sh");
+    }
+
     #[test]
     fn test_listen_ip_and_localhost() {
         serde_yaml::from_str::<PortForwardConfig>(
@@ -198,6 +774,28 @@ mod tests {
         .expect_err("literal host names must be exactly `localhost`");
     }
 
+    #[test]
+    fn test_invalid_hostnames() {
+        serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            hostnames: [api.staging, "api\n10.0.0.1 bank.example.com"]
+            ports: [80]
+        "#,
+        )
+        .expect_err("host names must not inject lines into the hosts file");
+
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            hostnames: [api.staging]
+            ports: [80]
+        "#,
+        )
+        .unwrap();
+        assert!(config.hostnames.contains("api.staging"));
+    }
+
     #[test]
     fn test_listen_invalid_ipv4() {
         serde_yaml::from_str::<PortForwardConfig>(
@@ -225,4 +823,112 @@ mod tests {
         )
         .expect_err("the IPv6 address is invalid");
     }
+
+    #[test]
+    fn test_normalize_listen_addrs() {
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs:
+              - "127.0.0.1"
+              - "[::1]"
+              - "localhost"
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let mut ipv4 = config.clone();
+        ipv4.normalize_listen_addrs(IpFamily::Ipv4)
+            .expect_err("::1 is not an IPv4 address");
+
+        // kubectl can't listen on the loopback addresses next to `localhost`.
+        let warnings = config.normalize_listen_addrs(IpFamily::Dual).unwrap();
+        assert_eq!(config.listen_addrs, vec!["localhost"]);
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_covered_listen_addrs() {
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs:
+              - "10.0.0.1"
+              - "[0:0:0:0:0:0:0:1]"
+              - "0.0.0.0"
+              - "::1"
+              - "::"
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let warnings = config.normalize_listen_addrs(IpFamily::Dual).unwrap();
+        assert_eq!(config.listen_addrs, vec!["0.0.0.0", "::"]);
+        assert_eq!(
+            warnings[0].to_string(),
+            "The listen address 10.0.0.1 of target foo is already covered by 0.0.0.0 and is ignored"
+        );
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_listen_addrs() {
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs: ["localhost", "::1"]
+            ports: ["1234:5678"]
+        "#,
+        )
+        .unwrap();
+        config.merge_listen_addrs(&["[::1]".into(), "127.0.0.1".into()]);
+        assert_eq!(config.listen_addrs, vec!["localhost", "::1", "127.0.0.1"]);
+    }
+
+    #[test]
+    fn test_normalize_default_listen_addrs() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let mut dual = config.clone();
+        dual.normalize_listen_addrs(IpFamily::Dual).unwrap();
+        assert!(dual.listen_addrs.is_empty());
+
+        let mut ipv4 = config.clone();
+        ipv4.normalize_listen_addrs(IpFamily::Ipv4).unwrap();
+        assert_eq!(ipv4.listen_addrs, vec!["127.0.0.1"]);
+
+        let mut ipv6 = config;
+        ipv6.normalize_listen_addrs(IpFamily::Ipv6).unwrap();
+        assert_eq!(ipv6.listen_addrs, vec!["::1"]);
+    }
+
+    #[test]
+    fn test_normalize_ipv6_listen_addrs() {
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs:
+              - "[::1]"
+              - "::1"
+              - "localhost"
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        config.normalize_listen_addrs(IpFamily::Ipv6).unwrap();
+        assert_eq!(config.listen_addrs, vec!["::1"]);
+    }
 }
@@ -2,38 +2,165 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
+use crate::config::file_defaults;
+use crate::config::interpolation::{self, InterpolationError};
+use crate::config::secrets::{SecretError, SecretResolvers};
 use crate::config::{
-    ConfigMeta, MergeWith, OperationalConfig, PortForwardConfig, HIGHEST_SUPPORTED_VERSION,
-    LOWEST_SUPPORTED_VERSION,
+    ConfigFormat, ConfigMeta, MergeStrategy, MergeWith, OperationalConfig, PortForwardConfig,
+    ProfileConfig, HIGHEST_SUPPORTED_VERSION, LOWEST_SUPPORTED_VERSION,
 };
+use crate::target_filter::{MatchesAnyFilter, TargetFilter};
+use just_a_tag::Tag;
+use schemars::JsonSchema;
 use semver::Version;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct PortForwardConfigs {
+    /// The version of the configuration format, e.g. `0.3.0`.
+    #[schemars(with = "String")]
     pub version: Version,
+    /// Other files to load, relative to this one; this file takes precedence over them.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    /// The tags added to each target of this file.
+    #[serde(default)]
+    #[schemars(with = "HashSet<String>")]
+    pub tags: HashSet<Tag>,
+    /// Whether the targets of the files of lower precedence are added to the ones of this file.
+    #[serde(default)]
+    pub merge: MergeStrategy,
     #[serde(default)]
     pub config: Option<OperationalConfig>,
     #[serde(default)]
     pub targets: Vec<PortForwardConfig>,
+    /// Named working setups, selected with `--profile`.
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// Named sets of targets, selected with `--group`; the members are matched like filters.
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<TargetFilter>>,
 }
 
 impl PortForwardConfigs {
+    /// Gets the settings that run commands on this machine, e.g. `targets.api.on_ready`.
+    fn commands(&self) -> Vec<String> {
+        let operational = self
+            .config
+            .iter()
+            .map(|config| ("config".to_string(), config));
+        let profiles = self.profiles.iter().filter_map(|(name, profile)| {
+            let config = profile.config.as_ref()?;
+            Some((format!("profiles.{name}.config"), config))
+        });
+
+        let mut commands = Vec::new();
+        for (prefix, config) in operational.chain(profiles) {
+            let hooks = [
+                ("on_ready", &config.hooks.on_ready),
+                ("on_restart", &config.hooks.on_restart),
+                ("on_permanent_failure", &config.hooks.on_permanent_failure),
+            ];
+            for (hook, _) in hooks.iter().filter(|(_, command)| command.is_some()) {
+                commands.push(format!("{prefix}.hooks.{hook}"));
+            }
+
+            for (cluster, cluster_config) in &config.clusters {
+                if cluster_config.auth_check.is_some() {
+                    commands.push(format!("{prefix}.clusters.{cluster}.auth_check"));
+                }
+            }
+        }
+
+        for target in &self.targets {
+            let name = target.name.as_ref().unwrap_or(&target.target);
+            let hooks = [("on_ready", &target.on_ready), ("on_stop", &target.on_stop)];
+            for (hook, _) in hooks.iter().filter(|(_, command)| command.is_some()) {
+                commands.push(format!("targets.{name}.{hook}"));
+            }
+        }
+
+        commands
+    }
+
     pub fn set_source_file(&mut self, file: PathBuf) {
         for target in &mut self.targets {
             target.set_source_file(file.clone());
         }
     }
+
+    /// Merges the configurations of several files, ordered by descending precedence.
+    ///
+    /// Once a file with `merge: replace` is merged, the targets of the remaining files are
+    /// ignored. Returns `None` if there are no configurations.
+    pub fn merge_files(configs: impl IntoIterator<Item = Self>) -> Option<Self> {
+        let mut configs = configs.into_iter();
+        let mut merged = configs.next()?;
+        let mut replaced = merged.merge == MergeStrategy::Replace;
+        for mut config in configs {
+            if replaced {
+                config.targets.clear();
+            }
+            replaced |= config.merge == MergeStrategy::Replace;
+            merged.merge_with(&config);
+        }
+
+        Some(merged)
+    }
+
+    /// Keeps only the targets selected by the profile and applies its operational settings.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), SelectionError> {
+        let Some(profile) = self.profiles.get(name) else {
+            let available = sorted_keys(&self.profiles);
+            return Err(SelectionError::UnknownProfile(name.to_string(), available));
+        };
+
+        if let Some(overrides) = &profile.config {
+            let mut config = overrides.clone();
+            config.merge_with(&self.config);
+            self.config = Some(config);
+        }
+        self.targets.retain(|target| profile.selects(target));
+        Ok(())
+    }
+
+    /// Keeps only the targets that are members of any of the groups.
+    pub fn select_groups(&mut self, names: &[String]) -> Result<(), SelectionError> {
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        let mut members = Vec::new();
+        for name in names {
+            let Some(group) = self.groups.get(name) else {
+                let available = sorted_keys(&self.groups);
+                return Err(SelectionError::UnknownGroup(name.clone(), available));
+            };
+            members.extend(group.iter().filter(|member| !member.is_empty()).cloned());
+        }
+
+        // An empty list of filters would match all targets.
+        self.targets
+            .retain(|target| !members.is_empty() && members.matches(target));
+        Ok(())
+    }
+}
+
+/// Gets the names of the map in alphabetical order.
+fn sorted_keys<T>(map: &HashMap<String, T>) -> Vec<String> {
+    let mut keys: Vec<_> = map.keys().cloned().collect();
+    keys.sort();
+    keys
 }
 
 impl MergeWith for PortForwardConfigs {
     fn merge_with(&mut self, other: &Self) {
-        self.version = other.version.clone();
-
         match &mut self.config {
             None => self.config = other.config.clone(),
             Some(config) => config.merge_with(&other.config),
@@ -44,38 +171,176 @@ impl MergeWith for PortForwardConfigs {
         } else {
             self.targets.merge_with(&other.targets);
         }
+
+        self.profiles.merge_with(&other.profiles);
+        for (name, members) in &other.groups {
+            self.groups
+                .entry(name.clone())
+                .or_insert_with(|| members.clone());
+        }
     }
 }
 
-pub trait FromYaml {
-    fn into_configuration(self, source: &ConfigMeta) -> Result<PortForwardConfigs, FromYamlError>;
+/// Reads a configuration file in any of the supported formats, i.e. YAML, JSON or TOML.
+pub trait FromConfigFile {
+    fn into_configuration(
+        self,
+        source: &ConfigMeta,
+    ) -> Result<PortForwardConfigs, FromConfigFileError>;
 }
 
-impl FromYaml for File {
+impl FromConfigFile for File {
     fn into_configuration(
-        mut self,
+        self,
         source: &ConfigMeta,
-    ) -> Result<PortForwardConfigs, FromYamlError> {
-        let mut contents = String::new();
-        self.read_to_string(&mut contents)?;
-        let mut config: PortForwardConfigs = serde_yaml::from_str(&contents)?;
+    ) -> Result<PortForwardConfigs, FromConfigFileError> {
+        load(self, source, &mut Vec::new())
+    }
+}
 
-        if source.load_config_only {
-            config.targets.clear();
-        } else {
-            config.set_source_file(source.path.clone());
+/// Loads a configuration file along with the files it includes.
+///
+/// The including files are tracked to detect cycles.
+fn load(
+    mut file: File,
+    source: &ConfigMeta,
+    including: &mut Vec<PathBuf>,
+) -> Result<PortForwardConfigs, FromConfigFileError> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    // Expand variables such as `${USER}`, then resolve value references such as
+    // `!env VAR` relative to the file, then fill in the file-level defaults of the targets.
+    let format = ConfigFormat::detect(&source.path, &contents);
+    let base_dir = source.path.parent().unwrap_or(Path::new("."));
+    let mut value: serde_yaml::Value = parse(format, &contents)?;
+    let interpolated = interpolation::interpolate_targets(&mut value)?;
+    let resolvers = match source.remote {
+        true => SecretResolvers::remote(),
+        false => SecretResolvers::new(base_dir),
+    };
+    let resolved = resolvers.resolve(&mut value)?;
+    let defaulted = file_defaults::apply_file_defaults(&mut value);
+    let mut config: PortForwardConfigs = if interpolated || resolved || defaulted {
+        serde_yaml::from_value(value)?
+    } else {
+        // Errors in the original text carry their location.
+        parse(format, &contents)?
+    };
+
+    // Hooks and checks run as the user; the checksum of a remote file only proves that it
+    // is the one published, not that its commands can be trusted.
+    if source.remote {
+        let commands = config.commands();
+        if !commands.is_empty() {
+            return Err(FromConfigFileError::RemoteCommands(
+                source.path.clone(),
+                commands,
+            ));
         }
+    }
+
+    let operational = config.config.iter_mut();
+    let profiles = config
+        .profiles
+        .values_mut()
+        .filter_map(|p| p.config.as_mut());
+    for operational in operational.chain(profiles) {
+        operational.resolve_kubeconfigs(base_dir);
+    }
+
+    if source.load_config_only {
+        config.targets.clear();
+    } else {
+        config.set_source_file(source.path.clone());
+    }
+
+    for target in &mut config.targets {
+        target.tags.extend(config.tags.iter().cloned());
+    }
+
+    let path = source.path.canonicalize()?;
+    if including.contains(&path) {
+        return Err(FromConfigFileError::IncludeCycle(source.path.clone()));
+    }
 
-        Ok(config)
+    including.push(path);
+    for include in std::mem::take(&mut config.include) {
+        let meta = ConfigMeta {
+            path: base_dir.join(include),
+            auto_detected: source.auto_detected,
+            load_config_only: source.load_config_only,
+            remote: source.remote,
+        };
+        let included = File::open(&meta.path)
+            .map_err(FromConfigFileError::from)
+            .and_then(|file| load(file, &meta, including))
+            .and_then(|included| match included.is_supported_version() {
+                true => Ok(included),
+                false => Err(FromConfigFileError::UnsupportedVersion(included.version)),
+            })
+            .map_err(|e| match e {
+                // Report the innermost file.
+                FromConfigFileError::IncludeFailed(..) | FromConfigFileError::IncludeCycle(_) => e,
+                e => FromConfigFileError::IncludeFailed(meta.path.clone(), Box::new(e)),
+            })?;
+        config.merge_with(&included);
     }
+    including.pop();
+
+    Ok(config)
+}
+
+/// Deserializes the contents of a configuration file in the given format.
+fn parse<T: DeserializeOwned>(
+    format: ConfigFormat,
+    contents: &str,
+) -> Result<T, FromConfigFileError> {
+    Ok(match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+        ConfigFormat::Json => serde_json::from_str(contents)?,
+        ConfigFormat::Toml => toml::from_str(contents)?,
+    })
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum FromYamlError {
+pub enum FromConfigFileError {
     #[error(transparent)]
     InvalidConfiguration(#[from] serde_yaml::Error),
     #[error(transparent)]
+    InvalidJsonConfiguration(#[from] serde_json::Error),
+    #[error(transparent)]
+    InvalidTomlConfiguration(#[from] toml::de::Error),
+    #[error(transparent)]
     FileReadFailed(#[from] io::Error),
+    #[error(transparent)]
+    SecretResolutionFailed(#[from] SecretError),
+    #[error(transparent)]
+    InterpolationFailed(#[from] InterpolationError),
+    #[error("Configuration version {0} is not supported by this application")]
+    UnsupportedVersion(Version),
+    #[error("Failed to include {path}: {1}", path = .0.display())]
+    IncludeFailed(PathBuf, Box<FromConfigFileError>),
+    #[error("{path} includes itself", path = .0.display())]
+    IncludeCycle(PathBuf),
+    #[error("{path} was fetched from a remote source and may not run commands on this machine, but sets {settings}", path = .0.display(), settings = .1.join(", "))]
+    RemoteCommands(PathBuf, Vec<String>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SelectionError {
+    #[error("The profile {0} is not configured{}", available("profiles", .1))]
+    UnknownProfile(String, Vec<String>),
+    #[error("The group {0} is not configured{}", available("groups", .1))]
+    UnknownGroup(String, Vec<String>),
+}
+
+/// Lists the available names for an error message, if there are any.
+fn available(kind: &str, names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        names => format!(" - available {kind}: {names}", names = names.join(", ")),
+    }
 }
 
 impl IntoIterator for PortForwardConfigs {
@@ -130,4 +395,318 @@ mod tests {
         let config: PortForwardConfigs = serde_yaml::from_str(config).unwrap();
         assert_eq!(config.targets.len(), 2);
     }
+
+    #[test]
+    fn test_merge_precedence() {
+        let mut nearest: PortForwardConfigs = serde_yaml::from_str(
+            r#"
+            version: 0.3.0
+            config:
+              max_retries: 3
+            targets:
+              - target: foo
+                namespace: local
+                ports:
+                  - "5012:80"
+        "#,
+        )
+        .unwrap();
+        let inherited: PortForwardConfigs = serde_yaml::from_str(
+            r#"
+            version: 0.2.0
+            config:
+              max_retries: 10
+              drain_timeout_sec: 5
+            targets:
+              - target: foo
+                namespace: local
+                context: staging
+                ports:
+                  - "5012:80"
+        "#,
+        )
+        .unwrap();
+
+        nearest.merge_with(&inherited);
+        let operational = nearest.config.unwrap();
+        assert_eq!(operational.max_retries, Some(3));
+        assert!(operational.drain_timeout_sec.is_some());
+        assert_eq!(nearest.version, Version::new(0, 3, 0));
+        assert_eq!(nearest.targets[0].namespace, "local");
+        assert_eq!(nearest.targets[0].context.as_deref(), Some("staging"));
+    }
+
+    #[test]
+    fn test_merge_order_is_stable() {
+        let parse = |targets: &str| -> PortForwardConfigs {
+            serde_yaml::from_str(&format!("version: 0.3.0\ntargets: {targets}")).unwrap()
+        };
+        let mut merged = parse("[{ target: db, ports: [15432] }]");
+        merged.merge_with(&parse(
+            "[{ target: web, ports: [80] }, { target: db, ports: [5432] }]",
+        ));
+        merged.merge_with(&parse(
+            "[{ target: api, ports: [80] }, { target: cache, ports: [6379] }]",
+        ));
+
+        let targets: Vec<_> = merged.targets.iter().map(|t| t.target.as_str()).collect();
+        assert_eq!(targets, vec!["db", "web", "api", "cache"]);
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let parse = |merge: &str, target: &str| -> PortForwardConfigs {
+            serde_yaml::from_str(&format!(
+                "version: 0.3.0\nmerge: {merge}\nconfig: {{ max_retries: 3 }}\ntargets: [{{ target: {target}, ports: [80] }}]"
+            ))
+            .unwrap()
+        };
+
+        let merged = PortForwardConfigs::merge_files([
+            parse("extend", "local"),
+            parse("replace", "project"),
+            parse("extend", "home"),
+        ])
+        .unwrap();
+        let targets: Vec<_> = merged.targets.iter().map(|t| t.target.as_str()).collect();
+        assert_eq!(targets, vec!["local", "project"]);
+        assert_eq!(merged.config.unwrap().max_retries, Some(3));
+
+        let merged =
+            PortForwardConfigs::merge_files([parse("extend", "project"), parse("extend", "home")])
+                .unwrap();
+        assert_eq!(merged.targets.len(), 2);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("k8sfwd-test-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("services")).unwrap();
+        std::fs::write(
+            dir.join(".k8sfwd"),
+            r#"
+            version: 0.3.0
+            include: [services/api.yaml, services/db.toml]
+            tags: [team]
+            targets:
+              - target: api
+                namespace: local
+                ports: ["8080:80"]
+        "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("services/api.yaml"),
+            r#"
+            version: 0.3.0
+            targets:
+              - target: api
+                namespace: local
+                context: staging
+                ports: ["8080:80"]
+        "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("services/db.toml"),
+            "version = \"0.3.0\"\ninclude = [\"../.k8sfwd\"]\n",
+        )
+        .unwrap();
+
+        let source = |path: PathBuf| ConfigMeta {
+            path,
+            auto_detected: true,
+            load_config_only: false,
+            remote: false,
+        };
+        let open = |path: &PathBuf| File::open(path).unwrap();
+
+        // The included file includes the including one.
+        let path = dir.join(".k8sfwd");
+        let error = open(&path).into_configuration(&source(path.clone()));
+        assert!(matches!(error, Err(FromConfigFileError::IncludeCycle(_))));
+
+        std::fs::write(
+            dir.join("services/db.toml"),
+            "version = \"0.3.0\"\n[[targets]]\ntarget = \"db\"\nports = [5432]\n",
+        )
+        .unwrap();
+        let config = open(&path).into_configuration(&source(path.clone()));
+        std::fs::remove_dir_all(&dir).ok();
+
+        let config = config.unwrap();
+        assert!(config
+            .targets
+            .iter()
+            .all(|target| target.tags.contains(&Tag::new("team")) == (target.target == "api")));
+
+        let mut targets = config.targets;
+        targets.sort_by(|a, b| a.target.cmp(&b.target));
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].namespace, "local");
+        assert_eq!(targets[0].context.as_deref(), Some("staging"));
+        assert_eq!(targets[1].target, "db");
+        assert_eq!(
+            targets[1].source_file.as_deref(),
+            Some(dir.join("services/db.toml").as_path())
+        );
+    }
+
+    #[test]
+    fn test_remote_references() {
+        let dir = std::env::temp_dir().join(format!("k8sfwd-test-remote-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("team.yaml");
+        std::fs::write(
+            &path,
+            r#"
+            version: 0.3.0
+            targets:
+              - target: !cmd echo api
+                ports: [80]
+        "#,
+        )
+        .unwrap();
+
+        let load = |remote| {
+            let source = ConfigMeta {
+                path: path.clone(),
+                auto_detected: false,
+                load_config_only: false,
+                remote,
+            };
+            File::open(&path).unwrap().into_configuration(&source)
+        };
+        let remote = load(true);
+        let local = load(false);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(matches!(
+            remote,
+            Err(FromConfigFileError::SecretResolutionFailed(
+                SecretError::NotAllowed(_)
+            ))
+        ));
+        assert_eq!(local.unwrap().targets[0].target, "api");
+    }
+
+    #[test]
+    fn test_remote_commands() {
+        let dir = std::env::temp_dir().join(format!("k8sfwd-test-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("team.yaml");
+        std::fs::write(
+            &path,
+            r#"
+            version: 0.3.0
+            config:
+              clusters:
+                production:
+                  auth_check: "true"
+            targets:
+              - name: api
+                target: api
+                ports: [80]
+                on_ready: ./warmup.sh
+              - target: db
+                ports: [5432]
+        "#,
+        )
+        .unwrap();
+
+        let load = |remote| {
+            let source = ConfigMeta {
+                path: path.clone(),
+                auto_detected: false,
+                load_config_only: false,
+                remote,
+            };
+            File::open(&path).unwrap().into_configuration(&source)
+        };
+        let remote = load(true);
+        let local = load(false);
+        std::fs::remove_dir_all(&dir).ok();
+
+        match remote {
+            Err(FromConfigFileError::RemoteCommands(_, settings)) => assert_eq!(
+                settings,
+                [
+                    "config.clusters.production.auth_check",
+                    "targets.api.on_ready"
+                ]
+            ),
+            other => panic!("expected the commands to be refused, got {other:?}"),
+        }
+        assert!(local.is_ok());
+    }
+
+    #[test]
+    fn test_select_groups() {
+        let config = || {
+            serde_yaml::from_str::<PortForwardConfigs>(
+                r#"
+                version: 0.3.0
+                groups:
+                  backend: [api, db]
+                  frontend: [web]
+                targets:
+                  - target: api
+                    ports: [80]
+                  - target: db
+                    ports: [5432]
+                  - target: web
+                    ports: [80]
+            "#,
+            )
+            .unwrap()
+        };
+
+        let mut backend = config();
+        backend.select_groups(&["backend".to_string()]).unwrap();
+        assert_eq!(backend.targets.len(), 2);
+
+        let mut all = config();
+        all.select_groups(&[]).unwrap();
+        assert_eq!(all.targets.len(), 3);
+
+        let error = config().select_groups(&["nope".to_string()]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The group nope is not configured - available groups: backend, frontend"
+        );
+    }
+
+    #[test]
+    fn test_json_and_toml() {
+        let json: PortForwardConfigs = parse(
+            ConfigFormat::Json,
+            r#"{
+                "version": "0.3.0",
+                "config": { "retry_delay_sec": 3.14 },
+                "targets": [{ "target": "foo", "namespace": "bar", "ports": ["5012:80", 8080] }]
+            }"#,
+        )
+        .unwrap();
+        let toml: PortForwardConfigs = parse(
+            ConfigFormat::Toml,
+            r#"
+            version = "0.3.0"
+
+            [config]
+            retry_delay_sec = 3.14
+
+            [[targets]]
+            target = "foo"
+            namespace = "bar"
+            ports = ["5012:80", 8080]
+        "#,
+        )
+        .unwrap();
+
+        for config in [json, toml] {
+            assert_eq!(config.version, Version::new(0, 3, 0));
+            assert_eq!(config.targets[0].namespace, "bar");
+            assert_eq!(config.targets[0].ports.len(), 2);
+        }
+    }
 }
@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::{MergeWith, OperationalConfig, PortForwardConfig};
+use crate::target_filter::{MatchesAnyFilter, TargetFilter};
+use just_a_tag::{MatchesAnyTagUnion, TagUnion};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A named working setup, selecting some of the targets and overriding operational settings.
+///
+/// ```yaml
+/// profiles:
+///   staging:
+///     targets: [api, db]
+///     tags: [staging]
+///     config:
+///       retry_delay_sec: 1
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct ProfileConfig {
+    /// The prefixes of the names of the targets to select, like the filters on the command line.
+    #[serde(default)]
+    pub targets: Vec<TargetFilter>,
+    /// The tags of the targets to select, like the `--tags` option.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The operational settings taking precedence over the ones of the files.
+    #[serde(default)]
+    pub config: Option<OperationalConfig>,
+}
+
+impl ProfileConfig {
+    /// Determines whether the profile selects the target; profiles without
+    /// targets and tags select all targets.
+    pub fn selects(&self, config: &PortForwardConfig) -> bool {
+        let tags: Vec<TagUnion> = self
+            .tags
+            .iter()
+            .filter_map(|tags| tags.parse().ok())
+            .collect();
+        (tags.is_empty() || tags.matches_set(&config.tags)) && self.targets.matches(config)
+    }
+}
+
+impl MergeWith for ProfileConfig {
+    fn merge_with(&mut self, other: &Self) {
+        if self.targets.is_empty() {
+            self.targets = other.targets.clone();
+        }
+        if self.tags.is_empty() {
+            self.tags = other.tags.clone();
+        }
+        match &mut self.config {
+            None => self.config = other.config.clone(),
+            Some(config) => config.merge_with(&other.config),
+        }
+    }
+}
+
+impl MergeWith for HashMap<String, ProfileConfig> {
+    fn merge_with(&mut self, other: &Self) {
+        for (name, profile) in other {
+            self.entry(name.clone())
+                .and_modify(|existing| existing.merge_with(profile))
+                .or_insert_with(|| profile.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selects() {
+        let targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: api
+              tags: [staging]
+              ports: [80]
+            - target: db
+              tags: [production]
+              ports: [5432]
+        "#,
+        )
+        .unwrap();
+
+        let profile = serde_yaml::from_str::<ProfileConfig>(r#"tags: [staging]"#).unwrap();
+        assert!(profile.selects(&targets[0]));
+        assert!(!profile.selects(&targets[1]));
+
+        let profile = serde_yaml::from_str::<ProfileConfig>(r#"targets: [d]"#).unwrap();
+        assert!(!profile.selects(&targets[0]));
+        assert!(profile.selects(&targets[1]));
+
+        assert!(ProfileConfig::default().selects(&targets[0]));
+    }
+}
@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// The protocol spoken on the first port of a target, which is verified to pass
+/// through the tunnel unmodified once the target is forwarding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Protocol {
+    /// HTTP/2 with prior knowledge, i.e. without TLS and without an upgrade from HTTP/1.1.
+    #[serde(rename = "http2")]
+    Http2,
+    /// gRPC without TLS, which runs on HTTP/2 with prior knowledge.
+    #[serde(rename = "grpc")]
+    Grpc,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Http2 => write!(f, "HTTP/2"),
+            Protocol::Grpc => write!(f, "gRPC"),
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Fetches configuration files published outside of the directory hierarchy: files on an
+//! HTTPS server, downloaded with `curl`, e.g. a canonical forward map of a platform team, and
+//! ConfigMaps of the cluster of the current context, read with `kubectl`.
+//!
+//! The fetched file is kept in the cache directory, where it is parsed like a local file
+//! and serves as a fallback when the server or cluster can't be reached.
+
+use crate::config::ConfigName;
+use crate::fnv::fnv1a;
+use crate::kubectl::{Kubectl, ShellError};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// How long a download may take, in seconds.
+const TIMEOUT_SECS: &str = "30";
+
+/// A configuration file fetched on every start and reload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteConfig {
+    /// A file on an HTTPS server, e.g. `https://example.com/team.k8sfwd`, optionally pinned to
+    /// the SHA-256 checksum given in the fragment, e.g. `#sha256=9f86d0…`.
+    Https {
+        /// The URL without the fragment.
+        url: String,
+        /// The expected SHA-256 checksum of the file, in lowercase hex.
+        sha256: Option<String>,
+    },
+    /// A ConfigMap of the current context, e.g. `configmap://dev-tools/k8sfwd`, optionally with
+    /// the key holding the file, e.g. `configmap://dev-tools/k8sfwd/team.yaml`.
+    ///
+    /// Without a key, the ConfigMap must hold a single file, or one named like a `.k8sfwd` file.
+    ConfigMap {
+        namespace: String,
+        name: String,
+        key: Option<String>,
+    },
+}
+
+impl RemoteConfig {
+    /// Determines whether the value looks like a URL rather than a path.
+    pub fn is_url(s: &str) -> bool {
+        s.contains("://")
+    }
+
+    /// Determines whether the file is a fetched copy in the cache directory.
+    pub fn is_fetched(path: &Path) -> bool {
+        cache_dir().is_some_and(|dir| path.starts_with(dir))
+    }
+
+    /// Fetches the file to the cache directory and verifies its checksum, if pinned.
+    ///
+    /// The cached copy is only replaced once the file was fetched and matches the checksum.
+    pub fn fetch(&self, kubectl: &Kubectl) -> Result<PathBuf, RemoteConfigError> {
+        let contents = match self {
+            RemoteConfig::Https { url, .. } => download(url)?,
+            RemoteConfig::ConfigMap {
+                namespace,
+                name,
+                key,
+            } => read_config_map(kubectl, namespace, name, key.as_deref())?,
+        };
+        self.verify(&contents)?;
+
+        let path = self.cache_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(RemoteConfigError::Cache)?;
+        }
+
+        let partial = path.with_extension("part");
+        fs::write(&partial, contents)
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(RemoteConfigError::Cache)?;
+        Ok(path)
+    }
+
+    /// Gets the copy fetched before, if it still matches the checksum.
+    pub fn cached(&self) -> Option<PathBuf> {
+        let path = self.cache_path().ok()?;
+        let contents = fs::read(&path).ok()?;
+        self.verify(&contents).ok().map(|_| path)
+    }
+
+    /// Verifies the checksum of the contents, if the file is pinned to one.
+    fn verify(&self, contents: &[u8]) -> Result<(), RemoteConfigError> {
+        let RemoteConfig::Https {
+            url,
+            sha256: Some(expected),
+        } = self
+        else {
+            return Ok(());
+        };
+
+        let actual = hex(&Sha256::digest(contents));
+        if actual == *expected {
+            Ok(())
+        } else {
+            Err(RemoteConfigError::ChecksumMismatch {
+                url: url.clone(),
+                expected: expected.clone(),
+                actual,
+            })
+        }
+    }
+
+    /// Gets the path of the fetched file in the cache directory.
+    ///
+    /// The name of the file in the URL is kept, such that its extension selects the format.
+    fn cache_path(&self) -> Result<PathBuf, RemoteConfigError> {
+        let dir = cache_dir().ok_or(RemoteConfigError::NoCacheDir)?;
+        Ok(dir.join(self.file_name()))
+    }
+
+    /// Gets the name to cache the file under, unique for the URL.
+    fn file_name(&self) -> String {
+        let name = match self {
+            RemoteConfig::Https { url, .. } => {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                path.rsplit('/').next().unwrap_or_default()
+            }
+            RemoteConfig::ConfigMap { key, .. } => key.as_deref().unwrap_or_default(),
+        };
+        let name: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+            .collect();
+        let name = name.trim_start_matches('.');
+        format!(
+            "{hash:08x}-{name}",
+            hash = fnv1a(self.to_string().as_bytes()),
+            name = if name.is_empty() { "config" } else { name }
+        )
+    }
+}
+
+impl Display for RemoteConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteConfig::Https { url, .. } => write!(f, "{url}"),
+            RemoteConfig::ConfigMap {
+                namespace,
+                name,
+                key: None,
+            } => write!(f, "configmap://{namespace}/{name}"),
+            RemoteConfig::ConfigMap {
+                namespace,
+                name,
+                key: Some(key),
+            } => write!(f, "configmap://{namespace}/{name}/{key}"),
+        }
+    }
+}
+
+impl FromStr for RemoteConfig {
+    type Err = RemoteConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("configmap://") {
+            let mut segments = path.splitn(3, '/');
+            let (Some(namespace), Some(name)) = (segments.next(), segments.next()) else {
+                return Err(RemoteConfigError::InvalidConfigMap(s.to_string()));
+            };
+            if namespace.is_empty() || name.is_empty() {
+                return Err(RemoteConfigError::InvalidConfigMap(s.to_string()));
+            }
+
+            return Ok(Self::ConfigMap {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+                key: segments
+                    .next()
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string),
+            });
+        }
+
+        if !s.starts_with("https://") {
+            return Err(RemoteConfigError::UnsupportedUrl(s.to_string()));
+        }
+
+        let (url, fragment) = match s.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (s, None),
+        };
+
+        let sha256 = match fragment {
+            None => None,
+            Some(fragment) => {
+                let checksum = fragment
+                    .strip_prefix("sha256=")
+                    .map(str::to_ascii_lowercase)
+                    .filter(|checksum| {
+                        checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())
+                    })
+                    .ok_or_else(|| RemoteConfigError::InvalidChecksum(fragment.to_string()))?;
+                Some(checksum)
+            }
+        };
+
+        Ok(Self::Https {
+            url: url.to_string(),
+            sha256,
+        })
+    }
+}
+
+/// Downloads the file with `curl`.
+fn download(url: &str) -> Result<Vec<u8>, RemoteConfigError> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--proto", "=https", "--max-time", TIMEOUT_SECS])
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => RemoteConfigError::CurlNotFound,
+            _ => RemoteConfigError::Spawn(e),
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(RemoteConfigError::Download(
+            url.to_string(),
+            stderr.trim().to_string(),
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Reads the file from the ConfigMap; see [`RemoteConfig::ConfigMap`] for how it is picked.
+fn read_config_map(
+    kubectl: &Kubectl,
+    namespace: &str,
+    name: &str,
+    key: Option<&str>,
+) -> Result<Vec<u8>, RemoteConfigError> {
+    let config_map = format!("{namespace}/{name}");
+    let mut data = kubectl
+        .config_map_data(namespace, name)
+        .map_err(|e| RemoteConfigError::ConfigMapFailed(config_map.clone(), e))?
+        .ok_or_else(|| RemoteConfigError::ConfigMapNotFound(config_map.clone()))?;
+
+    let key = match key {
+        Some(key) => key.to_string(),
+        None if data.len() == 1 => data.keys().next().cloned().unwrap_or_default(),
+        None => ConfigName::default()
+            .file_names()
+            .into_iter()
+            .find(|name| data.contains_key(name))
+            .ok_or_else(|| RemoteConfigError::AmbiguousConfigMap(config_map.clone()))?,
+    };
+
+    match data.remove(&key) {
+        Some(contents) => Ok(contents.into_bytes()),
+        None => {
+            let mut keys: Vec<_> = data.into_keys().collect();
+            keys.sort();
+            Err(RemoteConfigError::ConfigMapKeyNotFound {
+                config_map,
+                key,
+                keys,
+            })
+        }
+    }
+}
+
+/// Gets the directory fetched files are kept in.
+fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("k8sfwd").join("remote"))
+}
+
+/// Formats the bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteConfigError {
+    #[error("Only HTTPS and ConfigMap URLs are supported for config files: {0}")]
+    UnsupportedUrl(String),
+    #[error("Invalid ConfigMap URL {0} - expected configmap://<NAMESPACE>/<NAME>[/<KEY>]")]
+    InvalidConfigMap(String),
+    #[error("Invalid checksum `{0}` - expected `sha256=` followed by 64 hex digits")]
+    InvalidChecksum(String),
+    #[error("Downloading config files requires curl, which was not found")]
+    CurlNotFound,
+    #[error("Unable to start curl: {0}")]
+    Spawn(io::Error),
+    #[error("Unable to download {0}: {1}")]
+    Download(String, String),
+    #[error("The checksum of {url} is {actual}, but {expected} was expected")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Unable to read the ConfigMap {0}: {1}")]
+    ConfigMapFailed(String, ShellError),
+    #[error("The ConfigMap {0} does not exist or has no data")]
+    ConfigMapNotFound(String),
+    #[error("The ConfigMap {0} holds several files - add the key of the file to the URL, e.g. configmap://{0}/.k8sfwd")]
+    AmbiguousConfigMap(String),
+    #[error("The ConfigMap {config_map} has no key {key}; available are: {}", .keys.join(", "))]
+    ConfigMapKeyNotFound {
+        config_map: String,
+        key: String,
+        keys: Vec<String>,
+    },
+    #[error("Unable to determine the directory to store fetched config files in")]
+    NoCacheDir,
+    #[error("Unable to store the fetched config file: {0}")]
+    Cache(io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let remote: RemoteConfig =
+            "https://example.com/team.yaml#sha256=e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .parse()
+                .unwrap();
+        assert!(remote.verify(b"").is_ok());
+        assert!(matches!(
+            remote.verify(b"targets: []"),
+            Err(RemoteConfigError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_str() {
+        let checksum = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        let remote: RemoteConfig = format!("https://example.com/team.yaml#sha256={checksum}")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            remote,
+            RemoteConfig::Https {
+                url: "https://example.com/team.yaml".into(),
+                sha256: Some(checksum.to_ascii_lowercase())
+            }
+        );
+        assert!(remote.verify(b"test").is_ok());
+        assert!(remote.verify(b"tset").is_err());
+        assert!(remote.file_name().ends_with("-team.yaml"));
+
+        assert!("https://example.com/.k8sfwd#md5=abc"
+            .parse::<RemoteConfig>()
+            .is_err());
+        assert!("http://example.com/.k8sfwd"
+            .parse::<RemoteConfig>()
+            .is_err());
+
+        let remote: RemoteConfig = "configmap://dev-tools/k8sfwd/team.yaml".parse().unwrap();
+        assert_eq!(
+            remote,
+            RemoteConfig::ConfigMap {
+                namespace: "dev-tools".into(),
+                name: "k8sfwd".into(),
+                key: Some("team.yaml".into())
+            }
+        );
+        assert_eq!(remote.to_string(), "configmap://dev-tools/k8sfwd/team.yaml");
+        assert!(remote.file_name().ends_with("-team.yaml"));
+        assert!("configmap://dev-tools".parse::<RemoteConfig>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Looks up the remote ports of targets in the cluster: resolves the names of remote ports,
+//! e.g. `5432:postgres`, into the port numbers kubectl forwards, and discovers the ports of
+//! targets that configure none, as declared by the service or by the containers of the pod
+//! or deployment.
+
+use crate::config::{Port, PortForwardConfig, ResourceType};
+use crate::kubectl::{Kubectl, ShellError};
+use serde_json::Value;
+
+/// Resolves the named remote ports of the target; targets that only forward ports
+/// by their number are left as they are, without querying the cluster.
+pub fn resolve_port_names(
+    target: &mut PortForwardConfig,
+    kubectl: &Kubectl,
+) -> Result<(), RemotePortError> {
+    if target.ports.iter().all(Port::is_resolved) {
+        return Ok(());
+    }
+
+    let (resource, live) = get_live(target, kubectl)?;
+    let declared = declared_ports(&live, target.r#type);
+    for port in target.ports.iter_mut().filter(|port| !port.is_resolved()) {
+        let name = port.name.clone().unwrap_or_default();
+        port.remote = declared
+            .iter()
+            .find(|(declared, _)| declared.as_ref() == Some(&name))
+            .map(|(_, number)| *number)
+            .ok_or_else(|| RemotePortError::Unknown {
+                name,
+                resource: resource.clone(),
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Forwards all declared ports of the target to random local ports if it configures no ports.
+///
+/// Returns the resource the ports were discovered on, or `None` if the target configures ports.
+pub fn discover_ports(
+    target: &mut PortForwardConfig,
+    kubectl: &Kubectl,
+) -> Result<Option<String>, RemotePortError> {
+    if !target.ports.is_empty() {
+        return Ok(None);
+    }
+
+    let (resource, live) = get_live(target, kubectl)?;
+    for (name, remote) in declared_ports(&live, target.r#type) {
+        if !target.ports.iter().any(|port| port.remote == remote) {
+            target.ports.push(Port {
+                name,
+                ..Port::new(None, remote)
+            });
+        }
+    }
+
+    if target.ports.is_empty() {
+        return Err(RemotePortError::NoPorts(resource));
+    }
+    Ok(Some(resource))
+}
+
+/// Gets the resource of the target from the cluster, along with its name, e.g. `service/foo`.
+fn get_live(
+    target: &PortForwardConfig,
+    kubectl: &Kubectl,
+) -> Result<(String, Value), RemotePortError> {
+    let resource = format!("{}/{}", target.r#type.as_arg(), target.target);
+    let live = kubectl
+        .get_resource(
+            target.context.as_ref(),
+            target.cluster.as_ref(),
+            Some(&target.namespace),
+            &resource,
+        )
+        .map_err(|e| RemotePortError::Lookup(resource.clone(), e))?
+        .ok_or_else(|| RemotePortError::NotFound(resource.clone()))?;
+    Ok((resource, live))
+}
+
+/// Extracts the ports of a resource along with their names, if any; the ports of a service,
+/// or the container ports of a pod or of the pod template of a deployment.
+fn declared_ports(resource: &Value, r#type: ResourceType) -> Vec<(Option<String>, u16)> {
+    let (ports, number): (Vec<&Value>, _) = match r#type {
+        ResourceType::Service => (as_array(&resource["spec"]["ports"]), "port"),
+        ResourceType::Pod => (container_ports(&resource["spec"]), "containerPort"),
+        ResourceType::Deployment => (
+            container_ports(&resource["spec"]["template"]["spec"]),
+            "containerPort",
+        ),
+    };
+
+    ports
+        .into_iter()
+        .filter(|port| port["protocol"].as_str().unwrap_or("TCP") == "TCP")
+        .filter_map(|port| {
+            let number = u16::try_from(port[number].as_u64()?).ok()?;
+            Some((port["name"].as_str().map(String::from), number))
+        })
+        .collect()
+}
+
+/// Collects the ports of all containers of a pod specification.
+fn container_ports(spec: &Value) -> Vec<&Value> {
+    as_array(&spec["containers"])
+        .into_iter()
+        .flat_map(|container| as_array(&container["ports"]))
+        .collect()
+}
+
+fn as_array(value: &Value) -> Vec<&Value> {
+    value
+        .as_array()
+        .map(|items| items.iter().collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemotePortError {
+    #[error("Unable to look up the ports of {0}: {1}")]
+    Lookup(String, ShellError),
+    #[error("Unable to look up the ports of {0}, which does not exist")]
+    NotFound(String),
+    #[error("{resource} does not declare a port named {name}")]
+    Unknown { name: String, resource: String },
+    #[error("{0} declares no TCP ports to forward - configure the `ports` of the target")]
+    NoPorts(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_declared_ports() {
+        let service = json!({
+            "spec": { "ports": [
+                { "name": "postgres", "port": 5432, "targetPort": 15432 },
+                { "port": 9187 },
+                { "name": "dns", "port": 53, "protocol": "UDP" }
+            ]}
+        });
+        assert_eq!(
+            declared_ports(&service, ResourceType::Service),
+            vec![(Some("postgres".to_string()), 5432), (None, 9187)]
+        );
+
+        let deployment = json!({
+            "spec": { "template": { "spec": { "containers": [
+                { "ports": [{ "name": "http", "containerPort": 8080 }] },
+                { "ports": [{ "name": "metrics", "containerPort": 9090 }] }
+            ]}}}
+        });
+        assert_eq!(
+            declared_ports(&deployment, ResourceType::Deployment),
+            vec![
+                (Some("http".to_string()), 8080),
+                (Some("metrics".to_string()), 9090)
+            ]
+        );
+    }
+}
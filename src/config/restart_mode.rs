@@ -0,0 +1,63 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Determines whether a port-forward is restarted when its kubectl process exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum RestartMode {
+    /// Always restart the process.
+    #[serde(rename = "always")]
+    Always,
+    /// Only restart the process if it exited with an error.
+    #[serde(rename = "on-failure", alias = "on_failure")]
+    OnFailure,
+    /// Never restart the process.
+    #[serde(rename = "never")]
+    Never,
+}
+
+impl Default for RestartMode {
+    fn default() -> Self {
+        Self::Always
+    }
+}
+
+impl Display for RestartMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestartMode::Always => write!(f, "always"),
+            RestartMode::OnFailure => write!(f, "on-failure"),
+            RestartMode::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl RestartMode {
+    /// Determines whether to restart a process that exited successfully or with an error.
+    pub fn should_restart(&self, success: bool) -> bool {
+        match self {
+            RestartMode::Always => true,
+            RestartMode::OnFailure => !success,
+            RestartMode::Never => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_mode() {
+        let mode: RestartMode = serde_yaml::from_str("on-failure").unwrap();
+        assert_eq!(mode, RestartMode::OnFailure);
+        assert!(mode.should_restart(false));
+        assert!(!mode.should_restart(true));
+        assert!(!RestartMode::Never.should_restart(false));
+        assert!(RestartMode::Always.should_restart(true));
+    }
+}
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, StringValidation, SubschemaValidation};
+use schemars::JsonSchema;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// A non-negative duration in (fractional) seconds, e.g. a timeout or the delay between retries.
+///
+/// Specified either as a number of seconds or with a unit, e.g. `500ms`, `30s`, `5m` or `1h`.
+#[derive(Serialize, Debug, Default, Copy, Clone, PartialOrd, PartialEq)]
+pub struct Seconds(f64);
+
+impl Seconds {
+    pub const ZERO: Seconds = Seconds(0.0);
+
+    pub fn from_secs(secs: f64) -> Self {
+        Self(secs.max(0.0))
+    }
+
+    /// Ensures the value is not negative.
+    pub fn sanitized(self) -> Self {
+        Self::from_secs(self.0)
+    }
+
+    /// Parses a duration with a unit, e.g. `30s`, or a plain number of seconds.
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let split = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number: f64 = number.parse().ok()?;
+        let factor = match unit.trim() {
+            "ms" => 0.001,
+            "" | "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        Some(Self(number * factor))
+    }
+
+    /// Determines whether the value can be represented as a [`Duration`],
+    /// once negative values are sanitized.
+    fn is_representable(&self) -> bool {
+        self.0.is_finite() && Duration::try_from_secs_f64(self.0.max(0.0)).is_ok()
+    }
+}
+
+impl<'de> Deserialize<'de> for Seconds {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(f64),
+            Text(String),
+        }
+
+        let (value, text) = match Repr::deserialize(deserializer)? {
+            Repr::Number(secs) => (Some(Self(secs)), secs.to_string()),
+            Repr::Text(text) => (Self::parse(&text), text),
+        };
+        value.filter(Self::is_representable).ok_or_else(|| {
+            D::Error::custom(format!(
+                "Invalid duration {text:?}; expected e.g. 30, 500ms, 30s, 5m or 1h"
+            ))
+        })
+    }
+}
+
+impl JsonSchema for Seconds {
+    fn schema_name() -> String {
+        "Seconds".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let text = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(r"^\s*-?[0-9.]+\s*(ms|s|sec|m|min|h)?\s*$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![gen.subschema_for::<f64>(), text.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl From<Seconds> for Duration {
+    /// Converts the value, saturating at [`Duration::MAX`] for values that are too large.
+    fn from(val: Seconds) -> Self {
+        Duration::try_from_secs_f64(val.0.max(0.0)).unwrap_or(Duration::MAX)
+    }
+}
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} sec", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        let parse = |yaml: &str| serde_yaml::from_str::<Seconds>(yaml).unwrap();
+        assert_eq!(parse("2.5"), Seconds::from_secs(2.5));
+        assert_eq!(parse("\"10\""), Seconds::from_secs(10.0));
+        assert_eq!(parse("500ms"), Seconds::from_secs(0.5));
+        assert_eq!(parse("30s"), Seconds::from_secs(30.0));
+        assert_eq!(parse("5m"), Seconds::from_secs(300.0));
+        assert_eq!(parse("1h"), Seconds::from_secs(3600.0));
+        assert!(serde_yaml::from_str::<Seconds>("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for yaml in [".inf", "-.inf", ".nan", "1e400", "1e20", "\"1e20h\""] {
+            assert!(
+                serde_yaml::from_str::<Seconds>(yaml).is_err(),
+                "{yaml} is rejected"
+            );
+        }
+        assert_eq!(
+            Duration::from(Seconds::from_secs(f64::INFINITY)),
+            Duration::MAX
+        );
+        assert_eq!(Duration::from(Seconds::from_secs(-1.0)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,262 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Resolves references like `!env VAR`, `!file path` or `!cmd command` in configuration
+//! values, such that secrets don't need to be checked in with the configuration.
+
+use serde_yaml::value::TaggedValue;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Resolves the argument of a reference to its value.
+pub trait SecretResolver {
+    fn resolve(&self, argument: &str) -> Result<String, SecretError>;
+}
+
+/// The resolvers available to a configuration file, keyed by their tag.
+pub struct SecretResolvers {
+    resolvers: HashMap<String, Box<dyn SecretResolver>>,
+}
+
+impl SecretResolvers {
+    /// Creates the default resolvers, i.e. `!env`, `!file` and `!cmd`.
+    ///
+    /// Relative paths and commands are resolved against the specified directory,
+    /// usually the one containing the configuration file.
+    pub fn new(base_dir: &Path) -> Self {
+        let mut resolvers = Self {
+            resolvers: HashMap::new(),
+        };
+        resolvers.register("env", EnvResolver);
+        resolvers.register("file", FileResolver(base_dir.to_path_buf()));
+        resolvers.register("cmd", CommandResolver(base_dir.to_path_buf()));
+        resolvers
+    }
+
+    /// Creates the resolvers available to configuration files fetched from a remote source.
+    ///
+    /// Such files must not run commands or read files on this machine, so `!file` and `!cmd`
+    /// are refused and only `!env` is resolved.
+    pub fn remote() -> Self {
+        let mut resolvers = Self {
+            resolvers: HashMap::new(),
+        };
+        resolvers.register("env", EnvResolver);
+        resolvers.register("file", RefusedResolver("file"));
+        resolvers.register("cmd", RefusedResolver("cmd"));
+        resolvers
+    }
+
+    /// Registers a resolver for the specified tag, replacing any existing one.
+    pub fn register(&mut self, tag: &str, resolver: impl SecretResolver + 'static) {
+        self.resolvers.insert(tag.into(), Box::new(resolver));
+    }
+
+    /// Replaces all references in the value with the values they refer to.
+    ///
+    /// Returns whether the value contained any references.
+    pub fn resolve(&self, value: &mut Value) -> Result<bool, SecretError> {
+        let mut resolved = false;
+        match value {
+            Value::Tagged(tagged) => {
+                *value = Value::String(self.resolve_tagged(tagged)?);
+                resolved = true;
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    resolved |= self.resolve(item)?;
+                }
+            }
+            Value::Mapping(mapping) => {
+                for (_, item) in mapping.iter_mut() {
+                    resolved |= self.resolve(item)?;
+                }
+            }
+            Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {}
+        }
+
+        Ok(resolved)
+    }
+
+    fn resolve_tagged(&self, tagged: &TaggedValue) -> Result<String, SecretError> {
+        let tag = tagged.tag.to_string();
+        let tag = tag.trim_start_matches('!');
+        let resolver = self
+            .resolvers
+            .get(tag)
+            .ok_or_else(|| SecretError::UnknownResolver(tag.into()))?;
+
+        match &tagged.value {
+            Value::String(argument) => resolver.resolve(argument),
+            _ => Err(SecretError::InvalidArgument(tag.into())),
+        }
+    }
+}
+
+/// Resolves `!env VAR` to the value of an environment variable.
+struct EnvResolver;
+
+impl SecretResolver for EnvResolver {
+    fn resolve(&self, argument: &str) -> Result<String, SecretError> {
+        std::env::var(argument).map_err(|_| SecretError::EnvNotSet(argument.into()))
+    }
+}
+
+/// Resolves `!file path` to the contents of a file, without the trailing line break.
+struct FileResolver(PathBuf);
+
+impl SecretResolver for FileResolver {
+    fn resolve(&self, argument: &str) -> Result<String, SecretError> {
+        let path = self.0.join(argument);
+        let contents =
+            fs::read_to_string(&path).map_err(|e| SecretError::FileReadFailed(path, e))?;
+        Ok(contents.trim_end_matches(['\r', '\n']).into())
+    }
+}
+
+/// Resolves `!cmd command` to the output of a shell command, without the trailing line break.
+struct CommandResolver(PathBuf);
+
+impl SecretResolver for CommandResolver {
+    fn resolve(&self, argument: &str) -> Result<String, SecretError> {
+        #[cfg(unix)]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.args(["-c", argument]);
+            command
+        };
+
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.args(["/C", argument]);
+            command
+        };
+
+        if self.0.is_dir() {
+            command.current_dir(&self.0);
+        }
+
+        let output = command
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| SecretError::CommandFailed(argument.into(), e.to_string()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = match stderr.trim() {
+                "" => format!("exited with {status}", status = output.status),
+                stderr => stderr.into(),
+            };
+            return Err(SecretError::CommandFailed(argument.into(), message));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.trim_end_matches(['\r', '\n']).into())
+    }
+}
+
+/// Refuses to resolve a reference that is not allowed in the file.
+struct RefusedResolver(&'static str);
+
+impl SecretResolver for RefusedResolver {
+    fn resolve(&self, _argument: &str) -> Result<String, SecretError> {
+        Err(SecretError::NotAllowed(self.0.into()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("Unknown value reference !{0}; expected !env, !file or !cmd")]
+    UnknownResolver(String),
+    #[error("The value reference !{0} is not allowed in configuration files fetched from a remote source")]
+    NotAllowed(String),
+    #[error("The value reference !{0} must be followed by a string")]
+    InvalidArgument(String),
+    #[error("The environment variable {0} is not set")]
+    EnvNotSet(String),
+    #[error("The file {} could not be read: {1}", .0.display())]
+    FileReadFailed(PathBuf, io::Error),
+    #[error("The command `{0}` failed: {1}")]
+    CommandFailed(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(yaml: &str) -> Result<Value, SecretError> {
+        let mut value: Value = serde_yaml::from_str(yaml).unwrap();
+        SecretResolvers::new(Path::new(".")).resolve(&mut value)?;
+        Ok(value)
+    }
+
+    #[test]
+    fn test_env() {
+        std::env::set_var("K8SFWD_TEST_SECRET", "s3cr3t");
+        let value = resolve("token: !env K8SFWD_TEST_SECRET").unwrap();
+        assert_eq!(value["token"], Value::String("s3cr3t".into()));
+
+        let error = resolve("token: !env K8SFWD_TEST_UNSET").unwrap_err();
+        assert!(matches!(error, SecretError::EnvNotSet(_)));
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join("k8sfwd-test-secret");
+        fs::write(&path, "s3cr3t\n").unwrap();
+        let value = resolve(&format!("tokens: [!file {}]", path.display())).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(value["tokens"][0], Value::String("s3cr3t".into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cmd() {
+        let value = resolve("token: !cmd echo s3cr3t").unwrap();
+        assert_eq!(value["token"], Value::String("s3cr3t".into()));
+
+        let error = resolve("token: !cmd exit 3").unwrap_err();
+        assert!(matches!(error, SecretError::CommandFailed(..)));
+    }
+
+    #[test]
+    fn test_remote() {
+        std::env::set_var("K8SFWD_TEST_REMOTE", "s3cr3t");
+        let mut value: Value = serde_yaml::from_str("token: !env K8SFWD_TEST_REMOTE").unwrap();
+        SecretResolvers::remote().resolve(&mut value).unwrap();
+        assert_eq!(value["token"], Value::String("s3cr3t".into()));
+
+        for yaml in ["token: !cmd echo s3cr3t", "token: !file /etc/passwd"] {
+            let mut value: Value = serde_yaml::from_str(yaml).unwrap();
+            let error = SecretResolvers::remote().resolve(&mut value).unwrap_err();
+            assert!(matches!(error, SecretError::NotAllowed(_)));
+        }
+    }
+
+    #[test]
+    fn test_custom_resolver() {
+        struct Upper;
+        impl SecretResolver for Upper {
+            fn resolve(&self, argument: &str) -> Result<String, SecretError> {
+                Ok(argument.to_uppercase())
+            }
+        }
+
+        let mut value: Value = serde_yaml::from_str("token: !upper abc").unwrap();
+        let mut resolvers = SecretResolvers::new(Path::new("."));
+        assert!(matches!(
+            resolvers.resolve(&mut value.clone()),
+            Err(SecretError::UnknownResolver(_))
+        ));
+
+        resolvers.register("upper", Upper);
+        resolvers.resolve(&mut value).unwrap();
+        assert_eq!(value["token"], Value::String("ABC".into()));
+    }
+}
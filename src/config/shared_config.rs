@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::MergeWith;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Settings of the shared mode, in which multiple users request forwards from one session.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+pub struct SharedConfig {
+    /// The first local port handed out to users.
+    pub port_base: Option<u16>,
+    /// The number of local ports reserved for each user.
+    pub ports_per_user: Option<u16>,
+    /// The group whose members may connect to the shared session; without one,
+    /// only the user running the session can.
+    pub group: Option<String>,
+}
+
+impl SharedConfig {
+    pub const DEFAULT_PORT_BASE: u16 = 20000;
+    pub const DEFAULT_PORTS_PER_USER: u16 = 100;
+
+    pub fn port_base(&self) -> u16 {
+        self.port_base.unwrap_or(Self::DEFAULT_PORT_BASE)
+    }
+
+    pub fn ports_per_user(&self) -> u16 {
+        self.ports_per_user
+            .unwrap_or(Self::DEFAULT_PORTS_PER_USER)
+            .max(1)
+    }
+}
+
+impl MergeWith for SharedConfig {
+    fn merge_with(&mut self, other: &Self) {
+        self.port_base.merge_with(&other.port_base);
+        self.ports_per_user.merge_with(&other.ports_per_user);
+        self.group.merge_with(&other.group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared() {
+        let config = serde_yaml::from_str::<SharedConfig>(r#"port_base: 30000"#)
+            .expect("configuration is valid");
+        assert_eq!(config.port_base(), 30000);
+        assert_eq!(
+            config.ports_per_user(),
+            SharedConfig::DEFAULT_PORTS_PER_USER
+        );
+    }
+}
@@ -0,0 +1,36 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Determines what happens to the other targets when a target fails to spawn its processes,
+/// e.g. because kubectl could not be started.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+pub enum SpawnPolicy {
+    /// Stop all targets and exit.
+    #[serde(rename = "abort")]
+    Abort,
+    /// Mark the target as failed and keep the other targets running.
+    #[serde(rename = "continue")]
+    Continue,
+}
+
+impl Default for SpawnPolicy {
+    fn default() -> Self {
+        Self::Abort
+    }
+}
+
+impl Display for SpawnPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnPolicy::Abort => write!(f, "abort"),
+            SpawnPolicy::Continue => write!(f, "continue"),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::ConfigName;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The git repository of configuration fragments shared by a team, copied to the user's
+/// config directory by `k8sfwd sync` and merged below the other files of the hierarchy.
+///
+/// ```yaml
+/// sync:
+///   repository: https://github.com/example/k8sfwd-team.git
+///   branch: main
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SyncConfig {
+    /// The URL of the repository, as understood by `git clone`.
+    pub repository: String,
+    /// The branch to use; defaults to the default branch of the repository.
+    pub branch: Option<String>,
+}
+
+impl SyncConfig {
+    /// Gets the directory the repository is copied to.
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("k8sfwd").join("team"))
+    }
+
+    /// Lists the configuration fragments in the top directory of the synchronized repository,
+    /// in order of their names: the files named like a configuration file, e.g. `.k8sfwd` or
+    /// `payments.k8sfwd.yaml`.
+    pub fn fragments(dir: &Path, name: &ConfigName) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let names = name.file_names();
+        let mut fragments: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| names.iter().any(|name| file_name.ends_with(name)))
+            })
+            .collect();
+        fragments.sort();
+        fragments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragments() {
+        let dir = std::env::temp_dir().join(format!("k8sfwd-sync-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "payments.k8sfwd.yaml",
+            ".k8sfwd",
+            ".k8sfwd.local",
+            "README.md",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let fragments = SyncConfig::fragments(&dir, &ConfigName::default());
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(
+            fragments,
+            vec![dir.join(".k8sfwd"), dir.join("payments.k8sfwd.yaml")]
+        );
+    }
+}
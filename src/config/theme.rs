@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::status::ForwardState;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The glyphs marking the states of the targets, both in the output and in `k8sfwd status`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+pub enum Theme {
+    /// Describe the states in words only.
+    #[serde(rename = "plain")]
+    Plain,
+    /// Mark the states with ASCII symbols, e.g. `[ok]`, for terminals without Unicode support.
+    #[serde(rename = "ascii")]
+    Ascii,
+    /// Mark the states with Unicode symbols, e.g. `●`.
+    #[serde(rename = "unicode")]
+    Unicode,
+    /// Mark the states with colored emoji, e.g. `🟢`.
+    #[serde(rename = "emoji")]
+    Emoji,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::Plain
+    }
+}
+
+impl Theme {
+    /// Gets the glyph marking the state, if any.
+    pub fn glyph(&self, state: ForwardState) -> Option<&'static str> {
+        use ForwardState::*;
+        let glyph = match self {
+            Theme::Plain => return None,
+            Theme::Ascii => match state {
+                Starting => "[..]",
+                Forwarding => "[ok]",
+                Restarting => "[~~]",
+                Stopped => "[--]",
+                Failed => "[!!]",
+            },
+            Theme::Unicode => match state {
+                Starting => "◌",
+                Forwarding => "●",
+                Restarting => "↻",
+                Stopped => "■",
+                Failed => "✗",
+            },
+            Theme::Emoji => match state {
+                Starting => "🟡",
+                Forwarding => "🟢",
+                Restarting => "🔄",
+                Stopped => "💤",
+                Failed => "🔴",
+            },
+        };
+        Some(glyph)
+    }
+
+    /// Prefixes the text with the glyph marking the state, if any.
+    pub fn mark(&self, state: ForwardState, text: impl Display) -> String {
+        match self.glyph(state) {
+            Some(glyph) => format!("{glyph} {text}"),
+            None => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mark() {
+        let theme: Theme = serde_yaml::from_str("ascii").unwrap();
+        assert_eq!(theme.mark(ForwardState::Failed, "failed"), "[!!] failed");
+        assert_eq!(
+            Theme::Plain.mark(ForwardState::Forwarding, "forwarding"),
+            "forwarding"
+        );
+    }
+}
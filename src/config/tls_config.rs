@@ -0,0 +1,53 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Terminates TLS on the local ports of a target, such that clients connect with HTTPS while
+/// the backend speaks plain HTTP.
+///
+/// Without a certificate, a self-signed one is generated:
+///
+/// ```yaml
+/// tls:
+///   cert: ./certs/localhost.pem
+///   key: ./certs/localhost-key.pem
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TlsConfig {
+    /// The PEM encoded certificate (chain) presented to clients.
+    pub cert: Option<PathBuf>,
+    /// The PEM encoded private key of the certificate.
+    pub key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Gets the certificate and key, or `None` if a self-signed certificate is to be used.
+    ///
+    /// Returns an error naming the missing setting if only one of them is set.
+    pub fn files(&self) -> Result<Option<(&PathBuf, &PathBuf)>, &'static str> {
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err("key"),
+            (None, Some(_)) => Err("cert"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let config: TlsConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(config.files(), Ok(None));
+
+        let config: TlsConfig = serde_yaml::from_str("{ cert: localhost.pem }").unwrap();
+        assert_eq!(config.files(), Err("key"));
+    }
+}
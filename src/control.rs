@@ -0,0 +1,636 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! The control socket of a running session.
+//!
+//! Requests and responses are exchanged as single-line JSON documents over a
+//! Unix domain socket (or a named pipe on Windows). Requests are handed to the
+//! main loop, which may answer with one or more responses before closing the connection.
+
+use crate::config::{Port, PortForwardConfig, ResourceType};
+use crate::kubectl::StreamSource;
+use crate::status::TargetStatus;
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{ListenerOptions, Name, Stream};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::OnceLock;
+use std::{io, thread};
+
+/// The user running the shared session whose socket to use, see [`use_shared_socket`].
+static SHARED_SOCKET: OnceLock<String> = OnceLock::new();
+
+/// Selects the control socket of the shared (multi-user) session run by the specified
+/// user instead of the socket in the user's state directory, both for serving and connecting.
+pub fn use_shared_socket(owner: String) {
+    SHARED_SOCKET.set(owner).ok();
+}
+
+fn is_shared_socket() -> bool {
+    SHARED_SOCKET.get().is_some()
+}
+
+/// A request sent to a running session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Checks whether the session is alive.
+    Ping,
+    /// Shuts the session down.
+    ///
+    /// In a shared session, only the user running the session can shut it down;
+    /// for other users, this releases their forwards instead.
+    Shutdown,
+    /// Starts the specified targets on behalf of the requesting user.
+    Attach { targets: Vec<AttachTarget> },
+    /// Reports the status of all running targets.
+    Status,
+    /// Stops a running target.
+    Stop {
+        /// The ID, name or target of the running target.
+        target: String,
+    },
+    /// Restarts the kubectl process of a running target.
+    Restart {
+        /// The ID, name or target of the running target.
+        target: String,
+    },
+    /// Streams the recent output of a running target.
+    Logs {
+        /// The ID, name or target of the running target.
+        target: String,
+        /// The number of recent lines to send.
+        lines: usize,
+        /// Whether to keep streaming new output.
+        follow: bool,
+    },
+    /// Exposes a remote port of a running target on a different local port.
+    Remap {
+        /// The ID, name or target of the running target.
+        target: String,
+        /// The new port mapping, e.g. `15432:5432`.
+        port: String,
+    },
+}
+
+/// A response of a running session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum ControlResponse {
+    /// The request was processed.
+    Ok,
+    /// The answer to [`ControlRequest::Ping`].
+    Pong {
+        /// The process ID of the session.
+        pid: u32,
+    },
+    /// The answer to requests affecting an individual target, e.g. [`ControlRequest::Stop`].
+    Target {
+        /// The ID of the affected target.
+        id: String,
+    },
+    /// The answer to [`ControlRequest::Status`].
+    Status { targets: Vec<TargetStatus> },
+    /// The answer to [`ControlRequest::Attach`].
+    Attached { targets: Vec<AttachedTarget> },
+    /// The answer to [`ControlRequest::Shutdown`] in a shared session,
+    /// listing the IDs of the user's forwards that were stopped.
+    Released { ids: Vec<String> },
+    /// A line of output, sent in answer to [`ControlRequest::Logs`].
+    Log {
+        stream: StreamSource,
+        line: String,
+        /// The number of the attempt, i.e. the kubectl process, that printed the line.
+        #[serde(default)]
+        attempt: usize,
+    },
+    /// The answer to [`ControlRequest::Remap`].
+    Remapped {
+        /// The ID of the remapped target.
+        id: String,
+        /// The previous port mapping.
+        previous: String,
+        /// The new port mapping.
+        current: String,
+    },
+    /// The request could not be processed.
+    Error { message: String },
+}
+
+/// A target requested through [`ControlRequest::Attach`].
+///
+/// Attached targets run as the user of the session, so other users can only choose what to
+/// forward; requests with further settings, e.g. hooks, environment variables or host names,
+/// are refused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachTarget {
+    /// The name of the target.
+    pub name: Option<String>,
+    /// The name of the kubeconfig context to use.
+    pub context: Option<String>,
+    /// The name of the kubeconfig cluster to use.
+    pub cluster: Option<String>,
+    /// The namespace to forward to.
+    pub namespace: String,
+    /// The type of resource to forward to.
+    #[serde(default)]
+    pub r#type: ResourceType,
+    /// The name of the resource to forward to.
+    pub target: String,
+    /// The ports to forward; the local ports are assigned by the session.
+    pub ports: Vec<Port>,
+}
+
+impl From<&PortForwardConfig> for AttachTarget {
+    fn from(config: &PortForwardConfig) -> Self {
+        Self {
+            name: config.name.clone(),
+            context: config.context.clone(),
+            cluster: config.cluster.clone(),
+            namespace: config.namespace.clone(),
+            r#type: config.r#type,
+            target: config.target.clone(),
+            ports: config.ports.clone(),
+        }
+    }
+}
+
+impl From<AttachTarget> for PortForwardConfig {
+    fn from(target: AttachTarget) -> Self {
+        Self {
+            name: target.name,
+            context: target.context,
+            cluster: target.cluster,
+            namespace: target.namespace,
+            r#type: target.r#type,
+            target: target.target,
+            ports: target.ports,
+            ..Self::default()
+        }
+    }
+}
+
+impl AttachTarget {
+    /// Resolves the requested target against the targets configured for the session, such
+    /// that other users can only forward to what the user of the session chose to offer.
+    ///
+    /// The requested ports must be forwarded by the configured target; without any, all of
+    /// its ports are used.
+    pub fn resolve(&self, configured: &[PortForwardConfig]) -> Result<Self, AttachError> {
+        let requested = PortForwardConfig::from(self.clone());
+        let label = || self.name.clone().unwrap_or_else(|| self.target.clone());
+        let mut matches = configured
+            .iter()
+            .filter(|config| config.is_same_identity(&requested));
+        let config = match (matches.next(), matches.next()) {
+            (Some(config), None) => config,
+            (None, _) => return Err(AttachError::NotConfigured(label())),
+            (Some(_), Some(_)) => return Err(AttachError::Ambiguous(label())),
+        };
+
+        let mut target = Self::from(config);
+        if !self.ports.is_empty() {
+            let mut ports = Vec::new();
+            for port in &self.ports {
+                let offered = config.ports.iter().find(|offered| {
+                    (port.remote != 0 && offered.remote == port.remote)
+                        || (port.name.is_some() && offered.name == port.name)
+                });
+                match offered {
+                    Some(offered) => ports.push(offered.clone()),
+                    None => return Err(AttachError::PortNotConfigured(label(), port.to_string())),
+                }
+            }
+            target.ports = ports;
+        }
+        Ok(target)
+    }
+}
+
+/// A target started through [`ControlRequest::Attach`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachedTarget {
+    /// The ID of the target.
+    pub id: String,
+    /// The name of the target, or the target itself if unnamed.
+    pub name: String,
+    /// The ports with their assigned local ports.
+    pub ports: Vec<Port>,
+}
+
+/// A request received by the control server, awaiting its response(s).
+pub struct PendingRequest {
+    pub request: ControlRequest,
+    /// The user that sent the request.
+    pub user: String,
+    responses: Sender<ControlResponse>,
+}
+
+impl PendingRequest {
+    /// Sends a response to the client.
+    ///
+    /// The connection is closed once the pending request is dropped.
+    pub fn reply(&self, response: ControlResponse) {
+        self.try_reply(response);
+    }
+
+    /// Sends a response to the client, returning `false` if the client has disconnected.
+    pub fn try_reply(&self, response: ControlResponse) -> bool {
+        self.responses.send(response).is_ok()
+    }
+}
+
+/// The server side of the control socket.
+///
+/// The socket is removed when the server is dropped.
+pub struct ControlServer {
+    requests: Receiver<PendingRequest>,
+}
+
+impl ControlServer {
+    /// Binds the control socket and accepts connections in a background thread.
+    ///
+    /// The shared socket is only accessible to the members of the specified group, if any.
+    pub fn start(group: Option<&str>) -> Result<Self, ControlError> {
+        let requests = start_server(group)?;
+        Ok(Self { requests })
+    }
+
+    /// Gets the next pending request, if any.
+    pub fn try_recv(&self) -> Option<PendingRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        // The listener thread never finishes, so the socket is not reclaimed automatically.
+        remove_stale_socket();
+    }
+}
+
+/// Binds the control socket and accepts connections in a background thread.
+///
+/// Received requests are forwarded through the returned channel.
+fn start_server(group: Option<&str>) -> Result<Receiver<PendingRequest>, ControlError> {
+    if is_shared_socket() {
+        prepare_shared_dir(group)?;
+    }
+
+    let listener = match ListenerOptions::new().name(socket_name()?).create_sync() {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            // Only take over the socket if it is not served by a running session.
+            if connect().is_ok() {
+                return Err(ControlError::AlreadyRunning);
+            }
+
+            remove_stale_socket();
+            ListenerOptions::new().name(socket_name()?).create_sync()?
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if is_shared_socket() {
+        share_socket(group)?;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|stream| stream.ok()) {
+            let tx = tx.clone();
+            thread::spawn(move || handle_connection(stream, tx));
+        }
+    });
+
+    Ok(rx)
+}
+
+fn handle_connection(stream: Stream, requests: Sender<PendingRequest>) {
+    let user = peer_user(&stream);
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    match serde_json::from_str(&line) {
+        Ok(request) => {
+            let pending = PendingRequest {
+                request,
+                user,
+                responses: tx,
+            };
+            if requests.send(pending).is_err() {
+                return;
+            }
+        }
+        Err(e) => {
+            tx.send(ControlResponse::Error {
+                message: format!("Invalid request: {e}"),
+            })
+            .ok();
+        }
+    }
+
+    let mut writer = &stream;
+    for response in rx {
+        let Ok(mut response) = serde_json::to_string(&response) else {
+            continue;
+        };
+        response.push('\n');
+        if writer.write_all(response.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+/// A connection to the control socket of a running session.
+pub struct ControlClient {
+    reader: BufReader<Stream>,
+}
+
+impl ControlClient {
+    /// Connects to the running session.
+    pub fn connect() -> Result<Self, ControlError> {
+        let stream = connect().map_err(|_| ControlError::NotRunning)?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    /// Sends a request to the session.
+    pub fn send(&mut self, request: &ControlRequest) -> Result<(), ControlError> {
+        let mut request = serde_json::to_string(request)?;
+        request.push('\n');
+        self.reader.get_mut().write_all(request.as_bytes())?;
+        Ok(())
+    }
+
+    /// Receives the next response, or `None` if the session closed the connection.
+    pub fn receive(&mut self) -> Result<Option<ControlResponse>, ControlError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+
+    /// Sends a request and waits for its first response.
+    pub fn request(request: &ControlRequest) -> Result<ControlResponse, ControlError> {
+        let mut client = Self::connect()?;
+        client.send(request)?;
+        client.receive()?.ok_or(ControlError::ConnectionClosed)
+    }
+}
+
+fn connect() -> io::Result<Stream> {
+    #[cfg(unix)]
+    if let Some(owner) = SHARED_SOCKET.get() {
+        // Don't hand requests to a session someone else set up under the user's name.
+        let uid = crate::shared::user_id(owner).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Unknown user {owner}"))
+        })?;
+        verify_shared_dir(&shared_dir(owner), uid)?;
+    }
+
+    Stream::connect(socket_name()?)
+}
+
+/// Gets the directory of the shared control socket of the session run by the user.
+///
+/// It is created by the session, accessible to its user and the group given in the
+/// `shared` settings only.
+#[cfg(unix)]
+fn shared_dir(owner: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("/tmp/k8sfwd-{owner}"))
+}
+
+/// Gets the path of the control socket, i.e. a file in the state directory
+/// or the shared socket.
+#[cfg(unix)]
+fn socket_path() -> io::Result<std::path::PathBuf> {
+    if let Some(owner) = SHARED_SOCKET.get() {
+        return Ok(shared_dir(owner).join("shared.sock"));
+    }
+
+    let path = crate::session::state_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory available"))?;
+    std::fs::create_dir_all(&path)?;
+    Ok(path.join("control.sock"))
+}
+
+/// Gets the name of the control socket.
+#[cfg(unix)]
+fn socket_name() -> io::Result<Name<'static>> {
+    use interprocess::local_socket::GenericFilePath;
+    socket_path()?.to_fs_name::<GenericFilePath>()
+}
+
+/// Gets the name of the control socket, i.e. a named pipe.
+#[cfg(windows)]
+fn socket_name() -> io::Result<Name<'static>> {
+    use interprocess::local_socket::GenericNamespaced;
+    let name = if is_shared_socket() {
+        "k8sfwd-shared"
+    } else {
+        "k8sfwd-control"
+    };
+    name.to_ns_name::<GenericNamespaced>()
+}
+
+/// Creates the directory of the shared socket, accessible to the members of the group only.
+#[cfg(unix)]
+fn prepare_shared_dir(group: Option<&str>) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = shared_dir(&crate::shared::current_user());
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    verify_shared_dir(&dir, unsafe { libc::geteuid() })?;
+
+    let mode = match group {
+        Some(group) => {
+            std::os::unix::fs::chown(&dir, None, Some(shared_group_id(group)?))?;
+            0o750
+        }
+        None => 0o700,
+    };
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(windows)]
+fn prepare_shared_dir(_group: Option<&str>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Allows the members of the group to connect to the shared socket.
+#[cfg(unix)]
+fn share_socket(group: Option<&str>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path()?;
+    let mode = match group {
+        Some(group) => {
+            std::os::unix::fs::chown(&path, None, Some(shared_group_id(group)?))?;
+            0o660
+        }
+        None => 0o600,
+    };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(windows)]
+fn share_socket(_group: Option<&str>) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shared_group_id(group: &str) -> io::Result<libc::gid_t> {
+    crate::shared::group_id(group)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Unknown group {group}")))
+}
+
+/// Ensures the directory of the shared socket belongs to the user and can't be written to by
+/// others, who could otherwise replace the socket.
+#[cfg(unix)]
+fn verify_shared_dir(dir: &std::path::Path, uid: libc::uid_t) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o022 == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{dir} is not a directory of the session's user, or writable by others",
+                dir = dir.display()
+            ),
+        ))
+    }
+}
+
+/// Determines the user on the other end of the connection.
+#[cfg(unix)]
+fn peer_user(stream: &Stream) -> String {
+    match stream.peer_creds().ok().and_then(|creds| creds.euid()) {
+        Some(uid) => crate::shared::user_name(uid),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Determines the user on the other end of the connection.
+///
+/// Named pipes don't reveal the user of the client; as the pipe is only
+/// accessible to the current user by default, it is assumed to be the same user.
+#[cfg(windows)]
+fn peer_user(_stream: &Stream) -> String {
+    crate::shared::current_user()
+}
+
+#[cfg(unix)]
+fn remove_stale_socket() {
+    if let Ok(path) = socket_path() {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(windows)]
+fn remove_stale_socket() {}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachError {
+    #[error("The target {0} is not configured in the shared session")]
+    NotConfigured(String),
+    #[error("The target {0} matches several targets of the shared session; specify its context")]
+    Ambiguous(String),
+    #[error("The port {1} of {0} is not configured in the shared session")]
+    PortNotConfigured(String, String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("Another k8sfwd session is already serving the control socket")]
+    AlreadyRunning,
+    #[error("No running k8sfwd session was found")]
+    NotRunning,
+    #[error("The session closed the connection unexpectedly")]
+    ConnectionClosed,
+    #[error("Invalid control message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_request() {
+        let request = r#"{"command":"attach","targets":[{"namespace":"default","target":"api","ports":["8080:80"]}]}"#;
+        let ControlRequest::Attach { targets } = serde_json::from_str(request).unwrap() else {
+            panic!("expected an attach request");
+        };
+        let config = PortForwardConfig::from(targets[0].clone());
+        assert_eq!(config.target, "api");
+        assert_eq!(config.ports.len(), 1);
+        assert!(config.on_ready.is_none());
+
+        // Other users must not be able to run commands as the user of the session.
+        let request = r#"{"command":"attach","targets":[{"namespace":"default","target":"api","ports":["8080:80"],"on_ready":"touch /tmp/owned"}]}"#;
+        assert!(serde_json::from_str::<ControlRequest>(request).is_err());
+    }
+
+    #[test]
+    fn test_resolve_attach_target() {
+        let configured: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - name: API
+              target: api
+              context: staging
+              ports: ["8080:80", "9090:90"]
+              on_ready: ./warmup.sh
+            - target: db
+              context: staging
+              ports: [5432]
+        "#,
+        )
+        .unwrap();
+        let request = |yaml: &str| serde_yaml::from_str::<AttachTarget>(yaml).unwrap();
+
+        let target = request("{namespace: default, target: api, ports: ['80']}")
+            .resolve(&configured)
+            .unwrap();
+        assert_eq!(target.name.as_deref(), Some("API"));
+        assert_eq!(target.context.as_deref(), Some("staging"));
+        assert_eq!(target.ports, [Port::new(Some(8080), 80)]);
+
+        let target = request("{namespace: default, target: db, ports: []}")
+            .resolve(&configured)
+            .unwrap();
+        assert_eq!(target.ports.len(), 1);
+
+        // Anything else would be forwarded with the credentials of the session's user.
+        let error = request("{namespace: kube-system, target: api, ports: ['80']}")
+            .resolve(&configured)
+            .unwrap_err();
+        assert!(matches!(error, AttachError::NotConfigured(_)));
+        let error =
+            request("{namespace: default, target: api, context: production, ports: ['80']}")
+                .resolve(&configured)
+                .unwrap_err();
+        assert!(matches!(error, AttachError::NotConfigured(_)));
+        let error = request("{namespace: default, target: api, ports: ['22']}")
+            .resolve(&configured)
+            .unwrap_err();
+        assert!(matches!(error, AttachError::PortNotConfigured(..)));
+    }
+}
@@ -0,0 +1,119 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::session::{is_process_alive, state_dir};
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// The environment variable marking the re-spawned background process.
+const DETACHED_ENV: &str = "K8SFWD_DETACHED";
+
+/// Determines whether the current process is the detached background process.
+pub fn is_detached() -> bool {
+    std::env::var_os(DETACHED_ENV).is_some()
+}
+
+/// Re-spawns the current process in the background, detached from the terminal.
+///
+/// The output of the background process is written to a log file in the state directory.
+/// Returns the process ID of the background process and the path of the log file.
+pub fn detach() -> Result<(u32, PathBuf), DaemonError> {
+    if let Some(pid) = PidFile::running_pid() {
+        return Err(DaemonError::AlreadyRunning(pid));
+    }
+
+    let dir = state_dir().ok_or(DaemonError::NoStateDirectory)?;
+    fs::create_dir_all(&dir)?;
+
+    let log_path = dir.join("daemon.log");
+    let log = File::create(&log_path)?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(DETACHED_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Start a new session such that the terminal's signals don't reach the process.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let child = command.spawn()?;
+    Ok((child.id(), log_path))
+}
+
+/// The PID file of the background process; removed when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    fn path() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("k8sfwd.pid"))
+    }
+
+    /// Writes the ID of the current process to the PID file.
+    pub fn create() -> Result<Self, DaemonError> {
+        if let Some(pid) = Self::running_pid() {
+            return Err(DaemonError::AlreadyRunning(pid));
+        }
+
+        let path = Self::path().ok_or(DaemonError::NoStateDirectory)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&path, std::process::id().to_string())?;
+        Ok(Self { path })
+    }
+
+    /// Gets the ID of the running background process, if any.
+    pub fn running_pid() -> Option<u32> {
+        let pid = fs::read_to_string(Self::path()?).ok()?;
+        let pid = pid.trim().parse().ok()?;
+        if pid != std::process::id() && is_process_alive(pid) {
+            Some(pid)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DaemonError {
+    #[error("k8sfwd is already running in the background (PID {0})")]
+    AlreadyRunning(u32),
+    #[error("No state directory is available for the PID and log files")]
+    NoStateDirectory,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Starts the targets in the order of their `depends_on` settings, and holds back dependent
+//! targets until the local ports of their dependencies accept connections, e.g. for targets
+//! that are only reachable through a forwarded auth proxy.
+
+use crate::config::{ConfigId, PortForwardConfig};
+use crate::health;
+use crate::kubectl::{ChildEvent, StreamSource};
+use crate::signals::ShutdownSignal;
+use crate::status::StatusHandle;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// How often to check whether the dependencies accept connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A running target that needs to accept connections before a dependent target is started.
+#[derive(Clone)]
+pub struct Dependency {
+    name: String,
+    address: IpAddr,
+    /// The configured local port of the first port; otherwise, the port kubectl picked is used.
+    port: Option<u16>,
+    status: StatusHandle,
+}
+
+impl Dependency {
+    fn new(config: &PortForwardConfig, status: &StatusHandle) -> Self {
+        Self {
+            name: label(config),
+            address: health::local_addresses(&config.listen_addrs)[0],
+            port: config.ports.first().and_then(|port| port.local),
+            status: status.clone(),
+        }
+    }
+
+    /// Determines whether the first local port of the dependency accepts connections.
+    fn accepts_connections(&self) -> bool {
+        let port = self
+            .port
+            .or_else(|| self.status.snapshot().local_ports.first().copied());
+        port.is_some_and(|port| health::connect(SocketAddr::new(self.address, port)).is_ok())
+    }
+}
+
+/// Orders the targets such that each target follows the targets it depends on,
+/// keeping the configured order otherwise.
+///
+/// Dependencies on targets that are not among the targets are ignored;
+/// see [`unselected`] for reporting them.
+pub fn order(targets: Vec<PortForwardConfig>) -> Result<Vec<PortForwardConfig>, DependencyError> {
+    let mut remaining = targets;
+    let mut ordered: Vec<PortForwardConfig> = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let next = remaining.iter().position(|target| {
+            target.depends_on.iter().all(|name| {
+                !remaining.iter().any(|other| is_named(other, name))
+                    || ordered.iter().any(|other| is_named(other, name))
+            })
+        });
+
+        match next {
+            Some(index) => ordered.push(remaining.remove(index)),
+            None => {
+                let names = remaining.iter().map(label).collect();
+                return Err(DependencyError::Cycle(names));
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Describes the dependencies that are not among the targets, and therefore not waited for.
+pub fn unselected(targets: &[PortForwardConfig]) -> Vec<String> {
+    targets
+        .iter()
+        .flat_map(|target| {
+            target
+                .depends_on
+                .iter()
+                .filter(|name| !targets.iter().any(|other| is_named(other, name)))
+                .map(move |name| {
+                    format!(
+                        "Target {target} depends on {name}, which is not selected - starting it without waiting",
+                        target = label(target)
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Finds the dependencies of the target among the running targets.
+pub fn resolve<'a>(
+    config: &PortForwardConfig,
+    running: impl Iterator<Item = (&'a PortForwardConfig, &'a StatusHandle)>,
+) -> Vec<Dependency> {
+    let running: Vec<_> = running
+        .filter(|(other, _)| !other.is_same_identity(config))
+        .collect();
+    config
+        .depends_on
+        .iter()
+        .filter_map(|name| {
+            running
+                .iter()
+                .find(|(other, _)| is_named(other, name))
+                .map(|(other, status)| Dependency::new(other, status))
+        })
+        .collect()
+}
+
+/// Waits until all dependencies accept connections, reporting what the target waits for.
+///
+/// Returns `true` if the wait was interrupted by a shutdown request.
+pub fn wait_for(
+    id: ConfigId,
+    dependencies: &[Dependency],
+    out_tx: &Sender<ChildEvent>,
+    shutdown: &ShutdownSignal,
+) -> bool {
+    for dependency in dependencies {
+        let mut reported = false;
+        while !dependency.accepts_connections() {
+            if !reported {
+                let message = format!(
+                    "Waiting for {name} to accept connections",
+                    name = dependency.name
+                );
+                out_tx
+                    .send(ChildEvent::Output(id, StreamSource::StdOut, message))
+                    .ok();
+                reported = true;
+            }
+
+            if shutdown.sleep(POLL_INTERVAL) {
+                return true;
+            }
+        }
+    }
+
+    shutdown.is_requested()
+}
+
+/// Determines whether the entry of `depends_on` refers to the target, by its name or resource.
+fn is_named(config: &PortForwardConfig, name: &str) -> bool {
+    config.name.as_deref() == Some(name) || config.target == name
+}
+
+/// Gets the name of the target, or its resource if unnamed.
+fn label(config: &PortForwardConfig) -> String {
+    config.name.clone().unwrap_or_else(|| config.target.clone())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DependencyError {
+    #[error("The targets {} depend on each other", .0.join(", "))]
+    Cycle(Vec<String>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: api
+              depends_on: [auth]
+              ports: [80]
+            - target: db
+              ports: [5432]
+            - name: auth
+              target: auth-proxy
+              depends_on: [missing]
+              ports: [4180]
+        "#,
+        )
+        .unwrap();
+
+        let ordered = order(targets.clone()).unwrap();
+        let names: Vec<_> = ordered.iter().map(label).collect();
+        assert_eq!(names, vec!["db", "auth", "api"]);
+        assert_eq!(unselected(&targets).len(), 1);
+
+        let mut cyclic = targets;
+        cyclic[2].depends_on = vec!["api".into()];
+        assert!(matches!(
+            order(cyclic),
+            Err(DependencyError::Cycle(names)) if names == vec!["api", "auth"]
+        ));
+    }
+}
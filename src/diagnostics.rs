@@ -0,0 +1,169 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Recognizes kubectl error messages that retrying won't fix.
+
+use crate::config::PortForwardConfig;
+
+/// An error reported by kubectl that points at the configuration or the credentials
+/// rather than a transient problem.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum KubectlError {
+    #[error("The namespace {0} does not exist; check the `namespace` of the target")]
+    NamespaceNotFound(String),
+    #[error("The {kind} {name} does not exist; check the `target` and `type` of the target")]
+    ResourceNotFound { kind: String, name: String },
+    #[error("The kubeconfig context {0} does not exist; check the `context` of the target")]
+    ContextNotFound(String),
+    #[error("Not logged in to the cluster; please log in again")]
+    Unauthorized,
+    #[error("Permission denied: {0}")]
+    Forbidden(String),
+    #[error("The TLS connection to the cluster failed: {0}")]
+    Tls(String),
+    #[error("Local port {0} is already in use; choose another `local` port or set `on_port_conflict: remap`")]
+    AddressInUse(u16),
+    #[error("Local port {0} is held by kubectl (PID {1}), left over from a k8sfwd session that did not shut down; stop it or set `on_port_conflict: reclaim`")]
+    HeldByOrphan(u16, u32),
+}
+
+impl KubectlError {
+    /// Classifies a line kubectl printed to its standard error.
+    ///
+    /// Returns `None` for lines that don't describe a known permanent error.
+    pub fn classify(line: &str) -> Option<Self> {
+        let line = line.trim();
+
+        if let Some(message) = line.strip_prefix("Error from server (NotFound): ") {
+            // e.g. `namespaces "foo" not found` or `deployments.apps "foo" not found`
+            let (kind, rest) = message.split_once(' ')?;
+            let name = rest.strip_suffix(" not found")?.trim_matches('"');
+            let kind = kind.split('.').next().unwrap_or(kind);
+            let kind = kind.strip_suffix('s').unwrap_or(kind);
+            return Some(match kind {
+                "namespace" => Self::NamespaceNotFound(name.into()),
+                _ => Self::ResourceNotFound {
+                    kind: kind.into(),
+                    name: name.into(),
+                },
+            });
+        }
+
+        if let Some(message) = line.strip_prefix("Error from server (Forbidden): ") {
+            return Some(Self::Forbidden(message.into()));
+        }
+
+        if line.contains("(Unauthorized)") {
+            return Some(Self::Unauthorized);
+        }
+
+        if let Some(message) = line.strip_prefix("error: context ") {
+            let name = message.strip_suffix(" does not exist")?.trim_matches('"');
+            return Some(Self::ContextNotFound(name.into()));
+        }
+
+        if let Some(name) = line.strip_prefix("error: no context exists with the name: ") {
+            return Some(Self::ContextNotFound(name.trim_matches('"').into()));
+        }
+
+        // e.g. `Unable to listen on port 5012: Listeners failed to create with the following errors:
+        // [unable to create listener: Error listen tcp4 127.1.0.1:5012: bind: address already in use]`
+        if let Some(message) = line.strip_prefix("Unable to listen on port ") {
+            if message.contains("address already in use") {
+                let (port, _) = message.split_once(':')?;
+                return port.parse().ok().map(Self::AddressInUse);
+            }
+        }
+
+        if line.contains("x509: ") || line.contains("tls: ") {
+            return Some(Self::Tls(line.into()));
+        }
+
+        None
+    }
+
+    /// Determines whether the error is permanent for the target.
+    ///
+    /// Pods backing a service or deployment come and go, so only missing resources
+    /// the target refers to directly are considered permanent.
+    pub fn is_fatal_for(&self, target: &PortForwardConfig) -> bool {
+        match self {
+            Self::ResourceNotFound { kind, name } => match &target.via {
+                Some(gateway) => kind == "pod" && name == &gateway.pod,
+                None => kind == target.r#type.as_arg() && name == &target.target,
+            },
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            KubectlError::classify(
+                r#"Error from server (NotFound): namespaces "test-api" not found"#
+            ),
+            Some(KubectlError::NamespaceNotFound("test-api".into()))
+        );
+        assert_eq!(
+            KubectlError::classify(
+                r#"Error from server (NotFound): deployments.apps "foo" not found"#
+            ),
+            Some(KubectlError::ResourceNotFound {
+                kind: "deployment".into(),
+                name: "foo".into()
+            })
+        );
+        assert_eq!(
+            KubectlError::classify("error: You must be logged in to the server (Unauthorized)"),
+            Some(KubectlError::Unauthorized)
+        );
+        assert_eq!(
+            KubectlError::classify(r#"error: context "staging" does not exist"#),
+            Some(KubectlError::ContextNotFound("staging".into()))
+        );
+        assert!(matches!(
+            KubectlError::classify(
+                "Unable to connect to the server: x509: certificate signed by unknown authority"
+            ),
+            Some(KubectlError::Tls(_))
+        ));
+        assert_eq!(
+            KubectlError::classify("Unable to listen on port 5012: Listeners failed to create with the following errors: [unable to create listener: Error listen tcp4 127.1.0.1:5012: bind: address already in use]"),
+            Some(KubectlError::AddressInUse(5012))
+        );
+        assert_eq!(
+            KubectlError::classify("E0716 error: lost connection to pod"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_fatal_for() {
+        let target = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports:
+              - "1234:5678"
+        "#,
+        )
+        .unwrap();
+
+        let service =
+            KubectlError::classify(r#"Error from server (NotFound): services "foo" not found"#)
+                .unwrap();
+        assert!(service.is_fatal_for(&target));
+
+        // The service's pod was replaced while connecting.
+        let pod = KubectlError::classify(
+            r#"Error from server (NotFound): pods "foo-59b58f5d68-6t6bh" not found"#,
+        )
+        .unwrap();
+        assert!(!pod.is_fatal_for(&target));
+    }
+}
@@ -0,0 +1,321 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! A minimal DNS server answering the names of the forwarded services, e.g.
+//! `my-service.default.svc.fwd`, with the local address they are forwarded to, such that
+//! applications can refer to services by name. Only `A` and `AAAA` queries are answered.
+
+use crate::config::{DnsConfig, ResourceType};
+use crate::engine::Engine;
+use crate::preflight;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The interval at which the socket is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The interval at which the records are updated from the running targets.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long resolvers may cache the answers, in seconds; short, as targets come and go.
+const TTL: u32 = 5;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMAT_ERROR: u8 = 1;
+const RCODE_NAME_ERROR: u8 = 3;
+const RCODE_NOT_IMPLEMENTED: u8 = 4;
+const RCODE_REFUSED: u8 = 5;
+
+/// The addresses of each name, keyed by the lowercase name.
+type Records = HashMap<String, Vec<IpAddr>>;
+
+/// The DNS server of the `dns` setting; stops answering queries when dropped.
+pub struct DnsServer {
+    config: DnsConfig,
+    address: SocketAddr,
+    records: Arc<Mutex<Records>>,
+    last_update: Option<Instant>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DnsServer {
+    /// Listens on the port of the server; records are added by [`poll`](Self::poll).
+    pub fn bind(config: DnsConfig) -> Result<Self, DnsError> {
+        let address = config.address();
+        let socket = UdpSocket::bind(address).map_err(|e| DnsError::Listen(address, e))?;
+        let address = socket
+            .local_addr()
+            .map_err(|e| DnsError::Listen(address, e))?;
+        socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| DnsError::Listen(address, e))?;
+
+        let records = Arc::new(Mutex::new(HashMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = serve(socket, config.domain(), records.clone(), stop.clone());
+        Ok(Self {
+            config,
+            address,
+            records,
+            last_update: None,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the configuration the server was started with.
+    pub fn config(&self) -> &DnsConfig {
+        &self.config
+    }
+
+    /// Gets the address the server listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Updates the records from the service targets of the engine, at most once per interval.
+    pub fn poll(&mut self, engine: &Engine) {
+        if self
+            .last_update
+            .is_some_and(|last| last.elapsed() < UPDATE_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_update = Some(Instant::now());
+        let domain = self.config.domain();
+        let mut records = HashMap::new();
+        for (_, config, _) in engine.watched_targets() {
+            if config.r#type != ResourceType::Service {
+                continue;
+            }
+
+            let name = format!(
+                "{target}.{namespace}.svc.{domain}",
+                target = config.target,
+                namespace = config.namespace
+            );
+            records
+                .entry(name.to_ascii_lowercase())
+                .or_insert_with(|| local_addresses(&config.listen_addrs));
+        }
+
+        *self.records.lock().expect("lock is not poisoned") = records;
+    }
+}
+
+impl Drop for DnsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Gets the addresses a target can be reached at locally; unspecified addresses,
+/// e.g. `0.0.0.0`, are reached via the loopback address of their family.
+fn local_addresses(listen_addrs: &[String]) -> Vec<IpAddr> {
+    let (addresses, _) = preflight::listen_addresses(listen_addrs);
+    let mut local = Vec::new();
+    for ip in addresses {
+        let ip = match ip {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        if !local.contains(&ip) {
+            local.push(ip);
+        }
+    }
+    local
+}
+
+/// Answers the queries received on the socket until stopped.
+fn serve(
+    socket: UdpSocket,
+    domain: String,
+    records: Arc<Mutex<Records>>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0; 512];
+        while !stop.load(Ordering::SeqCst) {
+            let (read, client) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+
+            let records = records.lock().expect("lock is not poisoned");
+            if let Some(response) = answer(&buffer[..read], &domain, &records) {
+                socket.send_to(&response, client).ok();
+            }
+        }
+    })
+}
+
+/// Builds the response to a query; returns `None` for messages that aren't answered at all,
+/// e.g. responses or messages too short to contain a header.
+fn answer(query: &[u8], domain: &str, records: &Records) -> Option<Vec<u8>> {
+    if query.len() < 12 || query[2] & 0x80 != 0 {
+        return None;
+    }
+
+    let opcode = (query[2] >> 3) & 0x0f;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if opcode != 0 {
+        return Some(response(query, 12, RCODE_NOT_IMPLEMENTED, &[]));
+    }
+    if questions != 1 {
+        return Some(response(query, 12, RCODE_FORMAT_ERROR, &[]));
+    }
+
+    let Some((name, end)) = parse_question(query) else {
+        return Some(response(query, 12, RCODE_FORMAT_ERROR, &[]));
+    };
+    let record_type = u16::from_be_bytes([query[end - 4], query[end - 3]]);
+    let class = u16::from_be_bytes([query[end - 2], query[end - 1]]);
+
+    if !name.ends_with(&format!(".{domain}")) {
+        return Some(response(query, end, RCODE_REFUSED, &[]));
+    }
+
+    let Some(addresses) = records.get(&name) else {
+        return Some(response(query, end, RCODE_NAME_ERROR, &[]));
+    };
+
+    // Names of other types or classes exist, but have no records.
+    let answers: Vec<IpAddr> = addresses
+        .iter()
+        .copied()
+        .filter(|_| class == CLASS_IN)
+        .filter(|ip| match ip {
+            IpAddr::V4(_) => record_type == TYPE_A,
+            IpAddr::V6(_) => record_type == TYPE_AAAA,
+        })
+        .collect();
+    Some(response(query, end, 0, &answers))
+}
+
+/// Reads the name of the only question, lowercased and without the trailing dot,
+/// along with the offset of the end of the question.
+fn parse_question(query: &[u8]) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    loop {
+        let length = *query.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        // Compressed names don't occur in the question of a query.
+        if length > 63 {
+            return None;
+        }
+
+        let label = query.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += length;
+    }
+
+    let end = offset + 4;
+    (end <= query.len() && !labels.is_empty()).then(|| (labels.join("."), end))
+}
+
+/// Builds a response repeating the question up to `end`, followed by the answers.
+fn response(query: &[u8], end: usize, rcode: u8, answers: &[IpAddr]) -> Vec<u8> {
+    let mut response = Vec::with_capacity(end + answers.len() * 28);
+    response.extend_from_slice(&query[..2]);
+    // A response (QR), authoritative (AA), keeping the opcode and whether recursion was desired.
+    response.push(0x80 | (query[2] & 0x79) | 0x04);
+    response.push(rcode);
+
+    let questions: u16 = if end > 12 { 1 } else { 0 };
+    response.extend_from_slice(&questions.to_be_bytes());
+    response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(&query[12..end]);
+
+    for ip in answers {
+        // The name is a pointer to the name of the question.
+        response.extend_from_slice(&[0xc0, 0x0c]);
+        let (record_type, data) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        response.extend_from_slice(&record_type.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&TTL.to_be_bytes());
+        response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        response.extend_from_slice(&data);
+    }
+
+    response
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DnsError {
+    #[error("Unable to listen on {0}: {1}")]
+    Listen(SocketAddr, io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a query for the name with the record type.
+    fn query(name: &str, record_type: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&record_type.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn test_answer() {
+        let records = Records::from([(
+            "api.staging.svc.fwd".to_string(),
+            local_addresses(&["localhost".into()]),
+        )]);
+
+        let response = answer(&query("API.staging.svc.fwd", TYPE_A), "fwd", &records).unwrap();
+        assert_eq!(&response[..4], &[0x12, 0x34, 0x85, 0x00]);
+        assert_eq!(u16::from_be_bytes([response[6], response[7]]), 1);
+        assert_eq!(&response[response.len() - 4..], &[127, 0, 0, 1]);
+
+        let response = answer(&query("api.staging.svc.fwd", TYPE_AAAA), "fwd", &records).unwrap();
+        assert_eq!(
+            &response[response.len() - 16..],
+            &Ipv6Addr::LOCALHOST.octets()
+        );
+
+        let response = answer(&query("web.staging.svc.fwd", TYPE_A), "fwd", &records).unwrap();
+        assert_eq!(response[3], RCODE_NAME_ERROR);
+
+        let response = answer(&query("example.com", TYPE_A), "fwd", &records).unwrap();
+        assert_eq!(response[3], RCODE_REFUSED);
+    }
+
+    #[test]
+    fn test_local_addresses() {
+        assert_eq!(
+            local_addresses(&["0.0.0.0".into(), "127.0.0.1".into()]),
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Periodically compares the running targets against the live cluster, such that
+//! configurations that went stale are noticed before they break.
+
+use crate::config::{PortForwardConfig, ResourceType, Seconds};
+use crate::engine::Engine;
+use crate::kubectl::{ChildEvent, Kubectl, ShellError};
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A difference between the configuration of a target and the cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// The namespace of the target does not exist.
+    MissingNamespace(String),
+    /// The resource to forward to does not exist.
+    MissingResource(String),
+    /// The service does not expose a forwarded remote port.
+    UnexposedPort(String, u16),
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::MissingNamespace(namespace) => write!(f, "namespace {namespace} does not exist"),
+            Drift::MissingResource(resource) => write!(f, "{resource} does not exist"),
+            Drift::UnexposedPort(resource, port) => {
+                write!(f, "{resource} does not expose port {port}")
+            }
+        }
+    }
+}
+
+/// Compares a target against the cluster.
+pub fn check(kubectl: &Kubectl, target: &PortForwardConfig) -> Result<Vec<Drift>, ShellError> {
+    let context = target.context.as_ref();
+    let cluster = target.cluster.as_ref();
+
+    let namespace = format!("namespace/{}", target.namespace);
+    if kubectl
+        .get_resource(context, cluster, None, &namespace)?
+        .is_none()
+    {
+        return Ok(vec![Drift::MissingNamespace(target.namespace.clone())]);
+    }
+
+    let resource = format!("{}/{}", target.r#type.as_arg(), target.target);
+    let display_name = format!("{resource}.{namespace}", namespace = target.namespace);
+    let Some(live) = kubectl.get_resource(context, cluster, Some(&target.namespace), &resource)?
+    else {
+        return Ok(vec![Drift::MissingResource(display_name)]);
+    };
+
+    // Pods accept connections on undeclared ports; only services need to expose them.
+    if target.r#type != ResourceType::Service {
+        return Ok(Vec::new());
+    }
+
+    let exposed = service_ports(&live);
+    Ok(target
+        .ports
+        .iter()
+        .filter(|port| !exposed.contains(&port.remote))
+        .map(|port| Drift::UnexposedPort(display_name.clone(), port.remote))
+        .collect())
+}
+
+/// Extracts the ports of a service.
+fn service_ports(service: &Value) -> Vec<u16> {
+    service["spec"]["ports"]
+        .as_array()
+        .map(|ports| {
+            ports
+                .iter()
+                .filter_map(|port| port["port"].as_u64())
+                .filter_map(|port| u16::try_from(port).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs the drift checks of the running targets in the background, at most once per interval.
+pub struct DriftWatch {
+    kubectl: Kubectl,
+    out_tx: Sender<ChildEvent>,
+    interval: Duration,
+    last_check: Option<Instant>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DriftWatch {
+    pub fn new(kubectl: Kubectl, out_tx: Sender<ChildEvent>, interval: Seconds) -> Self {
+        Self {
+            kubectl,
+            out_tx,
+            interval: interval.into(),
+            last_check: None,
+            worker: None,
+        }
+    }
+
+    /// Starts checking the targets of the engine if the interval passed
+    /// and the previous checks are done.
+    pub fn poll(&mut self, engine: &Engine) {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < self.interval)
+            || self
+                .worker
+                .as_ref()
+                .is_some_and(|worker| !worker.is_finished())
+        {
+            return;
+        }
+
+        self.last_check = Some(Instant::now());
+        let kubectl = self.kubectl.clone();
+        let out_tx = self.out_tx.clone();
+        let targets = engine.watched_targets();
+        self.worker = Some(thread::spawn(move || {
+            for (id, config, status) in targets {
+                let drift: Vec<String> = match check(&kubectl, &config) {
+                    Ok(drift) => drift.iter().map(Drift::to_string).collect(),
+                    // The cluster may just be unreachable; kubectl reports that on its own.
+                    Err(_) => continue,
+                };
+
+                if status.drift(drift.clone()) {
+                    out_tx.send(ChildEvent::Drift(id, drift)).ok();
+                }
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_ports() {
+        let service = serde_json::json!({
+            "spec": {
+                "ports": [
+                    { "name": "http", "port": 80, "targetPort": 8080 },
+                    { "name": "grpc", "port": 9090 }
+                ]
+            }
+        });
+
+        assert_eq!(service_ports(&service), vec![80, 9090]);
+        assert!(service_ports(&serde_json::json!({})).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Tells whether the traffic on a forwarded port is encrypted with TLS, such that users know
+//! whether to connect with e.g. `https://` or `http://`. The first bytes clients send through
+//! the proxy reveal it passively; the `detect_tls` setting actively probes each port.
+
+use crate::config::{ConfigId, PortForwardConfig};
+use crate::health;
+use crate::kubectl::{ChildEvent, StreamSource};
+use crate::status::StatusHandle;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for servers that speak first, e.g. SSH or database servers.
+const GREETING_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How long to wait for the response to the `ClientHello`.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Whether the traffic on a port is encrypted with TLS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encryption {
+    Tls,
+    Plaintext,
+}
+
+impl Display for Encryption {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Encryption::Tls => write!(f, "tls"),
+            Encryption::Plaintext => write!(f, "plain"),
+        }
+    }
+}
+
+impl Encryption {
+    /// Classifies the first bytes sent on a connection by either side; a TLS connection
+    /// starts with a handshake record, or an alert if the server rejects the handshake.
+    pub fn detect(first_bytes: &[u8]) -> Option<Self> {
+        match first_bytes {
+            [] => None,
+            [0x15 | 0x16] => None,
+            [0x15 | 0x16, 0x03, ..] => Some(Encryption::Tls),
+            _ => Some(Encryption::Plaintext),
+        }
+    }
+}
+
+/// Probes the local ports of a forwarding target in the background, recording the outcome
+/// in its status and reporting ports that speak TLS as output of the target.
+pub fn probe_ports(
+    id: ConfigId,
+    config: &PortForwardConfig,
+    status: StatusHandle,
+    out_tx: Sender<ChildEvent>,
+) {
+    let ip = health::local_addresses(&config.listen_addrs)[0];
+    let configured: Vec<_> = config.ports.iter().map(|port| port.local).collect();
+    thread::spawn(move || {
+        let local_ports = status.snapshot().local_ports;
+        for (index, local) in configured.into_iter().enumerate() {
+            let Some(port) = local.or_else(|| local_ports.get(index).copied()) else {
+                continue;
+            };
+
+            let Ok(Some(encryption)) = status.probe(|| probe(SocketAddr::new(ip, port))) else {
+                continue;
+            };
+
+            status.encryption(port, encryption);
+            if encryption == Encryption::Tls {
+                let message =
+                    format!("Port {port} speaks TLS - connect with e.g. https://localhost:{port}");
+                out_tx
+                    .send(ChildEvent::Output(id, StreamSource::StdOut, message))
+                    .ok();
+            }
+        }
+    });
+}
+
+/// Determines whether the server on a port speaks TLS by starting a handshake.
+///
+/// Returns `None` if the server neither spoke first nor answered the `ClientHello`.
+fn probe(address: SocketAddr) -> io::Result<Option<Encryption>> {
+    let mut stream = health::connect(address)?;
+
+    // TLS servers never speak first.
+    stream.set_read_timeout(Some(GREETING_TIMEOUT))?;
+    if let Some(greeting) = read_first_bytes(&mut stream)? {
+        return Ok(Encryption::detect(&greeting).or(Some(Encryption::Plaintext)));
+    }
+
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.write_all(&client_hello())?;
+    Ok(read_first_bytes(&mut stream)?.and_then(|response| Encryption::detect(&response)))
+}
+
+/// Reads the first bytes sent by the server, or `None` if it sent nothing in time.
+fn read_first_bytes(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut buffer = [0; 5];
+    match stream.read(&mut buffer) {
+        Ok(0) => Ok(None),
+        Ok(read) => Ok(Some(buffer[..read].to_vec())),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Builds a TLS `ClientHello` for `localhost` that common servers respond to, offering
+/// TLS 1.2 cipher suites as well as the TLS 1.3 ones.
+fn client_hello() -> Vec<u8> {
+    let ciphers: &[u16] = &[
+        0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0x009c, 0x002f,
+    ];
+    let host = b"localhost";
+
+    let mut extensions = Vec::new();
+    // server_name
+    let name_length = host.len() as u16;
+    extension(&mut extensions, 0x0000, &{
+        let mut data = (name_length + 3).to_be_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(&name_length.to_be_bytes());
+        data.extend_from_slice(host);
+        data
+    });
+    // supported_groups: x25519, secp256r1, secp384r1
+    extension(
+        &mut extensions,
+        0x000a,
+        &[0, 6, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18],
+    );
+    // ec_point_formats: uncompressed
+    extension(&mut extensions, 0x000b, &[1, 0]);
+    // signature_algorithms: ecdsa_secp256r1_sha256, rsa_pss_rsae_sha256, rsa_pkcs1_sha256
+    extension(
+        &mut extensions,
+        0x000d,
+        &[0, 6, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01],
+    );
+
+    let mut hello = vec![0x03, 0x03];
+    hello.extend_from_slice(&[0x6b; 32]);
+    hello.push(0);
+    hello.extend_from_slice(&((ciphers.len() * 2) as u16).to_be_bytes());
+    for cipher in ciphers {
+        hello.extend_from_slice(&cipher.to_be_bytes());
+    }
+    hello.extend_from_slice(&[1, 0]);
+    hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+    hello.extend_from_slice(&extensions);
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// Appends an extension of the `ClientHello`.
+fn extension(extensions: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    extensions.extend_from_slice(&kind.to_be_bytes());
+    extensions.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    extensions.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Encryption::detect(&client_hello()), Some(Encryption::Tls));
+        assert_eq!(
+            Encryption::detect(b"\x15\x03\x03\x00\x02"),
+            Some(Encryption::Tls)
+        );
+        assert_eq!(
+            Encryption::detect(b"GET / HTTP/1.1"),
+            Some(Encryption::Plaintext)
+        );
+        assert_eq!(Encryption::detect(b""), None);
+    }
+
+    #[test]
+    fn test_probe() {
+        // A server answering the handshake with an alert.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0; 5];
+            stream.read_exact(&mut header).unwrap();
+            stream.write_all(b"\x15\x03\x03\x00\x02\x02\x28").unwrap();
+        });
+        assert_eq!(probe(address).unwrap(), Some(Encryption::Tls));
+
+        // A server speaking first.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        });
+        assert_eq!(probe(address).unwrap(), Some(Encryption::Plaintext));
+    }
+}
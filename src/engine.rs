@@ -0,0 +1,740 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::backoff::{Backoff, BackoffStore};
+use crate::config::{ConfigId, OperationalConfig, Port, PortForwardConfig};
+use crate::dependencies;
+use crate::kubectl::{
+    ChildEvent, ForwardCommand, ForwardContext, ForwardSummary, Kubectl, Rebind, SpawnError,
+};
+use crate::proxy::ProxyError;
+use crate::session::Session;
+use crate::shared::{self, PortAllocator};
+use crate::signals::ShutdownSignal;
+use crate::status::{StatusHandle, TargetStatus};
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long to wait for a forward to move its local port.
+const REBIND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A port-forward managed by the [`Engine`].
+struct Forward {
+    id: ConfigId,
+    operational: OperationalConfig,
+    config: PortForwardConfig,
+    /// The user that requested the forward in a shared session;
+    /// `None` for forwards started from the session's own configuration.
+    owner: Option<String>,
+    /// The statistics of previous runs of this forward, e.g. before it was remapped.
+    carried: ForwardSummary,
+    status: StatusHandle,
+    /// Stops this forward only.
+    stop: ShutdownSignal,
+    /// Sends commands to the forward's thread.
+    commands: Sender<ForwardCommand>,
+    handle: JoinHandle<Result<ForwardSummary, anyhow::Error>>,
+}
+
+impl Forward {
+    /// Determines whether the forward was given up after exceeding its retries.
+    ///
+    /// Failed forwards are kept around such that they show up in the status
+    /// and can be restarted.
+    fn is_failed(&self) -> bool {
+        self.handle.is_finished() && !self.stop.is_requested() && self.status.is_failed()
+    }
+
+    /// Waits for the forward's thread to finish.
+    fn join(self) -> FinishedForward {
+        let result = self
+            .handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Port-forward thread panicked")))
+            .map(|summary| summary.combine(self.carried));
+        FinishedForward {
+            id: self.id,
+            name: self.config.name,
+            result,
+        }
+    }
+}
+
+/// The outcome of a finished port-forward.
+pub struct FinishedForward {
+    pub id: ConfigId,
+    pub name: Option<String>,
+    pub result: Result<ForwardSummary, anyhow::Error>,
+}
+
+/// The changes applied by [`Engine::reconcile`].
+#[derive(Debug, Default)]
+pub struct ReconcileResult {
+    /// The forwards that were stopped.
+    pub removed: Vec<(ConfigId, PortForwardConfig)>,
+    /// The forwards that were started.
+    pub added: Vec<(ConfigId, PortForwardConfig)>,
+}
+
+/// The changes a new set of targets makes to the running forwards, see [`Engine::diff`].
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    /// The targets that will be started.
+    pub added: Vec<PortForwardConfig>,
+    /// The forwards that will be stopped.
+    pub removed: Vec<(ConfigId, PortForwardConfig)>,
+    /// The forwards that will be restarted with a new configuration, along with the
+    /// names of the changed fields.
+    pub changed: Vec<(ConfigId, PortForwardConfig, Vec<&'static str>)>,
+}
+
+impl ConfigDiff {
+    /// Compares the configurations of the running forwards with the new targets.
+    ///
+    /// A target that isn't equivalent to any running forward but refers to the same
+    /// target as one of them, see [`PortForwardConfig::is_same_identity`], is a change of
+    /// that forward rather than an addition.
+    fn new(running: &[(ConfigId, &PortForwardConfig)], targets: &[PortForwardConfig]) -> Self {
+        let mut added: Vec<_> = targets.iter().collect();
+        let mut stale = Vec::new();
+        for (id, config) in running {
+            match added.iter().position(|target| target.is_equivalent(config)) {
+                Some(index) => {
+                    added.remove(index);
+                }
+                None => stale.push((*id, *config)),
+            }
+        }
+
+        let mut diff = Self::default();
+        for (id, config) in stale {
+            match added
+                .iter()
+                .position(|target| target.is_same_identity(config))
+            {
+                Some(index) => {
+                    let target = added.remove(index);
+                    let fields = config.changed_fields(target);
+                    diff.changed.push((id, target.clone(), fields));
+                }
+                None => diff.removed.push((id, config.clone())),
+            }
+        }
+
+        diff.added = added.into_iter().cloned().collect();
+        diff
+    }
+
+    /// Determines whether the targets are the same as the running forwards.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Determines whether running forwards are stopped or restarted.
+    pub fn is_destructive(&self) -> bool {
+        !self.removed.is_empty() || !self.changed.is_empty()
+    }
+}
+
+/// Starts, stops and keeps track of the running port-forwards.
+pub struct Engine {
+    kubectl: Kubectl,
+    out_tx: Sender<ChildEvent>,
+    shutdown: ShutdownSignal,
+    session: Session,
+    backoff: BackoffStore,
+    forwards: Vec<Forward>,
+    finished: Vec<FinishedForward>,
+    /// The user running the session, who may control all forwards.
+    admin: String,
+    /// Hands out local ports to the users of a shared session.
+    ports: PortAllocator,
+}
+
+impl Engine {
+    pub fn new(
+        kubectl: Kubectl,
+        out_tx: Sender<ChildEvent>,
+        shutdown: ShutdownSignal,
+        session: Session,
+        backoff: BackoffStore,
+    ) -> Self {
+        Self {
+            kubectl,
+            out_tx,
+            shutdown,
+            session,
+            backoff,
+            forwards: Vec::new(),
+            finished: Vec::new(),
+            admin: shared::current_user(),
+            ports: PortAllocator::default(),
+        }
+    }
+
+    /// Starts a new port-forward and returns its ID.
+    pub fn start(
+        &mut self,
+        operational: &OperationalConfig,
+        config: PortForwardConfig,
+    ) -> Result<ConfigId, SpawnError> {
+        self.start_owned(operational, config, None)
+    }
+
+    fn start_owned(
+        &mut self,
+        operational: &OperationalConfig,
+        config: PortForwardConfig,
+        owner: Option<String>,
+    ) -> Result<ConfigId, SpawnError> {
+        // Only a missing kubectl is subject to `on_spawn_error`; once started,
+        // the forward retries failures to spawn its processes like any other failure.
+        self.kubectl.ensure_spawnable()?;
+
+        // Configured targets keep the ID assigned when loading the configuration; the targets
+        // of users of a shared session are given one that no running forward uses.
+        let id = match config.id.filter(|_| owner.is_none()) {
+            Some(id) => id,
+            None => ConfigId::of(&config, |id| {
+                self.forwards
+                    .iter()
+                    .any(|forward| forward.id == id && !forward.stop.is_requested())
+            }),
+        };
+        let status = StatusHandle::new(id, &config, owner.clone());
+        let forward = self.spawn(
+            id,
+            operational.clone(),
+            config,
+            owner,
+            ForwardSummary::default(),
+            status,
+        );
+        self.forwards.push(forward);
+        Ok(id)
+    }
+
+    /// Starts port-forwards on behalf of a user of a shared session.
+    ///
+    /// Each port is assigned a local port from the block reserved for the user;
+    /// the configured local ports are ignored.
+    pub fn attach(
+        &mut self,
+        operational: &OperationalConfig,
+        user: &str,
+        targets: Vec<PortForwardConfig>,
+    ) -> Result<Vec<(ConfigId, PortForwardConfig)>, EngineError> {
+        let block = self
+            .ports
+            .block(user, &operational.shared)
+            .ok_or_else(|| EngineError::PortsExhausted(user.to_string()))?;
+
+        let mut used = self.local_ports();
+
+        // Assign all ports before starting anything, such that either all or no targets start.
+        let mut targets = targets;
+        for target in &mut targets {
+            for port in &mut target.ports {
+                let local = block
+                    .clone()
+                    .find(|candidate| !used.contains(candidate))
+                    .ok_or_else(|| EngineError::PortsExhausted(user.to_string()))?;
+                used.push(local);
+                port.local = Some(local);
+            }
+        }
+
+        let mut started = Vec::new();
+        for target in targets {
+            let id = self.start_owned(operational, target.clone(), Some(user.to_string()))?;
+            started.push((id, target));
+        }
+
+        Ok(started)
+    }
+
+    /// Stops all forwards requested by the user of a shared session.
+    pub fn release(&self, user: &str) -> Vec<ConfigId> {
+        self.forwards
+            .iter()
+            .filter(|forward| !forward.stop.is_requested())
+            .filter(|forward| forward.owner.as_deref() == Some(user))
+            .map(|forward| {
+                forward.stop.stop();
+                forward.id
+            })
+            .collect()
+    }
+
+    /// Determines whether the user may control all forwards, e.g. shut down the session.
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.admin == user
+    }
+
+    fn spawn(
+        &self,
+        id: ConfigId,
+        operational: OperationalConfig,
+        config: PortForwardConfig,
+        owner: Option<String>,
+        carried: ForwardSummary,
+        status: StatusHandle,
+    ) -> Forward {
+        let stop = self.shutdown.child();
+        let (commands, commands_rx) = mpsc::channel();
+        let running = self
+            .forwards
+            .iter()
+            .filter(|forward| !forward.stop.is_requested())
+            .map(|forward| (&forward.config, &forward.status));
+        let dependencies = dependencies::resolve(&config, running);
+        let handle = self.kubectl.port_forward(
+            operational.clone(),
+            config.clone(),
+            ForwardContext {
+                id,
+                out_tx: self.out_tx.clone(),
+                shutdown: stop.clone(),
+                session: self.session.clone(),
+                status: status.clone(),
+                commands: commands_rx,
+                commands_tx: commands.clone(),
+                backoff: self.backoff.clone(),
+                dependencies,
+            },
+        );
+
+        Forward {
+            id,
+            operational,
+            config,
+            owner,
+            carried,
+            status,
+            stop,
+            commands,
+            handle,
+        }
+    }
+
+    /// Finds the running forward selected by its ID (e.g. `#3fa2`), name or target
+    /// that the user is allowed to control.
+    ///
+    /// Forwards that are about to stop are ignored.
+    fn find(&self, selector: &str, user: &str) -> Result<usize, EngineError> {
+        let index = self.find_any(selector)?;
+        let forward = &self.forwards[index];
+        if !self.is_admin(user) && forward.owner.as_deref() != Some(user) {
+            return Err(EngineError::NotPermitted(forward.id));
+        }
+
+        Ok(index)
+    }
+
+    fn find_any(&self, selector: &str) -> Result<usize, EngineError> {
+        let running = self
+            .forwards
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| !f.stop.is_requested());
+
+        if let Ok(id) = selector.parse::<ConfigId>() {
+            if let Some((index, _)) = running.clone().find(|(_, f)| f.id == id) {
+                return Ok(index);
+            }
+        }
+
+        let mut matches = running.filter(|(_, f)| {
+            f.config.name.as_deref() == Some(selector) || f.config.target == selector
+        });
+
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Ok(index),
+            (Some(_), Some(_)) => Err(EngineError::AmbiguousTarget(selector.to_string())),
+            (None, _) => Err(EngineError::TargetNotFound(selector.to_string())),
+        }
+    }
+
+    /// Gets the ID of the running forward selected by its ID, name or target.
+    pub fn resolve(&self, selector: &str, user: &str) -> Result<ConfigId, EngineError> {
+        Ok(self.forwards[self.find(selector, user)?].id)
+    }
+
+    /// Stops a running forward; it is not restarted until the configuration is reloaded.
+    pub fn stop(&self, selector: &str, user: &str) -> Result<ConfigId, EngineError> {
+        let forward = &self.forwards[self.find(selector, user)?];
+        forward.stop.stop();
+        Ok(forward.id)
+    }
+
+    /// Terminates the kubectl process of a running forward and starts it again immediately.
+    ///
+    /// Forwards that were given up after exceeding their retries are started anew.
+    pub fn restart(&mut self, selector: &str, user: &str) -> Result<ConfigId, EngineError> {
+        let index = self.find(selector, user)?;
+        let forward = &self.forwards[index];
+        if !forward.is_failed() {
+            forward
+                .commands
+                .send(ForwardCommand::Restart)
+                .map_err(|_| EngineError::TargetNotFound(selector.to_string()))?;
+            return Ok(forward.id);
+        }
+
+        let forward = self.forwards.remove(index);
+        let (id, operational, config, owner, status) = (
+            forward.id,
+            forward.operational.clone(),
+            forward.config.clone(),
+            forward.owner.clone(),
+            forward.status.clone(),
+        );
+        let carried = match forward.join().result {
+            Ok(summary) => summary,
+            Err(e) => return Err(EngineError::Stopped(id, e)),
+        };
+
+        // An explicit restart grants a new set of retries.
+        self.backoff.record(&config, Backoff::default());
+        let forward = self.spawn(id, operational, config, owner, carried, status);
+        self.forwards.insert(index, forward);
+        Ok(id)
+    }
+
+    /// Changes the local port that a remote port of a running forward is exposed on.
+    ///
+    /// The proxy of the forward moves to the new local port while kubectl keeps running;
+    /// connections accepted on the previous port are kept open. Users of a shared session
+    /// can only move ports within the block reserved for them.
+    /// Returns the ID of the forward along with the previous and the new mapping.
+    pub fn remap(
+        &mut self,
+        selector: &str,
+        port: Port,
+        user: &str,
+    ) -> Result<(ConfigId, Port, Port), EngineError> {
+        let index = self.find(selector, user)?;
+        let forward = &self.forwards[index];
+        let id = forward.id;
+
+        // Only the proxy can move the local port without restarting kubectl; with TLS, it
+        // listens behind the TLS terminator.
+        let proxied =
+            forward.config.proxy == Some(true) || forward.config.load_balance == Some(true);
+        if !proxied || forward.config.tls.is_some() {
+            return Err(EngineError::NotRemappable(id));
+        }
+
+        let mut local = port.local;
+        if !self.is_admin(user) {
+            let block = self
+                .ports
+                .block(user, &forward.operational.shared)
+                .ok_or_else(|| EngineError::PortsExhausted(user.to_string()))?;
+            match local {
+                Some(local) if !block.contains(&local) => {
+                    return Err(EngineError::OutsideOfBlock(local, block));
+                }
+                Some(_) => {}
+                None => {
+                    let used = self.local_ports();
+                    let free = block
+                        .clone()
+                        .find(|candidate| !used.contains(candidate))
+                        .ok_or_else(|| EngineError::PortsExhausted(user.to_string()))?;
+                    local = Some(free);
+                }
+            }
+        }
+
+        if let Some(local) = local {
+            let conflict = self
+                .forwards
+                .iter()
+                .find(|f| f.id != id && f.config.ports.iter().any(|p| p.local == Some(local)));
+            if let Some(other) = conflict {
+                return Err(EngineError::LocalPortInUse(other.id, local));
+            }
+        }
+
+        let forward = &mut self.forwards[index];
+        // Remote ports may be given by the name they were configured with.
+        let Some(position) =
+            forward.config.ports.iter().position(|p| {
+                p.remote == port.remote || (port.name.is_some() && p.name == port.name)
+            })
+        else {
+            let remote = port.name.clone().unwrap_or(port.remote.to_string());
+            return Err(EngineError::PortNotForwarded(id, remote));
+        };
+
+        let (reply, response) = mpsc::channel();
+        forward
+            .commands
+            .send(ForwardCommand::Rebind(Rebind {
+                index: position,
+                local,
+                reply,
+            }))
+            .map_err(|_| EngineError::TargetNotFound(selector.to_string()))?;
+        let local = match response.recv_timeout(REBIND_TIMEOUT) {
+            Ok(Ok(local)) => local,
+            Ok(Err(e)) => return Err(EngineError::Rebind(id, e)),
+            Err(_) => return Err(EngineError::NotResponding(id)),
+        };
+
+        let existing = &mut forward.config.ports[position];
+        let previous = existing.clone();
+        existing.local = Some(local);
+        Ok((id, previous, existing.clone()))
+    }
+
+    /// Gets the local ports used by any of the forwards.
+    fn local_ports(&self) -> Vec<u16> {
+        self.forwards
+            .iter()
+            .flat_map(|forward| forward.config.ports.iter().filter_map(|port| port.local))
+            .collect()
+    }
+
+    /// Determines how [`reconcile`](Self::reconcile) would change the running forwards,
+    /// without applying the targets.
+    pub fn diff(&self, targets: &[PortForwardConfig]) -> ConfigDiff {
+        let running: Vec<_> = self
+            .forwards
+            .iter()
+            .filter(|forward| forward.owner.is_none())
+            .map(|forward| (forward.id, &forward.config))
+            .collect();
+        ConfigDiff::new(&running, targets)
+    }
+
+    /// Reports the changes of a reload as events of the forwards they affect.
+    pub fn announce(&self, diff: &ConfigDiff) {
+        for (id, _) in &diff.removed {
+            self.out_tx.send(ChildEvent::Removed(*id)).ok();
+        }
+        for (id, _, fields) in &diff.changed {
+            self.out_tx
+                .send(ChildEvent::Reconfigured(*id, fields.clone()))
+                .ok();
+        }
+    }
+
+    /// Applies a new set of targets: Forwards that are no longer configured
+    /// are stopped, new ones are started and unchanged ones keep running.
+    ///
+    /// Forwards requested by users of a shared session are not affected.
+    pub fn reconcile(
+        &mut self,
+        operational: &OperationalConfig,
+        mut targets: Vec<PortForwardConfig>,
+    ) -> Result<ReconcileResult, SpawnError> {
+        let mut result = ReconcileResult::default();
+
+        for forward in self.forwards.iter().filter(|f| f.owner.is_none()) {
+            match targets
+                .iter()
+                .position(|target| target.is_equivalent(&forward.config))
+            {
+                Some(index) => {
+                    targets.remove(index);
+                }
+                None => {
+                    forward.stop.stop();
+                    result.removed.push((forward.id, forward.config.clone()));
+                }
+            }
+        }
+
+        for target in targets {
+            let id = self.start(operational, target.clone())?;
+            result.added.push((id, target));
+        }
+
+        Ok(result)
+    }
+
+    /// Gets the current status of all running forwards.
+    pub fn status(&self) -> Vec<TargetStatus> {
+        self.forwards
+            .iter()
+            .map(|forward| forward.status.snapshot())
+            .collect()
+    }
+
+    /// Gets the configurations of the forwards that are not about to stop,
+    /// e.g. to compare them against the cluster.
+    pub fn watched_targets(&self) -> Vec<(ConfigId, PortForwardConfig, StatusHandle)> {
+        self.forwards
+            .iter()
+            .filter(|forward| !forward.stop.is_requested())
+            .map(|forward| (forward.id, forward.config.clone(), forward.status.clone()))
+            .collect()
+    }
+
+    /// Determines whether all forwards have finished.
+    pub fn is_finished(&self) -> bool {
+        self.forwards
+            .iter()
+            .all(|forward| forward.handle.is_finished())
+    }
+
+    /// Collects the outcome of all forwards that have finished.
+    pub fn collect_finished(&mut self) {
+        let (finished, running) = self
+            .forwards
+            .drain(..)
+            .partition(|forward| forward.handle.is_finished() && !forward.is_failed());
+        self.forwards = running;
+
+        self.finished
+            .extend(finished.into_iter().map(Forward::join));
+    }
+
+    /// Waits for all forwards to finish and returns their outcomes; the forwards that finished
+    /// before come first, followed by the others in the order they were started.
+    pub fn join(mut self) -> Vec<FinishedForward> {
+        let forwards = self.forwards.drain(..);
+        self.finished.extend(forwards.map(Forward::join));
+        std::mem::take(&mut self.finished)
+    }
+}
+
+impl Drop for Engine {
+    /// Stops the forwards that were not joined, e.g. when returning early on an error,
+    /// such that their kubectl processes don't outlive the session.
+    fn drop(&mut self) {
+        for forward in &self.forwards {
+            forward.stop.stop();
+        }
+        for forward in self.forwards.drain(..) {
+            forward.join();
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EngineError {
+    #[error("No running target matches `{0}`")]
+    TargetNotFound(String),
+    #[error("More than one running target matches `{0}`, please select it by its ID")]
+    AmbiguousTarget(String),
+    #[error("{0} does not forward the remote port {1}")]
+    PortNotForwarded(ConfigId, String),
+    #[error("The local port {1} is already used by {0}")]
+    LocalPortInUse(ConfigId, u16),
+    #[error("{0} can only be remapped with `proxy: true` and without `tls`")]
+    NotRemappable(ConfigId),
+    #[error("The local port {0} is outside of your block of ports {}-{}", .1.start(), .1.end())]
+    OutsideOfBlock(u16, RangeInclusive<u16>),
+    #[error("Unable to remap {0}: {1}")]
+    Rebind(ConfigId, ProxyError),
+    #[error("{0} did not respond in time")]
+    NotResponding(ConfigId),
+    #[error("{0} failed while stopping: {1}")]
+    Stopped(ConfigId, anyhow::Error),
+    #[error("You are not allowed to control {0}")]
+    NotPermitted(ConfigId),
+    #[error("No more local ports are available for user {0}")]
+    PortsExhausted(String),
+    #[error(transparent)]
+    StartFailed(#[from] SpawnError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::is_process_alive;
+    use std::fs;
+    use std::time::Instant;
+
+    fn target(yaml: &str) -> PortForwardConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let api = target("{ name: api, target: api, ports: [\"8080:80\"] }");
+        let web = target("{ target: web, ports: [\"8081:80\"] }");
+        let db = target("{ target: db, ports: [\"5432\"] }");
+        let running = [(ConfigId::from(0), &api), (ConfigId::from(1), &web)];
+
+        let mut moved = api.clone();
+        moved.ports = target("{ target: api, ports: [\"9090:80\"] }").ports;
+        let diff = ConfigDiff::new(&running, &[moved, db.clone()]);
+        assert_eq!(diff.added, vec![db]);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].0, ConfigId::from(1));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].2, vec!["ports"]);
+        assert!(diff.is_destructive());
+
+        let diff = ConfigDiff::new(&running, &[web.clone(), api.clone()]);
+        assert!(diff.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_drop_stops_forwards() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A kubectl that records its process ID and keeps running like a port-forward.
+        let dir = std::env::temp_dir().join(format!("k8sfwd-test-engine-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pid_file = dir.join("pid");
+        let script = dir.join("kubectl");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho $$ > {pid}\nexec sleep 60\n",
+                pid = pid_file.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let kubectl = Kubectl::new(Some(script.to_str().unwrap().parse().unwrap())).unwrap();
+        let (out_tx, _out_rx) = mpsc::channel();
+        let session = Session::new();
+        let mut engine = Engine::new(
+            kubectl.clone(),
+            out_tx,
+            ShutdownSignal::default(),
+            session.clone(),
+            BackoffStore::default(),
+        );
+        engine
+            .start(
+                &OperationalConfig::default(),
+                target("{ target: api, ports: [\"80\"] }"),
+            )
+            .unwrap();
+
+        let started = Instant::now();
+        let pid = loop {
+            if let Some(pid) = fs::read_to_string(&pid_file)
+                .ok()
+                .and_then(|pid| pid.trim().parse::<u32>().ok())
+            {
+                break pid;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "kubectl was not started"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        assert!(is_process_alive(pid));
+
+        // Returning early, e.g. on a spawn error, must not leave the kubectl processes behind.
+        drop(engine);
+        assert!(!is_process_alive(pid));
+
+        session.teardown(&kubectl);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,424 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Periodically connects to the forwarded local ports, such that dead tunnels are noticed:
+//! kubectl frequently keeps running with a broken connection to the pod, and only a real
+//! connection attempt reveals it. Optionally, an HTTP endpoint is requested instead.
+
+use crate::config::{ConfigId, HealthCheckConfig, PortForwardConfig, Seconds};
+use crate::kubectl::{ChildEvent, ForwardCommand, StreamSource};
+use crate::status::StatusHandle;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long to wait for a probe to connect.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a probe connection must stay open to count as healthy.
+const HOLD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long to wait for the response to an HTTP health check.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interval at which idle tunnels are looked for.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A running health check of a port-forward; stops checking when dropped.
+pub struct HealthCheck {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HealthCheck {
+    /// Starts checking the local ports of the target while it is forwarding.
+    ///
+    /// Once the configured number of consecutive checks failed, the forward is restarted
+    /// through its own command channel and the check ends. Returns `None` if the port
+    /// to check is not forwarded.
+    pub fn start(
+        id: ConfigId,
+        config: &HealthCheckConfig,
+        target: &PortForwardConfig,
+        status: StatusHandle,
+        out_tx: Sender<ChildEvent>,
+        commands: Sender<ForwardCommand>,
+    ) -> Option<Self> {
+        let Some(ports) = ports_to_check(config, target) else {
+            let message = format!(
+                "The health check port {port} is not forwarded - not checking",
+                port = config.port.unwrap_or_default()
+            );
+            out_tx
+                .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                .ok();
+            return None;
+        };
+
+        let interval = config.interval();
+        let threshold = config.failure_threshold();
+        let http = config.http_path().map(str::to_string);
+        let address = probe_address(&target.listen_addrs);
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            let mut failures = 0;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !status.is_forwarding() {
+                    failures = 0;
+                    continue;
+                }
+
+                // Ports without a fixed local port are known once kubectl reported them.
+                let local_ports = status.snapshot().local_ports;
+                let result = ports.iter().try_for_each(|(index, local)| {
+                    let Some(port) = local.or_else(|| local_ports.get(*index).copied()) else {
+                        return Ok(());
+                    };
+
+                    let stream = status.probe(|| connect(SocketAddr::new(address, port)));
+                    match &http {
+                        Some(path) => stream
+                            .and_then(|stream| probe_http(stream, path))
+                            .map_err(|e| format!("GET {path} on port {port} failed: {e}")),
+                        None => stream
+                            .and_then(probe)
+                            .map_err(|e| format!("port {port} is not reachable: {e}")),
+                    }
+                });
+
+                status.healthy(result.is_ok());
+                match result {
+                    Ok(()) => failures = 0,
+                    Err(message) => {
+                        failures += 1;
+                        if failures < threshold {
+                            continue;
+                        }
+
+                        let message = format!("Health check failed {failures} times: {message}");
+                        status.error(message.as_str());
+                        out_tx.send(ChildEvent::Unhealthy(id, message)).ok();
+                        commands.send(ForwardCommand::Restart).ok();
+                        break;
+                    }
+                }
+            }
+        });
+
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for HealthCheck {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// A running watch for idle tunnels of a port-forward; stops watching when dropped.
+///
+/// The kubelet closes port-forward streams that carried no connection for a while, after which
+/// kubectl keeps running but forwards nothing. Restarting kubectl before that happens, or right
+/// after it did, replaces the dead tunnel with a new one.
+pub struct IdleWatch {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl IdleWatch {
+    /// Starts watching the target while it is forwarding.
+    ///
+    /// Once kubectl didn't handle a connection for the timeout, the forward is restarted
+    /// through its own command channel and the watch ends. Returns `None` for a timeout of zero.
+    pub fn start(
+        id: ConfigId,
+        timeout: Seconds,
+        status: StatusHandle,
+        out_tx: Sender<ChildEvent>,
+        commands: Sender<ForwardCommand>,
+    ) -> Option<Self> {
+        let timeout = Duration::from(timeout);
+        if timeout.is_zero() {
+            return None;
+        }
+
+        let interval = IDLE_CHECK_INTERVAL.min(timeout);
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !status.is_forwarding() {
+                    continue;
+                }
+
+                let Some(idle) = status.idle_time().filter(|idle| *idle >= timeout) else {
+                    continue;
+                };
+
+                let message = format!(
+                    "No connections for {idle} sec - restarting kubectl before the tunnel is closed for being idle",
+                    idle = idle.as_secs()
+                );
+                out_tx
+                    .send(ChildEvent::Output(id, StreamSource::StdOut, message))
+                    .ok();
+                commands.send(ForwardCommand::Restart).ok();
+                break;
+            }
+        });
+
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for IdleWatch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Connects to a local port.
+pub fn connect(address: SocketAddr) -> io::Result<TcpStream> {
+    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+}
+
+/// Checks that a connection to a local port isn't closed right away,
+/// which is what kubectl does when it can't reach the pod.
+fn probe(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(HOLD_TIMEOUT))?;
+
+    let mut buffer = [0; 1];
+    match stream.read(&mut buffer) {
+        Ok(0) => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "the connection was closed",
+        )),
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Requests the path via HTTP and checks for a successful (2xx) or redirecting (3xx) status.
+fn probe_http(mut stream: TcpStream, path: &str) -> io::Result<()> {
+    let port = stream.peer_addr()?.port();
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    };
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: localhost:{port}\r\nUser-Agent: k8sfwd\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match parse_status_code(&status_line) {
+        Some(code) if (200..400).contains(&code) => Ok(()),
+        Some(code) => Err(io::Error::other(format!("responded with status {code}"))),
+        None if status_line.is_empty() => Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "the connection was closed",
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the response is not HTTP",
+        )),
+    }
+}
+
+/// Extracts the status code from an HTTP status line, e.g. `HTTP/1.1 200 OK`.
+fn parse_status_code(status_line: &str) -> Option<u16> {
+    let mut parts = status_line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+/// Determines the ports to check as their index in the target's ports and, if fixed, their local port.
+///
+/// Returns `None` if the configured port is not forwarded.
+fn ports_to_check(
+    config: &HealthCheckConfig,
+    target: &PortForwardConfig,
+) -> Option<Vec<(usize, Option<u16>)>> {
+    let ports = target.ports.iter().map(|port| port.local).enumerate();
+    match (config.port, config.http_path()) {
+        (Some(remote), _) => {
+            let index = target.ports.iter().position(|port| port.remote == remote)?;
+            Some(vec![(index, target.ports[index].local)])
+        }
+        (None, Some(_)) => Some(ports.take(1).collect()),
+        (None, None) => Some(ports.collect()),
+    }
+}
+
+/// Picks the address to connect to from the addresses kubectl listens on.
+fn probe_address(listen_addrs: &[String]) -> IpAddr {
+    local_addresses(listen_addrs)[0]
+}
+
+/// Gets the addresses to connect to in order to reach the addresses kubectl listens on.
+///
+/// Unspecified addresses are reached via the loopback address of their family, and
+/// `localhost` (the default), on which kubectl listens on both families, via `127.0.0.1`.
+pub fn local_addresses(listen_addrs: &[String]) -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    for address in listen_addrs {
+        let ip = match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            Ok(IpAddr::V6(ip)) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            Ok(ip) => ip,
+            Err(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        if !addresses.contains(&ip) {
+            addresses.push(ip);
+        }
+    }
+
+    if addresses.is_empty() {
+        addresses.push(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_probe_address() {
+        assert_eq!(probe_address(&[]), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(
+            probe_address(&["::".into(), "localhost".into()]),
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        );
+        assert_eq!(
+            local_addresses(&["localhost".into(), "0.0.0.0".into(), "::".into()]),
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]
+        );
+        assert_eq!(
+            probe_address(&["127.1.0.1".into()]),
+            "127.1.0.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_status_code() {
+        assert_eq!(parse_status_code("HTTP/1.1 200 OK\r\n"), Some(200));
+        assert_eq!(parse_status_code("HTTP/1.0 503"), Some(503));
+        assert_eq!(parse_status_code("SSH-2.0-OpenSSH_9.3"), None);
+    }
+
+    #[test]
+    fn test_ports_to_check() {
+        let target = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports:
+              - "5012:80"
+              - "8080"
+        "#,
+        )
+        .unwrap();
+
+        let tcp = HealthCheckConfig::default();
+        assert_eq!(
+            ports_to_check(&tcp, &target),
+            Some(vec![(0, Some(5012)), (1, None)])
+        );
+
+        let http = HealthCheckConfig {
+            http: Some("/healthz".into()),
+            ..HealthCheckConfig::default()
+        };
+        assert_eq!(ports_to_check(&http, &target), Some(vec![(0, Some(5012))]));
+
+        let port = HealthCheckConfig {
+            port: Some(8080),
+            ..http
+        };
+        assert_eq!(ports_to_check(&port, &target), Some(vec![(1, None)]));
+
+        let missing = HealthCheckConfig {
+            port: Some(443),
+            ..HealthCheckConfig::default()
+        };
+        assert_eq!(ports_to_check(&missing, &target), None);
+    }
+
+    #[test]
+    fn test_probe_http() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for response in [
+                "HTTP/1.1 204 No Content",
+                "HTTP/1.1 503 Service Unavailable",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                assert_eq!(request, "GET /healthz HTTP/1.1\r\n");
+                write!(stream, "{response}\r\n\r\n").unwrap();
+            }
+        });
+
+        assert!(probe_http(connect(address).unwrap(), "/healthz").is_ok());
+        assert!(probe_http(connect(address).unwrap(), "healthz").is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_probe() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // An accepted connection that is kept open is healthy.
+        let server = thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+        assert!(probe(connect(address).unwrap()).is_ok());
+
+        // A connection that is closed right away is not.
+        drop(server.join().unwrap().unwrap());
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            drop(stream);
+        });
+        let result = probe(connect(address).unwrap());
+        server.join().unwrap();
+        assert!(result.is_err());
+    }
+}
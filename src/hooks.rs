@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Runs the commands configured for the events of a target, e.g. `on_ready` or `on_stop`.
+
+use crate::config::{ConfigId, PortForwardConfig};
+use crate::kubectl::{read_line_lossy, ChildEvent, Kubectl, StreamSource, MAX_LINE_LENGTH};
+use std::io::{self, Read};
+use std::process::Stdio;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// Runs a hook command in the background, forwarding its output as output of the target.
+///
+/// The command receives the given environment, usually built by [`hook_env`], along with
+/// the name of the hook in `K8SFWD_EVENT`. Returns the thread waiting for the command,
+/// if it could be run.
+pub fn run(
+    kubectl: &Kubectl,
+    hook: &'static str,
+    command_line: &str,
+    id: ConfigId,
+    config: &PortForwardConfig,
+    env: Vec<(String, String)>,
+    out_tx: Sender<ChildEvent>,
+) -> Option<thread::JoinHandle<()>> {
+    let mut command = kubectl.shell_command(command_line);
+    Kubectl::apply_env(&mut command, config);
+    command
+        .envs(env)
+        .env("K8SFWD_EVENT", hook)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let message = format!("{hook} command could not be run: {e}");
+            out_tx
+                .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                .ok();
+            return None;
+        }
+    };
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    Some(thread::spawn(move || {
+        let readers = [
+            forward_lines(hook, id, stdout, StreamSource::StdOut, out_tx.clone()),
+            forward_lines(hook, id, stderr, StreamSource::StdErr, out_tx.clone()),
+        ];
+        for reader in readers.into_iter().flatten() {
+            reader.join().ok();
+        }
+
+        let message = match child.wait() {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("{hook} command exited with {status}"),
+            Err(e) => format!("{hook} command failed: {e}"),
+        };
+        out_tx
+            .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+            .ok();
+    }))
+}
+
+/// Builds the environment of a hook command.
+///
+/// - `K8SFWD_ID`, `K8SFWD_NAME`, `K8SFWD_TARGET`, `K8SFWD_TYPE`, `K8SFWD_NAMESPACE`,
+///   `K8SFWD_CONTEXT` and `K8SFWD_CLUSTER` describe the target,
+/// - `K8SFWD_PORT` is the first local port and `K8SFWD_PORTS` lists all of them, separated by commas,
+/// - `K8SFWD_PORT_<REMOTE>` is the local port of each remote port, e.g. `K8SFWD_PORT_5432`.
+pub fn hook_env(
+    id: ConfigId,
+    config: &PortForwardConfig,
+    local_ports: &[u16],
+) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("K8SFWD_ID".into(), id.to_string()),
+        (
+            "K8SFWD_NAME".into(),
+            config.name.clone().unwrap_or_default(),
+        ),
+        ("K8SFWD_TARGET".into(), config.target.clone()),
+        ("K8SFWD_TYPE".into(), config.r#type.as_arg().into()),
+        ("K8SFWD_NAMESPACE".into(), config.namespace.clone()),
+        (
+            "K8SFWD_CONTEXT".into(),
+            config.context.clone().unwrap_or_default(),
+        ),
+        (
+            "K8SFWD_CLUSTER".into(),
+            config.cluster.clone().unwrap_or_default(),
+        ),
+    ];
+
+    if let Some(port) = local_ports.first() {
+        env.push(("K8SFWD_PORT".into(), port.to_string()));
+    }
+
+    let ports: Vec<_> = local_ports.iter().map(u16::to_string).collect();
+    env.push(("K8SFWD_PORTS".into(), ports.join(",")));
+
+    // kubectl reports the local ports in the order of the configured ports.
+    for (port, local) in config.ports.iter().zip(local_ports) {
+        env.push((format!("K8SFWD_PORT_{}", port.remote), local.to_string()));
+    }
+
+    env
+}
+
+fn forward_lines<T: Read + Send + 'static>(
+    hook: &'static str,
+    id: ConfigId,
+    pipe: Option<T>,
+    source: StreamSource,
+    out_tx: Sender<ChildEvent>,
+) -> Option<thread::JoinHandle<()>> {
+    pipe.map(|pipe| {
+        thread::spawn(move || {
+            let mut reader = io::BufReader::new(pipe);
+            while let Ok(Some(line)) = read_line_lossy(&mut reader, MAX_LINE_LENGTH) {
+                let message = format!("{hook}: {line}");
+                out_tx.send(ChildEvent::Output(id, source, message)).ok();
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_env() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            name: Database
+            target: postgres
+            namespace: db
+            ports:
+              - "15432:5432"
+              - 9187
+        "#,
+        )
+        .unwrap();
+
+        let env = hook_env(ConfigId::from(2), &config, &[15432, 39187]);
+        let get = |key: &str| {
+            env.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(get("K8SFWD_ID"), Some("#0002"));
+        assert_eq!(get("K8SFWD_NAME"), Some("Database"));
+        assert_eq!(get("K8SFWD_NAMESPACE"), Some("db"));
+        assert_eq!(get("K8SFWD_PORT"), Some("15432"));
+        assert_eq!(get("K8SFWD_PORTS"), Some("15432,39187"));
+        assert_eq!(get("K8SFWD_PORT_9187"), Some("39187"));
+    }
+}
@@ -0,0 +1,281 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Maps the `hostnames` of the targets to their local addresses in the hosts file.
+//!
+//! The entries are written into a block marked with the process ID of the session, which is
+//! removed on shutdown or, if the session crashed, by the next session that manages the file.
+
+use crate::engine::Engine;
+use crate::health;
+use crate::session;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The interval at which the entries are updated from the running targets.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts the block of a session, followed by its process ID.
+const BEGIN_MARKER: &str = "# BEGIN k8sfwd session ";
+
+/// Ends the block of a session, followed by its process ID.
+const END_MARKER: &str = "# END k8sfwd session ";
+
+/// Gets the path of the hosts file of the system.
+pub fn default_path() -> PathBuf {
+    #[cfg(windows)]
+    {
+        let root = std::env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+        PathBuf::from(root).join("System32\\drivers\\etc\\hosts")
+    }
+
+    #[cfg(not(windows))]
+    {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+/// The entries of the current session in the hosts file; removed when dropped.
+pub struct HostsFile {
+    path: PathBuf,
+    pid: u32,
+    entries: Vec<(IpAddr, String)>,
+    last_update: Option<Instant>,
+}
+
+impl HostsFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            pid: std::process::id(),
+            entries: Vec::new(),
+            last_update: None,
+        }
+    }
+
+    /// Gets the path of the managed hosts file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Updates the entries from the targets of the engine, at most once per interval.
+    ///
+    /// The file is only written if the entries changed.
+    pub fn poll(&mut self, engine: &Engine) -> Result<(), io::Error> {
+        if self
+            .last_update
+            .is_some_and(|last| last.elapsed() < UPDATE_INTERVAL)
+        {
+            return Ok(());
+        }
+
+        self.last_update = Some(Instant::now());
+        let mut entries = Vec::new();
+        for (_, config, _) in engine.watched_targets() {
+            let mut hostnames: Vec<_> = config.hostnames.iter().cloned().collect();
+            hostnames.sort();
+            for ip in health::local_addresses(&config.listen_addrs) {
+                entries.extend(hostnames.iter().map(|hostname| (ip, hostname.clone())));
+            }
+        }
+
+        if entries == self.entries {
+            return Ok(());
+        }
+
+        // Don't retry failed writes until the entries change again.
+        self.entries = entries;
+        self.write()
+    }
+
+    /// Replaces the block of this session in the hosts file with the current entries.
+    fn write(&self) -> Result<(), io::Error> {
+        let contents = fs::read_to_string(&self.path)?;
+        let block = (!self.entries.is_empty()).then(|| block(self.pid, &self.entries));
+        let updated = rewrite(&contents, |pid| pid != self.pid, block);
+        if updated != contents {
+            replace(&self.path, &updated)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HostsFile {
+    fn drop(&mut self) {
+        if !self.entries.is_empty() {
+            self.entries.clear();
+            self.write().ok();
+        }
+    }
+}
+
+/// Removes the blocks of sessions that are no longer running, e.g. because they crashed.
+///
+/// Returns the number of removed blocks.
+pub fn remove_stale(path: &Path) -> Result<usize, io::Error> {
+    let contents = fs::read_to_string(path)?;
+    let mut stale = 0;
+    let updated = rewrite(
+        &contents,
+        |pid| {
+            let alive = pid == std::process::id() || session::is_process_alive(pid);
+            stale += usize::from(!alive);
+            alive
+        },
+        None,
+    );
+
+    if updated != contents {
+        replace(path, &updated)?;
+    }
+    Ok(stale)
+}
+
+/// Replaces the contents of the file by renaming a copy written next to it over it,
+/// such that a crash while writing can't leave the file truncated.
+///
+/// Files that can't be renamed over, e.g. the hosts file bind-mounted into a container,
+/// are written in place instead.
+fn replace(path: &Path, contents: &str) -> Result<(), io::Error> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{name}.k8sfwd-{pid}", pid = std::process::id()));
+    let written = fs::write(&partial, contents)
+        .and_then(|_| fs::set_permissions(&partial, fs::metadata(path)?.permissions()));
+    if let Err(e) = written {
+        fs::remove_file(&partial).ok();
+        return Err(e);
+    }
+
+    if fs::rename(&partial, path).is_err() {
+        fs::remove_file(&partial).ok();
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Determines whether the name is a valid host name, i.e. dot-separated labels of up to 63
+/// letters, digits and hyphens, not starting or ending with a hyphen (RFC 1123).
+pub fn is_valid_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+/// Formats the block of a session.
+fn block(pid: u32, entries: &[(IpAddr, String)]) -> String {
+    let mut block = format!("{BEGIN_MARKER}{pid} - managed by k8sfwd, do not edit\n");
+    for (ip, hostname) in entries {
+        block.push_str(&format!("{ip}\t{hostname}\n"));
+    }
+    block.push_str(&format!("{END_MARKER}{pid}\n"));
+    block
+}
+
+/// Keeps the lines outside of blocks and the blocks of sessions for which `keep` is true,
+/// then appends the new block, if any.
+///
+/// A block missing its end marker extends to the end of the file.
+fn rewrite(contents: &str, mut keep: impl FnMut(u32) -> bool, block: Option<String>) -> String {
+    let newline = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut lines = Vec::new();
+    let mut skipping = None;
+    for line in contents.lines() {
+        if let Some(pid) = skipping {
+            if line.strip_prefix(END_MARKER).and_then(parse_pid) == Some(pid) {
+                skipping = None;
+            }
+            continue;
+        }
+
+        match line.strip_prefix(BEGIN_MARKER).and_then(parse_pid) {
+            Some(pid) if !keep(pid) => skipping = Some(pid),
+            _ => lines.push(line),
+        }
+    }
+
+    let mut updated = lines.join(newline);
+    if !updated.is_empty() {
+        updated.push_str(newline);
+    }
+    if let Some(block) = block {
+        updated.push_str(&block.replace('\n', newline));
+    }
+    updated
+}
+
+/// Reads the process ID following a marker.
+fn parse_pid(text: &str) -> Option<u32> {
+    text.split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_rewrite() {
+        let hosts = "127.0.0.1\tlocalhost\n";
+        let entries = vec![(IpAddr::V4(Ipv4Addr::LOCALHOST), "api.staging".to_string())];
+        let added = rewrite(hosts, |_| true, Some(block(42, &entries)));
+        assert_eq!(
+            added,
+            "127.0.0.1\tlocalhost\n\
+             # BEGIN k8sfwd session 42 - managed by k8sfwd, do not edit\n\
+             127.0.0.1\tapi.staging\n\
+             # END k8sfwd session 42\n"
+        );
+
+        // Blocks of other sessions are kept.
+        assert_eq!(rewrite(&added, |pid| pid != 7, None), added);
+        assert_eq!(rewrite(&added, |pid| pid != 42, None), hosts);
+    }
+
+    #[test]
+    fn test_replace() {
+        let dir = std::env::temp_dir().join(format!("k8sfwd-test-hosts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hosts");
+        fs::write(&path, "127.0.0.1\tlocalhost\n").unwrap();
+
+        replace(&path, "127.0.0.1\tapi.staging\n").unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(contents, "127.0.0.1\tapi.staging\n");
+        assert_eq!(files, 1, "the partial file is renamed");
+    }
+
+    #[test]
+    fn test_is_valid_hostname() {
+        assert!(is_valid_hostname("api"));
+        assert!(is_valid_hostname("api-v2.staging.localhost"));
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("api..staging"));
+        assert!(!is_valid_hostname("-api"));
+        assert!(!is_valid_hostname("api staging"));
+        assert!(!is_valid_hostname("api\n10.0.0.1\tbank.example.com"));
+        assert!(!is_valid_hostname(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_rewrite_crlf() {
+        let hosts = "127.0.0.1 localhost\r\n# BEGIN k8sfwd session 42\r\n127.0.0.1 a\r\n";
+        assert_eq!(rewrite(hosts, |_| false, None), "127.0.0.1 localhost\r\n");
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Keeps a connection open through every forwarded port, such that idle tunnels aren't
+//! closed by the cluster (e.g. the kubelet's streaming connection idle timeout).
+
+use crate::config::KeepaliveConfig;
+use crate::health::{self, local_addresses};
+use crate::status::StatusHandle;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The keepalive connections of a port-forward; closes them when dropped.
+pub struct Keepalive {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Keepalive {
+    /// Starts connecting to each local port on each listen address once the target is forwarding.
+    ///
+    /// Returns `None` if keepalive connections are disabled.
+    pub fn start(
+        config: &KeepaliveConfig,
+        listen_addrs: &[String],
+        status: StatusHandle,
+    ) -> Option<Self> {
+        if !config.is_enabled() {
+            return None;
+        }
+
+        let addresses = local_addresses(listen_addrs);
+        let interval = config.interval();
+        let tcp_keepalive = config.tcp_keepalive();
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            let mut connections = HashMap::new();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !status.is_forwarding() {
+                    continue;
+                }
+
+                let ports = status.snapshot().local_ports;
+                connections.retain(|_, stream| is_open(stream));
+                for address in endpoints(&addresses, &ports) {
+                    if connections.contains_key(&address) {
+                        continue;
+                    }
+
+                    if let Ok(stream) = status.probe(|| connect(address, tcp_keepalive)) {
+                        connections.insert(address, stream);
+                    }
+                }
+            }
+        });
+
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Gets the addresses to keep a connection to: each local port on each listen address.
+fn endpoints(addresses: &[IpAddr], ports: &[u16]) -> Vec<SocketAddr> {
+    addresses
+        .iter()
+        .flat_map(|address| ports.iter().map(|port| SocketAddr::new(*address, *port)))
+        .collect()
+}
+
+fn connect(address: SocketAddr, tcp_keepalive: Option<Duration>) -> io::Result<TcpStream> {
+    let stream = health::connect(address)?;
+    stream.set_nonblocking(true)?;
+    if let Some(idle) = tcp_keepalive {
+        set_tcp_keepalive(&stream, idle)?;
+    }
+    Ok(stream)
+}
+
+/// Enables TCP keepalive probes on the connection, sent once it was idle for the specified time.
+#[cfg(unix)]
+fn set_tcp_keepalive(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPALIVE;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPIDLE;
+
+    let set = |level, name, value: libc::c_int| {
+        // SAFETY: The option value points to a c_int of the given size.
+        let result = unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    };
+
+    let idle = idle.as_secs().clamp(1, libc::c_int::MAX as u64) as libc::c_int;
+    set(libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    set(libc::IPPROTO_TCP, TCP_KEEPIDLE, idle)
+}
+
+/// Enables TCP keepalive probes on the connection; relies on the system's settings on this platform.
+#[cfg(not(unix))]
+fn set_tcp_keepalive(_stream: &TcpStream, _idle: Duration) -> io::Result<()> {
+    Ok(())
+}
+
+/// Determines whether the connection is still open, discarding anything the server sent.
+fn is_open(stream: &mut TcpStream) -> bool {
+    let mut buffer = [0; 1024];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return false,
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn test_endpoints() {
+        let addresses = local_addresses(&["127.0.0.1".into(), "::1".into()]);
+        let endpoints = endpoints(&addresses, &[5012, 8080]);
+        assert_eq!(endpoints.len(), 4);
+        assert!(endpoints.contains(&"[::1]:8080".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_is_open() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let idle = Some(Duration::from_secs(30));
+        let mut stream = connect(listener.local_addr().unwrap(), idle).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        server.write_all(b"banner\r\n").unwrap();
+        assert!(is_open(&mut stream));
+
+        drop(server);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!is_open(&mut stream));
+    }
+}
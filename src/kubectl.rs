@@ -2,15 +2,44 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
+use crate::backoff::{Backoff, BackoffStore};
 use crate::cli::KubectlPathBuf;
-use crate::config::{ConfigId, OperationalConfig, PortForwardConfig, RetryDelay};
-use serde::Deserialize;
-use std::env::current_dir;
+use crate::config::{
+    ConfigId, GatewayConfig, HookCommand, OperationalConfig, Port, PortConflict, PortForwardConfig,
+    ResourceType, Seconds, TlsConfig,
+};
+use crate::dependencies::{self, Dependency};
+use crate::diagnostics::KubectlError;
+use crate::encryption;
+use crate::fnv::fnv1a;
+use crate::health::{HealthCheck, IdleWatch};
+use crate::hooks;
+use crate::keepalive::Keepalive;
+use crate::passthrough;
+use crate::pod_watch::{self, PodSelector, PodWatch, DEFAULT_READY_TIMEOUT};
+use crate::preflight;
+use crate::proxy::{self, Proxy, ProxyError};
+use crate::replicas::Replicas;
+use crate::session::{
+    self, ChildProcess, HelperResource, Session, MANAGED_BY_LABEL, SESSION_LABEL_KEY,
+    USER_ANNOTATION_PREFIX,
+};
+use crate::signals::ShutdownSignal;
+use crate::status::{StatusHandle, Traffic};
+use crate::tls::{TlsError, TlsTerminator};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env::{self, current_dir, JoinPathsError};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
 use std::io::{BufRead, Read};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::{io, process, thread};
 
 #[cfg(not(windows))]
@@ -18,10 +47,36 @@ const ENV_PATH_SEPARATOR: char = ':';
 #[cfg(windows)]
 const ENV_PATH_SEPARATOR: char = ';';
 
-#[derive(Debug)]
+/// The interval at which running child processes are checked for termination.
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The longest line of process output that is forwarded, in bytes; the rest of the line is dropped.
+pub const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+/// The environment variables kubectl and its credential plugins need to run;
+/// kept for targets that don't inherit the environment.
+const ESSENTIAL_ENV: [&str; 12] = [
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "KUBECONFIG",
+    "TMPDIR",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "TEMP",
+    "TMP",
+    "SYSTEMROOT",
+    "COMSPEC",
+];
+
+#[derive(Debug, Clone)]
 pub struct Kubectl {
     kubectl: PathBuf,
     current_dir: PathBuf,
+    /// The `KUBECONFIG` of all invocations, if configured; shared by all clones
+    /// such that a reloaded configuration applies to running targets as well.
+    kubeconfig: Arc<RwLock<Option<OsString>>>,
 }
 
 impl Kubectl {
@@ -34,12 +89,33 @@ impl Kubectl {
         Ok(Self {
             kubectl,
             current_dir: path.to_path_buf(),
+            kubeconfig: Arc::default(),
         })
     }
 
+    /// Points all invocations at the kubeconfig files by joining them into `KUBECONFIG`,
+    /// or at the inherited `KUBECONFIG` again if there are none.
+    pub fn set_kubeconfigs(&self, paths: &[PathBuf]) -> Result<(), JoinPathsError> {
+        let kubeconfig = match paths {
+            [] => None,
+            paths => Some(env::join_paths(paths)?),
+        };
+        *self.kubeconfig.write().expect("lock is not poisoned") = kubeconfig;
+        Ok(())
+    }
+
+    /// Gets the `KUBECONFIG` variable to set on the invocations, if configured.
+    fn kubeconfig_env(&self) -> Option<(&'static str, OsString)> {
+        let kubeconfig = self.kubeconfig.read().expect("lock is not poisoned");
+        kubeconfig
+            .clone()
+            .map(|kubeconfig| ("KUBECONFIG", kubeconfig))
+    }
+
     pub fn version(&self) -> Result<String, VersionError> {
         let output = Command::new(&self.kubectl)
             .current_dir(&self.current_dir)
+            .envs(self.kubeconfig_env())
             .args(["version", "--output=json"])
             .output()?;
 
@@ -51,6 +127,7 @@ impl Kubectl {
     pub fn current_context(&self) -> Result<String, ContextError> {
         let output = Command::new(&self.kubectl)
             .current_dir(&self.current_dir)
+            .envs(self.kubeconfig_env())
             .args([
                 "config",
                 "view",
@@ -65,10 +142,52 @@ impl Kubectl {
         Ok(value.into())
     }
 
+    /// Gets the names of all contexts of the kubeconfig.
+    pub fn contexts(&self) -> Result<Vec<String>, ShellError> {
+        let output = self
+            .command()
+            .args(["config", "get-contexts", "-o", "name"])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    /// Gets the names of all clusters of the kubeconfig.
+    pub fn clusters(&self) -> Result<Vec<String>, ShellError> {
+        let output = self.command().args(["config", "get-clusters"]).output()?;
+
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
+
+        // The names follow a `NAME` header.
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
     /// Gets the currently active contexts' cluster.
     pub fn current_cluster(&self) -> Result<Option<String>, ContextError> {
         let output = Command::new(&self.kubectl)
             .current_dir(&self.current_dir)
+            .envs(self.kubeconfig_env())
             .args([
                 "config",
                 "view",
@@ -87,37 +206,25 @@ impl Kubectl {
         }
     }
 
-    /// Given the name of the cluster, identifies a context.
-    pub fn context_from_cluster(
-        &self,
-        cluster: Option<&String>,
-    ) -> Result<Option<String>, ContextError> {
-        if cluster.is_none() {
-            return Ok(None);
-        }
-
-        let context = cluster.expect("value exists");
+    /// Gets the names of the contexts of the kubeconfig that use the cluster.
+    pub fn contexts_of_cluster(&self, cluster: &str) -> Result<Vec<String>, ContextError> {
         let jsonpath =
-            format!("jsonpath='{{$.contexts[?(@.context.cluster==\"{context}\")].name}}'");
+            format!("jsonpath='{{$.contexts[?(@.context.cluster==\"{cluster}\")].name}}'");
         let output = Command::new(&self.kubectl)
             .current_dir(&self.current_dir)
+            .envs(self.kubeconfig_env())
             .args(["config", "view", "--merge=true", "-o", &jsonpath])
             .output()?;
 
-        let value = String::from_utf8_lossy(&output.stdout);
-        let value = value.trim_matches('\'');
         // Array values (in case multiple match) are separated by space.
-        let values: Vec<_> = value.split(' ').collect();
-        if values.len() > 1 {
-            return Ok(None);
-        }
-
-        let value = values[0];
-        if !value.is_empty() {
-            Ok(Some(value.into()))
-        } else {
-            Ok(None)
-        }
+        let value = String::from_utf8_lossy(&output.stdout);
+        Ok(value
+            .trim()
+            .trim_matches('\'')
+            .split(' ')
+            .filter(|context| !context.is_empty())
+            .map(String::from)
+            .collect())
     }
 
     /// Given the name of the context, identifies its cluster.
@@ -134,6 +241,7 @@ impl Kubectl {
             format!("jsonpath='{{$.contexts[?(@.name==\"{context}\")].context.cluster}}'");
         let output = Command::new(&self.kubectl)
             .current_dir(&self.current_dir)
+            .envs(self.kubeconfig_env())
             .args(["config", "view", "--merge=true", "-o", &jsonpath])
             .output()?;
 
@@ -153,224 +261,2532 @@ impl Kubectl {
         }
     }
 
-    pub fn port_forward(
+    /// Ensures the gateway pod exists, launching it from its image if necessary.
+    ///
+    /// Gateway pods launched by k8sfwd can be shared by several sessions, which record their
+    /// use in an annotation of the pod; the pod is tracked by each session and removed by the
+    /// last one to exit. Pods deployed otherwise are used as they are and never removed.
+    pub fn ensure_gateway_pod(
         &self,
-        id: ConfigId,
-        config: OperationalConfig,
-        fwd_config: PortForwardConfig,
-        out_tx: Sender<ChildEvent>,
-    ) -> Result<JoinHandle<Result<(), anyhow::Error>>, VersionError> {
-        let target = format!(
-            "{resource}/{name}",
-            resource = fwd_config.r#type.as_arg(),
-            name = fwd_config.target
-        );
+        context: Option<&String>,
+        gateway: &GatewayConfig,
+        session: &Session,
+    ) -> Result<(), GatewayError> {
+        let resource = HelperResource {
+            kind: "pod".into(),
+            name: gateway.pod.clone(),
+            namespace: gateway.namespace.clone(),
+            context: context.cloned(),
+            shared: true,
+        };
+        let annotation = format!("{USER_ANNOTATION_PREFIX}{id}=true", id = session.id());
 
-        let kubectl = self.kubectl.clone();
-        let current_dir = self.current_dir.clone();
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["get", "pod", &gateway.pod, "-n", &gateway.namespace])
+            .args(["-o", "json"])
+            .output()?;
+        if output.status.success() {
+            let pod: KubectlItem = serde_json::from_slice(&output.stdout)
+                .map_err(|e| GatewayError::CommandFailed(e.into()))?;
+            if !pod.metadata.is_managed() {
+                return Ok(());
+            }
 
-        let child_thread = thread::spawn(move || {
-            let retry_delay_sec = config.retry_delay_sec.expect("retry_delay_sec exists");
+            session
+                .track(resource.clone())
+                .map_err(GatewayError::TrackingFailed)?;
+            let mut command = self.command();
+            Self::apply_context(&mut command, context, None);
+            let output = command
+                .args(["annotate", "pod", &gateway.pod, "-n", &gateway.namespace])
+                .args(["--overwrite", &annotation])
+                .output()?;
+            if !output.status.success() {
+                session.untrack(&resource).ok();
+                return Err(GatewayError::LaunchFailed(
+                    gateway.pod.clone(),
+                    String::from_utf8_lossy(&output.stderr).trim().into(),
+                ));
+            }
+            return Ok(());
+        }
 
-            let mut bootstrap = true;
-            'new_process: loop {
-                // Only delay start at the second iteration.
-                if !bootstrap && retry_delay_sec > RetryDelay::NONE {
-                    thread::sleep(retry_delay_sec.into());
-                }
-                bootstrap = false;
-
-                let mut command = Command::new(kubectl.clone());
-                command
-                    .env("PATH", Self::get_env_path(&current_dir))
-                    .current_dir(current_dir.clone())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .args(["port-forward"]);
-
-                // the context to use
-                if let Some(context) = &fwd_config.context {
-                    command.args(["--context", context]);
-                }
+        let image = match &gateway.image {
+            Some(image) => image,
+            None => return Err(GatewayError::PodNotFound(gateway.pod.clone())),
+        };
 
-                // the cluster to use
-                if let Some(cluster) = &fwd_config.cluster {
-                    command.args(["--cluster", cluster]);
-                }
+        // Recorded before launching, such that the pod is found even if k8sfwd
+        // crashes right after.
+        session
+            .track(resource.clone())
+            .map_err(GatewayError::TrackingFailed)?;
 
-                // which addresses to listen on locally
-                match &fwd_config.listen_addrs[..] {
-                    [] => {}
-                    addresses => {
-                        let addresses = addresses.join(",");
-                        command.args(["--address", &addresses]);
-                    }
-                };
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["run", &gateway.pod, "-n", &gateway.namespace])
+            .arg(format!("--image={image}"))
+            .arg(format!("--labels={labels}", labels = session.labels()))
+            .arg(format!("--annotations={annotation}"))
+            .args(["--restart=Never", "--command", "--", "sleep", "infinity"])
+            .output()?;
+        if !output.status.success() {
+            session.untrack(&resource).ok();
+            return Err(GatewayError::LaunchFailed(
+                gateway.pod.clone(),
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-                // the namespace to select
-                command.args(["-n", &fwd_config.namespace]);
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["wait", "--for=condition=Ready", "--timeout=60s", "-n"])
+            .arg(&gateway.namespace)
+            .arg(format!("pod/{pod}", pod = gateway.pod))
+            .output()?;
+        if !output.status.success() {
+            return Err(GatewayError::LaunchFailed(
+                gateway.pod.clone(),
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-                // pod/name, deployment/name, service/name
-                command.arg(target.clone());
+        Ok(())
+    }
 
-                // Apply the port bindings
-                for port in &fwd_config.ports {
-                    let value = if let Some(local) = port.local {
-                        format!("{local}:{remote}", remote = port.remote)
-                    } else {
-                        format!(":{remote}", remote = port.remote)
-                    };
+    /// Removes the session from the users of a shared helper resource, deleting the resource
+    /// if no other session uses it anymore.
+    pub fn release_shared_resource(
+        &self,
+        resource: &HelperResource,
+        session: &str,
+    ) -> Result<(), ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, resource.context.as_ref(), None);
+        let output = command
+            .args(["annotate", &resource.kind, &resource.name, "-n"])
+            .arg(&resource.namespace)
+            .arg(format!("{USER_ANNOTATION_PREFIX}{session}-"))
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return match stderr.contains("NotFound") {
+                true => Ok(()),
+                false => Err(ShellError::Failed(stderr.trim().into())),
+            };
+        }
 
-                    command.arg(&value);
-                }
+        let mut command = self.command();
+        Self::apply_context(&mut command, resource.context.as_ref(), None);
+        let output = command
+            .args(["get", &resource.kind, &resource.name, "-n"])
+            .arg(&resource.namespace)
+            .args(["-o", "json"])
+            .output()?;
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-                let mut child = command.spawn()?;
+        let item: KubectlItem = serde_json::from_slice(&output.stdout)?;
+        if item.metadata.users().is_empty() {
+            self.delete_helper_resource(resource)
+        } else {
+            Ok(())
+        }
+    }
 
-                // Read stdout and stderr in separate threads.
-                Self::handle_pipe(
-                    id,
-                    out_tx.clone(),
-                    child.stdout.take(),
-                    StreamSource::StdOut,
-                );
+    /// Deletes an in-cluster helper resource created by k8sfwd.
+    pub fn delete_helper_resource(&self, resource: &HelperResource) -> Result<(), ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, resource.context.as_ref(), None);
+        let output = command
+            .args([
+                "delete",
+                &resource.kind,
+                &resource.name,
+                "-n",
+                &resource.namespace,
+            ])
+            .args(["--ignore-not-found", "--wait=false"])
+            .output()?;
 
-                // TODO: Handle `Error from server (NotFound): pods "foo-78b4c5d554-6z55j" not found")`
-                // TODO: Handle `Unable to listen on port 5012: Listeners failed to create with the following errors: [unable to create listener: Error listen tcp4 127.1.0.1:5012: bind: address already in use]`
-                Self::handle_pipe(
-                    id,
-                    out_tx.clone(),
-                    child.stderr.take(),
-                    StreamSource::StdErr,
-                );
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ))
+        }
+    }
 
-                let mut child = ChildGuard(child);
+    /// Gets a resource (e.g. `service/foo`) as JSON, or `None` if it doesn't exist.
+    pub fn get_resource(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: Option<&str>,
+        resource: &str,
+    ) -> Result<Option<serde_json::Value>, ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, cluster);
+        command.args(["get", resource, "--ignore-not-found", "-o", "json"]);
+        if let Some(namespace) = namespace {
+            command.args(["-n", namespace]);
+        }
 
-                // Wait for the child process to finish
-                let status = child.wait();
-                let status = match status {
-                    Ok(status) => status,
-                    Err(e) => {
-                        out_tx.send(ChildEvent::Error(id, ChildError::Wait(e))).ok();
-                        // TODO: Break out of this loop if the error is unfixable?
-                        continue 'new_process;
-                    }
-                };
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-                out_tx
-                    .send(ChildEvent::Exit(
-                        id,
-                        status,
-                        RestartPolicy::WillRestartIn(retry_delay_sec),
-                    ))
-                    .ok();
-            }
-        });
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
 
-        Ok(child_thread)
+        Ok(Some(serde_json::from_slice(&output.stdout)?))
     }
 
-    fn get_env_path(current_dir: &Path) -> String {
-        let mut path = std::env::var("PATH").unwrap_or_else(|_| String::new());
-        if !path.is_empty() {
-            path.push(ENV_PATH_SEPARATOR);
+    /// Gets the data of a ConfigMap of the current context, or `None` if it doesn't exist
+    /// or has no data.
+    pub fn config_map_data(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<HashMap<String, String>>, ShellError> {
+        let output = self
+            .command()
+            .args([
+                "get",
+                "configmap",
+                name,
+                "-n",
+                namespace,
+                "--ignore-not-found",
+            ])
+            .args(["-o", "jsonpath={.data}"])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
         }
-        path.push_str(&current_dir.display().to_string());
-        path
+
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&output.stdout)?))
     }
 
-    fn handle_pipe<T: Read + Send + 'static>(
-        id: ConfigId,
-        out_tx: Sender<ChildEvent>,
-        pipe: Option<T>,
-        source: StreamSource,
-    ) {
-        if let Some(pipe) = pipe {
-            thread::spawn(move || {
-                let reader = io::BufReader::new(pipe);
-                for line in reader.lines() {
-                    if line.is_err() {
-                        break;
-                    }
+    /// Lists the names of the resources of a kind (e.g. `service`) in the namespace.
+    pub fn list_names(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: &str,
+        kind: &str,
+    ) -> Result<Vec<String>, ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, cluster);
+        let output = command
+            .args(["get", kind, "-n", namespace, "-o", "name"])
+            .output()?;
 
-                    let line = line.unwrap();
-                    out_tx.send(ChildEvent::Output(id, source, line)).ok();
-                }
-            });
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
         }
+
+        // The names are prefixed with their kind, e.g. `service/foo`.
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('/'))
+            .map(|(_, name)| name.to_string())
+            .collect())
     }
-}
 
-#[derive(Debug)]
-pub enum ChildEvent {
-    Output(ConfigId, StreamSource, String),
-    Exit(ConfigId, ExitStatus, RestartPolicy),
-    Error(ConfigId, ChildError),
-}
+    /// Gets a label selector matching the siblings of a pod, e.g. the other pods of its deployment,
+    /// or `None` if the pod doesn't exist or has no labels.
+    pub fn pod_selector(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: &str,
+        pod: &str,
+    ) -> Result<Option<String>, ShellError> {
+        let pod = self.get_resource(context, cluster, Some(namespace), &format!("pod/{pod}"))?;
+        Ok(pod.as_ref().and_then(sibling_selector))
+    }
 
-#[derive(Debug)]
-pub enum RestartPolicy {
-    WillRestartIn(RetryDelay),
-}
+    /// Finds a running pod matching the label selector.
+    pub fn find_pod(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: &str,
+        selector: &str,
+    ) -> Result<Option<String>, ShellError> {
+        let selector = PodSelector::Labels(selector.into());
+        let list = self.list_pods(context, cluster, namespace, &selector)?;
+        Ok(running_pod(&list))
+    }
 
-#[derive(Debug, thiserror::Error)]
-pub enum ChildError {
-    /// Failed to wait for the child process' status.
-    #[error(transparent)]
-    Wait(#[from] io::Error),
-}
+    /// Lists the selected pods as JSON.
+    pub fn list_pods(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: &str,
+        selector: &PodSelector,
+    ) -> Result<serde_json::Value, ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, cluster);
+        let output = command
+            .args(["get", "pods", "-n", namespace])
+            .args(selector.as_args())
+            .args(["-o", "json"])
+            .output()?;
 
-#[derive(Debug, Copy, Clone)]
-pub enum StreamSource {
-    StdOut,
-    StdErr,
-}
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-#[derive(Deserialize)]
-struct KubectlVersion {
-    #[serde(alias = "clientVersion")]
-    client_version: KubectlClientVersion,
-}
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
 
-#[derive(Deserialize)]
-#[allow(dead_code)]
-struct KubectlClientVersion {
-    major: String,
-    minor: String,
-    #[serde(alias = "gitVersion")]
-    git_version: String,
-}
+    /// Starts watching the selected pods; the watch events are written to the
+    /// standard output of the returned process as a stream of JSON objects.
+    pub fn watch_pods(
+        &self,
+        context: Option<&String>,
+        cluster: Option<&String>,
+        namespace: &str,
+        selector: &PodSelector,
+    ) -> Result<process::Child, io::Error> {
+        let mut command = self.command();
+        command.stdout(Stdio::piped()).stderr(Stdio::null());
+        Self::apply_context(&mut command, context, cluster);
+        command
+            .args(["get", "pods", "-n", namespace])
+            .args(selector.as_args())
+            .args(["--watch", "--output-watch-events", "-o", "json"])
+            .spawn()
+    }
 
-#[derive(Debug, thiserror::Error)]
-pub enum ShellError {
-    #[error(transparent)]
-    CommandFailed(#[from] io::Error),
-}
+    /// Lists all helper pods created by k8sfwd in any namespace of the specified context,
+    /// together with the IDs of the sessions using them, i.e. the ones recorded in a shared
+    /// pod, or else the one that created the pod.
+    pub fn list_helper_pods(
+        &self,
+        context: Option<&String>,
+    ) -> Result<Vec<(HelperResource, Vec<String>)>, ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["get", "pods", "--all-namespaces", "-o", "json"])
+            .arg(format!("--selector={MANAGED_BY_LABEL}"))
+            .output()?;
 
-#[derive(Debug, thiserror::Error)]
-pub enum VersionError {
-    #[error("The version format could not be read")]
-    InvalidFormat(#[from] serde_json::Error),
-    #[error(transparent)]
-    CommandFailed(#[from] io::Error),
-}
+        if !output.status.success() {
+            return Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ));
+        }
 
-#[derive(Debug, thiserror::Error)]
-pub enum ContextError {
-    #[error(transparent)]
-    CommandFailed(#[from] io::Error),
-}
+        let list: KubectlList = serde_json::from_slice(&output.stdout)?;
+        Ok(list
+            .items
+            .into_iter()
+            .map(|item| {
+                let mut sessions = item.metadata.users();
+                if sessions.is_empty() {
+                    sessions.extend(item.metadata.labels.get(SESSION_LABEL_KEY).cloned());
+                }
+                let resource = HelperResource {
+                    kind: "pod".into(),
+                    name: item.metadata.name,
+                    namespace: item.metadata.namespace.unwrap_or_default(),
+                    context: context.cloned(),
+                    shared: false,
+                };
+                (resource, sessions)
+            })
+            .collect())
+    }
 
-/// A guard to ensure the child process is terminated when the thread is cancelled.
-struct ChildGuard(process::Child);
+    /// Determines whether the current user may perform the action, e.g. `create pods/portforward`.
+    pub fn can_i(
+        &self,
+        context: Option<&String>,
+        namespace: &str,
+        verb: &str,
+        resource: &str,
+    ) -> Result<bool, ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["auth", "can-i", verb, resource, "-n", namespace])
+            .output()?;
 
-impl ChildGuard {
-    pub fn wait(&mut self) -> io::Result<ExitStatus> {
-        self.0.wait()
+        // kubectl answers "no" with exit code 1.
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "yes" => Ok(true),
+            "no" => Ok(false),
+            _ => Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            )),
+        }
     }
-}
 
-impl Drop for ChildGuard {
-    fn drop(&mut self) {
-        self.0.kill().ok();
+    /// Starts a pod as a helper resource of the session, running the command in the image.
+    ///
+    /// The pod is recorded in the session manifest, such that it is removed by the
+    /// garbage collection if the session doesn't delete it.
+    pub fn run_helper_pod(
+        &self,
+        context: Option<&String>,
+        namespace: &str,
+        pod: &str,
+        image: &str,
+        args: &[&str],
+        session: &Session,
+    ) -> Result<(), ShellError> {
+        // Recorded before launching, such that the pod is found even if k8sfwd
+        // crashes right after.
+        let resource = HelperResource {
+            kind: "pod".into(),
+            name: pod.into(),
+            namespace: namespace.into(),
+            context: context.cloned(),
+            shared: false,
+        };
+        session.track(resource.clone()).ok();
+
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["run", pod, "-n", namespace])
+            .arg(format!("--image={image}"))
+            .arg(format!("--labels={labels}", labels = session.labels()))
+            .args(["--restart=Never", "--command", "--"])
+            .args(args)
+            .output()?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            session.untrack(&resource).ok();
+            Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ))
+        }
+    }
+
+    /// Waits for a pod to be ready.
+    pub fn wait_pod_ready(
+        &self,
+        context: Option<&String>,
+        namespace: &str,
+        pod: &str,
+        timeout: Duration,
+    ) -> Result<(), ShellError> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        let output = command
+            .args(["wait", "--for=condition=Ready", "-n", namespace])
+            .arg(format!("--timeout={secs}s", secs = timeout.as_secs()))
+            .arg(format!("pod/{pod}"))
+            .output()?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(ShellError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().into(),
+            ))
+        }
+    }
+
+    /// Spawns kubectl forwarding a random local loopback port to the port of a pod.
+    pub fn port_forward_pod(
+        &self,
+        context: Option<&String>,
+        namespace: &str,
+        pod: &str,
+        port: u16,
+    ) -> Result<process::Child, io::Error> {
+        let mut command = self.command();
+        Self::apply_context(&mut command, context, None);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(["port-forward", "--address", "127.0.0.1", "-n", namespace])
+            .arg(format!("pod/{pod}"))
+            .arg(format!(":{port}"))
+            .spawn()
+    }
+
+    /// Determines the in-cluster host name or address a gateway relays the traffic to.
+    fn gateway_destination(&self, fwd_config: &PortForwardConfig) -> Result<String, GatewayError> {
+        match fwd_config.r#type {
+            ResourceType::Service => Ok(format!(
+                "{name}.{namespace}.svc",
+                name = fwd_config.target,
+                namespace = fwd_config.namespace
+            )),
+            ResourceType::Pod => {
+                let mut command = self.command();
+                Self::apply_context(&mut command, fwd_config.context.as_ref(), None);
+                let output = command
+                    .args([
+                        "get",
+                        "pod",
+                        &fwd_config.target,
+                        "-n",
+                        &fwd_config.namespace,
+                    ])
+                    .args(["-o", "jsonpath='{.status.podIP}'"])
+                    .output()?;
+
+                let value = String::from_utf8_lossy(&output.stdout);
+                let value = value.trim_matches('\'');
+                if output.status.success() && !value.is_empty() {
+                    Ok(value.into())
+                } else {
+                    Err(GatewayError::UnknownPodAddress(fwd_config.target.clone()))
+                }
+            }
+            ResourceType::Deployment => Err(GatewayError::UnsupportedResourceType),
+        }
+    }
+
+    /// Spawns a `socat` relay in the gateway pod, forwarding the relay port
+    /// to the specified remote port of the destination.
+    fn spawn_gateway_relay(
+        &self,
+        fwd_config: &PortForwardConfig,
+        gateway: &GatewayConfig,
+        destination: &str,
+        port: &Port,
+        niceness: Option<u8>,
+    ) -> Result<process::Child, io::Error> {
+        let mut command = self.command();
+        Self::apply_niceness(&mut command, niceness);
+        command.stdout(Stdio::null()).stderr(Stdio::piped());
+        Self::apply_context(&mut command, fwd_config.context.as_ref(), None);
+        Self::apply_request_timeout(&mut command, fwd_config.request_timeout_sec);
+        command
+            .args([
+                "exec",
+                "-n",
+                &gateway.namespace,
+                &gateway.pod,
+                "--",
+                "socat",
+            ])
+            .arg(format!(
+                "TCP-LISTEN:{relay},fork,reuseaddr",
+                relay = Self::gateway_relay_port(fwd_config, port)
+            ))
+            .arg(format!("TCP:{destination}:{remote}", remote = port.remote));
+        command.spawn()
+    }
+
+    /// Spawns a kubectl process forwarding to a single pod of a load-balanced target,
+    /// listening on the given loopback ports of the proxy in the order of the target's ports.
+    fn spawn_replica(
+        &self,
+        fwd_config: &PortForwardConfig,
+        pod: &str,
+        ports: &[u16],
+        niceness: Option<u8>,
+    ) -> Result<process::Child, io::Error> {
+        let mut command = self.command();
+        Self::apply_env(&mut command, fwd_config);
+        Self::apply_niceness(&mut command, niceness);
+        command
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .args(["port-forward"]);
+        Self::apply_context(&mut command, fwd_config.context.as_ref(), None);
+        Self::apply_request_timeout(&mut command, fwd_config.request_timeout_sec);
+        command
+            .args(["--address", &proxy::UPSTREAM_ADDRESS.to_string()])
+            .args(["-n", &fwd_config.namespace])
+            .arg(format!("pod/{pod}"));
+        for (port, local) in fwd_config.ports.iter().zip(ports) {
+            command.arg(format!("{local}:{remote}", remote = port.remote));
+        }
+        command.spawn()
+    }
+
+    /// Derives the port a gateway relay listens on from the target's identity,
+    /// such that different targets sharing a gateway don't collide.
+    fn gateway_relay_port(fwd_config: &PortForwardConfig, port: &Port) -> u16 {
+        const RELAY_PORT_BASE: u32 = 40000;
+        const RELAY_PORT_RANGE: u32 = 20000;
+
+        let identity = format!(
+            "{namespace}/{name}:{remote}",
+            namespace = fwd_config.namespace,
+            name = fwd_config.target,
+            remote = port.remote
+        );
+        let hash = fnv1a(identity.as_bytes());
+
+        (RELAY_PORT_BASE + hash % RELAY_PORT_RANGE) as u16
+    }
+
+    pub fn port_forward(
+        &self,
+        config: OperationalConfig,
+        fwd_config: PortForwardConfig,
+        context: ForwardContext,
+    ) -> JoinHandle<Result<ForwardSummary, anyhow::Error>> {
+        let kubectl = self.clone();
+
+        thread::spawn(move || {
+            let result = kubectl
+                .run_port_forward_loop(config, fwd_config, &context)
+                .map(|summary| ForwardSummary {
+                    traffic: context.status.snapshot().traffic,
+                    ..summary
+                });
+            // Only this target failed; the others keep running.
+            if let Err(e) = &result {
+                context.status.error(e.to_string());
+                context.status.failed();
+                let message = format!("Port-forward failed: {e}");
+                context
+                    .out_tx
+                    .send(ChildEvent::Output(
+                        context.id,
+                        StreamSource::StdErr,
+                        message,
+                    ))
+                    .ok();
+            }
+            context.status.stopped();
+
+            result
+        })
+    }
+
+    /// Ensures that kubectl can be spawned, e.g. that its binary wasn't removed during an upgrade.
+    pub fn ensure_spawnable(&self) -> Result<(), SpawnError> {
+        which::which_in(
+            &self.kubectl,
+            Some(Self::get_env_path(&self.current_dir)),
+            &self.current_dir,
+        )
+        .map(|_| ())
+        .map_err(|e| SpawnError::NotFound(self.kubectl.clone(), e))
+    }
+
+    /// Runs the port-forward, restarting it whenever it exits,
+    /// until a shutdown is requested.
+    fn run_port_forward_loop(
+        &self,
+        config: OperationalConfig,
+        mut fwd_config: PortForwardConfig,
+        context: &ForwardContext,
+    ) -> Result<ForwardSummary, anyhow::Error> {
+        let ForwardContext {
+            id,
+            out_tx,
+            shutdown,
+            session,
+            status,
+            commands,
+            commands_tx: _,
+            backoff,
+            dependencies,
+        } = context;
+        let id = *id;
+        let retry_delay_sec = config.retry_delay_sec.expect("retry_delay_sec exists");
+        let restart = fwd_config.restart.unwrap_or_default();
+        let mut pod_selector = None;
+        let on_ready_ran = Arc::new(AtomicBool::new(false));
+        let event_ready_ran = Arc::new(AtomicBool::new(false));
+        let mut forwarded_ports = Vec::new();
+
+        let on_port_conflict = fwd_config.on_port_conflict.unwrap_or_default();
+        let mut summary = ForwardSummary::default();
+
+        // Continue counting the retries of targets that kept failing in a previous session,
+        // and don't start them again right away.
+        let previous = backoff.get(&fwd_config);
+        let mut retries = RetryBudget::new(fwd_config.max_retries, previous.retries);
+        if previous.retries > 0
+            && retry_delay_sec > Seconds::ZERO
+            && Self::wait_for_retry(retry_delay_sec.into(), shutdown, commands, None)
+        {
+            return Ok(summary);
+        }
+
+        // Targets behind e.g. a forwarded auth proxy are useless until the proxy accepts connections.
+        if dependencies::wait_for(id, dependencies, out_tx, shutdown) {
+            return Ok(summary);
+        }
+
+        // Remapped ports don't change the identity of the target.
+        let target = fwd_config.clone();
+        let mut try_retry = |forwarded: bool| {
+            let retry = retries.try_retry(forwarded);
+            backoff.record(&target, retries.backoff());
+            retry
+        };
+
+        // Forward to every ready pod of a deployment, taking turns between them in the proxy.
+        let load_balance = fwd_config.load_balance == Some(true);
+        if load_balance && !fwd_config.is_load_balanced() {
+            let message =
+                "Only the pods of deployments can be load balanced - forwarding to a single pod";
+            out_tx
+                .send(ChildEvent::Output(id, StreamSource::StdErr, message.into()))
+                .ok();
+        }
+
+        // Speak TLS on the local ports; kubectl, or the proxy, listens on plain loopback ports behind them.
+        let tls = match fwd_config.tls.clone() {
+            Some(tls) => match self.start_tls(&tls, &mut fwd_config, context) {
+                Ok(terminator) => Some(terminator),
+                Err(e) => {
+                    status.error(e.to_string());
+                    status.failed();
+                    out_tx.send(ChildEvent::Error(id, e.into())).ok();
+                    return Ok(summary);
+                }
+            },
+            None => None,
+        };
+
+        // Own the local ports such that clients don't see refused connections while kubectl restarts.
+        let proxy = if fwd_config.proxy == Some(true) || load_balance {
+            match Self::bind_proxy(&mut fwd_config, on_port_conflict, context) {
+                Some(proxy) => Some(proxy),
+                None => return Ok(summary),
+            }
+        } else {
+            None
+        };
+
+        let mut bootstrap = true;
+        let mut restart_requested = false;
+        let mut attempt = 0;
+        'new_process: loop {
+            // Only delay start at the second iteration, unless a restart was requested.
+            if !bootstrap {
+                if !restart_requested
+                    && retry_delay_sec > Seconds::ZERO
+                    && Self::wait_for_retry(
+                        retry_delay_sec.into(),
+                        shutdown,
+                        commands,
+                        proxy.as_ref(),
+                    )
+                {
+                    break 'new_process;
+                }
+                summary.restarts += 1;
+            }
+            restart_requested = false;
+
+            if shutdown.is_requested() {
+                break 'new_process;
+            }
+
+            // Ports moved by remapping the target while waiting to retry apply to the new process.
+            if let Some(proxy) = &proxy {
+                for (port, local) in fwd_config.ports.iter_mut().zip(proxy.local_ports()) {
+                    port.local = Some(local);
+                }
+            }
+
+            // Remember how to find the pod's replacement while it still exists.
+            if bootstrap && fwd_config.r#type == ResourceType::Pod && fwd_config.via.is_none() {
+                pod_selector = self
+                    .pod_selector(
+                        fwd_config.context.as_ref(),
+                        fwd_config.cluster.as_ref(),
+                        &fwd_config.namespace,
+                        &fwd_config.target,
+                    )
+                    .ok()
+                    .flatten();
+            }
+
+            status.started(!bootstrap);
+            bootstrap = false;
+            attempt += 1;
+            out_tx.send(ChildEvent::Attempt(id, attempt)).ok();
+
+            // When forwarding through a gateway, set up the relays first.
+            let (relays, relay_readers) = match &fwd_config.via {
+                Some(gateway) => {
+                    match self.start_gateway_relays(&fwd_config, gateway, &config, context) {
+                        Ok(relays) => relays,
+                        Err(e) => {
+                            status.error(e.to_string());
+                            out_tx.send(ChildEvent::Error(id, e)).ok();
+                            if !restart.should_restart(false) || try_retry(false).is_none() {
+                                break 'new_process;
+                            }
+                            continue 'new_process;
+                        }
+                    }
+                }
+                None => (Vec::new(), Vec::new()),
+            };
+
+            // Don't start kubectl while no pod could accept the connections yet.
+            if fwd_config.wait_ready == Some(true) {
+                let timeout = fwd_config
+                    .ready_timeout_sec
+                    .map_or(DEFAULT_READY_TIMEOUT, Duration::from);
+                match pod_watch::wait_ready(self, &fwd_config, timeout, shutdown) {
+                    Ok(Some(_)) => {}
+                    Ok(None) if shutdown.is_requested() => break 'new_process,
+                    Ok(None) => {
+                        status.error(format!(
+                            "No pod became ready within {secs} sec",
+                            secs = timeout.as_secs_f64()
+                        ));
+                        out_tx.send(ChildEvent::NotReady(id, timeout)).ok();
+                        if !restart.should_restart(false) || try_retry(false).is_none() {
+                            break 'new_process;
+                        }
+                        continue 'new_process;
+                    }
+                    Err(e) => {
+                        let message = format!("Unable to determine whether the pod is ready: {e}");
+                        out_tx
+                            .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                            .ok();
+                    }
+                }
+            }
+
+            // List the pods anew on each attempt, e.g. after scaling the deployment.
+            let pods = match fwd_config.is_load_balanced() {
+                true => pod_watch::ready_pods(self, &fwd_config).unwrap_or_else(|e| {
+                    let message = format!("Unable to list the pods of the target: {e}");
+                    out_tx
+                        .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                        .ok();
+                    Vec::new()
+                }),
+                false => Vec::new(),
+            };
+            if pods.len() > 1 {
+                out_tx.send(ChildEvent::Balancing(id, pods.clone())).ok();
+            }
+
+            // The binary may be missing for a moment, e.g. during an upgrade of kubectl.
+            let spawned =
+                self.spawn_port_forward(&fwd_config, &config, proxy.as_ref(), &pods, status);
+            let SpawnedForward {
+                mut child,
+                local_ports,
+                replica_ports,
+            } = match spawned {
+                Ok(spawned) => spawned,
+                Err(e) => {
+                    status.error(e.to_string());
+                    out_tx.send(ChildEvent::Error(id, e)).ok();
+                    if !restart.should_restart(false) || try_retry(false).is_none() {
+                        break 'new_process;
+                    }
+                    continue 'new_process;
+                }
+            };
+            let started = Instant::now();
+            status.spawned();
+
+            // Forward to the remaining pods next to it.
+            let replicas =
+                self.spawn_replicas(&fwd_config, &config, &pods, &replica_ports, context);
+
+            // Remember the process in case this session crashes and leaves it behind.
+            let pid = child.id();
+            session
+                .track_process(ChildProcess {
+                    pid,
+                    ports: local_ports.into_iter().flatten().collect(),
+                })
+                .ok();
+
+            // Read stdout and stderr in separate threads.
+            let on_ready = self.on_ready(
+                &fwd_config,
+                &config,
+                [&on_ready_ran, &event_ready_ran],
+                context,
+            );
+            let stdout_reader = Self::handle_pipe(
+                id,
+                out_tx.clone(),
+                child.stdout.take(),
+                StreamSource::StdOut,
+                status.clone(),
+                on_ready,
+            );
+
+            let stderr_reader = Self::handle_pipe(
+                id,
+                out_tx.clone(),
+                child.stderr.take(),
+                StreamSource::StdErr,
+                status.clone(),
+                None,
+            );
+
+            let mut child = ChildGuard(child);
+            let mut watchers = Watchers::start(self, &fwd_config, context);
+
+            // Wait for the child process to finish, or terminate it on shutdown once the
+            // in-flight connections are drained.
+            let drain_timeout = fwd_config.drain_timeout_sec.map(Duration::from);
+            let outcome = child.wait_or_terminate(
+                shutdown,
+                commands,
+                proxy.as_ref(),
+                drain_timeout,
+                |index, local| {
+                    // Check the port the proxy listens on now.
+                    fwd_config.ports[index].local = Some(local);
+                    watchers.restart_health_check(&fwd_config, context);
+                },
+            );
+            summary.uptime += started.elapsed();
+            let local_ports = status.snapshot().local_ports;
+            if !local_ports.is_empty() {
+                forwarded_ports = local_ports;
+            }
+            if let Some(latency) = status.latency() {
+                summary.established(latency);
+            }
+
+            // Ensure the processes are gone before collecting the remaining output.
+            drop(watchers);
+            drop(child);
+            drop(relays);
+            session.untrack_process(pid).ok();
+            if let Some(replicas) = replicas {
+                for pid in replicas.pids() {
+                    session.untrack_process(*pid).ok();
+                }
+            }
+            let mut fatal = None;
+            for reader in [stdout_reader, stderr_reader]
+                .into_iter()
+                .chain(relay_readers)
+                .flatten()
+            {
+                let error = reader.join().ok().flatten();
+                fatal = fatal.or(error.filter(|e| e.is_fatal_for(&fwd_config)));
+            }
+
+            let exit_status = match outcome {
+                Ok(WaitOutcome::Exited(exit_status)) => exit_status,
+                Ok(WaitOutcome::Terminated) => break 'new_process,
+                Ok(WaitOutcome::RestartRequested) => {
+                    restart_requested = true;
+                    continue 'new_process;
+                }
+                Err(e) => {
+                    let forwarded = status.is_forwarding();
+                    status.error(e.to_string());
+                    out_tx.send(ChildEvent::Error(id, ChildError::Wait(e))).ok();
+                    if !restart.should_restart(false) || try_retry(forwarded).is_none() {
+                        break 'new_process;
+                    }
+                    continue 'new_process;
+                }
+            };
+
+            // Don't announce a restart if the process was taken down by the shutdown signal.
+            if shutdown.is_requested() {
+                break 'new_process;
+            }
+
+            let forwarded = status.is_forwarding();
+            status.exited(&exit_status);
+
+            // Behind the proxy, kubectl listens on different ports after restarting.
+            if let Some(proxy) = &proxy {
+                proxy.pause();
+                fatal = fatal.filter(|error| !matches!(error, KubectlError::AddressInUse(_)));
+            }
+
+            // Retrying won't fix errors in the configuration or the credentials,
+            // unless their cause can be dealt with right away.
+            if let Some(error) = fatal {
+                let recovery = self.recover(
+                    error,
+                    &mut fwd_config,
+                    on_port_conflict,
+                    pod_selector.as_deref(),
+                    context,
+                );
+                let error = match recovery {
+                    Recovery::Restart => {
+                        restart_requested = true;
+                        continue 'new_process;
+                    }
+                    Recovery::Fail(error) => error,
+                };
+
+                out_tx
+                    .send(ChildEvent::Exit(
+                        id,
+                        exit_status,
+                        RestartPolicy::WillNotRestart,
+                    ))
+                    .ok();
+                status.error(error.to_string());
+                status.failed();
+                self.run_event_hook(
+                    "on_permanent_failure",
+                    &config.hooks.on_permanent_failure,
+                    &fwd_config,
+                    &forwarded_ports,
+                    Some(error.to_string()),
+                    context,
+                );
+                out_tx.send(ChildEvent::Fatal(id, error)).ok();
+                break 'new_process;
+            }
+
+            let retry = match restart.should_restart(exit_status.success()) {
+                true => try_retry(forwarded),
+                false => None,
+            };
+            let Some(retry) = retry else {
+                out_tx
+                    .send(ChildEvent::Exit(
+                        id,
+                        exit_status,
+                        RestartPolicy::WillNotRestart,
+                    ))
+                    .ok();
+                break 'new_process;
+            };
+
+            self.run_event_hook(
+                "on_restart",
+                &config.hooks.on_restart,
+                &fwd_config,
+                &forwarded_ports,
+                None,
+                context,
+            );
+
+            // Switch to another pod right away rather than after the retry delay.
+            if fwd_config.failover == Some(true) && forwarded {
+                out_tx
+                    .send(ChildEvent::Exit(
+                        id,
+                        exit_status,
+                        RestartPolicy::WillRestartIn(Seconds::ZERO, retry),
+                    ))
+                    .ok();
+                restart_requested = true;
+                continue 'new_process;
+            }
+
+            out_tx
+                .send(ChildEvent::Exit(
+                    id,
+                    exit_status,
+                    RestartPolicy::WillRestartIn(retry_delay_sec, retry),
+                ))
+                .ok();
+        }
+
+        self.finish(
+            &config,
+            &fwd_config,
+            tls,
+            &forwarded_ports,
+            &retries,
+            context,
+        );
+
+        Ok(summary)
+    }
+
+    /// Cleans up once the port-forward stopped for good: runs the `on_stop` command and
+    /// gives up on the target if it ran out of retries.
+    fn finish(
+        &self,
+        config: &OperationalConfig,
+        fwd_config: &PortForwardConfig,
+        tls: Option<TlsTerminator>,
+        forwarded_ports: &[u16],
+        retries: &RetryBudget,
+        context: &ForwardContext,
+    ) {
+        let ForwardContext {
+            id,
+            out_tx,
+            session,
+            status,
+            backoff,
+            ..
+        } = context;
+        let id = *id;
+
+        if let Some(tls) = tls {
+            for pid in tls.pids() {
+                session.untrack_process(pid).ok();
+            }
+        }
+
+        // Run the `on_stop` command to completion, such that it isn't cut short by the exit of k8sfwd.
+        if let Some(hook) = fwd_config
+            .on_stop
+            .as_ref()
+            .filter(|_| !forwarded_ports.is_empty())
+        {
+            let waiter = hooks::run(
+                self,
+                "on_stop",
+                &hook.command,
+                id,
+                fwd_config,
+                hooks::hook_env(id, fwd_config, forwarded_ports),
+                out_tx.clone(),
+            );
+            if let Some(waiter) = waiter {
+                waiter.join().ok();
+            }
+        }
+
+        // Give up rather than retrying forever; the thread ends here.
+        if retries.is_exhausted() {
+            status.failed();
+            let error = format!(
+                "Giving up after {retries} retries",
+                retries = retries.retries
+            );
+            self.run_event_hook(
+                "on_permanent_failure",
+                &config.hooks.on_permanent_failure,
+                fwd_config,
+                forwarded_ports,
+                Some(error),
+                context,
+            );
+            out_tx.send(ChildEvent::Failed(id, retries.retries)).ok();
+        } else if status.is_forwarding() {
+            backoff.record(fwd_config, Backoff::default());
+        }
+    }
+
+    /// Runs the command of the `hooks` setting for the event, passing the error that caused it, if any.
+    fn run_event_hook(
+        &self,
+        event: &'static str,
+        hook: &Option<HookCommand>,
+        target: &PortForwardConfig,
+        local_ports: &[u16],
+        error: Option<String>,
+        context: &ForwardContext,
+    ) {
+        if let Some(hook) = hook {
+            let mut env = hooks::hook_env(context.id, target, local_ports);
+            env.extend(error.map(|error| ("K8SFWD_ERROR".to_string(), error)));
+            hooks::run(
+                self,
+                event,
+                &hook.command,
+                context.id,
+                target,
+                env,
+                context.out_tx.clone(),
+            );
+        }
+    }
+
+    /// Terminates TLS on the local ports of the target, remembering the processes in case
+    /// this session crashes and leaves them behind.
+    fn start_tls(
+        &self,
+        tls: &TlsConfig,
+        fwd_config: &mut PortForwardConfig,
+        context: &ForwardContext,
+    ) -> Result<TlsTerminator, TlsError> {
+        let terminator = TlsTerminator::start(
+            context.id,
+            tls,
+            fwd_config,
+            &self.current_dir,
+            &context.status,
+            context.out_tx.clone(),
+        )?;
+        for pid in terminator.pids() {
+            context
+                .session
+                .track_process(ChildProcess {
+                    pid,
+                    ports: terminator.local_ports().to_vec(),
+                })
+                .ok();
+        }
+        Ok(terminator)
+    }
+
+    /// Listens on the local ports of the target with the proxy, moving to the next free ports
+    /// if they are in use and remapping is enabled.
+    ///
+    /// Returns `None` if the ports can't be listened on, which is reported as a failure of the target.
+    fn bind_proxy(
+        fwd_config: &mut PortForwardConfig,
+        on_port_conflict: PortConflict,
+        context: &ForwardContext,
+    ) -> Option<Proxy> {
+        let ForwardContext {
+            id, out_tx, status, ..
+        } = context;
+        loop {
+            let error = match Proxy::bind(fwd_config, status) {
+                Ok(proxy) => return Some(proxy),
+                Err(e) => e,
+            };
+
+            let Some(port) = error.port_in_use() else {
+                status.error(error.to_string());
+                status.failed();
+                out_tx.send(ChildEvent::Error(*id, error.into())).ok();
+                return None;
+            };
+
+            let free = match on_port_conflict {
+                PortConflict::Remap => preflight::next_free_port(&fwd_config.listen_addrs, port),
+                _ => None,
+            };
+            let Some(free) = free else {
+                let error = KubectlError::AddressInUse(port);
+                status.error(error.to_string());
+                status.failed();
+                out_tx.send(ChildEvent::Fatal(*id, error)).ok();
+                return None;
+            };
+
+            for mapping in &mut fwd_config.ports {
+                if mapping.local == Some(port) {
+                    mapping.local = Some(free);
+                }
+            }
+            out_tx.send(ChildEvent::Remapped(*id, port, free)).ok();
+        }
+    }
+
+    /// Ensures the gateway pod exists and relays each port of the target through it.
+    ///
+    /// Returns the relay processes along with the threads reading their errors.
+    fn start_gateway_relays(
+        &self,
+        fwd_config: &PortForwardConfig,
+        gateway: &GatewayConfig,
+        config: &OperationalConfig,
+        context: &ForwardContext,
+    ) -> Result<(Vec<ChildGuard>, Vec<PipeReader>), ChildError> {
+        self.ensure_gateway_pod(fwd_config.context.as_ref(), gateway, &context.session)?;
+        let destination = self.gateway_destination(fwd_config)?;
+
+        let mut relays = Vec::new();
+        let mut readers = Vec::new();
+        for port in &fwd_config.ports {
+            let mut relay = self
+                .spawn_gateway_relay(
+                    fwd_config,
+                    gateway,
+                    &destination,
+                    port,
+                    config.child_niceness,
+                )
+                .map_err(ChildError::Spawn)?;
+            readers.push(Self::handle_pipe(
+                context.id,
+                context.out_tx.clone(),
+                relay.stderr.take(),
+                StreamSource::StdErr,
+                context.status.clone(),
+                None,
+            ));
+            relays.push(ChildGuard(relay));
+        }
+        Ok((relays, readers))
+    }
+
+    /// Starts kubectl for the target, forwarding to the first of the pods if load balanced.
+    fn spawn_port_forward(
+        &self,
+        fwd_config: &PortForwardConfig,
+        config: &OperationalConfig,
+        proxy: Option<&Proxy>,
+        pods: &[String],
+        status: &StatusHandle,
+    ) -> Result<SpawnedForward, ChildError> {
+        let mut command = self.command();
+        Self::apply_env(&mut command, fwd_config);
+        Self::apply_niceness(&mut command, config.child_niceness);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(["port-forward"]);
+
+        // the context to use; port-forward doesn't accept a cluster, which is resolved
+        // to its context when sanitizing the configuration
+        Self::apply_context(&mut command, fwd_config.context.as_ref(), None);
+        Self::apply_request_timeout(&mut command, fwd_config.request_timeout_sec);
+
+        // which addresses to listen on locally; behind the proxy, kubectl listens on new
+        // loopback ports each time
+        let mut replica_ports = Vec::new();
+        let local_ports = match proxy {
+            Some(proxy) => {
+                let mut routes = proxy
+                    .route(pods.len())
+                    .map_err(ChildError::Spawn)?
+                    .into_iter();
+                let upstream = routes.next().unwrap_or_default();
+                replica_ports.extend(routes);
+                command.args(["--address", &proxy::UPSTREAM_ADDRESS.to_string()]);
+                status.proxied(upstream.iter().copied().zip(proxy.local_ports()));
+                upstream.into_iter().map(Some).collect()
+            }
+            None => {
+                match &fwd_config.listen_addrs[..] {
+                    [] => {}
+                    addresses => {
+                        let addresses = addresses.join(",");
+                        command.args(["--address", &addresses]);
+                    }
+                };
+                fwd_config
+                    .ports
+                    .iter()
+                    .map(|port| port.local)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        if let Some(gateway) = &fwd_config.via {
+            // the gateway pod and its namespace
+            command.args(["-n", &gateway.namespace]);
+            command.arg(format!("pod/{pod}", pod = gateway.pod));
+        } else if let Some(pod) = pods.first() {
+            // the first of the pods to balance across
+            command.args(["-n", &fwd_config.namespace]);
+            command.arg(format!("pod/{pod}"));
+        } else {
+            // the namespace to select
+            command.args(["-n", &fwd_config.namespace]);
+
+            // pod/name, deployment/name, service/name
+            command.arg(format!(
+                "{resource}/{name}",
+                resource = fwd_config.r#type.as_arg(),
+                name = fwd_config.target
+            ));
+        }
+
+        // Apply the port bindings
+        for (port, local) in fwd_config.ports.iter().zip(&local_ports) {
+            // Gateways expose the remote port on their relay port.
+            let remote = match fwd_config.via {
+                Some(_) => Self::gateway_relay_port(fwd_config, port),
+                None => port.remote,
+            };
+
+            let value = if let Some(local) = local {
+                format!("{local}:{remote}")
+            } else {
+                format!(":{remote}")
+            };
+
+            command.arg(&value);
+        }
+
+        let child = command.spawn().map_err(ChildError::Spawn)?;
+        Ok(SpawnedForward {
+            child,
+            local_ports,
+            replica_ports,
+        })
+    }
+
+    /// Forwards to the pods after the first one on the given ports of the proxy.
+    ///
+    /// Pods that can't be forwarded to are reported and left out.
+    fn spawn_replicas(
+        &self,
+        fwd_config: &PortForwardConfig,
+        config: &OperationalConfig,
+        pods: &[String],
+        replica_ports: &[Vec<u16>],
+        context: &ForwardContext,
+    ) -> Option<Replicas> {
+        let ForwardContext {
+            id,
+            out_tx,
+            session,
+            commands_tx,
+            ..
+        } = context;
+        let mut replicas = Vec::new();
+        for (pod, ports) in pods.iter().skip(1).zip(replica_ports) {
+            match self.spawn_replica(fwd_config, pod, ports, config.child_niceness) {
+                Ok(replica) => {
+                    session
+                        .track_process(ChildProcess {
+                            pid: replica.id(),
+                            ports: ports.clone(),
+                        })
+                        .ok();
+                    replicas.push((pod.clone(), replica));
+                }
+                Err(e) => {
+                    let message = format!("Unable to forward to pod {pod}: {e}");
+                    out_tx
+                        .send(ChildEvent::Output(*id, StreamSource::StdErr, message))
+                        .ok();
+                }
+            }
+        }
+        (!replicas.is_empty())
+            .then(|| Replicas::start(*id, replicas, out_tx.clone(), commands_tx.clone()))
+    }
+
+    /// Builds the callback that runs the `on_ready` commands and probes the ports once kubectl
+    /// listens on all ports.
+    ///
+    /// The flags record whether the `on_ready` command of the target and of the `hooks`
+    /// setting ran, such that they only run again on restarts if they are to repeat.
+    fn on_ready(
+        &self,
+        fwd_config: &PortForwardConfig,
+        config: &OperationalConfig,
+        ran: [&Arc<AtomicBool>; 2],
+        context: &ForwardContext,
+    ) -> Option<OnReady> {
+        let ready_hooks: Vec<_> = [fwd_config.on_ready.clone(), config.hooks.on_ready.clone()]
+            .into_iter()
+            .zip(ran)
+            .filter_map(|(hook, ran)| hook.map(|hook| (hook, ran.clone())))
+            .collect();
+        let protocol = fwd_config.protocol;
+        let detect_tls = fwd_config.detect_tls == Some(true);
+        if ready_hooks.is_empty() && protocol.is_none() && !detect_tls {
+            return None;
+        }
+
+        let kubectl = self.clone();
+        let config = fwd_config.clone();
+        let id = context.id;
+        let status = context.status.clone();
+        let out_tx = context.out_tx.clone();
+        Some(Box::new(move || {
+            if let Some(protocol) = protocol {
+                passthrough::check(id, protocol, &config, status.clone(), out_tx.clone());
+            }
+            if detect_tls {
+                encryption::probe_ports(id, &config, status.clone(), out_tx.clone());
+            }
+
+            let local_ports = status.snapshot().local_ports;
+            for (hook, ran) in ready_hooks {
+                if ran.swap(true, Ordering::SeqCst) && !hook.repeat {
+                    continue;
+                }
+
+                hooks::run(
+                    &kubectl,
+                    "on_ready",
+                    &hook.command,
+                    id,
+                    &config,
+                    hooks::hook_env(id, &config, &local_ports),
+                    out_tx.clone(),
+                );
+            }
+        }))
+    }
+
+    /// Deals with an error reported by kubectl that retrying won't fix, if possible.
+    ///
+    /// Ports held by kubectl processes of crashed sessions are taken over, ports held by other
+    /// processes are moved out of the way, and pods that were replaced, e.g. by a rollout,
+    /// are followed, depending on the configuration.
+    fn recover(
+        &self,
+        error: KubectlError,
+        fwd_config: &mut PortForwardConfig,
+        on_port_conflict: PortConflict,
+        pod_selector: Option<&str>,
+        context: &ForwardContext,
+    ) -> Recovery {
+        let ForwardContext {
+            id, out_tx, status, ..
+        } = context;
+        let id = *id;
+        let mut error = error;
+
+        // Take over ports held by kubectl processes of crashed sessions, or point them out.
+        if let KubectlError::AddressInUse(port) = error {
+            if let Some(orphan) = Session::find_orphan(port) {
+                if on_port_conflict == PortConflict::Reclaim
+                    && session::terminate_process(orphan).is_ok()
+                {
+                    out_tx.send(ChildEvent::Reclaimed(id, port, orphan)).ok();
+                    return Recovery::Restart;
+                }
+
+                if on_port_conflict != PortConflict::Remap {
+                    error = KubectlError::HeldByOrphan(port, orphan);
+                }
+            }
+        }
+
+        // Move out of the way of other processes listening on the local port.
+        if let KubectlError::AddressInUse(port) = error {
+            if on_port_conflict == PortConflict::Remap {
+                if let Some(free) = preflight::next_free_port(&fwd_config.listen_addrs, port) {
+                    for mapping in &mut fwd_config.ports {
+                        if mapping.local == Some(port) {
+                            mapping.local = Some(free);
+                        }
+                    }
+
+                    out_tx.send(ChildEvent::Remapped(id, port, free)).ok();
+                    return Recovery::Restart;
+                }
+            }
+        }
+
+        // Follow pods that were replaced, e.g. by a rollout.
+        if let (KubectlError::ResourceNotFound { kind, name }, Some(selector)) =
+            (&error, pod_selector)
+        {
+            if kind == "pod" && name == &fwd_config.target {
+                let replacement = self
+                    .find_pod(
+                        fwd_config.context.as_ref(),
+                        fwd_config.cluster.as_ref(),
+                        &fwd_config.namespace,
+                        selector,
+                    )
+                    .ok()
+                    .flatten();
+
+                if let Some(pod) = replacement {
+                    let previous = std::mem::replace(&mut fwd_config.target, pod.clone());
+                    status.pod(format!(
+                        "{pod}.{namespace}",
+                        namespace = fwd_config.namespace
+                    ));
+                    out_tx.send(ChildEvent::PodReplaced(id, previous, pod)).ok();
+                    return Recovery::Restart;
+                }
+            }
+        }
+
+        Recovery::Fail(error)
+    }
+
+    /// Waits for the retry delay to pass.
+    ///
+    /// Returns `true` if a shutdown was requested, or `false` once the delay
+    /// passed or a restart was requested.
+    fn wait_for_retry(
+        delay: Duration,
+        shutdown: &ShutdownSignal,
+        commands: &Receiver<ForwardCommand>,
+        proxy: Option<&Proxy>,
+    ) -> bool {
+        let deadline = Instant::now() + delay;
+        loop {
+            if shutdown.is_requested() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            match commands.recv_timeout(CHILD_POLL_INTERVAL.min(deadline - now)) {
+                Ok(ForwardCommand::Restart) => return false,
+                Ok(ForwardCommand::Rebind(rebind)) => {
+                    rebind.apply(proxy);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return shutdown.sleep(deadline - now),
+            }
+        }
+    }
+
+    /// Creates a new kubectl command.
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.kubectl);
+        command
+            .env("PATH", Self::get_env_path(&self.current_dir))
+            .envs(self.kubeconfig_env())
+            .current_dir(&self.current_dir);
+        command
+    }
+
+    /// Creates a command running a user-provided command line through the system shell.
+    ///
+    /// The path of the kubectl binary is provided in the `KUBECTL` environment variable.
+    pub fn shell_command(&self, command_line: &str) -> Command {
+        #[cfg(unix)]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.args(["-c", command_line]);
+            command
+        };
+
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.args(["/C", command_line]);
+            command
+        };
+
+        command
+            .env("PATH", Self::get_env_path(&self.current_dir))
+            .env("KUBECTL", &self.kubectl)
+            .envs(self.kubeconfig_env())
+            .current_dir(&self.current_dir);
+        command
+    }
+
+    /// Applies the environment settings of a target to a command spawned for it.
+    ///
+    /// Unless the target inherits the environment, all inherited variables except for
+    /// the [`ESSENTIAL_ENV`] are removed; variables set on the command itself are kept.
+    pub fn apply_env(command: &mut Command, fwd_config: &PortForwardConfig) {
+        if fwd_config.inherit_env == Some(false) {
+            let explicit: HashSet<_> = command.get_envs().map(|(key, _)| key.to_owned()).collect();
+            for (key, _) in std::env::vars_os() {
+                let essential = ESSENTIAL_ENV.iter().any(|name| key == *name);
+                if !essential && !explicit.contains(&key) {
+                    command.env_remove(key);
+                }
+            }
+        }
+
+        command.envs(&fwd_config.env);
+    }
+
+    /// Lowers the scheduling priority of the spawned process by the niceness, if specified,
+    /// such that many forwards don't compete with other work for the CPU.
+    fn apply_niceness(command: &mut Command, niceness: Option<u8>) {
+        let niceness = match niceness {
+            Some(niceness) if niceness > 0 => niceness,
+            _ => return,
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            // Failing to lower the priority is not worth failing the forward over.
+            unsafe {
+                command.pre_exec(move || {
+                    libc::nice(niceness as libc::c_int);
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x00004000;
+            const IDLE_PRIORITY_CLASS: u32 = 0x00000040;
+            command.creation_flags(match niceness {
+                1..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+                _ => IDLE_PRIORITY_CLASS,
+            });
+        }
+    }
+
+    /// Limits how long kubectl waits for a response of the Kubernetes API, if configured.
+    fn apply_request_timeout(command: &mut Command, timeout: Option<Seconds>) {
+        let Some(timeout) = timeout.map(Duration::from) else {
+            return;
+        };
+
+        // kubectl treats a timeout of zero as no timeout at all.
+        if !timeout.is_zero() {
+            command.arg(format!(
+                "--request-timeout={}ms",
+                timeout.as_millis().max(1)
+            ));
+        }
+    }
+
+    /// Selects the context and cluster to use, if specified.
+    fn apply_context(command: &mut Command, context: Option<&String>, cluster: Option<&String>) {
+        // the context to use
+        if let Some(context) = context {
+            command.args(["--context", context]);
+        }
+
+        // the cluster to use
+        if let Some(cluster) = cluster {
+            command.args(["--cluster", cluster]);
+        }
+    }
+
+    fn get_env_path(current_dir: &Path) -> String {
+        let mut path = std::env::var("PATH").unwrap_or_else(|_| String::new());
+        if !path.is_empty() {
+            path.push(ENV_PATH_SEPARATOR);
+        }
+        path.push_str(&current_dir.display().to_string());
+        path
+    }
+
+    fn handle_pipe<T: Read + Send + 'static>(
+        id: ConfigId,
+        out_tx: Sender<ChildEvent>,
+        pipe: Option<T>,
+        source: StreamSource,
+        status: StatusHandle,
+        mut on_ready: Option<OnReady>,
+    ) -> PipeReader {
+        pipe.map(|pipe| {
+            thread::spawn(move || {
+                let mut fatal = None;
+                let mut reader = io::BufReader::new(pipe);
+                while let Ok(Some(mut line)) = read_line_lossy(&mut reader, MAX_LINE_LENGTH) {
+                    if source == StreamSource::StdOut {
+                        status.connection_handled(&line);
+                    }
+
+                    let established = match source {
+                        StreamSource::StdOut if status.is_probe(&line) => continue,
+                        StreamSource::StdOut => {
+                            let established = status.output(&line);
+                            line = status.annotate(line);
+                            established
+                        }
+                        StreamSource::StdErr => {
+                            status.error(line.as_str());
+                            fatal = fatal.or_else(|| KubectlError::classify(&line));
+                            None
+                        }
+                    };
+                    out_tx.send(ChildEvent::Output(id, source, line)).ok();
+
+                    if let Some(latency) = established {
+                        out_tx.send(ChildEvent::Established(id, latency)).ok();
+                        if let Some(on_ready) = on_ready.take() {
+                            on_ready();
+                        }
+                    }
+                }
+
+                // Report the first error that retrying may not fix.
+                fatal
+            })
+        })
+    }
+}
+
+/// Reads the next line, replacing invalid UTF-8 and cutting lines longer than `max_len` bytes short,
+/// such that a single odd line doesn't end the output of a process.
+///
+/// Returns `None` at the end of the stream.
+pub fn read_line_lossy(reader: &mut impl BufRead, max_len: usize) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut truncated = false;
+    let mut eof = true;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            break;
+        }
+        eof = false;
+
+        let newline = available.iter().position(|&b| b == b'\n');
+        let chunk = &available[..newline.unwrap_or(available.len())];
+        let room = max_len.saturating_sub(line.len());
+        truncated |= chunk.len() > room;
+        line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+
+        let consumed = newline.map_or(available.len(), |newline| newline + 1);
+        reader.consume(consumed);
+        if newline.is_some() {
+            break;
+        }
+    }
+
+    if eof {
+        return Ok(None);
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    let mut line = String::from_utf8_lossy(&line).into_owned();
+    if truncated {
+        line.push_str(" [truncated]");
+    }
+    Ok(Some(line))
+}
+
+/// Called once kubectl listens on all ports of a target.
+type OnReady = Box<dyn FnOnce() + Send>;
+
+/// Reads the output of a process; returns the error reported in it that retrying won't fix, if any.
+type PipeReader = Option<JoinHandle<Option<KubectlError>>>;
+
+/// The handles a port-forward thread uses to communicate with the rest of the application.
+pub struct ForwardContext {
+    /// The ID of the target.
+    pub id: ConfigId,
+    /// Receives the output and lifecycle events of the kubectl process.
+    pub out_tx: Sender<ChildEvent>,
+    /// Stops the port-forward.
+    pub shutdown: ShutdownSignal,
+    /// Tracks the helper resources created for the port-forward.
+    pub session: Session,
+    /// Reports the state of the port-forward.
+    pub status: StatusHandle,
+    /// Receives commands for the individual port-forward.
+    pub commands: Receiver<ForwardCommand>,
+    /// Sends commands to the port-forward itself, e.g. to restart it when its pod is replaced.
+    pub commands_tx: Sender<ForwardCommand>,
+    /// Persists the retry state of the port-forward across sessions.
+    pub backoff: BackoffStore,
+    /// The targets that need to accept connections before kubectl is started.
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A command sent to an individual running port-forward.
+#[derive(Debug)]
+pub enum ForwardCommand {
+    /// Terminates the kubectl process and starts it again without delay.
+    Restart,
+    /// Moves a local port of the proxy while kubectl keeps running.
+    Rebind(Rebind),
+}
+
+/// Moves the target's port with the given index to another local port of the proxy.
+#[derive(Debug)]
+pub struct Rebind {
+    /// The index of the port in the target's ports.
+    pub index: usize,
+    /// The new local port; a free one is picked if unset.
+    pub local: Option<u16>,
+    /// Receives the local port listened on now.
+    pub reply: Sender<Result<u16, ProxyError>>,
+}
+
+impl Rebind {
+    /// Moves the port and replies with the outcome. If nobody waits for the reply anymore,
+    /// the previous port is restored.
+    ///
+    /// Returns the new local port if the port was moved.
+    fn apply(self, proxy: Option<&Proxy>) -> Option<u16> {
+        let Some(proxy) = proxy else {
+            self.reply.send(Err(ProxyError::NotProxied)).ok();
+            return None;
+        };
+
+        match proxy.rebind(self.index, self.local) {
+            Ok((previous, local)) => {
+                if self.reply.send(Ok(local)).is_err() {
+                    proxy.rebind(self.index, Some(previous)).ok();
+                    return None;
+                }
+                (previous != local).then_some(local)
+            }
+            Err(e) => {
+                self.reply.send(Err(e)).ok();
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ChildEvent {
+    /// A new attempt with the given one-based number is started, i.e. a new kubectl process.
+    ///
+    /// The readers of the previous process are joined before, such that all following
+    /// events of the target belong to this attempt.
+    Attempt(ConfigId, usize),
+    Output(ConfigId, StreamSource, String),
+    Exit(ConfigId, ExitStatus, RestartPolicy),
+    Error(ConfigId, ChildError),
+    /// The port-forward was given up after the given number of retries.
+    Failed(ConfigId, usize),
+    /// The kubectl process reported an error that retrying won't fix.
+    Fatal(ConfigId, KubectlError),
+    /// The differences between the configuration and the cluster changed.
+    Drift(ConfigId, Vec<String>),
+    /// A local port was in use and was replaced by the next free one.
+    Remapped(ConfigId, u16, u16),
+    /// A local port was held by the kubectl process with the given ID, left over from
+    /// a crashed session, which was terminated.
+    Reclaimed(ConfigId, u16, u32),
+    /// The pod to forward to disappeared and was replaced by a pod with the same labels.
+    PodReplaced(ConfigId, String, String),
+    /// The watched pod backing the port-forward was terminated.
+    PodTerminated(ConfigId, String),
+    /// No pod backing the port-forward became ready within the given time.
+    NotReady(ConfigId, Duration),
+    /// kubectl listens on all ports, the given time after it was started.
+    Established(ConfigId, Duration),
+    /// The health check of the port-forward kept failing; kubectl is restarted.
+    Unhealthy(ConfigId, String),
+    /// Connections are balanced across the given pods.
+    Balancing(ConfigId, Vec<String>),
+    /// The target was removed from the configuration on reload and is stopped.
+    Removed(ConfigId),
+    /// The given fields of the target changed on reload; it is restarted with the new configuration.
+    Reconfigured(ConfigId, Vec<&'static str>),
+}
+
+/// Statistics about a port-forward, collected until shutdown.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ForwardSummary {
+    /// The accumulated time the kubectl processes were running.
+    pub uptime: Duration,
+    /// The number of times the kubectl process was restarted.
+    pub restarts: usize,
+    /// The number of attempts in which kubectl listened on all ports.
+    pub established: usize,
+    /// The accumulated time from starting kubectl until it listened on all ports.
+    pub latency: Duration,
+    /// The longest time from starting kubectl until it listened on all ports.
+    pub max_latency: Duration,
+    /// The connections and bytes relayed by the proxy, if the target has one.
+    pub traffic: Option<Traffic>,
+}
+
+impl ForwardSummary {
+    /// Records the time an attempt took until kubectl listened on all ports.
+    fn established(&mut self, latency: Duration) {
+        self.established += 1;
+        self.latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    /// Gets the average time from starting kubectl until it listened on all ports.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        (self.established > 0).then(|| self.latency / self.established as u32)
+    }
+
+    /// Adds the statistics of a previous run of the same target, e.g. before a reload.
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            uptime: self.uptime + other.uptime,
+            restarts: self.restarts + other.restarts,
+            established: self.established + other.established,
+            latency: self.latency + other.latency,
+            max_latency: self.max_latency.max(other.max_latency),
+            traffic: match (self.traffic, other.traffic) {
+                (Some(traffic), Some(other)) => Some(traffic.combine(other)),
+                (traffic, other) => traffic.or(other),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RestartPolicy {
+    WillRestartIn(Seconds, Retry),
+    /// The maximum number of retries was exceeded.
+    WillNotRestart,
+}
+
+/// A retry of a port-forward that failed, counted since it last forwarded traffic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Retry {
+    /// The number of the retry, starting at 1.
+    pub number: usize,
+    /// The maximum number of retries, if limited.
+    pub max: Option<usize>,
+}
+
+impl Display for Retry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let number = self.number;
+        match self.max {
+            Some(max) if number >= max => write!(f, "attempt {number} of {max}, the last one"),
+            Some(max) => write!(
+                f,
+                "attempt {number} of {max}, {left} left",
+                left = max - number
+            ),
+            None => write!(f, "attempt {number}"),
+        }
+    }
+}
+
+/// Counts the consecutive failed attempts of a port-forward against its `max_retries`.
+#[derive(Debug)]
+struct RetryBudget {
+    max_retries: Option<usize>,
+    retries: usize,
+    exhausted: bool,
+}
+
+impl RetryBudget {
+    /// Creates a budget that already used up the specified number of retries.
+    fn new(max_retries: Option<usize>, retries: usize) -> Self {
+        Self {
+            max_retries,
+            retries,
+            exhausted: false,
+        }
+    }
+
+    /// Records the end of an attempt and determines whether another one may be started,
+    /// returning the retry if so.
+    ///
+    /// Attempts that got to forward traffic reset the count.
+    fn try_retry(&mut self, forwarded: bool) -> Option<Retry> {
+        if forwarded {
+            self.retries = 0;
+        }
+
+        if self.max_retries.is_some_and(|max| self.retries >= max) {
+            self.exhausted = true;
+            return None;
+        }
+
+        self.retries += 1;
+        Some(Retry {
+            number: self.retries,
+            max: self.max_retries,
+        })
+    }
+
+    /// Determines whether the maximum number of retries was exceeded.
+    fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Gets the state to persist across sessions.
+    fn backoff(&self) -> Backoff {
+        Backoff {
+            retries: self.retries,
+            failed: self.exhausted,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChildError {
+    /// Failed to wait for the child process' status.
+    #[error(transparent)]
+    Wait(#[from] io::Error),
+    /// Failed to start a process, or to bind the ports it listens on.
+    #[error("Unable to start kubectl: {0}")]
+    Spawn(io::Error),
+    /// Failed to set up the gateway pod.
+    #[error(transparent)]
+    Gateway(#[from] GatewayError),
+    /// Failed to listen on the local ports of the proxy.
+    #[error(transparent)]
+    Proxy(#[from] ProxyError),
+    /// Failed to terminate TLS on the local ports.
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamSource {
+    #[serde(rename = "stdout")]
+    StdOut,
+    #[serde(rename = "stderr")]
+    StdErr,
+}
+
+#[derive(Deserialize)]
+struct KubectlVersion {
+    #[serde(alias = "clientVersion")]
+    client_version: KubectlClientVersion,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct KubectlClientVersion {
+    major: String,
+    minor: String,
+    #[serde(alias = "gitVersion")]
+    git_version: String,
+}
+
+#[derive(Deserialize)]
+struct KubectlList {
+    items: Vec<KubectlItem>,
+}
+
+#[derive(Deserialize)]
+struct KubectlItem {
+    metadata: KubectlMetadata,
+}
+
+#[derive(Deserialize)]
+struct KubectlMetadata {
+    name: String,
+    namespace: Option<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+impl KubectlMetadata {
+    /// Determines whether the resource was created by k8sfwd.
+    fn is_managed(&self) -> bool {
+        let (key, value) = MANAGED_BY_LABEL
+            .split_once('=')
+            .expect("the label has a value");
+        self.labels.get(key).is_some_and(|label| label == value)
+    }
+
+    /// Gets the IDs of the sessions using a shared helper resource.
+    fn users(&self) -> Vec<String> {
+        self.annotations
+            .keys()
+            .filter_map(|key| key.strip_prefix(USER_ANNOTATION_PREFIX))
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShellError {
+    #[error(transparent)]
+    CommandFailed(#[from] io::Error),
+    #[error("kubectl failed: {0}")]
+    Failed(String),
+    #[error("The kubectl output could not be read")]
+    InvalidFormat(#[from] serde_json::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VersionError {
+    #[error("The version format could not be read")]
+    InvalidFormat(#[from] serde_json::Error),
+    #[error(transparent)]
+    CommandFailed(#[from] io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpawnError {
+    #[error("kubectl can't be run from {path}: {1}", path = .0.display())]
+    NotFound(PathBuf, which::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GatewayError {
+    #[error("The gateway pod {0} does not exist")]
+    PodNotFound(String),
+    #[error("The gateway pod {0} could not be launched: {1}")]
+    LaunchFailed(String, String),
+    #[error("Forwarding through a gateway is only supported for services and pods")]
+    UnsupportedResourceType,
+    #[error("The address of pod {0} could not be determined")]
+    UnknownPodAddress(String),
+    #[error("The gateway pod could not be tracked: {0}")]
+    TrackingFailed(io::Error),
+    #[error(transparent)]
+    CommandFailed(#[from] io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContextError {
+    #[error(transparent)]
+    CommandFailed(#[from] io::Error),
+}
+
+/// Labels that differ between the revisions or the instances of a workload.
+const INSTANCE_LABELS: [&str; 5] = [
+    "pod-template-hash",
+    "pod-template-generation",
+    "controller-revision-hash",
+    "statefulset.kubernetes.io/pod-name",
+    "apps.kubernetes.io/pod-index",
+];
+
+/// Builds a label selector from the labels a pod shares with the other pods of its workload.
+fn sibling_selector(pod: &serde_json::Value) -> Option<String> {
+    let labels = pod["metadata"]["labels"].as_object()?;
+    let selector: Vec<_> = labels
+        .iter()
+        .filter(|(key, _)| !INSTANCE_LABELS.contains(&key.as_str()))
+        .filter_map(|(key, value)| Some(format!("{key}={value}", value = value.as_str()?)))
+        .collect();
+
+    (!selector.is_empty()).then(|| selector.join(","))
+}
+
+/// Gets the name of the first running pod of a list that isn't being deleted.
+fn running_pod(list: &serde_json::Value) -> Option<String> {
+    list["items"]
+        .as_array()?
+        .iter()
+        .filter(|pod| pod["status"]["phase"] == "Running")
+        .filter(|pod| pod["metadata"]["deletionTimestamp"].is_null())
+        .find_map(|pod| pod["metadata"]["name"].as_str())
+        .map(String::from)
+}
+
+/// A guard to ensure the child process is terminated when the thread is cancelled.
+struct ChildGuard(process::Child);
+
+impl ChildGuard {
+    /// Waits for the child process to exit, or terminates it if a shutdown
+    /// or a restart is requested. Ports of the proxy moved in the meantime are passed
+    /// to the callback by their index, along with the new local port.
+    ///
+    /// On shutdown, the proxy is drained for up to the given timeout first, such that
+    /// in-flight connections can finish while the child still relays them.
+    pub fn wait_or_terminate(
+        &mut self,
+        shutdown: &ShutdownSignal,
+        commands: &Receiver<ForwardCommand>,
+        proxy: Option<&Proxy>,
+        drain_timeout: Option<Duration>,
+        mut on_rebound: impl FnMut(usize, u16),
+    ) -> io::Result<WaitOutcome> {
+        loop {
+            if let Some(status) = self.0.try_wait()? {
+                return Ok(WaitOutcome::Exited(status));
+            }
+
+            if shutdown.is_requested() {
+                if let (Some(proxy), Some(timeout)) = (proxy, drain_timeout) {
+                    proxy.drain(timeout);
+                }
+                self.terminate()?;
+                return Ok(WaitOutcome::Terminated);
+            }
+
+            match commands.try_recv() {
+                Ok(ForwardCommand::Restart) => {
+                    self.terminate()?;
+                    return Ok(WaitOutcome::RestartRequested);
+                }
+                Ok(ForwardCommand::Rebind(rebind)) => {
+                    let index = rebind.index;
+                    if let Some(local) = rebind.apply(proxy) {
+                        on_rebound(index, local);
+                    }
+                }
+                Err(_) => thread::sleep(CHILD_POLL_INTERVAL),
+            }
+        }
+    }
+
+    fn terminate(&mut self) -> io::Result<()> {
+        self.0.kill().ok();
+        self.0.wait()?;
+        Ok(())
+    }
+}
+
+/// The reason [`ChildGuard::wait_or_terminate`] returned.
+enum WaitOutcome {
+    /// The process exited on its own.
+    Exited(ExitStatus),
+    /// The process was terminated due to a shutdown.
+    Terminated,
+    /// The process was terminated to be restarted.
+    RestartRequested,
+}
+
+/// A kubectl process started by [`Kubectl::spawn_port_forward`].
+struct SpawnedForward {
+    child: process::Child,
+    /// The local ports kubectl listens on, if fixed.
+    local_ports: Vec<Option<u16>>,
+    /// The ports of the proxy the pods after the first one are to be forwarded to.
+    replica_ports: Vec<Vec<u16>>,
+}
+
+/// The outcome of [`Kubectl::recover`].
+enum Recovery {
+    /// The cause of the error was dealt with; kubectl is restarted right away.
+    Restart,
+    /// The error remains; the target is given up.
+    Fail(KubectlError),
+}
+
+/// The threads watching a running kubectl process; stopped when dropped.
+#[allow(dead_code)]
+struct Watchers {
+    /// Restarts kubectl as soon as the pod goes away, rather than once the tunnel breaks.
+    pod_watch: Option<PodWatch>,
+    /// Restarts kubectl once the tunnel stops accepting connections, even if kubectl keeps running.
+    health_check: Option<HealthCheck>,
+    /// Restarts kubectl before an idle tunnel is closed by the cluster without kubectl noticing.
+    idle_watch: Option<IdleWatch>,
+    /// Keeps the tunnel busy such that it isn't closed for being idle.
+    keepalive: Option<Keepalive>,
+}
+
+impl Watchers {
+    fn start(kubectl: &Kubectl, fwd_config: &PortForwardConfig, context: &ForwardContext) -> Self {
+        let ForwardContext {
+            id,
+            out_tx,
+            status,
+            commands_tx,
+            ..
+        } = context;
+        let id = *id;
+
+        let pod_watch = if fwd_config.watch == Some(true) {
+            PodWatch::start(
+                kubectl,
+                fwd_config,
+                id,
+                status,
+                out_tx.clone(),
+                commands_tx.clone(),
+            )
+            .unwrap_or_else(|e| {
+                let message = format!("Unable to watch the pod of the target: {e}");
+                out_tx
+                    .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                    .ok();
+                None
+            })
+        } else {
+            None
+        };
+        let health_check = Self::health_check(fwd_config, context);
+        let idle_watch = fwd_config.idle_timeout_sec.and_then(|timeout| {
+            IdleWatch::start(
+                id,
+                timeout,
+                status.clone(),
+                out_tx.clone(),
+                commands_tx.clone(),
+            )
+        });
+        let keepalive = Keepalive::start(
+            &fwd_config.keepalive.unwrap_or_default(),
+            &fwd_config.listen_addrs,
+            status.clone(),
+        );
+
+        Self {
+            pod_watch,
+            health_check,
+            idle_watch,
+            keepalive,
+        }
+    }
+
+    fn health_check(
+        fwd_config: &PortForwardConfig,
+        context: &ForwardContext,
+    ) -> Option<HealthCheck> {
+        fwd_config.healthcheck.as_ref().and_then(|healthcheck| {
+            HealthCheck::start(
+                context.id,
+                healthcheck,
+                fwd_config,
+                context.status.clone(),
+                context.out_tx.clone(),
+                context.commands_tx.clone(),
+            )
+        })
+    }
+
+    /// Checks the ports of the target anew, e.g. after a port of the proxy was moved.
+    fn restart_health_check(&mut self, fwd_config: &PortForwardConfig, context: &ForwardContext) {
+        self.health_check = None;
+        self.health_check = Self::health_check(fwd_config, context);
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        // Reap the process such that restarted relays do not leave zombies behind.
+        self.0.wait().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read_line_lossy() {
+        let input = b"first\r\ninvalid \xff utf-8\n0123456789\nlast";
+        let mut reader = io::BufReader::with_capacity(4, &input[..]);
+        let mut lines = Vec::new();
+        while let Some(line) = read_line_lossy(&mut reader, 12).unwrap() {
+            lines.push(line);
+        }
+
+        assert_eq!(
+            lines,
+            vec![
+                "first",
+                "invalid \u{fffd} ut [truncated]",
+                "0123456789",
+                "last"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_env() {
+        std::env::set_var("K8SFWD_TEST_INHERITED", "1");
+        let mut config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports: ["5012:80"]
+            env:
+              AWS_PROFILE: staging
+        "#,
+        )
+        .unwrap();
+
+        let env = |config: &PortForwardConfig| {
+            let mut command = Command::new("kubectl");
+            command.env("PATH", "/bin");
+            Kubectl::apply_env(&mut command, config);
+            command
+                .get_envs()
+                .map(|(key, value)| {
+                    let value = value.map(|v| v.to_string_lossy().into_owned());
+                    (key.to_string_lossy().into_owned(), value)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        let inherited = env(&config);
+        assert_eq!(inherited["AWS_PROFILE"], Some("staging".into()));
+        assert!(!inherited.contains_key("K8SFWD_TEST_INHERITED"));
+
+        config.inherit_env = Some(false);
+        let isolated = env(&config);
+        assert_eq!(isolated["PATH"], Some("/bin".into()));
+        assert_eq!(isolated["AWS_PROFILE"], Some("staging".into()));
+        assert_eq!(isolated["K8SFWD_TEST_INHERITED"], None);
+    }
+
+    #[test]
+    fn test_sibling_selector() {
+        let pod = json!({
+            "metadata": {
+                "name": "foo-78b4c5d554-6z55j",
+                "labels": {
+                    "app": "foo",
+                    "pod-template-hash": "78b4c5d554",
+                    "tier": "backend"
+                }
+            }
+        });
+        assert_eq!(
+            sibling_selector(&pod).as_deref(),
+            Some("app=foo,tier=backend")
+        );
+        assert_eq!(sibling_selector(&json!({ "metadata": {} })), None);
+    }
+
+    #[test]
+    fn test_running_pod() {
+        let list = json!({
+            "items": [
+                { "metadata": { "name": "foo-1" }, "status": { "phase": "Pending" } },
+                {
+                    "metadata": { "name": "foo-2", "deletionTimestamp": "2023-07-22T10:00:00Z" },
+                    "status": { "phase": "Running" }
+                },
+                { "metadata": { "name": "foo-3" }, "status": { "phase": "Running" } }
+            ]
+        });
+        assert_eq!(running_pod(&list).as_deref(), Some("foo-3"));
+        assert_eq!(running_pod(&json!({ "items": [] })), None);
+    }
+
+    #[test]
+    fn test_shared_pod_users() {
+        let pod: KubectlItem = serde_json::from_value(json!({
+            "metadata": {
+                "name": "gateway",
+                "labels": { "app.kubernetes.io/managed-by": "k8sfwd", "k8sfwd.io/session": "1-2" },
+                "annotations": { "k8sfwd.io/used-by-1-2": "true", "k8sfwd.io/used-by-3-4": "true" }
+            }
+        }))
+        .unwrap();
+        assert!(pod.metadata.is_managed());
+        let mut users = pod.metadata.users();
+        users.sort();
+        assert_eq!(users, ["1-2", "3-4"]);
+
+        // Pods deployed by others are never removed.
+        let pod: KubectlItem =
+            serde_json::from_value(json!({ "metadata": { "name": "gateway" } })).unwrap();
+        assert!(!pod.metadata.is_managed());
+        assert!(pod.metadata.users().is_empty());
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let mut unlimited = RetryBudget::new(None, 0);
+        assert!((0..100).all(|_| unlimited.try_retry(false).is_some()));
+
+        let mut retries = RetryBudget::new(Some(2), 0);
+        assert!(retries.try_retry(false).is_some());
+        assert!(retries.try_retry(false).is_some());
+        let retry = retries
+            .try_retry(true)
+            .expect("forwarding resets the count");
+        assert_eq!(retry.to_string(), "attempt 1 of 2, 1 left");
+        let retry = retries.try_retry(false).unwrap();
+        assert_eq!(retry.to_string(), "attempt 2 of 2, the last one");
+        assert!(retries.try_retry(false).is_none());
+        assert!(retries.is_exhausted());
+
+        let mut none = RetryBudget::new(Some(0), 0);
+        assert!(none.try_retry(false).is_none());
+
+        let mut resumed = RetryBudget::new(Some(2), 2);
+        assert!(resumed.try_retry(false).is_none());
+        assert_eq!(
+            resumed.backoff(),
+            Backoff {
+                retries: 2,
+                failed: true
+            }
+        );
     }
 }
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::ConfigId;
+use crate::control::{ControlResponse, PendingRequest};
+use crate::kubectl::StreamSource;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// The number of lines kept per target.
+const LOG_BUFFER_LINES: usize = 500;
+
+/// Keeps the recent output of each target and streams new output to followers,
+/// e.g. clients of `k8sfwd logs --follow`.
+#[derive(Clone, Default)]
+pub struct LogBook {
+    inner: Arc<Mutex<LogBookInner>>,
+}
+
+#[derive(Default)]
+struct LogBookInner {
+    buffers: HashMap<ConfigId, VecDeque<(usize, StreamSource, String)>>,
+    followers: Vec<(ConfigId, PendingRequest)>,
+}
+
+impl LogBook {
+    /// Records a line of output of an attempt of a target and sends it to its followers.
+    pub fn record(&self, id: ConfigId, attempt: usize, stream: StreamSource, line: &str) {
+        let mut inner = self.inner.lock().expect("lock is not poisoned");
+
+        let buffer = inner.buffers.entry(id).or_default();
+        if buffer.len() == LOG_BUFFER_LINES {
+            buffer.pop_front();
+        }
+        buffer.push_back((attempt, stream, line.to_string()));
+
+        // Drop the followers that have disconnected.
+        inner.followers.retain(|(follower, pending)| {
+            *follower != id
+                || pending.try_reply(ControlResponse::Log {
+                    stream,
+                    line: line.to_string(),
+                    attempt,
+                })
+        });
+    }
+
+    /// Sends the most recent lines of a target to the client and,
+    /// if requested, keeps the connection open to stream new lines.
+    pub fn subscribe(&self, id: ConfigId, lines: usize, follow: bool, pending: PendingRequest) {
+        let mut inner = self.inner.lock().expect("lock is not poisoned");
+
+        if let Some(buffer) = inner.buffers.get(&id) {
+            let skip = buffer.len().saturating_sub(lines);
+            for (attempt, stream, line) in buffer.iter().skip(skip) {
+                let response = ControlResponse::Log {
+                    stream: *stream,
+                    line: line.clone(),
+                    attempt: *attempt,
+                };
+                if !pending.try_reply(response) {
+                    return;
+                }
+            }
+        }
+
+        if follow {
+            inner.followers.push((id, pending));
+        }
+    }
+}
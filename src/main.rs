@@ -2,35 +2,104 @@
 // SPDX-License-Identifier: EUPL-1.2
 // SPDX-FileType: SOURCE
 
-use crate::cli::Cli;
+use crate::backoff::BackoffStore;
+use crate::cli::{Cli, Command, ConfigFileArg, TargetCommand};
 use crate::config::{
-    collect_config_files, sanitize_config, ConfigId, FromYaml, FromYamlError, MergeWith,
-    PortForwardConfig, RetryDelay,
+    collect_config_files, sanitize_config, sanitize_targets, ConfigId, ConfigMeta,
+    FindConfigFileError, FromConfigFile, FromConfigFileError, MergeStrategy, OperationalConfig,
+    Port, PortForwardConfig, PortForwardConfigs, Seconds, SpawnPolicy, Theme,
+    HIGHEST_SUPPORTED_VERSION,
+};
+use crate::control::{
+    AttachedTarget, ControlRequest, ControlResponse, ControlServer, PendingRequest,
+};
+use crate::daemon::PidFile;
+use crate::dns::DnsServer;
+use crate::drift::DriftWatch;
+use crate::engine::{ConfigDiff, Engine};
+use crate::hosts::HostsFile;
+use crate::kubectl::{ChildEvent, ForwardSummary, Kubectl, RestartPolicy, StreamSource};
+use crate::logs::LogBook;
+use crate::pod_watch::DEFAULT_READY_TIMEOUT;
+use crate::proxy::DEFAULT_HOLD_TIMEOUT;
+use crate::router::HttpRouter;
+use crate::session::Session;
+use crate::signals::{ReloadSignal, ShutdownSignal};
+use crate::status::{ForwardState, Traffic};
+use crate::table::Table;
+use crate::target_filter::{
+    fuzzy_fallback, is_excluded, rank_fuzzy, suggest_targets, MatchesAnyFilter, TargetFilter,
 };
-use crate::kubectl::{ChildEvent, Kubectl, RestartPolicy, StreamSource};
-use crate::target_filter::{MatchesAnyFilter, TargetFilter};
 use anyhow::Result;
 use clap::Parser;
 use just_a_tag::{MatchesAnyTagUnion, TagUnion};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
 use std::process::ExitCode;
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::thread::JoinHandle;
+use std::time::Duration;
 use std::{env, thread};
 
+mod backoff;
 mod banner;
 mod cli;
+mod commands;
 mod config;
+mod control;
+mod daemon;
+mod dependencies;
+mod diagnostics;
+mod dns;
+mod drift;
+mod encryption;
+mod engine;
+mod fnv;
+mod health;
+mod hooks;
+mod hosts;
+mod keepalive;
 mod kubectl;
+mod logs;
+mod passthrough;
+mod pod_watch;
+mod preflight;
+mod proxy;
+mod replicas;
+mod router;
+mod session;
+mod shared;
+mod signals;
+mod status;
+mod table;
 mod target_filter;
+mod tls;
+mod wizard;
 
 fn main() -> Result<ExitCode> {
-    dotenvy::dotenv().ok();
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    match load_dotenv(&cli) {
+        // Options can be set through the environment, e.g. KUBECTL_PATH.
+        Ok(true) => cli = Cli::parse(),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Unable to load the environment file: {e}");
+            return exitcode(exitcode::CONFIG);
+        }
+    }
+    if cli.shared {
+        control::use_shared_socket(cli.shared_user.clone().unwrap_or_else(shared::current_user));
+    }
+
+    // The regular expressions select targets like the prefix filters.
+    let regex_filters = std::mem::take(&mut cli.regex_filters);
+    cli.filters.extend(regex_filters);
 
     // Ensure kubectl is available.
-    let kubectl = Kubectl::new(cli.kubectl)?;
+    let kubectl = Kubectl::new(cli.kubectl.clone())?;
     let kubectl_version = match kubectl.version() {
         Ok(version) => version,
         Err(e) => {
@@ -39,36 +108,565 @@ fn main() -> Result<ExitCode> {
         }
     };
 
+    match cli.command.take() {
+        Some(Command::Up(args)) if args.detach && !daemon::is_detached() => {
+            return commands::detach()
+        }
+        Some(Command::Up(args)) => cli.filters.extend(args.filters),
+        None => {}
+        Some(Command::Down) => return commands::down(),
+        Some(Command::Attach(args)) => {
+            cli.filters.extend(args.filters);
+            return commands::attach(&cli, &kubectl);
+        }
+        Some(Command::Status(args)) => {
+            return commands::status(args, cli.wide, cli.theme.unwrap_or_default())
+        }
+        Some(Command::Stop(args)) => return commands::stop(args),
+        Some(Command::Logs(args)) => return commands::logs(args),
+        Some(Command::Restart(args)) => return commands::restart(args),
+        Some(Command::Target {
+            command: TargetCommand::Remap(args),
+        }) => return commands::remap(args),
+        Some(Command::Resolve(args)) => {
+            cli.filters.extend(args.filters);
+            return commands::resolve(&cli, &kubectl);
+        }
+        Some(Command::Cleanup(args)) => return commands::cleanup(&kubectl, args),
+        Some(Command::Selftest(args)) => return commands::selftest(&kubectl, args),
+        Some(Command::Sync(args)) => return commands::sync(&cli, &kubectl, args),
+        Some(Command::Init(args)) => return commands::init(&cli, &kubectl, args),
+        Some(Command::Add(args)) => return commands::add(&cli, args),
+        Some(Command::Migrate(args)) => return commands::migrate(&cli, args),
+        Some(Command::Schema) => return commands::schema(),
+    }
+
     print_header(kubectl_version);
 
-    // TODO: Watch the configuration file, stop missing bits and start new ones. (Hash the entries?)
+    // Offer to create a configuration on the first run, unless the configured file name
+    // requires a format other than YAML.
+    let wizard_file = cli
+        .config_name
+        .yaml_file_name()
+        .filter(|_| cli.config.is_empty() && !cli.no_hierarchy);
+    if let Some(file_name) = wizard_file {
+        if matches!(
+            collect_config_files(Vec::new(), &cli.config_name, false),
+            Err(FindConfigFileError::FileNotFound)
+        ) && wizard::is_interactive()
+        {
+            match wizard::run(&kubectl, file_name) {
+                Ok(Some(path)) => println!("Created {path}", path = path.display()),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Unable to create a configuration: {e}");
+                    return exitcode(exitcode::UNAVAILABLE);
+                }
+            }
+            println!();
+        }
+    }
 
     // Attempt to find the configuration file in parent directories and ensure configuration can be loaded.
-    let mut configs = Vec::new();
+    let mut config = match load_config(&cli, &kubectl) {
+        Ok(config) => config,
+        Err(code) => return exitcode(code),
+    };
+
+    println!();
+
+    // Early exit; a shared session may start without targets of its own.
+    if config.targets.is_empty() && !cli.shared {
+        eprintln!("No targets configured.");
+        return exitcode(exitcode::CONFIG);
+    }
+
+    // Create channels for communication.
+    let (out_tx, out_rx) = mpsc::channel();
+    let logs = LogBook::default();
+    let theme = cli
+        .theme
+        .or_else(|| config.config.as_ref().and_then(|config| config.theme))
+        .unwrap_or_default();
+    let print_thread = start_output_loop_thread(out_rx, logs.clone(), cli.verbose, theme);
+
+    // Sanitize default values.
+    if let Err(code) = use_kubeconfigs(&kubectl, &config) {
+        return exitcode(code);
+    }
+    let current_context = kubectl.current_context()?;
+    let current_cluster = kubectl.current_cluster()?;
+
+    match sanitize_config(&mut config, current_context, current_cluster, &kubectl) {
+        Ok(warnings) => warnings.iter().for_each(|warning| eprintln!("{warning}")),
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            return exitcode(exitcode::CONFIG);
+        }
+    }
+    apply_cli_overrides(&cli, &mut config);
+
+    let mut operational = config.config.expect("operational config exists");
+
+    // Other users of a shared session can attach only to the targets configured here.
+    let mut attachable = config.targets.clone();
+
+    // Map out the config, starting dependencies first.
+    let targets = match dependencies::order(select_targets(
+        &config.targets,
+        &cli.tags,
+        &cli.filters,
+        &cli.excludes,
+    )) {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            return exitcode(exitcode::CONFIG);
+        }
+    };
+    for warning in dependencies::unselected(&targets) {
+        eprintln!("{warning}");
+    }
+    if !check_local_ports(&targets) {
+        return exitcode(exitcode::CONFIG);
+    }
+    if !targets.is_empty() {
+        println!("Forwarding to the following targets:");
+    } else if cli.shared {
+        println!("Serving a shared session without targets of its own.");
+    } else {
+        eprintln!("No targets selected.");
+        print_selection_hints(&config.targets, &cli.filters);
+        return exitcode(exitcode::DATAERR);
+    }
+
+    let listed: Vec<_> = targets
+        .iter()
+        .map(|target| (target.id.expect("IDs are assigned when sanitizing"), target))
+        .collect();
+    print_targets(&listed, cli.verbose, cli.wide);
+    println!();
+
+    // Verify permissions up front rather than failing with kubectl errors later.
+    if !check_auth(&kubectl, &operational, &targets) {
+        return exitcode(exitcode::NOPERM);
+    }
+
+    if operational.check_targets == Some(true) && !check_resources(&kubectl, &targets) {
+        return exitcode(exitcode::CONFIG);
+    }
+
+    if !add_loopback_aliases(&targets)
+        || !check_listen_addrs(&targets)
+        || !check_local_ports_free(&targets)
+    {
+        return exitcode(exitcode::UNAVAILABLE);
+    }
+
+    print_passthrough_problems(&targets);
+
+    // Terminate all children on Ctrl+C / SIGTERM and reload on SIGHUP.
+    let shutdown = ShutdownSignal::install()?;
+    let reload = ReloadSignal::install()?;
+
+    // Remove helper resources left behind by crashed sessions.
+    for resource in Session::collect_garbage(&kubectl) {
+        println!("Deleted {resource} left over from a previous session");
+    }
+
+    // Keep track of the background process.
+    let _pid_file = if daemon::is_detached() {
+        Some(PidFile::create()?)
+    } else {
+        None
+    };
+
+    // Accept control commands from other terminals.
+    let control = match ControlServer::start(operational.shared.group.as_deref()) {
+        Ok(control) => Some(control),
+        Err(e) if cli.shared => {
+            eprintln!("Unable to serve the shared session: {e}");
+            return exitcode(exitcode::UNAVAILABLE);
+        }
+        Err(e) => {
+            eprintln!("The control socket is unavailable: {e}");
+            None
+        }
+    };
+
+    // Targets that failed in previous sessions keep their retry count unless reset.
+    if cli.reset_backoff {
+        if let Err(e) = BackoffStore::reset() {
+            eprintln!("Failed to reset the retries of previous sessions: {e}");
+        }
+    }
+
+    let session = Session::new();
+    let mut engine = Engine::new(
+        kubectl.clone(),
+        out_tx.clone(),
+        shutdown.clone(),
+        session.clone(),
+        BackoffStore::load(),
+    );
+
+    // For each configuration, attempt a port-forward.
+    println!("Spawning child processes:");
+    let mut result = Ok(());
+    for fwd_config in targets {
+        let target = fwd_config.target.clone();
+        if let Err(e) = engine.start(&operational, fwd_config) {
+            eprintln!("Failed to spawn the port-forward to {target}: {e}");
+            if operational.on_spawn_error.unwrap_or_default() == SpawnPolicy::Abort {
+                // Stop the forwards started so far and tear down the session as on any shutdown.
+                shutdown.request();
+                result = Err(e.into());
+                break;
+            }
+        }
+    }
+
+    // Compare the targets against the cluster, if enabled.
+    let drift_watch = |operational: &OperationalConfig| {
+        operational
+            .drift_check_interval_sec
+            .map(|interval| DriftWatch::new(kubectl.clone(), out_tx.clone(), interval))
+    };
+    let mut drift = drift_watch(&operational);
+
+    // Route HTTP requests to the targets by their host, if enabled.
+    let mut router = start_router(&operational, None);
+
+    // Answer the names of the forwarded services, if enabled.
+    let mut dns = start_dns(&operational, None);
+
+    // Map the host names of the targets in the hosts file, if enabled.
+    let mut hosts = manage_hosts(&operational, None);
 
-    for (source, file) in collect_config_files(cli.config)? {
-        // TODO: Allow skipping of incompatible version (--ignore-errors?)
-        let config = match file.into_configuration(&source) {
-            Ok(configs) => configs,
-            Err(FromYamlError::InvalidConfiguration(e)) => {
-                eprintln!("Invalid configuration: {e}");
-                return exitcode(exitcode::CONFIG);
+    // The forwards only finish on shutdown or if one of them failed fatally.
+    let mut shutdown_announced = false;
+    while !(shutdown.is_requested() && engine.is_finished()) {
+        if shutdown.is_requested() {
+            if !shutdown_announced {
+                eprintln!("Shutting down ...");
+                shutdown_announced = true;
             }
-            Err(FromYamlError::FileReadFailed(e)) => {
-                eprintln!("Failed to read configuration file: {e}");
-                return exitcode(exitcode::UNAVAILABLE);
+        } else if reload.take() {
+            if let Some((reloaded, targets)) = reload_config(&cli, &kubectl, &mut engine) {
+                operational = reloaded;
+                attachable = targets;
+                drift = drift_watch(&operational);
+                router = start_router(&operational, router.take());
+                dns = start_dns(&operational, dns.take());
+                hosts = manage_hosts(&operational, hosts.take());
             }
-        };
+        } else if let Some(drift) = &mut drift {
+            drift.poll(&engine);
+        }
 
-        // Ensure version is supported.
-        // TODO: Allow skipping of incompatible version (--ignore-errors?)
-        if !config.is_supported_version() {
-            eprintln!(
-                "Configuration version {loaded} is not supported by this application",
-                loaded = config.version
-            );
-            return exitcode(exitcode::CONFIG);
+        if let Some(router) = &mut router {
+            router.poll(&engine);
+        }
+        if let Some(dns) = &mut dns {
+            dns.poll(&engine);
         }
+        if let Some(hosts) = &mut hosts {
+            if let Err(e) = hosts.poll(&engine) {
+                eprintln!(
+                    "Unable to update the hosts file {path}: {e}",
+                    path = hosts.path().display()
+                );
+            }
+        }
+
+        if let Some(control) = &control {
+            while let Some(pending) = control.try_recv() {
+                handle_control_request(
+                    pending,
+                    &shutdown,
+                    &mut engine,
+                    &logs,
+                    &operational,
+                    &attachable,
+                    &kubectl,
+                );
+            }
+        }
+
+        engine.collect_finished();
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut summaries = Vec::new();
+    for forward in engine.join() {
+        let id = forward.id;
+        match forward.result {
+            Ok(summary) => summaries.push((id, forward.name, summary)),
+            Err(e) => {
+                eprintln!("{id}: Port-forward failed: {e}");
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+
+    // Close the channel so that the output loop can finish.
+    drop(drift);
+    drop(router);
+    drop(dns);
+    drop(hosts);
+    drop(out_tx);
+    print_thread.join().ok();
+
+    for resource in session.teardown(&kubectl) {
+        println!("Deleted {resource}");
+    }
+
+    print_summary(summaries, cli.verbose);
+
+    result?;
+    exitcode(exitcode::OK)
+}
+
+/// Answers a request received through the control socket.
+fn handle_control_request(
+    pending: PendingRequest,
+    shutdown: &ShutdownSignal,
+    engine: &mut Engine,
+    logs: &LogBook,
+    operational: &OperationalConfig,
+    attachable: &[PortForwardConfig],
+    kubectl: &Kubectl,
+) {
+    let user = pending.user.as_str();
+    match &pending.request {
+        ControlRequest::Ping => pending.reply(ControlResponse::Pong {
+            pid: std::process::id(),
+        }),
+        ControlRequest::Shutdown if engine.is_admin(user) => {
+            shutdown.request();
+            pending.reply(ControlResponse::Ok);
+        }
+        ControlRequest::Shutdown => {
+            let ids = engine.release(user);
+            for id in &ids {
+                println!("{id}: Stopping (released by {user})");
+            }
+            pending.reply(ControlResponse::Released {
+                ids: ids.iter().map(ToString::to_string).collect(),
+            });
+        }
+        ControlRequest::Attach { targets } => {
+            let targets = match targets
+                .iter()
+                .map(|target| target.resolve(attachable).map(PortForwardConfig::from))
+                .collect()
+            {
+                Ok(targets) => targets,
+                Err(e) => {
+                    pending.reply(ControlResponse::Error {
+                        message: e.to_string(),
+                    });
+                    return;
+                }
+            };
+            match attach_targets(engine, operational, kubectl, user, targets) {
+                Ok(targets) => pending.reply(ControlResponse::Attached { targets }),
+                Err(message) => pending.reply(ControlResponse::Error { message }),
+            }
+        }
+        ControlRequest::Status => pending.reply(ControlResponse::Status {
+            targets: engine.status(),
+        }),
+        ControlRequest::Stop { target } => match engine.stop(target, user) {
+            Ok(id) => {
+                println!("{id}: Stopping");
+                pending.reply(ControlResponse::Target { id: id.to_string() });
+            }
+            Err(e) => pending.reply(ControlResponse::Error {
+                message: e.to_string(),
+            }),
+        },
+        ControlRequest::Restart { target } => match engine.restart(target, user) {
+            Ok(id) => {
+                println!("{id}: Restarting");
+                pending.reply(ControlResponse::Target { id: id.to_string() });
+            }
+            Err(e) => pending.reply(ControlResponse::Error {
+                message: e.to_string(),
+            }),
+        },
+        ControlRequest::Logs {
+            target,
+            lines,
+            follow,
+        } => match engine.resolve(target, user) {
+            Ok(id) => logs.subscribe(id, *lines, *follow, pending),
+            Err(e) => pending.reply(ControlResponse::Error {
+                message: e.to_string(),
+            }),
+        },
+        ControlRequest::Remap { target, port } => {
+            let port: Port = match port.parse() {
+                Ok(port) => port,
+                Err(e) => {
+                    pending.reply(ControlResponse::Error {
+                        message: format!("Invalid port mapping `{port}`: {e}"),
+                    });
+                    return;
+                }
+            };
+
+            match engine.remap(target, port, user) {
+                Ok((id, previous, current)) => {
+                    println!("{id}: Remapped {previous} to {current}");
+                    pending.reply(ControlResponse::Remapped {
+                        id: id.to_string(),
+                        previous: previous.to_string(),
+                        current: current.to_string(),
+                    });
+                }
+                Err(e) => pending.reply(ControlResponse::Error {
+                    message: e.to_string(),
+                }),
+            }
+        }
+    }
+}
+
+/// Starts the targets requested by a user of a shared session.
+///
+/// The targets, already resolved against the ones configured for the session, are checked
+/// against the session's kubeconfig and permission checks.
+fn attach_targets(
+    engine: &mut Engine,
+    operational: &OperationalConfig,
+    kubectl: &Kubectl,
+    user: &str,
+    mut targets: Vec<PortForwardConfig>,
+) -> Result<Vec<AttachedTarget>, String> {
+    let current_context = kubectl.current_context().map_err(|e| e.to_string())?;
+    let current_cluster = kubectl.current_cluster().map_err(|e| e.to_string())?;
+    let warnings = sanitize_targets(
+        &mut targets,
+        operational,
+        &current_context,
+        &current_cluster,
+        kubectl,
+    )
+    .map_err(|e| e.to_string())?;
+    warnings.iter().for_each(|warning| eprintln!("{warning}"));
+
+    if let Some(failure) = preflight::check_auth(kubectl, operational, &targets)
+        .into_iter()
+        .chain(preflight::check_listen_addrs(&targets))
+        .chain(preflight::check_local_ports(&targets))
+        .next()
+    {
+        return Err(failure.to_string());
+    }
+
+    let started = engine
+        .attach(operational, user, targets)
+        .map_err(|e| e.to_string())?;
+
+    println!("Started the following targets for {user}:");
+    let listed: Vec<_> = started.iter().map(|(id, config)| (*id, config)).collect();
+    print_targets(&listed, false, false);
+
+    Ok(started
+        .into_iter()
+        .map(|(id, config)| AttachedTarget {
+            id: id.to_string(),
+            name: config.name.unwrap_or(config.target),
+            ports: config.ports,
+        })
+        .collect())
+}
+
+/// Loads the environment variables of the nearest `.env` file, or of the file specified
+/// with `--env-file` instead, unless disabled with `--no-dotenv`.
+///
+/// Returns whether a file was loaded.
+fn load_dotenv(cli: &Cli) -> Result<bool, dotenvy::Error> {
+    if cli.no_dotenv {
+        return Ok(false);
+    }
+
+    match &cli.env_file {
+        Some(path) => dotenvy::from_path(path).map(|_| true),
+        None => Ok(dotenvy::dotenv().is_ok()),
+    }
+}
+
+/// Gets the paths of the config files given on the command line, fetching the ones given
+/// as URLs. If fetching fails, the previously fetched copy is used, if any.
+fn fetch_config_files(
+    files: &[ConfigFileArg],
+    kubectl: &Kubectl,
+) -> Result<Vec<PathBuf>, exitcode::ExitCode> {
+    files
+        .iter()
+        .map(|file| match file {
+            ConfigFileArg::Path(path) => Ok(path.clone()),
+            ConfigFileArg::Url(remote) => match remote.fetch(kubectl) {
+                Ok(path) => {
+                    println!("Fetched {remote} to {path}", path = path.display());
+                    Ok(path)
+                }
+                Err(e) => match remote.cached() {
+                    Some(path) => {
+                        eprintln!("{e} - using the copy fetched before");
+                        Ok(path)
+                    }
+                    None => {
+                        eprintln!("{e}");
+                        Err(exitcode::UNAVAILABLE)
+                    }
+                },
+            },
+        })
+        .collect()
+}
+
+/// Loads and merges the configuration files from the hierarchy, then selects the targets of
+/// the profile and groups, if given.
+///
+/// Errors are reported to the user and returned as exit codes.
+fn load_config(cli: &Cli, kubectl: &Kubectl) -> Result<PortForwardConfigs, exitcode::ExitCode> {
+    let mut configs = Vec::new();
+
+    let cli_files = fetch_config_files(&cli.config, kubectl)?;
+    let files = match collect_config_files(cli_files, &cli.config_name, cli.no_hierarchy) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(exitcode::UNAVAILABLE);
+        }
+    };
+
+    for (source, file) in files {
+        let loaded = file
+            .map_err(|e| {
+                eprintln!(
+                    "Failed to read configuration file {path}: {e}",
+                    path = source.path.display()
+                );
+                exitcode::UNAVAILABLE
+            })
+            .and_then(|file| load_config_file(&source, file));
+        let config = match loaded {
+            Ok(config) => config,
+            Err(_) if cli.ignore_errors => {
+                eprintln!(
+                    "Skipping {path} because of the error above",
+                    path = source.path.display()
+                );
+                continue;
+            }
+            Err(code) => return Err(code),
+        };
 
         configs.push((source, config));
     }
@@ -76,7 +674,7 @@ fn main() -> Result<ExitCode> {
     let mut config = match configs.len() {
         0 => {
             eprintln!("No valid configuration files found");
-            return exitcode(exitcode::UNAVAILABLE);
+            return Err(exitcode::UNAVAILABLE);
         }
         1 => {
             let (source, config) = configs.into_iter().next().expect("one entry exists");
@@ -84,16 +682,28 @@ fn main() -> Result<ExitCode> {
             config
         }
         n => {
+            // The merge order is taken from the first file that sets it.
+            let order = configs
+                .iter()
+                .find_map(|(_, config)| config.config.as_ref().and_then(|c| c.merge_order))
+                .unwrap_or_default();
+            order.prioritize(&mut configs);
+
             if cli.verbose {
-                println!("Merging configs from {n} locations:");
-                for (config, _) in &configs {
+                println!("Merging configs from {n} locations ({order}, highest precedence first):");
+                for (meta, config) in &configs {
                     println!(
-                        "- {path}{mode}",
-                        path = config.path.display(),
-                        mode = if config.auto_detected {
+                        "- {path}{mode}{merge}",
+                        path = meta.path.display(),
+                        mode = if meta.auto_detected {
                             " (auto-detected)"
                         } else {
                             ""
+                        },
+                        merge = if config.merge == MergeStrategy::Replace {
+                            " (replaces the targets of the files below)"
+                        } else {
+                            ""
                         }
                     );
                 }
@@ -101,60 +711,514 @@ fn main() -> Result<ExitCode> {
                 println!("Merging configs from {n} locations");
             }
 
-            let (_, mut merged) = configs.pop().expect("there is at least one config");
-            while let Some((_path, config)) = configs.pop() {
-                merged.merge_with(&config);
-            }
-            merged
+            PortForwardConfigs::merge_files(configs.into_iter().map(|(_, config)| config))
+                .expect("configs exist")
         }
     };
 
-    println!();
+    if let Some(profile) = &cli.profile {
+        if let Err(e) = config.apply_profile(profile) {
+            eprintln!("{e}");
+            return Err(exitcode::CONFIG);
+        }
+        if cli.verbose {
+            println!("Using profile {profile}");
+        }
+    }
+    if let Err(e) = config.select_groups(&cli.groups) {
+        eprintln!("{e}");
+        return Err(exitcode::CONFIG);
+    }
+    Ok(config)
+}
 
-    // Early exit.
-    if config.targets.is_empty() {
-        eprintln!("No targets configured.");
-        return exitcode(exitcode::CONFIG);
+/// Loads a single configuration file and ensures its version is supported.
+///
+/// Errors are reported to the user and returned as exit codes.
+fn load_config_file(
+    source: &ConfigMeta,
+    file: File,
+) -> Result<PortForwardConfigs, exitcode::ExitCode> {
+    let config = match file.into_configuration(source) {
+        Ok(configs) => configs,
+        Err(FromConfigFileError::InvalidConfiguration(e)) => {
+            eprintln!("Invalid configuration: {e}");
+            return Err(exitcode::CONFIG);
+        }
+        Err(FromConfigFileError::InvalidJsonConfiguration(e)) => {
+            eprintln!(
+                "Invalid configuration in {path}: {e}",
+                path = source.path.display()
+            );
+            return Err(exitcode::CONFIG);
+        }
+        Err(FromConfigFileError::InvalidTomlConfiguration(e)) => {
+            eprintln!(
+                "Invalid configuration in {path}: {e}",
+                path = source.path.display()
+            );
+            return Err(exitcode::CONFIG);
+        }
+        Err(FromConfigFileError::FileReadFailed(e)) => {
+            eprintln!("Failed to read configuration file: {e}");
+            return Err(exitcode::UNAVAILABLE);
+        }
+        Err(FromConfigFileError::SecretResolutionFailed(e)) => {
+            eprintln!(
+                "Failed to resolve a value of {path}: {e}",
+                path = source.path.display()
+            );
+            return Err(exitcode::CONFIG);
+        }
+        Err(FromConfigFileError::InterpolationFailed(e)) => {
+            eprintln!(
+                "Failed to expand a variable in {path}: {e}",
+                path = source.path.display()
+            );
+            return Err(exitcode::CONFIG);
+        }
+        Err(e @ FromConfigFileError::RemoteCommands(..)) => {
+            eprintln!("{e}");
+            return Err(exitcode::CONFIG);
+        }
+        Err(
+            e @ (FromConfigFileError::UnsupportedVersion(_)
+            | FromConfigFileError::IncludeFailed(..)
+            | FromConfigFileError::IncludeCycle(_)),
+        ) => {
+            eprintln!(
+                "Invalid configuration in {path}: {e}",
+                path = source.path.display()
+            );
+            return Err(exitcode::CONFIG);
+        }
+    };
+
+    // Ensure version is supported.
+    if !config.is_supported_version() {
+        eprintln!(
+            "Configuration version {loaded} is not supported by this application",
+            loaded = config.version
+        );
+        if config.version < *HIGHEST_SUPPORTED_VERSION {
+            eprintln!(
+                "Run `k8sfwd migrate {path}` to upgrade the file",
+                path = source.path.display()
+            );
+        }
+        return Err(exitcode::CONFIG);
     }
 
-    // Create channels for communication.
-    let (out_tx, out_rx) = mpsc::channel();
-    let print_thread = start_output_loop_thread(out_rx);
+    Ok(config)
+}
 
-    // Sanitize default values.
-    let current_context = kubectl.current_context()?;
-    let current_cluster = kubectl.current_cluster()?;
+/// Points all kubectl invocations at the kubeconfig files of the configuration, if any.
+fn use_kubeconfigs(
+    kubectl: &Kubectl,
+    config: &PortForwardConfigs,
+) -> Result<(), exitcode::ExitCode> {
+    let kubeconfigs = config
+        .config
+        .as_ref()
+        .map_or(&[][..], |config| &config.kubeconfigs);
+    kubectl.set_kubeconfigs(kubeconfigs).map_err(|e| {
+        eprintln!("Invalid kubeconfig path: {e}");
+        exitcode::CONFIG
+    })
+}
+
+/// Applies the command-line arguments that take precedence over the configuration files.
+fn apply_cli_overrides(cli: &Cli, config: &mut PortForwardConfigs) {
+    if cli.check_targets {
+        if let Some(operational) = &mut config.config {
+            operational.check_targets = Some(true);
+        }
+    }
+
+    if let Some(policy) = cli.on_spawn_error {
+        if let Some(operational) = &mut config.config {
+            operational.on_spawn_error = Some(policy);
+        }
+    }
+
+    if let Some(drain_timeout) = cli.drain_timeout {
+        let drain_timeout = Seconds::from_secs(drain_timeout);
+        if let Some(operational) = &mut config.config {
+            operational.drain_timeout_sec = Some(drain_timeout);
+        }
+
+        for target in &mut config.targets {
+            target.drain_timeout_sec = Some(drain_timeout);
+        }
+    }
+
+    if let Some(request_timeout) = cli.request_timeout {
+        let request_timeout = Seconds::from_secs(request_timeout);
+        if let Some(operational) = &mut config.config {
+            operational.request_timeout_sec = Some(request_timeout);
+        }
+
+        for target in &mut config.targets {
+            target.request_timeout_sec = Some(request_timeout);
+        }
+    }
+
+    if let Some(idle_timeout) = cli.idle_timeout {
+        let idle_timeout = Seconds::from_secs(idle_timeout);
+        if let Some(operational) = &mut config.config {
+            operational.idle_timeout_sec = Some(idle_timeout);
+        }
+
+        for target in &mut config.targets {
+            target.idle_timeout_sec = Some(idle_timeout);
+        }
+    }
+}
+
+/// Starts the HTTP router of the `http_router` setting, if any, keeping the current router
+/// if its settings didn't change.
+fn start_router(
+    operational: &OperationalConfig,
+    current: Option<HttpRouter>,
+) -> Option<HttpRouter> {
+    let config = operational.http_router?;
+    if let Some(router) = current.filter(|router| *router.config() == config) {
+        return Some(router);
+    }
+
+    match HttpRouter::bind(config) {
+        Ok(router) => {
+            println!(
+                "Routing HTTP requests by host on {address}",
+                address = router.address()
+            );
+            Some(router)
+        }
+        Err(e) => {
+            eprintln!("Unable to start the HTTP router: {e}");
+            None
+        }
+    }
+}
+
+/// Starts the DNS server of the `dns` setting, if any, keeping the current server
+/// if its settings didn't change.
+fn start_dns(operational: &OperationalConfig, current: Option<DnsServer>) -> Option<DnsServer> {
+    let config = operational.dns.clone()?;
+    if let Some(dns) = current.filter(|dns| *dns.config() == config) {
+        return Some(dns);
+    }
+
+    match DnsServer::bind(config) {
+        Ok(dns) => {
+            println!(
+                "Answering DNS queries for *.svc.{domain} on {address}",
+                domain = dns.config().domain(),
+                address = dns.address()
+            );
+            Some(dns)
+        }
+        Err(e) => {
+            eprintln!("Unable to start the DNS server: {e}");
+            None
+        }
+    }
+}
+
+/// Manages the hosts file of the `manage_hosts` setting, if enabled, keeping the current
+/// entries if the file didn't change.
+///
+/// Entries left behind by sessions that crashed are removed first.
+fn manage_hosts(operational: &OperationalConfig, current: Option<HostsFile>) -> Option<HostsFile> {
+    if operational.manage_hosts != Some(true) {
+        return None;
+    }
+
+    let path = operational
+        .hosts_file
+        .clone()
+        .unwrap_or_else(hosts::default_path);
+    if let Some(hosts) = current.filter(|hosts| hosts.path() == path) {
+        return Some(hosts);
+    }
+
+    match hosts::remove_stale(&path) {
+        Ok(0) => {}
+        Ok(stale) => println!(
+            "Removed the entries of {stale} previous session(s) from {path}",
+            path = path.display()
+        ),
+        Err(e) => eprintln!(
+            "Unable to update the hosts file {path}: {e}",
+            path = path.display()
+        ),
+    }
+    Some(HostsFile::new(path))
+}
+
+/// Re-reads the configuration files and applies the changed targets to the running engine.
+///
+/// If the configuration cannot be loaded, the current forwards are kept.
+/// Returns the new operational configuration and all configured targets if it was applied.
+fn reload_config(
+    cli: &Cli,
+    kubectl: &Kubectl,
+    engine: &mut Engine,
+) -> Option<(OperationalConfig, Vec<PortForwardConfig>)> {
+    println!();
+    println!("Reloading configuration ...");
+
+    let mut config = match load_config(cli, kubectl) {
+        Ok(config) => config,
+        Err(_) => {
+            eprintln!("Keeping the current configuration.");
+            return None;
+        }
+    };
+
+    if use_kubeconfigs(kubectl, &config).is_err() {
+        eprintln!("Keeping the current configuration.");
+        return None;
+    }
 
-    sanitize_config(&mut config, current_context, current_cluster, &kubectl);
+    let (current_context, current_cluster) =
+        match (kubectl.current_context(), kubectl.current_cluster()) {
+            (Ok(context), Ok(cluster)) => (context, cluster),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("Failed to determine the current context: {e}");
+                eprintln!("Keeping the current configuration.");
+                return None;
+            }
+        };
+
+    match sanitize_config(&mut config, current_context, current_cluster, kubectl) {
+        Ok(warnings) => warnings.iter().for_each(|warning| eprintln!("{warning}")),
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            eprintln!("Keeping the current configuration.");
+            return None;
+        }
+    }
+    apply_cli_overrides(cli, &mut config);
 
     let operational = config.config.expect("operational config exists");
+    let targets = match dependencies::order(select_targets(
+        &config.targets,
+        &cli.tags,
+        &cli.filters,
+        &cli.excludes,
+    )) {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            eprintln!("Keeping the current configuration.");
+            return None;
+        }
+    };
+    if !check_local_ports(&targets)
+        || !check_auth(kubectl, &operational, &targets)
+        || (operational.check_targets == Some(true) && !check_resources(kubectl, &targets))
+    {
+        eprintln!("Keeping the current configuration.");
+        return None;
+    }
 
-    // Map out the config.
-    println!("Forwarding to the following targets:");
-    let map = map_and_print_config(config.targets, cli.tags, cli.verbose, cli.filters);
-    if map.is_empty() {
-        eprintln!("No targets selected.");
-        return exitcode(exitcode::OK);
+    let diff = engine.diff(&targets);
+    if diff.is_empty() {
+        println!("No changes to the selected targets.");
+    } else {
+        print_diff(&diff);
     }
+
+    // Only report the aliases of targets that don't listen on them yet.
+    let relocated = diff
+        .changed
+        .iter()
+        .filter(|(_, _, fields)| fields.contains(&"listen_addrs"))
+        .map(|(_, config, _)| config);
+    if !add_loopback_aliases(diff.added.iter().chain(relocated)) || !check_listen_addrs(&targets) {
+        eprintln!("Keeping the current configuration.");
+        return None;
+    }
+
+    let changed = diff.changed.iter().map(|(_, config, _)| config);
+    print_passthrough_problems(diff.added.iter().chain(changed));
+
+    if cli.confirm_reload && diff.is_destructive() && !confirm_reload() {
+        eprintln!("Keeping the current configuration.");
+        return None;
+    }
+
+    engine.announce(&diff);
+    let changes = match engine.reconcile(&operational, targets) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!("Failed to apply the configuration: {e}");
+            return None;
+        }
+    };
+
+    if !changes.added.is_empty() {
+        println!("Started the following targets:");
+        let listed: Vec<_> = changes
+            .added
+            .iter()
+            .map(|(id, config)| (*id, config))
+            .collect();
+        print_targets(&listed, cli.verbose, cli.wide);
+    }
+
     println!();
+    Some((operational, config.targets))
+}
 
-    // For each configuration, attempt a port-forward.
-    println!("Spawning child processes:");
-    let mut handles = Vec::new();
-    for (id, fwd_config) in map {
-        // TODO: Fail all or fail some?
-        let handle =
-            kubectl.port_forward(id, operational.clone(), fwd_config.clone(), out_tx.clone())?;
-        handles.push(handle);
+/// Prints the targets a reload adds, removes and changes, along with the changed fields.
+fn print_diff(diff: &ConfigDiff) {
+    let label = |config: &PortForwardConfig| match &config.name {
+        Some(name) => name.clone(),
+        None => config.target.clone(),
+    };
+
+    println!("The configuration changed:");
+    for config in &diff.added {
+        println!("  + {target} (added)", target = label(config));
+    }
+    for (id, config) in &diff.removed {
+        println!("  - {id} {target} (removed)", target = label(config));
+    }
+    for (id, config, fields) in &diff.changed {
+        println!(
+            "  ~ {id} {target} (changed: {fields})",
+            target = label(config),
+            fields = fields.join(", ")
+        );
+    }
+}
+
+/// Asks whether to apply a reload that stops or restarts running targets.
+///
+/// Without a terminal to ask in, e.g. when running detached, the reload is not applied.
+fn confirm_reload() -> bool {
+    if !wizard::is_interactive() {
+        eprintln!("The changes stop or restart running targets and can't be confirmed without a terminal.");
+        return false;
     }
 
-    for handle in handles {
-        handle.join().unwrap_or(Ok(()))?;
+    print!("Stop or restart the affected targets? (y/N) ");
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
     }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
 
-    print_thread.join().ok();
+/// Runs the permission checks of the targets' clusters and reports failures.
+///
+/// Returns `true` if all checks passed.
+fn check_auth(
+    kubectl: &Kubectl,
+    operational: &OperationalConfig,
+    targets: &[PortForwardConfig],
+) -> bool {
+    let failures = preflight::check_auth(kubectl, operational, targets);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
 
-    exitcode(exitcode::OK)
+    if !failures.is_empty() {
+        eprintln!("Ensure you are logged in to the cluster and have the permissions checked above, e.g. `pods/portforward`.");
+    }
+
+    failures.is_empty()
+}
+
+/// Ensures the loopback aliases of the targets exist and reports them.
+///
+/// Returns `true` if all aliases exist.
+fn add_loopback_aliases<'a>(targets: impl IntoIterator<Item = &'a PortForwardConfig>) -> bool {
+    let mut success = true;
+    for target in targets {
+        let Some(alias) = target.loopback_alias else {
+            continue;
+        };
+
+        let label = target.name.as_ref().unwrap_or(&target.target);
+        match preflight::ensure_loopback_alias(alias) {
+            Ok(false) => println!("Listening on loopback alias {alias} for {label}"),
+            Ok(true) => println!("Listening on loopback alias {alias} for {label} (added to lo0)"),
+            Err(e) => {
+                eprintln!("{e}");
+                success = false;
+            }
+        }
+    }
+
+    success
+}
+
+/// Warns about settings of the targets that would break the `protocol` they speak.
+fn print_passthrough_problems<'a>(targets: impl IntoIterator<Item = &'a PortForwardConfig>) {
+    for target in targets {
+        let label = target.name.as_ref().unwrap_or(&target.target);
+        for problem in passthrough::diagnose(target) {
+            eprintln!("{label}: {problem}");
+        }
+    }
+}
+
+fn check_listen_addrs(targets: &[PortForwardConfig]) -> bool {
+    let failures = preflight::check_listen_addrs(targets);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+
+    if !failures.is_empty() {
+        eprintln!(
+            "Ensure the listen addresses exist on this machine, or change the `ip_family` setting."
+        );
+    }
+
+    failures.is_empty()
+}
+
+fn check_local_ports(targets: &[PortForwardConfig]) -> bool {
+    let failures = preflight::check_local_ports(targets);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+
+    if !failures.is_empty() {
+        eprintln!("Change the local port of one of the targets, or select only one of them.");
+    }
+
+    failures.is_empty()
+}
+
+fn check_resources(kubectl: &Kubectl, targets: &[PortForwardConfig]) -> bool {
+    let failures = preflight::check_resources(kubectl, targets);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+
+    if !failures.is_empty() {
+        eprintln!("Fix the names or namespaces of the targets, or deselect them.");
+    }
+
+    failures.is_empty()
+}
+
+fn check_local_ports_free(targets: &[PortForwardConfig]) -> bool {
+    let failures = preflight::check_local_ports_free(targets);
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+
+    if !failures.is_empty() {
+        eprintln!("Stop the processes using the ports, or set `on_port_conflict: remap` to listen on the next free port.");
+    }
+
+    failures.is_empty()
 }
 
 fn print_header(kubectl_version: String) {
@@ -166,105 +1230,443 @@ fn print_header(kubectl_version: String) {
     println!("Using kubectl version {kubectl_version}");
 }
 
-/// Prints out the details about the current configuration.
+/// Selects the targets matching the tags and filters, except the excluded ones.
 ///
-/// This method also unifies the "current" context/cluster configuration with the
-/// actual values previously read from kubectl.
-fn map_and_print_config(
-    configs: Vec<PortForwardConfig>,
-    tags: Vec<TagUnion>,
-    verbose: bool,
-    filters: Vec<TargetFilter>,
-) -> HashMap<ConfigId, PortForwardConfig> {
-    let mut map: HashMap<ConfigId, PortForwardConfig> = HashMap::new();
-
-    let configs = configs
-        .into_iter()
+/// Filters matching none of the targets by prefix select them by fuzzy matching instead,
+/// best matches first.
+fn select_targets(
+    configs: &[PortForwardConfig],
+    tags: &Vec<TagUnion>,
+    filters: &[TargetFilter],
+    excludes: &[TargetFilter],
+) -> Vec<PortForwardConfig> {
+    let filters = fuzzy_fallback(filters, configs);
+    let mut selected: Vec<PortForwardConfig> = configs
+        .iter()
         .filter(|config| tags.is_empty() || tags.matches_set(&config.tags))
-        .filter(|config| filters.matches(config));
+        .filter(|config| filters.matches(config))
+        .filter(|config| !is_excluded(excludes, config))
+        .cloned()
+        .collect();
+    rank_fuzzy(&filters, &mut selected);
+    selected
+}
 
-    for (id, config) in configs.enumerate() {
-        let id = ConfigId::new(id);
-        let padding = " ".repeat(id.to_string().len());
+/// Lists the targets closest to the filters and the available tags,
+/// to help with fixing a selection that matched nothing.
+fn print_selection_hints(configs: &[PortForwardConfig], filters: &[TargetFilter]) {
+    let suggestions = suggest_targets(filters, configs, 3);
+    if !suggestions.is_empty() {
+        eprintln!("Did you mean one of these targets?");
+        for suggestion in suggestions {
+            eprintln!("  {suggestion}");
+        }
+    }
 
-        if let Some(name) = &config.name {
-            println!("{id} {name}");
-            println!(
-                "{padding} target:  {resource}/{name}.{namespace}",
-                resource = config.r#type.as_arg(),
-                name = config.target,
-                namespace = config.namespace
-            );
-        } else {
-            println!(
-                "{id} target:  {resource}/{name}.{namespace}",
+    let mut tags: Vec<String> = configs
+        .iter()
+        .flat_map(|config| config.tags.iter().map(|tag| tag.to_string()))
+        .collect();
+    tags.sort();
+    tags.dedup();
+    if !tags.is_empty() {
+        eprintln!("Available tags: {tags}", tags = tags.join(", "));
+    }
+}
+
+/// Prints the targets as a table, followed by the details that don't fit into a column.
+fn print_targets(targets: &[(ConfigId, &PortForwardConfig)], verbose: bool, wide: bool) {
+    let mut table = Table::new([
+        "ID",
+        "NAME",
+        "TARGET",
+        "NAMESPACE",
+        "CONTEXT",
+        "CLUSTER",
+        "PORTS",
+        "TAGS",
+    ]);
+    for (id, config) in targets {
+        let ports = config
+            .ports
+            .iter()
+            .map(|port| port.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut tags: Vec<_> = config.tags.iter().map(|tag| tag.to_string()).collect();
+        tags.sort();
+
+        let or_dash = |value: Option<&String>| value.cloned().unwrap_or_else(|| "-".into());
+        table.row(vec![
+            id.to_string(),
+            or_dash(config.name.as_ref()),
+            format!(
+                "{resource}/{name}",
                 resource = config.r#type.as_arg(),
-                name = config.target,
-                namespace = config.namespace
-            );
+                name = config.target
+            ),
+            config.namespace.clone(),
+            or_dash(config.context.as_ref()),
+            or_dash(config.cluster.as_ref()),
+            ports,
+            or_dash(Some(&tags.join(",")).filter(|tags| !tags.is_empty())),
+        ]);
+    }
+    table.print(wide);
+
+    for (id, config) in targets {
+        let padding = " ".repeat(id.to_string().len());
+        for (index, detail) in config_details(config, verbose).iter().enumerate() {
+            match index {
+                0 => println!("{id} {detail}"),
+                _ => println!("{padding} {detail}"),
+            }
         }
+    }
+}
 
-        // Print the currently selected context
-        println!(
-            "{padding} context: {}",
-            config.context.as_deref().unwrap_or("(implicit)")
-        );
+/// Describes the settings of a target that are not shown in the table of targets;
+/// most of them only if `verbose` is set.
+fn config_details(config: &PortForwardConfig, verbose: bool) -> Vec<String> {
+    let mut details = Vec::new();
+
+    // The gateway pod, if any
+    if let Some(via) = &config.via {
+        details.push(format!(
+            "via:     pod/{pod}.{namespace}",
+            pod = via.pod,
+            namespace = via.namespace
+        ));
+    }
 
-        // Print the currently targeted cluster
+    if !verbose {
+        return details;
+    }
+
+    if !config.listen_addrs.is_empty() {
+        details.push(format!("listen:  {}", config.listen_addrs.join(", ")));
+    }
+
+    if let Some(drain_timeout) = &config.drain_timeout_sec {
+        details.push(format!("drain:   {drain_timeout}"));
+    }
+
+    if let Some(restart) = &config.restart {
+        details.push(format!("restart: {restart}"));
+    }
+
+    if let Some(on_port_conflict) = &config.on_port_conflict {
+        details.push(format!("in use:  {on_port_conflict}"));
+    }
+
+    if config.wait_ready == Some(true) {
+        let timeout = config
+            .ready_timeout_sec
+            .map_or(DEFAULT_READY_TIMEOUT, Duration::from);
+        details.push(format!(
+            "wait:    up to {timeout} for a ready pod",
+            timeout = format_duration(timeout)
+        ));
+    }
+
+    if let Some(healthcheck) = &config.healthcheck {
+        details.push(format!(
+            "health:  {check} every {interval}, restart after {threshold} failures",
+            check = healthcheck
+                .http_path()
+                .map_or_else(|| "connect".to_string(), |path| format!("GET {path}")),
+            interval = format_duration(healthcheck.interval()),
+            threshold = healthcheck.failure_threshold()
+        ));
+    }
+
+    if config.failover == Some(true) {
+        details.push("switch:  to another ready pod when the pod dies".into());
+    }
+
+    if config.load_balance == Some(true) {
+        details.push("balance: connections across all ready pods".into());
+    }
+
+    if config.proxy == Some(true) || config.load_balance == Some(true) {
+        let timeout = config
+            .hold_timeout_sec
+            .map_or(DEFAULT_HOLD_TIMEOUT, Duration::from);
+        details.push(format!(
+            "proxy:   connections held for up to {timeout} across restarts",
+            timeout = format_duration(timeout)
+        ));
+    }
+
+    if let Some(keepalive) = &config.keepalive {
+        details.push(match keepalive.is_enabled() {
+            true => format!(
+                "idle:    keepalive connections checked every {interval}",
+                interval = format_duration(keepalive.interval())
+            ),
+            false => "idle:    no keepalive connections".into(),
+        });
+    }
+
+    if let Some(on_ready) = &config.on_ready {
+        details.push(format!("ready:   {command}", command = on_ready.command));
+    }
+
+    if let Some(source_file) = &config.source_file {
+        details.push(format!(
+            "source:  {source_file}",
+            source_file = source_file.display()
+        ));
+    }
+
+    details
+}
+
+/// Prints the uptime, restart counts and establishment latencies of each target after shutdown,
+/// and in verbose mode the traffic relayed by the proxies.
+fn print_summary(summaries: Vec<(ConfigId, Option<String>, ForwardSummary)>, verbose: bool) {
+    if summaries.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Summary:");
+    for (id, name, summary) in summaries {
+        let name = name.map(|name| format!(" {name}")).unwrap_or_default();
+        let latency = summary
+            .mean_latency()
+            .map(|mean| {
+                format!(
+                    ", established after {mean} on average (max {max})",
+                    mean = format_latency(mean),
+                    max = format_latency(summary.max_latency)
+                )
+            })
+            .unwrap_or_default();
+        let traffic = summary
+            .traffic
+            .filter(|_| verbose)
+            .map(|traffic| format!(", {}", format_traffic(&traffic)))
+            .unwrap_or_default();
         println!(
-            "{padding} cluster: {}",
-            config.cluster.as_deref().unwrap_or("(implicit)")
+            "{id}{name}: up {uptime}, {restarts} {noun}{latency}{traffic}",
+            uptime = format_duration(summary.uptime),
+            restarts = summary.restarts,
+            noun = if summary.restarts == 1 {
+                "restart"
+            } else {
+                "restarts"
+            }
         );
+    }
+}
 
-        // Print the currently targeted cluster.
-        if verbose {
-            if let Some(source_file) = &config.source_file {
-                println!(
-                    "{padding} source:  {source_file}",
-                    source_file = source_file.display()
-                );
-            }
-        }
+/// Formats a duration as e.g. `1h 2m 3s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, (secs / 60) % 60, secs % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m {seconds}s"),
+        _ => format!("{hours}h {minutes}m {seconds}s"),
+    }
+}
 
-        map.insert(id, config);
+/// Formats the traffic of a target, e.g. `12 connections, 1.2 KiB sent, 3.4 MiB received`.
+fn format_traffic(traffic: &Traffic) -> String {
+    format!(
+        "{connections} {noun}, {sent} sent, {received} received",
+        connections = traffic.connections,
+        noun = if traffic.connections == 1 {
+            "connection"
+        } else {
+            "connections"
+        },
+        sent = format_bytes(traffic.bytes_sent),
+        received = format_bytes(traffic.bytes_received)
+    )
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `1.2 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
     }
-    map
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {unit}", unit = UNITS[unit])
+}
+
+/// Formats a short duration with sub-second precision, e.g. `1.25s`.
+fn format_latency(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
 }
 
-fn start_output_loop_thread(out_rx: Receiver<ChildEvent>) -> JoinHandle<()> {
+fn start_output_loop_thread(
+    out_rx: Receiver<ChildEvent>,
+    logs: LogBook,
+    verbose: bool,
+    theme: Theme,
+) -> JoinHandle<()> {
     thread::spawn(move || {
+        // Events arrive in order, so they belong to the most recent attempt of their target.
+        let mut attempts = HashMap::new();
         while let Ok(event) = out_rx.recv() {
-            match event {
-                ChildEvent::Output(id, channel, message) => {
-                    // TODO: use display name
-                    match channel {
-                        StreamSource::StdOut => println!("{id}: {message}"),
-                        StreamSource::StdErr => eprintln!("{id}: {message}"),
-                    }
+            let (id, source, message) = match event {
+                ChildEvent::Attempt(id, attempt) => {
+                    attempts.insert(id, attempt);
+                    continue;
                 }
+                ChildEvent::Output(id, source, message) => (id, source, message),
                 ChildEvent::Exit(id, status, policy) => {
-                    // TODO: use display name
-                    match policy {
-                        RestartPolicy::WillRestartIn(delay) => {
-                            if delay > RetryDelay::NONE {
-                                eprintln!(
-                                    "{id}: Process exited with {} - will retry in {}",
-                                    status, delay
-                                );
-                            } else {
-                                eprintln!(
-                                    "{id}: Process exited with {} - retrying immediately",
-                                    status
-                                );
-                            }
+                    let message = match policy {
+                        RestartPolicy::WillRestartIn(delay, retry) => {
+                            // Only limited retries are worth counting.
+                            let budget = match retry.max {
+                                Some(_) => format!(" ({retry})"),
+                                None => String::new(),
+                            };
+                            theme.mark(
+                                ForwardState::Restarting,
+                                if delay > Seconds::ZERO {
+                                    format!(
+                                        "Process exited with {} - will retry in {}{}",
+                                        status, delay, budget
+                                    )
+                                } else {
+                                    format!(
+                                        "Process exited with {} - retrying immediately{}",
+                                        status, budget
+                                    )
+                                },
+                            )
                         }
-                    }
-                }
-                ChildEvent::Error(id, error) => {
-                    // TODO: use display name
-                    eprintln!("{id}: An error occurred: {}", error);
+                        RestartPolicy::WillNotRestart => theme.mark(
+                            ForwardState::Stopped,
+                            format!("Process exited with {}", status),
+                        ),
+                    };
+                    (id, StreamSource::StdErr, message)
                 }
+                ChildEvent::Error(id, error) => (
+                    id,
+                    StreamSource::StdErr,
+                    format!("An error occurred: {}", error),
+                ),
+                ChildEvent::Fatal(id, error) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(
+                        ForwardState::Failed,
+                        format!("{error} - not retrying until restarted"),
+                    ),
+                ),
+                ChildEvent::Drift(id, drift) => (
+                    id,
+                    StreamSource::StdErr,
+                    if drift.is_empty() {
+                        "The configuration matches the cluster again".to_string()
+                    } else {
+                        format!("Configuration drift: {}", drift.join("; "))
+                    },
+                ),
+                ChildEvent::Remapped(id, port, free) => (
+                    id,
+                    StreamSource::StdErr,
+                    format!("Local port {port} is already in use - listening on port {free} instead"),
+                ),
+                ChildEvent::Reclaimed(id, port, pid) => (
+                    id,
+                    StreamSource::StdErr,
+                    format!("Local port {port} was held by kubectl (PID {pid}) of a crashed session - terminated it"),
+                ),
+                ChildEvent::PodReplaced(id, previous, pod) => (
+                    id,
+                    StreamSource::StdErr,
+                    format!("Pod {previous} no longer exists - forwarding to {pod} instead"),
+                ),
+                ChildEvent::PodTerminated(id, pod) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(
+                        ForwardState::Restarting,
+                        format!("Pod {pod} was terminated - restarting"),
+                    ),
+                ),
+                ChildEvent::NotReady(id, timeout) => (
+                    id,
+                    StreamSource::StdErr,
+                    format!(
+                        "No pod became ready within {timeout} - not forwarding yet",
+                        timeout = format_duration(timeout)
+                    ),
+                ),
+                ChildEvent::Established(_, _) if !verbose => continue,
+                ChildEvent::Established(id, latency) => (
+                    id,
+                    StreamSource::StdOut,
+                    theme.mark(
+                        ForwardState::Forwarding,
+                        format!("Forwarding established after {}", format_latency(latency)),
+                    ),
+                ),
+                ChildEvent::Balancing(id, pods) => (
+                    id,
+                    StreamSource::StdOut,
+                    format!(
+                        "Balancing connections across {count} pods: {pods}",
+                        count = pods.len(),
+                        pods = pods.join(", ")
+                    ),
+                ),
+                ChildEvent::Removed(id) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(
+                        ForwardState::Stopped,
+                        "Removed from the configuration - stopping",
+                    ),
+                ),
+                ChildEvent::Reconfigured(id, fields) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(
+                        ForwardState::Restarting,
+                        format!(
+                            "Configuration changed ({fields}) - restarting with the new configuration",
+                            fields = fields.join(", ")
+                        ),
+                    ),
+                ),
+                ChildEvent::Unhealthy(id, message) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(ForwardState::Restarting, format!("{message} - restarting")),
+                ),
+                ChildEvent::Failed(id, retries) => (
+                    id,
+                    StreamSource::StdErr,
+                    theme.mark(
+                        ForwardState::Failed,
+                        format!("Giving up after {retries} retries - use `k8sfwd restart '{id}'` to try again"),
+                    ),
+                ),
+            };
+
+            // TODO: use display name
+            let attempt = attempts.get(&id).copied().unwrap_or(1);
+            match source {
+                StreamSource::StdOut => println!("{id} [{attempt}]: {message}"),
+                StreamSource::StdErr => eprintln!("{id} [{attempt}]: {message}"),
             }
+
+            logs.record(id, attempt, source, &message);
         }
     })
 }
@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Verifies that HTTP/2 with prior knowledge, and with it gRPC, passes through the tunnel
+//! of a target unmodified, i.e. that the server answers the HTTP/2 connection preface with
+//! HTTP/2 rather than being downgraded to HTTP/1 somewhere along the way.
+
+use crate::config::{ConfigId, PortForwardConfig, Protocol};
+use crate::health;
+use crate::kubectl::{ChildEvent, StreamSource};
+use crate::status::StatusHandle;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for the server to answer the connection preface.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The connection preface of an HTTP/2 client, followed by an empty `SETTINGS` frame.
+const CLIENT_PREFACE: &[u8] =
+    b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\x00\x00\x00\x04\x00\x00\x00\x00\x00";
+
+/// The type of the `SETTINGS` frame, which a server must send first.
+const FRAME_SETTINGS: u8 = 0x04;
+
+/// Checks the first port of a forwarding target in the background, reporting the outcome
+/// as output of the target.
+pub fn check(
+    id: ConfigId,
+    protocol: Protocol,
+    config: &PortForwardConfig,
+    status: StatusHandle,
+    out_tx: Sender<ChildEvent>,
+) {
+    let ip = health::local_addresses(&config.listen_addrs)[0];
+    let local = config.ports.first().and_then(|port| port.local);
+    thread::spawn(move || {
+        let Some(port) = local.or_else(|| status.snapshot().local_ports.first().copied()) else {
+            return;
+        };
+
+        let (source, message) = match probe(SocketAddr::new(ip, port)) {
+            Ok(()) => (
+                StreamSource::StdOut,
+                format!("{protocol} passes through port {port} unmodified"),
+            ),
+            Err(failure) => {
+                let message = format!("{protocol} does not pass through port {port}: {failure}");
+                status.error(message.as_str());
+                (StreamSource::StdErr, message)
+            }
+        };
+        out_tx.send(ChildEvent::Output(id, source, message)).ok();
+    });
+}
+
+/// Sends the HTTP/2 connection preface and checks that the server answers with HTTP/2.
+fn probe(address: SocketAddr) -> Result<(), PassthroughError> {
+    let mut stream = health::connect(address)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_write_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.write_all(CLIENT_PREFACE)?;
+    classify(&read_response(&mut stream)?)
+}
+
+/// Reads the first bytes of the response, enough for the header of a frame.
+fn read_response(stream: &mut TcpStream) -> Result<Vec<u8>, PassthroughError> {
+    let mut response = Vec::new();
+    let mut buffer = [0; 9];
+    while response.len() < buffer.len() {
+        match stream.read(&mut buffer[response.len()..]) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(PassthroughError::NoResponse);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(response)
+}
+
+/// Determines whether the response starts with the `SETTINGS` frame of an HTTP/2 server.
+fn classify(response: &[u8]) -> Result<(), PassthroughError> {
+    match response {
+        [] => Err(PassthroughError::Closed),
+        [_, _, _, FRAME_SETTINGS, _, 0, 0, 0, 0] => Ok(()),
+        [b'H', b'T', b'T', b'P', b'/', ..] => Err(PassthroughError::Downgraded),
+        // The content type of a TLS record, e.g. an alert.
+        [0x14..=0x17, 0x03, ..] => Err(PassthroughError::Tls),
+        _ => Err(PassthroughError::NotHttp2),
+    }
+}
+
+/// Finds the settings of a target that would break its protocol, e.g. features that
+/// assume HTTP/1.
+pub fn diagnose(config: &PortForwardConfig) -> Vec<String> {
+    let Some(protocol) = config.protocol else {
+        return Vec::new();
+    };
+
+    let mut problems = Vec::new();
+    if config.host.is_some() {
+        problems.push(format!(
+            "The HTTP router of `host` only routes HTTP/1 requests - {protocol} clients must connect to the local port directly"
+        ));
+    }
+    if let Some(path) = config
+        .healthcheck
+        .as_ref()
+        .and_then(|check| check.http_path())
+    {
+        problems.push(format!(
+            "The health check requests {path} with HTTP/1.1, which {protocol} servers usually reject - use `type: tcp` to only check connections"
+        ));
+    }
+    if config.is_load_balanced() {
+        problems.push(format!(
+            "`load_balance` spreads connections, but {protocol} clients send all requests over a single connection"
+        ));
+    }
+    problems
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PassthroughError {
+    #[error("the server answered the HTTP/2 preface with HTTP/1 - it, or something in front of it, does not accept HTTP/2 without an upgrade")]
+    Downgraded,
+    #[error("the server expects TLS - connect with TLS instead of prior knowledge")]
+    Tls,
+    #[error("the server answered the HTTP/2 preface with something other than HTTP/2")]
+    NotHttp2,
+    #[error("the connection was closed without a response to the HTTP/2 preface")]
+    Closed,
+    #[error("the server did not answer the HTTP/2 preface")]
+    NoResponse,
+    #[error("the connection failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    /// Answers a single connection with the response after reading the preface.
+    fn serve(response: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut preface = [0; CLIENT_PREFACE.len()];
+            stream.read_exact(&mut preface).unwrap();
+            stream.write_all(response).unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn test_probe() {
+        let settings = b"\x00\x00\x06\x04\x00\x00\x00\x00\x00\x00\x03\x00\x00\x00\x64";
+        assert!(probe(serve(settings)).is_ok());
+
+        let http1 = b"HTTP/1.1 400 Bad Request\r\n\r\n";
+        assert!(matches!(
+            probe(serve(http1)),
+            Err(PassthroughError::Downgraded)
+        ));
+        assert!(matches!(probe(serve(b"")), Err(PassthroughError::Closed)));
+    }
+
+    #[test]
+    fn test_classify() {
+        assert!(matches!(
+            classify(b"\x15\x03\x01\x00\x02\x02\x46"),
+            Err(PassthroughError::Tls)
+        ));
+        // A frame other than SETTINGS, e.g. GOAWAY.
+        assert!(matches!(
+            classify(b"\x00\x00\x08\x07\x00\x00\x00\x00\x00"),
+            Err(PassthroughError::NotHttp2)
+        ));
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: api
+            protocol: grpc
+            host: api.localhost
+            healthcheck: { http: /healthz }
+            ports: ["50051"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(diagnose(&config).len(), 2);
+    }
+}
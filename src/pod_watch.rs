@@ -0,0 +1,425 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Watches the pod backing a port-forward, such that the forward is restarted as soon
+//! as the pod goes away rather than once the tunnel breaks on the next connection.
+
+use crate::config::{ConfigId, PortForwardConfig, ResourceType};
+use crate::kubectl::{ChildEvent, ForwardCommand, Kubectl, ShellError};
+use crate::signals::ShutdownSignal;
+use crate::status::StatusHandle;
+use serde_json::Value;
+use std::process;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The interval at which the readiness of a pod is polled.
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long to wait for a pod to become ready unless the target specifies otherwise.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Selects the pods that may back a port-forward.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PodSelector {
+    /// Exactly the named pod, e.g. for pod targets.
+    Name(String),
+    /// The pods matching a label selector, e.g. `app=foo,tier=backend`.
+    Labels(String),
+}
+
+impl PodSelector {
+    /// Gets the `kubectl get` arguments selecting the pods.
+    pub fn as_args(&self) -> [String; 2] {
+        match self {
+            PodSelector::Name(name) => ["--field-selector".into(), format!("metadata.name={name}")],
+            PodSelector::Labels(labels) => ["-l".into(), labels.clone()],
+        }
+    }
+}
+
+/// A running watch of the pod backing a port-forward; stops watching when dropped.
+pub struct PodWatch {
+    child: process::Child,
+}
+
+impl PodWatch {
+    /// Determines the pod backing the forward and watches it until it is deleted.
+    ///
+    /// The forward is then restarted through its own command channel.
+    /// Returns `None` if the pod could not be determined.
+    pub fn start(
+        kubectl: &Kubectl,
+        config: &PortForwardConfig,
+        id: ConfigId,
+        status: &StatusHandle,
+        out_tx: Sender<ChildEvent>,
+        commands: Sender<ForwardCommand>,
+    ) -> Result<Option<Self>, ShellError> {
+        let Some(BackingPod {
+            namespace,
+            selector,
+            pod,
+        }) = BackingPod::resolve(kubectl, config)?
+        else {
+            return Ok(None);
+        };
+
+        if let PodSelector::Labels(_) = selector {
+            status.pod(format!("{pod}.{namespace}"));
+        }
+
+        let context = config.context.as_ref();
+        let cluster = config.cluster.as_ref();
+        let mut child = kubectl.watch_pods(context, cluster, &namespace, &selector)?;
+        let Some(stdout) = child.stdout.take() else {
+            return Ok(None);
+        };
+
+        thread::spawn(move || {
+            let events = serde_json::Deserializer::from_reader(stdout).into_iter::<Value>();
+            for event in events {
+                let Ok(event) = event else {
+                    break;
+                };
+
+                if is_termination_of(&event, &pod) {
+                    out_tx.send(ChildEvent::PodTerminated(id, pod)).ok();
+                    commands.send(ForwardCommand::Restart).ok();
+                    break;
+                }
+            }
+        });
+
+        Ok(Some(Self { child }))
+    }
+}
+
+impl Drop for PodWatch {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// The pod a port-forward connects to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackingPod {
+    /// The namespace of the pod.
+    pub namespace: String,
+    /// Selects the pods that may back the port-forward.
+    pub selector: PodSelector,
+    /// The name of the pod.
+    pub pod: String,
+}
+
+impl BackingPod {
+    /// Determines the pod a port-forward connects to; for services and deployments,
+    /// the one kubectl would pick. Returns `None` if no such pod exists.
+    pub fn resolve(
+        kubectl: &Kubectl,
+        config: &PortForwardConfig,
+    ) -> Result<Option<Self>, ShellError> {
+        let Some((namespace, selector)) = Self::selector(kubectl, config)? else {
+            return Ok(None);
+        };
+
+        let context = config.context.as_ref();
+        let cluster = config.cluster.as_ref();
+        let pod = match &selector {
+            PodSelector::Name(name) => {
+                let resource = format!("pod/{name}");
+                if kubectl
+                    .get_resource(context, cluster, Some(&namespace), &resource)?
+                    .is_none()
+                {
+                    return Ok(None);
+                }
+                name.clone()
+            }
+            // kubectl picks the pod of services and deployments itself; pick the same one.
+            PodSelector::Labels(_) => {
+                let pods = kubectl.list_pods(context, cluster, &namespace, &selector)?;
+                let Some(pod) = backing_pod(&pods) else {
+                    return Ok(None);
+                };
+                pod
+            }
+        };
+
+        Ok(Some(Self {
+            namespace,
+            selector,
+            pod,
+        }))
+    }
+
+    /// Determines the namespace and the pods that may back the forward.
+    fn selector(
+        kubectl: &Kubectl,
+        config: &PortForwardConfig,
+    ) -> Result<Option<(String, PodSelector)>, ShellError> {
+        if let Some(gateway) = &config.via {
+            let selector = PodSelector::Name(gateway.pod.clone());
+            return Ok(Some((gateway.namespace.clone(), selector)));
+        }
+
+        let namespace = config.namespace.clone();
+        let pointer = match config.r#type {
+            ResourceType::Pod => {
+                let selector = PodSelector::Name(config.target.clone());
+                return Ok(Some((namespace, selector)));
+            }
+            ResourceType::Service => "/spec/selector",
+            ResourceType::Deployment => "/spec/selector/matchLabels",
+        };
+
+        let resource = format!("{}/{}", config.r#type.as_arg(), config.target);
+        let Some(resource) = kubectl.get_resource(
+            config.context.as_ref(),
+            config.cluster.as_ref(),
+            Some(&namespace),
+            &resource,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        Ok(resource
+            .pointer(pointer)
+            .and_then(label_selector)
+            .map(|labels| (namespace, PodSelector::Labels(labels))))
+    }
+}
+
+/// Waits until a pod that may back the forward is ready, e.g. while a deployment rolls out.
+///
+/// Returns the ready pod, or `None` if the timeout passed or a shutdown was requested first.
+pub fn wait_ready(
+    kubectl: &Kubectl,
+    config: &PortForwardConfig,
+    timeout: Duration,
+    shutdown: &ShutdownSignal,
+) -> Result<Option<String>, ShellError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        // The resource itself may not exist yet either.
+        if let Some((namespace, selector)) = BackingPod::selector(kubectl, config)? {
+            let pods = kubectl.list_pods(
+                config.context.as_ref(),
+                config.cluster.as_ref(),
+                &namespace,
+                &selector,
+            )?;
+            if let Some(pod) = ready_pod(&pods) {
+                return Ok(Some(pod));
+            }
+        }
+
+        let now = Instant::now();
+        if now >= deadline || shutdown.sleep(READY_POLL_INTERVAL.min(deadline - now)) {
+            return Ok(None);
+        }
+    }
+}
+
+/// Lists the ready pods that may back the forward, e.g. all replicas of a deployment.
+pub fn ready_pods(
+    kubectl: &Kubectl,
+    config: &PortForwardConfig,
+) -> Result<Vec<String>, ShellError> {
+    let Some((namespace, selector)) = BackingPod::selector(kubectl, config)? else {
+        return Ok(Vec::new());
+    };
+
+    let pods = kubectl.list_pods(
+        config.context.as_ref(),
+        config.cluster.as_ref(),
+        &namespace,
+        &selector,
+    )?;
+    Ok(ready_pods_of(&pods))
+}
+
+/// Formats a map of labels as a label selector.
+fn label_selector(labels: &Value) -> Option<String> {
+    let selector: Vec<_> = labels
+        .as_object()?
+        .iter()
+        .filter_map(|(key, value)| Some(format!("{key}={value}", value = value.as_str()?)))
+        .collect();
+
+    (!selector.is_empty()).then(|| selector.join(","))
+}
+
+/// Picks the pod kubectl forwards to from a list of pods: running and ready pods first,
+/// then the ones ready for the longest time, with the fewest restarts, and the oldest.
+fn backing_pod(list: &Value) -> Option<String> {
+    let pods = list["items"].as_array()?;
+    pods.iter()
+        .filter(|pod| pod["metadata"]["deletionTimestamp"].is_null())
+        .min_by_key(|pod| {
+            let phase = match pod["status"]["phase"].as_str() {
+                Some("Running") => 0,
+                Some("Unknown") => 1,
+                _ => 2,
+            };
+            let ready = ready_since(pod).map(String::from);
+            let restarts: u64 = pod["status"]["containerStatuses"]
+                .as_array()
+                .map(|containers| {
+                    containers
+                        .iter()
+                        .filter_map(|c| c["restartCount"].as_u64())
+                        .sum()
+                })
+                .unwrap_or_default();
+            let created = pod["metadata"]["creationTimestamp"]
+                .as_str()
+                .map(String::from);
+
+            // RFC 3339 timestamps sort chronologically; pods without one go last.
+            (
+                phase,
+                ready.is_none(),
+                ready,
+                restarts,
+                created.is_none(),
+                created,
+            )
+        })
+        .and_then(|pod| pod["metadata"]["name"].as_str())
+        .map(String::from)
+}
+
+/// Picks the first ready pod from a list of pods.
+fn ready_pod(list: &Value) -> Option<String> {
+    ready_pods_of(list).into_iter().next()
+}
+
+/// Gets the names of the ready pods from a list of pods.
+fn ready_pods_of(list: &Value) -> Vec<String> {
+    let Some(pods) = list["items"].as_array() else {
+        return Vec::new();
+    };
+
+    pods.iter()
+        .filter(|pod| pod["metadata"]["deletionTimestamp"].is_null())
+        .filter(|pod| ready_since(pod).is_some())
+        .filter_map(|pod| pod["metadata"]["name"].as_str())
+        .map(String::from)
+        .collect()
+}
+
+/// Gets the time since which the pod is ready, or `None` if it isn't.
+fn ready_since(pod: &Value) -> Option<&str> {
+    pod["status"]["conditions"]
+        .as_array()?
+        .iter()
+        .find(|c| c["type"] == "Ready" && c["status"] == "True")
+        .map(|condition| condition["lastTransitionTime"].as_str().unwrap_or_default())
+}
+
+/// Determines whether a watch event reports the pod as gone.
+fn is_termination_of(event: &Value, pod: &str) -> bool {
+    let object = &event["object"];
+    if object["metadata"]["name"] != pod {
+        return false;
+    }
+
+    event["type"] == "DELETED"
+        || matches!(
+            object["status"]["phase"].as_str(),
+            Some("Succeeded") | Some("Failed")
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_backing_pod() {
+        let list = json!({
+            "items": [
+                {
+                    "metadata": { "name": "pending", "creationTimestamp": "2023-07-22T08:00:00Z" },
+                    "status": { "phase": "Pending" }
+                },
+                {
+                    "metadata": { "name": "young", "creationTimestamp": "2023-07-22T10:00:00Z" },
+                    "status": {
+                        "phase": "Running",
+                        "conditions": [
+                            { "type": "Ready", "status": "True", "lastTransitionTime": "2023-07-22T10:01:00Z" }
+                        ]
+                    }
+                },
+                {
+                    "metadata": { "name": "old", "creationTimestamp": "2023-07-22T09:00:00Z" },
+                    "status": {
+                        "phase": "Running",
+                        "conditions": [
+                            { "type": "Ready", "status": "True", "lastTransitionTime": "2023-07-22T09:01:00Z" }
+                        ]
+                    }
+                }
+            ]
+        });
+
+        assert_eq!(backing_pod(&list).as_deref(), Some("old"));
+        assert_eq!(backing_pod(&json!({ "items": [] })), None);
+    }
+
+    #[test]
+    fn test_ready_pod() {
+        let list = json!({
+            "items": [
+                {
+                    "metadata": { "name": "starting" },
+                    "status": {
+                        "phase": "Running",
+                        "conditions": [{ "type": "Ready", "status": "False" }]
+                    }
+                },
+                {
+                    "metadata": { "name": "ready" },
+                    "status": {
+                        "phase": "Running",
+                        "conditions": [{ "type": "Ready", "status": "True" }]
+                    }
+                }
+            ]
+        });
+
+        assert_eq!(ready_pod(&list).as_deref(), Some("ready"));
+        assert_eq!(ready_pods_of(&list), vec!["ready"]);
+        assert_eq!(ready_pod(&json!({ "items": [list["items"][0]] })), None);
+    }
+
+    #[test]
+    fn test_is_termination_of() {
+        let deleted = json!({ "type": "DELETED", "object": { "metadata": { "name": "foo" } } });
+        assert!(is_termination_of(&deleted, "foo"));
+        assert!(!is_termination_of(&deleted, "bar"));
+
+        let modified = json!({
+            "type": "MODIFIED",
+            "object": { "metadata": { "name": "foo" }, "status": { "phase": "Running" } }
+        });
+        assert!(!is_termination_of(&modified, "foo"));
+    }
+
+    #[test]
+    fn test_label_selector() {
+        let labels = json!({ "app": "foo", "tier": "backend" });
+        assert_eq!(
+            label_selector(&labels).as_deref(),
+            Some("app=foo,tier=backend")
+        );
+        assert_eq!(label_selector(&json!({})), None);
+    }
+}
@@ -0,0 +1,504 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Checks run before any port-forward is spawned, such that problems are
+//! reported with an actionable message rather than as kubectl errors later on.
+
+use crate::config::{OperationalConfig, PortConflict, PortForwardConfig};
+use crate::kubectl::Kubectl;
+use crate::session::Session;
+use crate::target_filter::most_similar;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::process::{Command, Stdio};
+
+/// Runs the `auth_check` command of each target's cluster.
+///
+/// Each command is run once per context and namespace. The command receives the
+/// `K8SFWD_CONTEXT`, `K8SFWD_CLUSTER` and `K8SFWD_NAMESPACE` environment variables.
+pub fn check_auth(
+    kubectl: &Kubectl,
+    operational: &OperationalConfig,
+    targets: &[PortForwardConfig],
+) -> Vec<PreflightError> {
+    let mut checked = HashSet::new();
+    let mut failures = Vec::new();
+
+    for target in targets {
+        let Some(command) = operational
+            .cluster_config(target)
+            .and_then(|cluster| cluster.auth_check.as_ref())
+        else {
+            continue;
+        };
+
+        // Forwards through a gateway connect to the gateway's namespace.
+        let namespace = match &target.via {
+            Some(gateway) => &gateway.namespace,
+            None => &target.namespace,
+        };
+
+        let context = target.context.clone().unwrap_or_default();
+        let cluster = target.cluster.clone().unwrap_or_default();
+        if !checked.insert((command, context.clone(), cluster.clone(), namespace)) {
+            continue;
+        }
+
+        let output = kubectl
+            .shell_command(command)
+            .env("K8SFWD_CONTEXT", &context)
+            .env("K8SFWD_CLUSTER", &cluster)
+            .env("K8SFWD_NAMESPACE", namespace)
+            .stdin(Stdio::null())
+            .output();
+
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                failures.push(PreflightError::AuthCheckNotRunnable(command.clone(), e));
+                continue;
+            }
+        };
+
+        if !output.status.success() {
+            let mut message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                if !message.is_empty() {
+                    message.push_str(": ");
+                }
+                message.push_str(stderr.trim());
+            }
+            if message.is_empty() {
+                message = format!("exited with {status}", status = output.status);
+            }
+
+            failures.push(PreflightError::AuthCheckFailed {
+                command: command.clone(),
+                context,
+                namespace: namespace.clone(),
+                message,
+            });
+        }
+    }
+
+    failures
+}
+
+/// Verifies that the resources of the targets exist in their namespaces, suggesting a
+/// similarly named resource for each missing one.
+///
+/// Targets forwarding through a gateway pod refer to hosts rather than resources and are
+/// not checked. The resources of each kind are listed once per namespace.
+pub fn check_resources(kubectl: &Kubectl, targets: &[PortForwardConfig]) -> Vec<PreflightError> {
+    let mut listed = HashMap::new();
+    let mut failures = Vec::new();
+
+    for target in targets.iter().filter(|target| target.via.is_none()) {
+        let kind = target.r#type.as_arg();
+        let key = (&target.context, &target.cluster, &target.namespace, kind);
+        let names = listed.entry(key).or_insert_with(|| {
+            kubectl.list_names(
+                target.context.as_ref(),
+                target.cluster.as_ref(),
+                &target.namespace,
+                kind,
+            )
+        });
+
+        let resource = format!("{kind}/{target}", target = target.target);
+        let names = match names {
+            Ok(names) => names,
+            Err(e) => {
+                failures.push(PreflightError::ResourceLookup(resource, e.to_string()));
+                continue;
+            }
+        };
+
+        if names.contains(&target.target) {
+            continue;
+        }
+
+        let namespace = target.namespace.clone();
+        failures.push(match most_similar(&target.target, names) {
+            Some(similar) => PreflightError::ResourceNotFoundSimilar {
+                resource,
+                namespace,
+                similar: similar.to_string(),
+            },
+            None => PreflightError::ResourceNotFound {
+                resource,
+                namespace,
+            },
+        });
+    }
+
+    failures
+}
+
+/// Verifies that the local listen addresses of the targets are available.
+///
+/// This mainly catches IPv6 addresses on hosts without IPv6 support, on which
+/// `kubectl` would otherwise fail to listen on every restart. `localhost` is
+/// resolved by `kubectl`, which is content with either family, and is not checked.
+pub fn check_listen_addrs(targets: &[PortForwardConfig]) -> Vec<PreflightError> {
+    let mut checked = HashSet::new();
+    let mut failures = Vec::new();
+
+    for target in targets {
+        for address in &target.listen_addrs {
+            let Ok(ip) = address.parse::<IpAddr>() else {
+                continue;
+            };
+
+            if !checked.insert(ip) {
+                continue;
+            }
+
+            if let Err(e) = TcpListener::bind((ip, 0)) {
+                failures.push(PreflightError::ListenAddrUnavailable(ip, e));
+            }
+        }
+    }
+
+    failures
+}
+
+/// Verifies that no two targets listen on the same local port of the same address, which
+/// `kubectl` would only notice when the second target starts.
+///
+/// Unspecified addresses, e.g. `0.0.0.0`, overlap with every address of their family.
+pub fn check_local_ports(targets: &[PortForwardConfig]) -> Vec<PreflightError> {
+    let mut bound: Vec<(IpAddr, u16, &PortForwardConfig)> = Vec::new();
+    let mut failures = Vec::new();
+
+    for target in targets {
+        let (addresses, _) = listen_addresses(&target.listen_addrs);
+        for local in target.ports.iter().filter_map(|port| port.local) {
+            for ip in &addresses {
+                let conflict = bound.iter().find(|(other_ip, other_port, other)| {
+                    *other_port == local
+                        && !std::ptr::eq(*other, target)
+                        && addresses_overlap(*ip, *other_ip)
+                });
+                if let Some((_, _, other)) = conflict {
+                    failures.push(PreflightError::DuplicateLocalPort {
+                        address: *ip,
+                        port: local,
+                        first: describe(other),
+                        second: describe(target),
+                    });
+                    break;
+                }
+                bound.push((*ip, local, target));
+            }
+        }
+    }
+
+    failures
+}
+
+/// Verifies that the local ports of the targets are free, such that all occupied ports are
+/// reported at once rather than as interleaved kubectl errors of each target.
+///
+/// Targets remapping their ports on conflicts are not checked, nor are ports of targets
+/// reclaiming them from kubectl processes of crashed sessions.
+pub fn check_local_ports_free(targets: &[PortForwardConfig]) -> Vec<PreflightError> {
+    let mut failures = Vec::new();
+
+    for target in targets {
+        let on_port_conflict = target.on_port_conflict.unwrap_or_default();
+        if on_port_conflict == PortConflict::Remap {
+            continue;
+        }
+
+        let (addresses, _) = listen_addresses(&target.listen_addrs);
+        for local in target.ports.iter().filter_map(|port| port.local) {
+            let in_use = addresses.iter().find(|ip| {
+                matches!(TcpListener::bind((**ip, local)), Err(e) if e.kind() == io::ErrorKind::AddrInUse)
+            });
+            let Some(address) = in_use.copied() else {
+                continue;
+            };
+
+            let holder = match Session::find_orphan(local) {
+                Some(_) if on_port_conflict == PortConflict::Reclaim => continue,
+                Some(pid) => Some(format!("kubectl (PID {pid}) of a crashed k8sfwd session")),
+                None => process_listening_on(local),
+            };
+
+            let target = describe(target);
+            failures.push(match holder {
+                Some(holder) => PreflightError::LocalPortHeld {
+                    address,
+                    port: local,
+                    target,
+                    holder,
+                },
+                None => PreflightError::LocalPortInUse {
+                    address,
+                    port: local,
+                    target,
+                },
+            });
+        }
+    }
+
+    failures
+}
+
+/// Describes the process listening on the local port, if `lsof` can tell.
+fn process_listening_on(port: u16) -> Option<String> {
+    let output = Command::new("lsof")
+        .args(["-nP", "-sTCP:LISTEN", "-Fpc"])
+        .arg(format!("-iTCP:{port}"))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    parse_lsof(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the first process of the `-Fpc` output of `lsof`, i.e. lines of a
+/// field type character followed by the value, e.g. `p1234` and `cnginx`.
+fn parse_lsof(output: &str) -> Option<String> {
+    let mut pid = None;
+    for line in output.lines() {
+        match line.split_at_checked(1) {
+            Some(("p", value)) if pid.is_none() => pid = Some(value),
+            Some(("c", command)) => {
+                return pid.map(|pid| format!("{command} (PID {pid})"));
+            }
+            _ => {}
+        }
+    }
+
+    pid.map(|pid| format!("PID {pid}"))
+}
+
+/// Determines whether listening on both addresses conflicts.
+fn addresses_overlap(a: IpAddr, b: IpAddr) -> bool {
+    a == b || (a.is_ipv4() == b.is_ipv4() && (a.is_unspecified() || b.is_unspecified()))
+}
+
+/// Describes a target along with the file it was configured in, e.g. `api (.k8sfwd)`.
+fn describe(target: &PortForwardConfig) -> String {
+    let name = target.name.as_ref().unwrap_or(&target.target);
+    match &target.source_file {
+        Some(file) => format!("{name} ({file})", file = file.display()),
+        None => name.clone(),
+    }
+}
+
+/// Ensures that the loopback alias of a target exists, adding it to the loopback interface
+/// where only `127.0.0.1` exists by default, i.e. on macOS and the BSDs.
+///
+/// Returns `true` if the alias was added, which requires administrator rights.
+pub fn ensure_loopback_alias(alias: Ipv4Addr) -> Result<bool, PreflightError> {
+    match TcpListener::bind((alias, 0)) {
+        Ok(_) => Ok(false),
+        Err(e)
+            if cfg!(any(
+                target_os = "macos",
+                target_os = "freebsd",
+                target_os = "openbsd"
+            )) =>
+        {
+            let output = Command::new("ifconfig")
+                .args(["lo0", "alias", &alias.to_string(), "up"])
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|_| PreflightError::LoopbackAliasUnavailable(alias, e))?;
+            if !output.status.success() {
+                let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                return Err(PreflightError::LoopbackAliasNotAdded(alias, message));
+            }
+            Ok(true)
+        }
+        Err(e) => Err(PreflightError::LoopbackAliasUnavailable(alias, e)),
+    }
+}
+
+/// Finds the first local port after the specified one that is free on all listen addresses.
+///
+/// Without listen addresses, `kubectl` listens on `localhost`, i.e. on both loopback addresses.
+pub fn next_free_port(listen_addrs: &[String], port: u16) -> Option<u16> {
+    let (addresses, localhost) = listen_addresses(listen_addrs);
+    (port.checked_add(1)?..=u16::MAX).find(|candidate| {
+        addresses
+            .iter()
+            .all(|ip| bind_localhost(SocketAddr::new(*ip, *candidate), localhost).is_ok())
+    })
+}
+
+/// Listens on one of the addresses returned by [`listen_addresses`].
+///
+/// Hosts without IPv6 support can't listen on `::1`, which `kubectl` accepts for `localhost`;
+/// that address is then skipped and `None` is returned.
+pub fn bind_localhost(address: SocketAddr, localhost: bool) -> io::Result<Option<TcpListener>> {
+    match TcpListener::bind(address) {
+        Ok(listener) => Ok(Some(listener)),
+        Err(e) if localhost && address.is_ipv6() && e.kind() == io::ErrorKind::AddrNotAvailable => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Gets the addresses `kubectl` listens on for the listen addresses of a target, and whether
+/// they include `localhost`, i.e. both loopback addresses.
+///
+/// Without listen addresses, `kubectl` listens on `localhost`.
+pub fn listen_addresses(listen_addrs: &[String]) -> (Vec<IpAddr>, bool) {
+    let mut addresses: Vec<IpAddr> = listen_addrs
+        .iter()
+        .filter_map(|address| address.parse().ok())
+        .collect();
+    let localhost = listen_addrs.is_empty() || listen_addrs.iter().any(|a| a == "localhost");
+    if localhost {
+        addresses.extend([
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ]);
+    }
+
+    (addresses, localhost)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreflightError {
+    #[error("Permission check `{command}` failed for namespace {namespace} in context {context}: {message}")]
+    AuthCheckFailed {
+        command: String,
+        context: String,
+        namespace: String,
+        message: String,
+    },
+    #[error("Permission check `{0}` could not be run: {1}")]
+    AuthCheckNotRunnable(String, io::Error),
+    #[error("Unable to listen on {0}: {1}")]
+    ListenAddrUnavailable(IpAddr, io::Error),
+    #[error("Unable to listen on the loopback alias {0}: {1}")]
+    LoopbackAliasUnavailable(Ipv4Addr, io::Error),
+    #[error(
+        "Unable to add the loopback alias {0}: {1} - run `sudo ifconfig lo0 alias {0} up` once"
+    )]
+    LoopbackAliasNotAdded(Ipv4Addr, String),
+    #[error("Unable to check whether {0} exists: {1}")]
+    ResourceLookup(String, String),
+    #[error("The {resource} does not exist in namespace {namespace}")]
+    ResourceNotFound { resource: String, namespace: String },
+    #[error("The {resource} does not exist in namespace {namespace} - did you mean {similar}?")]
+    ResourceNotFoundSimilar {
+        resource: String,
+        namespace: String,
+        similar: String,
+    },
+    #[error("The local port {port} of {target} is in use on {address}")]
+    LocalPortInUse {
+        address: IpAddr,
+        port: u16,
+        target: String,
+    },
+    #[error("The local port {port} of {target} is in use on {address} by {holder}")]
+    LocalPortHeld {
+        address: IpAddr,
+        port: u16,
+        target: String,
+        holder: String,
+    },
+    #[error("The targets {first} and {second} both listen on {address} port {port}")]
+    DuplicateLocalPort {
+        address: IpAddr,
+        port: u16,
+        first: String,
+        second: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_free_port() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        let addresses = vec!["127.0.0.1".to_string()];
+
+        let port = next_free_port(&addresses, taken - 1).unwrap();
+        assert!(port >= taken);
+        assert_ne!(port, taken);
+        assert_eq!(next_free_port(&addresses, u16::MAX), None);
+    }
+
+    #[test]
+    fn test_check_local_ports() {
+        let mut targets: Vec<PortForwardConfig> = serde_yaml::from_str(
+            r#"
+            - target: api
+              ports: ["8080:80"]
+            - target: web
+              listen_addrs: ["127.1.0.1"]
+              ports: ["8080:80"]
+            - name: Admin
+              target: admin
+              listen_addrs: ["0.0.0.0"]
+              ports: ["8080:80", "9090:90"]
+        "#,
+        )
+        .unwrap();
+        targets[2].source_file = Some(".k8sfwd".into());
+
+        let failures = check_local_ports(&targets);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            failures[0].to_string(),
+            "The targets api and Admin (.k8sfwd) both listen on 0.0.0.0 port 8080"
+        );
+        assert!(check_local_ports(&targets[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_check_local_ports_free() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        let mut targets: Vec<PortForwardConfig> = serde_yaml::from_str(&format!(
+            r#"
+            - target: api
+              listen_addrs: ["127.0.0.1"]
+              ports: ["{taken}:80", "81"]
+            - target: web
+              listen_addrs: ["127.0.0.1"]
+              on_port_conflict: remap
+              ports: ["{taken}:80"]
+        "#
+        ))
+        .unwrap();
+
+        let failures = check_local_ports_free(&targets);
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(
+            &failures[0],
+            PreflightError::LocalPortInUse { port, target, .. } | PreflightError::LocalPortHeld { port, target, .. }
+                if *port == taken && target == "api"
+        ));
+
+        drop(listener);
+        targets.truncate(1);
+        assert!(check_local_ports_free(&targets).is_empty());
+    }
+
+    #[test]
+    fn test_parse_lsof() {
+        assert_eq!(
+            parse_lsof("p1234\ncnginx\nf6\n").as_deref(),
+            Some("nginx (PID 1234)")
+        );
+        assert_eq!(parse_lsof("p1234\n").as_deref(), Some("PID 1234"));
+        assert_eq!(parse_lsof(""), None);
+    }
+}
@@ -0,0 +1,701 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Owns the local listeners of a target and relays accepted connections to the ports
+//! kubectl forwards, such that clients see a brief stall rather than refused connections
+//! while kubectl is restarted. Data sent before kubectl responded is replayed to the
+//! restarted tunnel if the connection was lost in between. When kubectl forwards to several
+//! pods, connections are distributed across them in turn. The connections and bytes relayed
+//! are counted in the status of the target.
+
+use crate::config::PortForwardConfig;
+use crate::encryption::Encryption;
+use crate::preflight;
+use crate::status::StatusHandle;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long an accepted connection waits for the tunnel to be (re-)established before it is closed.
+pub const DEFAULT_HOLD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for kubectl to be restarted after it closed a connection before responding.
+const RESTART_GRACE: Duration = Duration::from_secs(1);
+
+/// The most data of a connection that is kept for replaying it to a restarted tunnel.
+const REPLAY_LIMIT: usize = 64 * 1024;
+
+/// The interval at which the listeners are polled and connecting to kubectl is retried.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The address kubectl listens on behind the proxy.
+pub const UPSTREAM_ADDRESS: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+/// The local listeners of a target; stops accepting connections when dropped.
+pub struct Proxy {
+    /// The addresses to listen on.
+    addresses: Vec<IpAddr>,
+    /// Whether the addresses stand for `localhost`, such that IPv6 is optional.
+    localhost: bool,
+    /// The local port of each of the target's ports.
+    local_ports: Mutex<Vec<u16>>,
+    /// The ports kubectl currently listens on for each of the target's ports.
+    upstreams: Vec<Arc<Upstream>>,
+    /// How long accepted connections wait for the tunnel.
+    hold_timeout: Duration,
+    /// Counts the open connections, e.g. to wait for them to finish.
+    status: StatusHandle,
+    /// Stops accepting new connections.
+    closed: Arc<AtomicBool>,
+    /// Stops the accepted connections waiting for the tunnel.
+    stop: Arc<AtomicBool>,
+    listeners: Mutex<Vec<Listener>>,
+}
+
+impl Proxy {
+    /// Listens on the local ports of the target on each of its listen addresses.
+    ///
+    /// Ports without a fixed local port are assigned one now, which is then kept
+    /// across restarts of kubectl.
+    pub fn bind(config: &PortForwardConfig, status: &StatusHandle) -> Result<Self, ProxyError> {
+        let (addresses, localhost) = preflight::listen_addresses(&config.listen_addrs);
+        let mut proxy = Self {
+            addresses,
+            localhost,
+            local_ports: Mutex::default(),
+            upstreams: config
+                .ports
+                .iter()
+                .map(|_| Arc::new(Upstream::default()))
+                .collect(),
+            hold_timeout: config
+                .hold_timeout_sec
+                .map_or(DEFAULT_HOLD_TIMEOUT, Duration::from),
+            status: status.clone(),
+            closed: Arc::new(AtomicBool::new(false)),
+            stop: Arc::new(AtomicBool::new(false)),
+            listeners: Mutex::default(),
+        };
+
+        for (index, port) in config.ports.iter().enumerate() {
+            let (local, listeners) = proxy.listen(index, port.local.unwrap_or_default())?;
+            let local_ports = proxy.local_ports.get_mut().expect("lock is not poisoned");
+            local_ports.push(local);
+            let all = proxy.listeners.get_mut().expect("lock is not poisoned");
+            all.extend(listeners);
+        }
+
+        status.track_traffic();
+        Ok(proxy)
+    }
+
+    /// Listens on the local port on each of the listen addresses, relaying the connections
+    /// to kubectl's port for the target's port with the given index.
+    ///
+    /// Returns the port listened on, which is picked now if `0`, along with the listeners.
+    fn listen(&self, index: usize, local: u16) -> Result<(u16, Vec<Listener>), ProxyError> {
+        let mut local = local;
+        let mut listeners = Vec::new();
+        for ip in &self.addresses {
+            let address = SocketAddr::new(*ip, local);
+            let listener = preflight::bind_localhost(address, self.localhost)
+                .map_err(|e| ProxyError::Listen(address, e))?;
+            let Some(listener) = listener else {
+                continue;
+            };
+
+            let address = listener
+                .local_addr()
+                .map_err(|e| ProxyError::Listen(address, e))?;
+            listener
+                .set_nonblocking(true)
+                .map_err(|e| ProxyError::Listen(address, e))?;
+
+            // Listen on the same port on all addresses.
+            local = address.port();
+            let retired = Arc::new(AtomicBool::new(false));
+            let thread = accept(
+                listener,
+                self.upstreams[index].clone(),
+                self.hold_timeout,
+                self.status.clone(),
+                [self.closed.clone(), retired.clone()],
+                self.stop.clone(),
+            );
+            listeners.push(Listener {
+                index,
+                retired,
+                thread: Some(thread),
+            });
+        }
+
+        if listeners.is_empty() {
+            let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), local);
+            let error = io::Error::from(io::ErrorKind::AddrNotAvailable);
+            return Err(ProxyError::Listen(address, error));
+        }
+
+        Ok((local, listeners))
+    }
+
+    /// Gets the local port of each of the target's ports.
+    pub fn local_ports(&self) -> Vec<u16> {
+        self.local_ports
+            .lock()
+            .expect("lock is not poisoned")
+            .clone()
+    }
+
+    /// Moves the target's port with the given index to another local port while kubectl keeps
+    /// running; a free port is picked if none is given. Connections accepted on the previous
+    /// port are kept open.
+    ///
+    /// Returns the previous and the new local port.
+    pub fn rebind(&self, index: usize, local: Option<u16>) -> Result<(u16, u16), ProxyError> {
+        let previous = self
+            .local_ports()
+            .get(index)
+            .copied()
+            .ok_or(ProxyError::NoSuchPort(index))?;
+        if local == Some(previous) {
+            return Ok((previous, previous));
+        }
+
+        let (local, listeners) = self.listen(index, local.unwrap_or_default())?;
+        let retired: Vec<_> = {
+            let mut current = self.listeners.lock().expect("lock is not poisoned");
+            let (retired, kept) = current
+                .drain(..)
+                .partition(|listener: &Listener| listener.index == index);
+            *current = kept;
+            current.extend(listeners);
+            retired
+        };
+        self.local_ports.lock().expect("lock is not poisoned")[index] = local;
+        self.status.rebound(previous, local);
+
+        // Stop accepting on the previous port.
+        drop(retired);
+        Ok((previous, local))
+    }
+
+    /// Picks new ports for the given number of kubectl processes to listen on and relays
+    /// new connections to them, taking turns between the processes.
+    ///
+    /// Returns the ports of each process in the order of the target's ports.
+    pub fn route(&self, processes: usize) -> io::Result<Vec<Vec<u16>>> {
+        let ports = (0..processes.max(1))
+            .map(|_| {
+                self.upstreams
+                    .iter()
+                    .map(|_| free_port())
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for (index, upstream) in self.upstreams.iter().enumerate() {
+            upstream.set(ports.iter().map(|ports| ports[index]).collect());
+        }
+
+        Ok(ports)
+    }
+
+    /// Holds new connections until the next call to [`route`](Self::route), e.g. when kubectl exited.
+    pub fn pause(&self) {
+        for upstream in &self.upstreams {
+            upstream.set(Vec::new());
+        }
+    }
+
+    /// Stops accepting new connections and waits up to the timeout for the open ones to be
+    /// closed, such that kubectl can be terminated without cutting off in-flight requests.
+    pub fn drain(&self, timeout: Duration) {
+        self.closed.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.status.open_connections() > 0 && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// The ports kubectl listens on for one of the target's ports, one for each kubectl process.
+#[derive(Debug, Default)]
+struct Upstream {
+    ports: Mutex<Vec<u16>>,
+    /// The number of connections made so far, to take turns between the ports.
+    connections: AtomicUsize,
+}
+
+impl Upstream {
+    fn set(&self, ports: Vec<u16>) {
+        *self.ports.lock().expect("lock is not poisoned") = ports;
+    }
+
+    /// Picks the port for the next connection.
+    fn next(&self) -> Option<u16> {
+        let ports = self.ports.lock().expect("lock is not poisoned");
+        if ports.is_empty() {
+            return None;
+        }
+        let turn = self.connections.fetch_add(1, Ordering::Relaxed);
+        Some(ports[turn % ports.len()])
+    }
+
+    fn contains(&self, port: u16) -> bool {
+        self.ports
+            .lock()
+            .expect("lock is not poisoned")
+            .contains(&port)
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.stop.store(true, Ordering::SeqCst);
+        self.listeners
+            .get_mut()
+            .expect("lock is not poisoned")
+            .clear();
+    }
+}
+
+/// Accepts the connections on a local port on one of the listen addresses.
+struct Listener {
+    /// The index of the target's port.
+    index: usize,
+    /// Stops accepting connections on this listener only, e.g. once the port was moved.
+    retired: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.retired.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Accepts connections on the listener until either flag is set, relaying each of them to kubectl.
+fn accept(
+    listener: TcpListener,
+    upstream: Arc<Upstream>,
+    hold_timeout: Duration,
+    status: StatusHandle,
+    closed: [Arc<AtomicBool>; 2],
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !closed.iter().any(|closed| closed.load(Ordering::SeqCst)) {
+            match listener.accept() {
+                Ok((client, _)) => {
+                    let upstream = upstream.clone();
+                    let status = status.clone();
+                    let stop = stop.clone();
+                    status.connection_opened();
+                    thread::spawn(move || {
+                        relay(client, &upstream, hold_timeout, &status, &stop);
+                        status.connection_closed();
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    })
+}
+
+/// Connects to the port kubectl listens on, waiting for it to be (re-)started if necessary,
+/// and copies the data between both connections until either side closes its connection.
+fn relay(
+    mut client: TcpStream,
+    upstream: &Upstream,
+    timeout: Duration,
+    status: &StatusHandle,
+    stop: &AtomicBool,
+) {
+    // Accepted connections may inherit the non-blocking mode of the listener.
+    if client.set_nonblocking(false).is_err() {
+        return;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let Some(server) = handshake(&mut client, upstream, deadline, status, stop) else {
+        return;
+    };
+
+    let (Ok(mut client_reader), Ok(mut server_writer)) = (client.try_clone(), server.try_clone())
+    else {
+        return;
+    };
+
+    let uploads = status.clone();
+    let upload = thread::spawn(move || {
+        copy(&mut client_reader, &mut server_writer, |read| {
+            uploads.transferred(read, 0)
+        });
+        server_writer.shutdown(Shutdown::Write).ok();
+    });
+
+    let (mut server_reader, mut client_writer) = (server, client);
+    copy(&mut server_reader, &mut client_writer, |read| {
+        status.transferred(0, read)
+    });
+    client_writer.shutdown(Shutdown::Write).ok();
+    upload.join().ok();
+}
+
+/// Relays the data of the client until kubectl responds for the first time.
+///
+/// If kubectl is restarted before that, e.g. because the pod went away, the client's data
+/// is replayed to the new tunnel. Returns the connection to kubectl once it responded,
+/// the client stopped sending, or too much data was sent to keep it for replaying.
+fn handshake(
+    client: &mut TcpStream,
+    upstream: &Upstream,
+    deadline: Instant,
+    status: &StatusHandle,
+    stop: &AtomicBool,
+) -> Option<TcpStream> {
+    let mut sent = Vec::new();
+    let mut buffer = [0; 8192];
+    client.set_read_timeout(Some(POLL_INTERVAL)).ok()?;
+
+    'connect: loop {
+        let (port, mut server) = connect(upstream, deadline, stop)?;
+        server.set_read_timeout(Some(POLL_INTERVAL)).ok()?;
+
+        let mut lost = server.write_all(&sent).is_err();
+        while !lost {
+            match server.read(&mut buffer) {
+                Ok(0) => lost = true,
+                Ok(read) => {
+                    // Servers speaking first, e.g. SSH or database servers, don't speak TLS.
+                    if sent.is_empty() {
+                        detect_encryption(client, &buffer[..read], status);
+                    }
+                    client.write_all(&buffer[..read]).ok()?;
+                    status.transferred(0, read);
+                    break;
+                }
+                Err(e) if is_timeout(&e) => {}
+                Err(_) => lost = true,
+            }
+
+            if lost {
+                continue;
+            }
+            if sent.len() >= REPLAY_LIMIT {
+                break;
+            }
+
+            match client.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    // The first bytes of the client tell whether it speaks TLS.
+                    if sent.is_empty() {
+                        detect_encryption(client, &buffer[..read], status);
+                    }
+                    status.transferred(read, 0);
+                    sent.extend_from_slice(&buffer[..read]);
+                    lost = server.write_all(&buffer[..read]).is_err();
+                }
+                Err(e) if is_timeout(&e) => {}
+                Err(_) => return None,
+            }
+        }
+
+        if lost {
+            if restarted(upstream, port, stop) && Instant::now() < deadline {
+                continue 'connect;
+            }
+            return None;
+        }
+
+        client.set_read_timeout(None).ok()?;
+        server.set_read_timeout(None).ok()?;
+        return Some(server);
+    }
+}
+
+/// Records whether the client connected to a local port speaks TLS, judging by its first bytes.
+fn detect_encryption(client: &TcpStream, first_bytes: &[u8], status: &StatusHandle) {
+    if let (Some(encryption), Ok(address)) = (Encryption::detect(first_bytes), client.local_addr())
+    {
+        status.encryption(address.port(), encryption);
+    }
+}
+
+/// Copies the data from the reader to the writer until either side closes its connection,
+/// reporting the number of bytes of each chunk.
+fn copy(reader: &mut TcpStream, writer: &mut TcpStream, mut transferred: impl FnMut(usize)) {
+    let mut buffer = [0; 8192];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+
+        if writer.write_all(&buffer[..read]).is_err() {
+            return;
+        }
+        transferred(read);
+    }
+}
+
+/// Connects to the port kubectl listens on, retrying until the tunnel is up or the deadline passed.
+///
+/// Returns the port along with the connection.
+fn connect(upstream: &Upstream, deadline: Instant, stop: &AtomicBool) -> Option<(u16, TcpStream)> {
+    loop {
+        if let Some(port) = upstream.next() {
+            let address = SocketAddr::new(IpAddr::V4(UPSTREAM_ADDRESS), port);
+            if let Ok(stream) = TcpStream::connect_timeout(&address, POLL_INTERVAL) {
+                return Some((port, stream));
+            }
+        }
+
+        if stop.load(Ordering::SeqCst) || Instant::now() >= deadline {
+            return None;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Determines whether kubectl is restarted shortly after it closed a connection on the port,
+/// rather than the server behind it closing the connection.
+fn restarted(upstream: &Upstream, port: u16, stop: &AtomicBool) -> bool {
+    let deadline = Instant::now() + RESTART_GRACE;
+    while !stop.load(Ordering::SeqCst) {
+        if !upstream.contains(port) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    false
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Picks a port that is currently free on the address kubectl listens on behind the proxy.
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind((UPSTREAM_ADDRESS, 0))?
+        .local_addr()?
+        .port())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("Unable to listen on {0}: {1}")]
+    Listen(SocketAddr, io::Error),
+    #[error("The target has no port {0}")]
+    NoSuchPort(usize),
+    #[error("The target does not listen through the proxy")]
+    NotProxied,
+}
+
+impl ProxyError {
+    /// Gets the local port that is already in use, if that is the cause of the error.
+    pub fn port_in_use(&self) -> Option<u16> {
+        match self {
+            Self::Listen(address, e) if e.kind() == io::ErrorKind::AddrInUse => {
+                Some(address.port())
+            }
+            Self::Listen(..) | Self::NoSuchPort(_) | Self::NotProxied => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigId;
+
+    #[test]
+    fn test_relay() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs: ["127.0.0.1"]
+            ports: [":80"]
+        "#,
+        )
+        .unwrap();
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+        let proxy = Proxy::bind(&config, &status).unwrap();
+        let local = SocketAddr::from((UPSTREAM_ADDRESS, proxy.local_ports()[0]));
+
+        // Connections wait for kubectl to listen rather than being refused.
+        let mut client = TcpStream::connect(local).unwrap();
+        let port = proxy.route(1).unwrap()[0][0];
+        let server = TcpListener::bind((UPSTREAM_ADDRESS, port)).unwrap();
+        let (mut server, _) = server.accept().unwrap();
+
+        client.write_all(b"ping").unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        server.write_all(b"pong").unwrap();
+        drop(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong");
+
+        let traffic = status.snapshot().traffic.unwrap();
+        assert_eq!((traffic.connections, traffic.bytes_sent), (1, 4));
+    }
+
+    #[test]
+    fn test_replay() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs: ["127.0.0.1"]
+            ports: [":80"]
+        "#,
+        )
+        .unwrap();
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+        let proxy = Proxy::bind(&config, &status).unwrap();
+        let local = SocketAddr::from((UPSTREAM_ADDRESS, proxy.local_ports()[0]));
+
+        let port = proxy.route(1).unwrap()[0][0];
+        let server = TcpListener::bind((UPSTREAM_ADDRESS, port)).unwrap();
+        let mut client = TcpStream::connect(local).unwrap();
+        client.write_all(b"ping").unwrap();
+        let (mut lost, _) = server.accept().unwrap();
+        let mut buffer = [0; 4];
+        lost.read_exact(&mut buffer).unwrap();
+
+        // The tunnel is lost before responding; the request is replayed to the restarted one.
+        let port = proxy.route(1).unwrap()[0][0];
+        let server = TcpListener::bind((UPSTREAM_ADDRESS, port)).unwrap();
+        drop(lost);
+        let (mut server, _) = server.accept().unwrap();
+        server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        server.write_all(b"pong").unwrap();
+        drop(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong");
+    }
+
+    #[test]
+    fn test_drain() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs: ["127.0.0.1"]
+            ports: [":80"]
+        "#,
+        )
+        .unwrap();
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+        let proxy = Arc::new(Proxy::bind(&config, &status).unwrap());
+        let local = SocketAddr::from((UPSTREAM_ADDRESS, proxy.local_ports()[0]));
+
+        let port = proxy.route(1).unwrap()[0][0];
+        let server = TcpListener::bind((UPSTREAM_ADDRESS, port)).unwrap();
+        let mut client = TcpStream::connect(local).unwrap();
+        client.write_all(b"ping").unwrap();
+        let (mut server, _) = server.accept().unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).unwrap();
+
+        // Stopping waits for the in-flight connection rather than cutting it off.
+        let draining = proxy.clone();
+        let drain = thread::spawn(move || {
+            let started = Instant::now();
+            draining.drain(Duration::from_secs(10));
+            started.elapsed()
+        });
+        thread::sleep(POLL_INTERVAL * 3);
+        assert!(!drain.is_finished());
+        assert!(TcpStream::connect(local).is_err());
+
+        server.write_all(b"pong").unwrap();
+        drop(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong");
+        drop(client);
+
+        assert!(drain.join().unwrap() < Duration::from_secs(10));
+        assert_eq!(status.snapshot().traffic.unwrap().active, 0);
+    }
+
+    #[test]
+    fn test_rebind() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            listen_addrs: ["127.0.0.1"]
+            ports: [":80"]
+        "#,
+        )
+        .unwrap();
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+        let proxy = Proxy::bind(&config, &status).unwrap();
+        let previous = SocketAddr::from((UPSTREAM_ADDRESS, proxy.local_ports()[0]));
+        let port = proxy.route(1).unwrap()[0][0];
+        let server = TcpListener::bind((UPSTREAM_ADDRESS, port)).unwrap();
+        let mut open = TcpStream::connect(previous).unwrap();
+        let (mut open_server, _) = server.accept().unwrap();
+
+        let (old, new) = proxy.rebind(0, None).unwrap();
+        assert_eq!(old, previous.port());
+        assert_ne!(new, old);
+        assert_eq!(proxy.local_ports(), vec![new]);
+        assert!(TcpStream::connect(previous).is_err());
+
+        // New connections go through the new port to the same kubectl process.
+        let mut client = TcpStream::connect((UPSTREAM_ADDRESS, new)).unwrap();
+        client.write_all(b"ping").unwrap();
+        let (mut server, _) = server.accept().unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        // Connections accepted on the previous port are kept.
+        open.write_all(b"open").unwrap();
+        open_server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"open");
+
+        assert!(matches!(
+            proxy.rebind(1, None),
+            Err(ProxyError::NoSuchPort(1))
+        ));
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let upstream = Upstream::default();
+        assert_eq!(upstream.next(), None);
+
+        upstream.set(vec![1000, 2000]);
+        assert_eq!(upstream.next(), Some(1000));
+        assert_eq!(upstream.next(), Some(2000));
+        assert_eq!(upstream.next(), Some(1000));
+        assert!(upstream.contains(2000));
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Runs the kubectl processes forwarding to the further pods of a load-balanced target,
+//! next to the target's own kubectl process, such that the proxy can take turns between
+//! all of its pods.
+
+use crate::config::ConfigId;
+use crate::kubectl::{read_line_lossy, ChildEvent, ForwardCommand, StreamSource, MAX_LINE_LENGTH};
+use std::io;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The interval at which the processes are checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The kubectl processes forwarding to the further pods of a target; terminates them when dropped.
+pub struct Replicas {
+    pids: Vec<u32>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Replicas {
+    /// Watches the kubectl processes, each forwarding to the named pod.
+    ///
+    /// Once one of them exits, e.g. because its pod went away, the forward is restarted
+    /// through its own command channel such that the pods are listed anew.
+    pub fn start(
+        id: ConfigId,
+        mut children: Vec<(String, process::Child)>,
+        out_tx: Sender<ChildEvent>,
+        commands: Sender<ForwardCommand>,
+    ) -> Self {
+        let pids = children.iter().map(|(_, child)| child.id()).collect();
+        for (pod, child) in &mut children {
+            if let Some(stderr) = child.stderr.take() {
+                forward_errors(id, pod.clone(), stderr, out_tx.clone());
+            }
+        }
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                let exited = children.iter_mut().find_map(|(pod, child)| {
+                    let status = child.try_wait().ok().flatten()?;
+                    Some((pod.clone(), status))
+                });
+
+                if let Some((pod, status)) = exited {
+                    let message =
+                        format!("The forward to pod {pod} exited with {status} - restarting");
+                    out_tx
+                        .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                        .ok();
+                    commands.send(ForwardCommand::Restart).ok();
+                    break;
+                }
+            }
+
+            for (_, child) in &mut children {
+                child.kill().ok();
+                child.wait().ok();
+            }
+        });
+
+        Self {
+            pids,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Gets the process IDs of the kubectl processes.
+    pub fn pids(&self) -> &[u32] {
+        &self.pids
+    }
+}
+
+impl Drop for Replicas {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Reports the errors kubectl prints for a pod, prefixed with the name of the pod.
+fn forward_errors(
+    id: ConfigId,
+    pod: String,
+    stderr: impl io::Read + Send + 'static,
+    out_tx: Sender<ChildEvent>,
+) {
+    thread::spawn(move || {
+        let mut reader = io::BufReader::new(stderr);
+        while let Ok(Some(line)) = read_line_lossy(&mut reader, MAX_LINE_LENGTH) {
+            let message = format!("{pod}: {line}");
+            out_tx
+                .send(ChildEvent::Output(id, StreamSource::StdErr, message))
+                .ok();
+        }
+    });
+}
@@ -0,0 +1,331 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Accepts HTTP requests on a single local port and passes each connection on to the target
+//! whose `host` matches the `Host` header of its first request, such that several services
+//! can be reached as e.g. `http://api.staging.localhost:8080` without remembering their ports.
+
+use crate::config::HttpRouterConfig;
+use crate::engine::Engine;
+use crate::health;
+use crate::status::StatusHandle;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The interval at which the listener is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The interval at which the routes are updated from the running targets.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client may take to send the head of its first request.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the local port of a target to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request head that is read to find the `Host` header.
+const MAX_HEAD_LENGTH: usize = 16 * 1024;
+
+/// The HTTP router of the `http_router` setting; stops accepting connections when dropped.
+pub struct HttpRouter {
+    config: HttpRouterConfig,
+    address: SocketAddr,
+    routes: Arc<Mutex<HashMap<String, Route>>>,
+    last_update: Option<Instant>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Where requests for a host are passed on to.
+#[derive(Debug, Clone)]
+struct Route {
+    /// The local address the target listens on.
+    ip: IpAddr,
+    /// The configured local port of the target's first port, if any.
+    port: Option<u16>,
+    status: StatusHandle,
+}
+
+impl Route {
+    /// Gets the local port of the target's first port, which is only known once kubectl
+    /// listens if it isn't configured.
+    fn local_port(&self) -> Option<u16> {
+        self.port
+            .or_else(|| self.status.snapshot().local_ports.first().copied())
+    }
+}
+
+impl HttpRouter {
+    /// Listens on the port of the router; routes are added by [`poll`](Self::poll).
+    pub fn bind(config: HttpRouterConfig) -> Result<Self, RouterError> {
+        let address = config.address();
+        let listener = TcpListener::bind(address).map_err(|e| RouterError::Listen(address, e))?;
+        let address = listener
+            .local_addr()
+            .map_err(|e| RouterError::Listen(address, e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| RouterError::Listen(address, e))?;
+
+        let routes = Arc::new(Mutex::new(HashMap::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = accept(listener, routes.clone(), stop.clone());
+        Ok(Self {
+            config,
+            address,
+            routes,
+            last_update: None,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Gets the configuration the router was started with.
+    pub fn config(&self) -> &HttpRouterConfig {
+        &self.config
+    }
+
+    /// Gets the address the router listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Updates the routes from the targets of the engine with a `host`, at most once per interval.
+    ///
+    /// If several targets share a host, the one started first receives the requests.
+    pub fn poll(&mut self, engine: &Engine) {
+        if self
+            .last_update
+            .is_some_and(|last| last.elapsed() < UPDATE_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_update = Some(Instant::now());
+        let mut routes = HashMap::new();
+        for (_, config, status) in engine.watched_targets() {
+            let Some(host) = config.host.as_deref().map(normalize_host) else {
+                continue;
+            };
+
+            routes.entry(host).or_insert_with(|| Route {
+                ip: health::local_addresses(&config.listen_addrs)[0],
+                port: config.ports.first().and_then(|port| port.local),
+                status,
+            });
+        }
+
+        *self.routes.lock().expect("lock is not poisoned") = routes;
+    }
+}
+
+impl Drop for HttpRouter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Accepts connections on the listener until stopped, routing each of them to its target.
+fn accept(
+    listener: TcpListener,
+    routes: Arc<Mutex<HashMap<String, Route>>>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((client, _)) => {
+                    let routes = routes.clone();
+                    thread::spawn(move || route(client, &routes));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    })
+}
+
+/// Reads the head of the first request, connects to the target of its host and copies
+/// the data between both connections until either side closes its connection.
+///
+/// Requests that can't be routed are answered with an error.
+fn route(mut client: TcpStream, routes: &Mutex<HashMap<String, Route>>) {
+    // Accepted connections may inherit the non-blocking mode of the listener.
+    if client.set_nonblocking(false).is_err()
+        || client.set_read_timeout(Some(HEAD_TIMEOUT)).is_err()
+    {
+        return;
+    }
+
+    let Ok(head) = read_head(&mut client) else {
+        return;
+    };
+
+    let Some(host) = host_header(&head) else {
+        return respond(client, "400 Bad Request", "The request has no Host header.");
+    };
+
+    let route = routes
+        .lock()
+        .expect("lock is not poisoned")
+        .get(&host)
+        .cloned();
+    let Some(route) = route else {
+        let message = format!("No target is configured for host {host}.");
+        return respond(client, "404 Not Found", &message);
+    };
+
+    let Some(port) = route.local_port() else {
+        let message = format!("The target of host {host} is not forwarding yet.");
+        return respond(client, "503 Service Unavailable", &message);
+    };
+
+    let address = SocketAddr::new(route.ip, port);
+    let mut server = match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+        Ok(server) => server,
+        Err(e) => {
+            let message = format!("The target of host {host} is unavailable: {e}");
+            return respond(client, "502 Bad Gateway", &message);
+        }
+    };
+
+    if server.write_all(&head).is_err() || client.set_read_timeout(None).is_err() {
+        return;
+    }
+
+    let (Ok(mut client_reader), Ok(mut server_writer)) = (client.try_clone(), server.try_clone())
+    else {
+        return;
+    };
+
+    let upload = thread::spawn(move || {
+        io::copy(&mut client_reader, &mut server_writer).ok();
+        server_writer.shutdown(Shutdown::Write).ok();
+    });
+
+    io::copy(&mut server, &mut client).ok();
+    client.shutdown(Shutdown::Write).ok();
+    upload.join().ok();
+}
+
+/// Reads from the client until the head of its request is complete, i.e. up to the first
+/// empty line, returning everything read so far.
+fn read_head(client: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 4096];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_HEAD_LENGTH {
+        match client.read(&mut buffer)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => head.extend_from_slice(&buffer[..read]),
+        }
+    }
+    Ok(head)
+}
+
+/// Extracts the host of the `Host` header of a request head, without the port.
+fn host_header(head: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(head);
+    head.split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .map(|(_, value)| normalize_host(value))
+        .filter(|host| !host.is_empty())
+}
+
+/// Removes the port and the trailing dot from a host, e.g. `API.localhost.:8080`, and lowercases it.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => match host.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => host,
+        },
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Answers a request with an error and closes the connection.
+fn respond(mut client: TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {length}\r\nConnection: close\r\n\r\n{message}\n",
+        length = message.len() + 1
+    );
+    client.write_all(response.as_bytes()).ok();
+    client.shutdown(Shutdown::Both).ok();
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RouterError {
+    #[error("Unable to listen on {0}: {1}")]
+    Listen(SocketAddr, io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigId, PortForwardConfig};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_host_header() {
+        let head = b"GET / HTTP/1.1\r\nhost: API.Staging.localhost.:8080\r\n\r\n";
+        assert_eq!(host_header(head), Some("api.staging.localhost".to_string()));
+        assert_eq!(normalize_host("[::1]:8080"), "::1");
+        assert_eq!(host_header(b"GET / HTTP/1.1\r\n\r\nHost: foo"), None);
+    }
+
+    #[test]
+    fn test_route() {
+        let config = HttpRouterConfig {
+            port: 0,
+            listen_addr: None,
+        };
+        let router = HttpRouter::bind(config).unwrap();
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let target = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports: [":80"]
+        "#,
+        )
+        .unwrap();
+        router.routes.lock().unwrap().insert(
+            "api.localhost".into(),
+            Route {
+                ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: Some(server.local_addr().unwrap().port()),
+                status: StatusHandle::new(ConfigId::from(0), &target, None),
+            },
+        );
+
+        let request = b"GET / HTTP/1.1\r\nHost: api.localhost:8080\r\n\r\n";
+        let mut client = TcpStream::connect(router.address()).unwrap();
+        client.write_all(request).unwrap();
+        let (mut server, _) = server.accept().unwrap();
+        let mut buffer = [0; 44];
+        server.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, request);
+
+        // Unknown hosts are answered by the router itself.
+        let mut client = TcpStream::connect(router.address()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: web.localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
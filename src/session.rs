@@ -0,0 +1,395 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::kubectl::Kubectl;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The label selecting all in-cluster helper resources created by k8sfwd.
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by=k8sfwd";
+
+/// The label key identifying the session that created a helper resource.
+pub const SESSION_LABEL_KEY: &str = "k8sfwd.io/session";
+
+/// The prefix of the annotation keys recording the sessions using a shared helper resource,
+/// followed by the ID of the session.
+pub const USER_ANNOTATION_PREFIX: &str = "k8sfwd.io/used-by-";
+
+/// An in-cluster helper resource created by k8sfwd, e.g. a gateway pod.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HelperResource {
+    /// The kind of the resource, e.g. `pod`.
+    pub kind: String,
+    /// The name of the resource.
+    pub name: String,
+    /// The namespace of the resource.
+    pub namespace: String,
+    /// The kubeconfig context the resource was created in.
+    pub context: Option<String>,
+    /// Whether other sessions may use the resource as well, e.g. a gateway pod with a fixed
+    /// name. Such resources record their users and are only deleted by the last of them.
+    #[serde(default)]
+    pub shared: bool,
+}
+
+impl Display for HelperResource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}.{}", self.kind, self.name, self.namespace)
+    }
+}
+
+/// A kubectl process started during a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChildProcess {
+    /// The process ID.
+    pub pid: u32,
+    /// The fixed local ports the process listens on.
+    pub ports: Vec<u16>,
+}
+
+/// The on-disk record of a session and the helper resources it created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionManifest {
+    /// The ID of the session.
+    id: String,
+    /// The process ID of the k8sfwd instance owning the session.
+    pid: u32,
+    /// The helper resources created during the session.
+    resources: Vec<HelperResource>,
+    /// The kubectl processes currently running for the session, such that ones left
+    /// behind by a crashed session can be found.
+    #[serde(default)]
+    processes: Vec<ChildProcess>,
+}
+
+/// The current session of k8sfwd.
+///
+/// Every in-cluster helper resource created during the session is recorded
+/// in a session manifest in the state directory, such that it can be removed
+/// on shutdown, or on the next startup if the session crashed.
+#[derive(Debug, Clone)]
+pub struct Session {
+    manifest: Arc<Mutex<SessionManifest>>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let pid = std::process::id();
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        Self {
+            manifest: Arc::new(Mutex::new(SessionManifest {
+                id: format!("{pid}-{started}"),
+                pid,
+                resources: Vec::new(),
+                processes: Vec::new(),
+            })),
+        }
+    }
+
+    /// Gets the ID of the session.
+    pub fn id(&self) -> String {
+        self.manifest
+            .lock()
+            .expect("lock is not poisoned")
+            .id
+            .clone()
+    }
+
+    /// Gets the labels to apply to helper resources created by this session.
+    pub fn labels(&self) -> String {
+        format!(
+            "{MANAGED_BY_LABEL},{SESSION_LABEL_KEY}={id}",
+            id = self.id()
+        )
+    }
+
+    /// Records a helper resource in the session manifest.
+    pub fn track(&self, resource: HelperResource) -> Result<(), io::Error> {
+        let mut manifest = self.manifest.lock().expect("lock is not poisoned");
+        if !manifest.resources.contains(&resource) {
+            manifest.resources.push(resource);
+        }
+        manifest.write()
+    }
+
+    /// Removes a helper resource that was not created after all from the session manifest.
+    pub fn untrack(&self, resource: &HelperResource) -> Result<(), io::Error> {
+        let mut manifest = self.manifest.lock().expect("lock is not poisoned");
+        manifest.resources.retain(|tracked| tracked != resource);
+        manifest.write()
+    }
+
+    /// Records a kubectl process in the session manifest.
+    pub fn track_process(&self, process: ChildProcess) -> Result<(), io::Error> {
+        let mut manifest = self.manifest.lock().expect("lock is not poisoned");
+        manifest.processes.push(process);
+        manifest.write()
+    }
+
+    /// Removes a kubectl process that exited from the session manifest.
+    pub fn untrack_process(&self, pid: u32) -> Result<(), io::Error> {
+        let mut manifest = self.manifest.lock().expect("lock is not poisoned");
+        manifest.processes.retain(|process| process.pid != pid);
+        manifest.write()
+    }
+
+    /// Finds a kubectl process left over from a previous session that did not shut down
+    /// properly and still listens on the specified local port.
+    pub fn find_orphan(port: u16) -> Option<u32> {
+        find_orphan(&SessionManifest::read_all(), port, |pid| {
+            is_process_alive(pid)
+        })
+        .filter(|pid| is_port_forward(*pid))
+    }
+
+    /// Deletes all helper resources of this session and removes its manifest.
+    ///
+    /// Returns the resources that were deleted.
+    pub fn teardown(&self, kubectl: &Kubectl) -> Vec<HelperResource> {
+        let manifest = self.manifest.lock().expect("lock is not poisoned");
+        manifest.teardown(kubectl)
+    }
+
+    /// Removes the helper resources of previous sessions that did not shut down properly.
+    ///
+    /// Returns the resources that were deleted.
+    pub fn collect_garbage(kubectl: &Kubectl) -> Vec<HelperResource> {
+        SessionManifest::read_all()
+            .into_iter()
+            .filter(|manifest| !is_process_alive(manifest.pid))
+            .flat_map(|manifest| manifest.teardown(kubectl))
+            .collect()
+    }
+
+    /// Gets the IDs of the sessions started on this machine that did not shut down properly.
+    ///
+    /// Sessions of other machines are not known here, so they are never considered stale.
+    pub fn stale_session_ids() -> Vec<String> {
+        SessionManifest::read_all()
+            .into_iter()
+            .filter(|manifest| !is_process_alive(manifest.pid))
+            .map(|manifest| manifest.id)
+            .collect()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionManifest {
+    /// Gets the directory containing the session manifests.
+    fn directory() -> Option<PathBuf> {
+        state_dir().map(|dir| dir.join("sessions"))
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        Self::directory().map(|dir| dir.join(format!("{id}.json", id = self.id)))
+    }
+
+    fn write(&self) -> Result<(), io::Error> {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+    }
+
+    /// Reads all session manifests, ignoring invalid ones.
+    fn read_all() -> Vec<Self> {
+        let entries = match Self::directory().map(fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|contents| serde_json::from_str(&contents).ok())
+            .collect()
+    }
+
+    fn teardown(&self, kubectl: &Kubectl) -> Vec<HelperResource> {
+        let mut deleted = Vec::new();
+        let mut remaining = Vec::new();
+        for resource in &self.resources {
+            let result = if resource.shared {
+                kubectl.release_shared_resource(resource, &self.id)
+            } else {
+                kubectl.delete_helper_resource(resource)
+            };
+            match result {
+                Ok(()) => deleted.push(resource.clone()),
+                Err(_) => remaining.push(resource.clone()),
+            }
+        }
+
+        // Keep the manifest around if some resources could not be deleted,
+        // or if kubectl processes of a crashed session are still running.
+        let processes: Vec<_> = self
+            .processes
+            .iter()
+            .filter(|process| is_process_alive(process.pid))
+            .cloned()
+            .collect();
+        if remaining.is_empty() && processes.is_empty() {
+            if let Some(path) = self.path() {
+                fs::remove_file(path).ok();
+            }
+        } else {
+            Self {
+                resources: remaining,
+                processes,
+                ..self.clone()
+            }
+            .write()
+            .ok();
+        }
+
+        deleted
+    }
+}
+
+/// Finds a live process listening on the port among the processes of sessions that ended.
+fn find_orphan(
+    manifests: &[SessionManifest],
+    port: u16,
+    is_alive: impl Fn(u32) -> bool,
+) -> Option<u32> {
+    manifests
+        .iter()
+        .filter(|manifest| !is_alive(manifest.pid))
+        .flat_map(|manifest| &manifest.processes)
+        .find(|process| process.ports.contains(&port) && is_alive(process.pid))
+        .map(|process| process.pid)
+}
+
+/// Determines whether the process is a `kubectl port-forward`, rather than an unrelated
+/// process that was given the ID of an exited one.
+#[cfg(target_os = "linux")]
+fn is_port_forward(pid: u32) -> bool {
+    fs::read(format!("/proc/{pid}/cmdline"))
+        .map(|cmdline| cmdline.split(|&b| b == 0).any(|arg| arg == b"port-forward"))
+        .unwrap_or(false)
+}
+
+/// Determines whether the process is a `kubectl port-forward`; assumed on this platform.
+#[cfg(not(target_os = "linux"))]
+fn is_port_forward(_pid: u32) -> bool {
+    true
+}
+
+/// Terminates a process and waits briefly for it to exit.
+pub fn terminate_process(pid: u32) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        let result = unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let status = std::process::Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/F"])
+            .output()?
+            .status;
+        if !status.success() {
+            return Err(io::Error::other(format!("taskkill exited with {status}")));
+        }
+    }
+
+    for _ in 0..20 {
+        if !is_process_alive(pid) {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Gets the directory k8sfwd stores its state in, e.g. `$XDG_STATE_HOME/k8sfwd`.
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("k8sfwd"))
+}
+
+/// Determines whether a process with the specified ID is running.
+#[cfg(unix)]
+pub fn is_process_alive(pid: u32) -> bool {
+    // Signal 0 only performs the permission and existence checks.
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Determines whether a process with the specified ID is running.
+#[cfg(windows)]
+pub fn is_process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+        .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_orphan() {
+        let manifest = |pid, processes| SessionManifest {
+            id: format!("{pid}-0"),
+            pid,
+            resources: Vec::new(),
+            processes,
+        };
+        let manifests = vec![
+            manifest(
+                10,
+                vec![ChildProcess {
+                    pid: 11,
+                    ports: vec![5012],
+                }],
+            ),
+            manifest(
+                20,
+                vec![ChildProcess {
+                    pid: 21,
+                    ports: vec![5012, 8080],
+                }],
+            ),
+        ];
+
+        // Session 10 is still running, so its processes are not orphaned.
+        let alive = |pid| pid != 20;
+        assert_eq!(find_orphan(&manifests, 5012, alive), Some(21));
+        assert_eq!(find_orphan(&manifests, 8080, alive), Some(21));
+        assert_eq!(find_orphan(&manifests, 9090, alive), None);
+
+        // The process of session 20 exited, too.
+        assert_eq!(
+            find_orphan(&manifests, 5012, |pid| pid == 10 || pid == 11),
+            None
+        );
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Support for the shared mode, in which a single session (e.g. on a shared
+//! development machine) serves the forwards requested by multiple users.
+
+use crate::config::SharedConfig;
+use std::ops::RangeInclusive;
+
+/// Hands out a distinct block of local ports to each user.
+#[derive(Debug, Default)]
+pub struct PortAllocator {
+    /// The users in the order their blocks were assigned.
+    users: Vec<String>,
+}
+
+impl PortAllocator {
+    /// Gets the block of local ports reserved for the user, reserving a new block if needed.
+    ///
+    /// Returns `None` if all blocks are taken.
+    pub fn block(&mut self, user: &str, config: &SharedConfig) -> Option<RangeInclusive<u16>> {
+        let slot = match self.users.iter().position(|u| u == user) {
+            Some(slot) => slot,
+            None => {
+                self.users.push(user.to_string());
+                self.users.len() - 1
+            }
+        };
+
+        let size = config.ports_per_user() as usize;
+        let first = config.port_base() as usize + slot * size;
+        let last = first + size - 1;
+        if last > u16::MAX as usize {
+            self.users.retain(|u| u != user);
+            return None;
+        }
+
+        Some(first as u16..=last as u16)
+    }
+}
+
+/// Gets the name of the user running this process.
+#[cfg(unix)]
+pub fn current_user() -> String {
+    user_name(unsafe { libc::geteuid() })
+}
+
+/// Gets the name of the user running this process.
+#[cfg(windows)]
+pub fn current_user() -> String {
+    std::env::var("USERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Gets the name of the user with the specified ID, or the ID itself if the user is unknown.
+#[cfg(unix)]
+pub fn user_name(uid: libc::uid_t) -> String {
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if status != 0 || result.is_null() {
+        return uid.to_string();
+    }
+
+    unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Gets the ID of the user with the specified name.
+#[cfg(unix)]
+pub fn user_id(name: &str) -> Option<libc::uid_t> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    (status == 0 && !result.is_null()).then_some(passwd.pw_uid)
+}
+
+/// Gets the ID of the group with the specified name.
+#[cfg(unix)]
+pub fn group_id(name: &str) -> Option<libc::gid_t> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    (status == 0 && !result.is_null()).then_some(group.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_blocks() {
+        let config = SharedConfig {
+            port_base: Some(20000),
+            ports_per_user: Some(10),
+            group: None,
+        };
+
+        let mut allocator = PortAllocator::default();
+        assert_eq!(allocator.block("alice", &config), Some(20000..=20009));
+        assert_eq!(allocator.block("bob", &config), Some(20010..=20019));
+        assert_eq!(allocator.block("alice", &config), Some(20000..=20009));
+    }
+
+    #[test]
+    fn test_port_blocks_exhausted() {
+        let config = SharedConfig {
+            port_base: Some(65530),
+            ports_per_user: Some(6),
+            group: None,
+        };
+
+        let mut allocator = PortAllocator::default();
+        assert_eq!(allocator.block("alice", &config), Some(65530..=65535));
+        assert_eq!(allocator.block("bob", &config), None);
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The granularity at which sleeping threads check for shutdown requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A cloneable flag signaling that the application is about to shut down.
+///
+/// Child signals additionally allow stopping an individual port-forward
+/// without affecting the rest of the application.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    global: Arc<AtomicBool>,
+    local: Option<Arc<AtomicBool>>,
+}
+
+impl ShutdownSignal {
+    /// Registers the handlers for `SIGINT` (Ctrl+C) and `SIGTERM`.
+    ///
+    /// Receiving a second signal while a shutdown is already in progress
+    /// terminates the application immediately.
+    pub fn install() -> Result<Self, io::Error> {
+        let signal = Self::default();
+        for sig in [SIGINT, SIGTERM] {
+            signal_hook::flag::register_conditional_shutdown(sig, 1, signal.global.clone())?;
+            signal_hook::flag::register(sig, signal.global.clone())?;
+        }
+        Ok(signal)
+    }
+
+    /// Creates a signal that is raised either on application shutdown
+    /// or when individually [stopped](Self::stop).
+    pub fn child(&self) -> Self {
+        Self {
+            global: self.global.clone(),
+            local: Some(Arc::default()),
+        }
+    }
+
+    /// Determines whether a shutdown (or stop) was requested.
+    pub fn is_requested(&self) -> bool {
+        self.global.load(Ordering::Relaxed)
+            || self
+                .local
+                .as_ref()
+                .is_some_and(|local| local.load(Ordering::Relaxed))
+    }
+
+    /// Requests a shutdown of the application, e.g. after a fatal error.
+    pub fn request(&self) {
+        self.global.store(true, Ordering::Relaxed)
+    }
+
+    /// Stops only the holders of this child signal.
+    ///
+    /// On a signal that was not created through [`child`](Self::child),
+    /// this requests a shutdown of the application.
+    pub fn stop(&self) {
+        match &self.local {
+            Some(local) => local.store(true, Ordering::Relaxed),
+            None => self.request(),
+        }
+    }
+
+    /// Sleeps for the specified duration or until a shutdown is requested.
+    ///
+    /// Returns `true` if the sleep was interrupted by a shutdown request.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.is_requested() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+/// A flag signaling that the configuration should be reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct ReloadSignal(Arc<AtomicBool>);
+
+impl ReloadSignal {
+    /// Registers the handler for `SIGHUP`.
+    ///
+    /// On platforms without `SIGHUP`, the signal is never raised.
+    pub fn install() -> Result<Self, io::Error> {
+        let signal = Self::default();
+        #[cfg(unix)]
+        signal_hook::flag::register(SIGHUP, signal.0.clone())?;
+        Ok(signal)
+    }
+
+    /// Determines whether a reload was requested and resets the flag.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,551 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+use crate::config::{ConfigId, PortForwardConfig, ResourceType};
+use crate::encryption::Encryption;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The lifecycle state of a port-forward.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardState {
+    /// The kubectl process was started but is not listening yet.
+    Starting,
+    /// The kubectl process is listening on its local ports.
+    Forwarding,
+    /// The kubectl process exited and is waiting to be restarted.
+    Restarting,
+    /// The port-forward was stopped.
+    Stopped,
+    /// The port-forward was given up after exceeding its maximum number of retries.
+    Failed,
+}
+
+impl Display for ForwardState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForwardState::Starting => write!(f, "starting"),
+            ForwardState::Forwarding => write!(f, "forwarding"),
+            ForwardState::Restarting => write!(f, "restarting"),
+            ForwardState::Stopped => write!(f, "stopped"),
+            ForwardState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A snapshot of the state of a port-forward, as reported by `k8sfwd status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetStatus {
+    /// The ID of the target, e.g. `#0`.
+    pub id: String,
+    /// The name of the target, if any.
+    pub name: Option<String>,
+    /// The forwarded resource, e.g. `service/foo.default`.
+    pub target: String,
+    /// The user that requested the target in a shared session.
+    pub owner: Option<String>,
+    /// The lifecycle state of the port-forward.
+    pub state: ForwardState,
+    /// The pod the traffic is forwarded to, if known.
+    pub pod: Option<String>,
+    /// The local ports kubectl is listening on.
+    pub local_ports: Vec<u16>,
+    /// The number of times the kubectl process was restarted.
+    pub restarts: usize,
+    /// The most recent error reported for the target.
+    pub last_error: Option<String>,
+    /// The differences between the configuration and the cluster, if checked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drift: Vec<String>,
+    /// Whether the most recent health check succeeded; unknown without health checks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthy: Option<bool>,
+    /// The milliseconds from starting kubectl until it listened on all ports,
+    /// for the most recent attempt that got that far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// The connections and bytes relayed by the proxy; unknown for targets without a proxy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traffic: Option<Traffic>,
+    /// Whether the traffic on each local port is encrypted with TLS, once known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encryption: Vec<PortEncryption>,
+}
+
+/// Whether the traffic on a local port is encrypted with TLS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortEncryption {
+    pub port: u16,
+    pub encryption: Encryption,
+}
+
+/// The connections and bytes relayed through the local ports of a target.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Traffic {
+    /// The number of connections accepted so far.
+    pub connections: u64,
+    /// The number of connections currently open.
+    pub active: u64,
+    /// The number of bytes sent by the local clients.
+    pub bytes_sent: u64,
+    /// The number of bytes received by the local clients.
+    pub bytes_received: u64,
+}
+
+impl Traffic {
+    /// Adds the traffic of a previous run of the same target, e.g. before a reload.
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            connections: self.connections + other.connections,
+            active: self.active + other.active,
+            bytes_sent: self.bytes_sent + other.bytes_sent,
+            bytes_received: self.bytes_received + other.bytes_received,
+        }
+    }
+}
+
+/// A shared, continuously updated [`TargetStatus`] of a running port-forward.
+#[derive(Debug, Clone)]
+pub struct StatusHandle {
+    status: Arc<Mutex<TargetStatus>>,
+    /// The current attempt: when kubectl was started, and how long it took to become ready.
+    attempt: Arc<Mutex<Attempt>>,
+    /// The number of local ports kubectl listens on once the target is ready.
+    expected_ports: usize,
+    /// The local ports of the proxy, keyed by the port kubectl listens on behind it.
+    proxied: Arc<Mutex<HashMap<u16, u16>>>,
+    /// The local ports speaking TLS, keyed by the plain port they relay to.
+    terminated: Arc<Mutex<HashMap<u16, u16>>>,
+}
+
+#[derive(Debug, Default)]
+struct Attempt {
+    spawned: Option<Instant>,
+    latency: Option<Duration>,
+    /// The connections of health checks kubectl hasn't reported yet.
+    probes: usize,
+    /// When kubectl last reported handling a connection.
+    last_connection: Option<Instant>,
+}
+
+impl StatusHandle {
+    pub fn new(id: ConfigId, config: &PortForwardConfig, owner: Option<String>) -> Self {
+        // kubectl doesn't report the pod it selects for services and deployments.
+        let pod = match (&config.via, config.r#type) {
+            (Some(gateway), _) => Some(format!("{}.{}", gateway.pod, gateway.namespace)),
+            (None, ResourceType::Pod) => Some(format!("{}.{}", config.target, config.namespace)),
+            (None, _) => None,
+        };
+
+        Self {
+            status: Arc::new(Mutex::new(TargetStatus {
+                id: id.to_string(),
+                name: config.name.clone(),
+                target: format!(
+                    "{resource}/{name}.{namespace}",
+                    resource = config.r#type.as_arg(),
+                    name = config.target,
+                    namespace = config.namespace
+                ),
+                owner,
+                state: ForwardState::Starting,
+                pod,
+                local_ports: Vec::new(),
+                restarts: 0,
+                last_error: None,
+                drift: Vec::new(),
+                healthy: None,
+                latency_ms: None,
+                traffic: None,
+                encryption: Vec::new(),
+            })),
+            attempt: Arc::default(),
+            expected_ports: config.ports.len(),
+            proxied: Arc::default(),
+            terminated: Arc::default(),
+        }
+    }
+
+    /// Gets a copy of the current status.
+    pub fn snapshot(&self) -> TargetStatus {
+        self.lock().clone()
+    }
+
+    /// Records the start of a new kubectl process.
+    pub fn started(&self, restart: bool) {
+        let mut status = self.lock();
+        status.state = ForwardState::Starting;
+        status.local_ports.clear();
+        status.healthy = None;
+        status.encryption.clear();
+        let terminated = self.terminated.lock().expect("lock is not poisoned");
+        status
+            .encryption
+            .extend(terminated.values().map(|port| PortEncryption {
+                port: *port,
+                encryption: Encryption::Tls,
+            }));
+        if restart {
+            status.restarts += 1;
+        }
+    }
+
+    /// Records that the kubectl process of the current attempt was spawned.
+    pub fn spawned(&self) {
+        *self.attempt() = Attempt {
+            spawned: Some(Instant::now()),
+            ..Attempt::default()
+        };
+    }
+
+    /// Records the ports kubectl listens on behind a proxy along with the local ports
+    /// of the proxy, such that the latter are reported.
+    pub fn proxied(&self, ports: impl IntoIterator<Item = (u16, u16)>) {
+        let mut proxied = self.proxied.lock().expect("lock is not poisoned");
+        proxied.clear();
+        proxied.extend(ports);
+    }
+
+    /// Records that the proxy moved one of its local ports, e.g. when the target was remapped.
+    pub fn rebound(&self, previous: u16, local: u16) {
+        let mut proxied = self.proxied.lock().expect("lock is not poisoned");
+        for port in proxied.values_mut().filter(|port| **port == previous) {
+            *port = local;
+        }
+        drop(proxied);
+
+        let mut status = self.lock();
+        for port in status
+            .local_ports
+            .iter_mut()
+            .filter(|port| **port == previous)
+        {
+            *port = local;
+        }
+    }
+
+    /// Records the local ports speaking TLS along with the plain ports they relay to,
+    /// such that the former are reported.
+    pub fn terminated(&self, ports: impl IntoIterator<Item = (u16, u16)>) {
+        let mut terminated = self.terminated.lock().expect("lock is not poisoned");
+        terminated.clear();
+        terminated.extend(ports);
+    }
+
+    /// Notes the local port of the proxy in lines like `Forwarding from 127.0.0.1:41234 -> 80`.
+    pub fn annotate(&self, line: String) -> String {
+        let Some(port) = parse_forwarding_line(&line) else {
+            return line;
+        };
+
+        match self.proxied_port(port) {
+            Some(local) => match self.terminated_port(local) {
+                Some(tls) => format!("{line} (proxied from TLS on local port {tls})"),
+                None => format!("{line} (proxied from local port {local})"),
+            },
+            None => match self.terminated_port(port) {
+                Some(tls) => format!("{line} (TLS on local port {tls})"),
+                None => line,
+            },
+        }
+    }
+
+    /// Records a line kubectl printed to its standard output.
+    ///
+    /// Lines like `Forwarding from 127.0.0.1:5012 -> 80` reveal the local ports.
+    /// Returns the time since kubectl was spawned if it now listens on all ports of the target,
+    /// i.e. if the target became ready.
+    pub fn output(&self, line: &str) -> Option<Duration> {
+        let port = parse_forwarding_line(line)?;
+        let port = self.proxied_port(port).unwrap_or(port);
+        let port = self.terminated_port(port).unwrap_or(port);
+
+        let mut status = self.lock();
+        status.state = ForwardState::Forwarding;
+        if status.local_ports.contains(&port) {
+            return None;
+        }
+
+        status.local_ports.push(port);
+        if status.local_ports.len() != self.expected_ports {
+            return None;
+        }
+
+        let mut attempt = self.attempt();
+        let latency = attempt
+            .spawned
+            .map(|spawned| spawned.elapsed())
+            .unwrap_or_default();
+        attempt.latency = Some(latency);
+        status.latency_ms = Some(latency.as_millis() as u64);
+        Some(latency)
+    }
+
+    /// Records the result of a health check.
+    pub fn healthy(&self, healthy: bool) {
+        self.lock().healthy = Some(healthy);
+    }
+
+    /// Connects to a local port on behalf of a health check or keepalive, such that kubectl's
+    /// report of the connection can be left out of the output.
+    pub fn probe<T>(&self, connect: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        self.attempt().probes += 1;
+        let result = connect();
+        if result.is_err() {
+            let mut attempt = self.attempt();
+            attempt.probes = attempt.probes.saturating_sub(1);
+        }
+        result
+    }
+
+    /// Determines whether a line kubectl printed reports the connection of a health check,
+    /// such that it can be left out of the output.
+    pub fn is_probe(&self, line: &str) -> bool {
+        if !line.starts_with("Handling connection for ") {
+            return false;
+        }
+
+        let mut attempt = self.attempt();
+        if attempt.probes == 0 {
+            return false;
+        }
+
+        attempt.probes -= 1;
+        true
+    }
+
+    /// Records lines like `Handling connection for 8080` as activity of the tunnel.
+    pub fn connection_handled(&self, line: &str) {
+        if line.starts_with("Handling connection for ") {
+            self.attempt().last_connection = Some(Instant::now());
+        }
+    }
+
+    /// Gets the time since kubectl last handled a connection, or since it was spawned
+    /// if it didn't handle any yet.
+    pub fn idle_time(&self) -> Option<Duration> {
+        let attempt = self.attempt();
+        attempt
+            .last_connection
+            .or(attempt.spawned)
+            .map(|since| since.elapsed())
+    }
+
+    /// Gets the time it took the current attempt to become ready, if it did.
+    pub fn latency(&self) -> Option<Duration> {
+        self.attempt().latency
+    }
+
+    /// Records an error of the target.
+    pub fn error(&self, message: impl Into<String>) {
+        self.lock().last_error = Some(message.into());
+    }
+
+    /// Records the exit of the kubectl process.
+    pub fn exited(&self, exit_status: &ExitStatus) {
+        let mut status = self.lock();
+        status.state = ForwardState::Restarting;
+        status.local_ports.clear();
+        if status.last_error.is_none() && !exit_status.success() {
+            status.last_error = Some(format!("Process exited with {exit_status}"));
+        }
+    }
+
+    /// Determines whether kubectl reported to be listening since its last start.
+    pub fn is_forwarding(&self) -> bool {
+        self.lock().state == ForwardState::Forwarding
+    }
+
+    /// Determines whether the port-forward was given up.
+    pub fn is_failed(&self) -> bool {
+        self.lock().state == ForwardState::Failed
+    }
+
+    /// Records the pod the traffic is forwarded to.
+    pub fn pod(&self, pod: String) {
+        self.lock().pod = Some(pod);
+    }
+
+    /// Starts counting the traffic of the target, e.g. once its proxy listens.
+    pub fn track_traffic(&self) {
+        self.lock().traffic.get_or_insert_with(Traffic::default);
+    }
+
+    /// Records a connection accepted on a local port.
+    pub fn connection_opened(&self) {
+        if let Some(traffic) = &mut self.lock().traffic {
+            traffic.connections += 1;
+            traffic.active += 1;
+        }
+    }
+
+    /// Records that a connection was closed.
+    pub fn connection_closed(&self) {
+        if let Some(traffic) = &mut self.lock().traffic {
+            traffic.active = traffic.active.saturating_sub(1);
+        }
+    }
+
+    /// Gets the number of connections currently open on the local ports.
+    pub fn open_connections(&self) -> u64 {
+        self.lock().traffic.map_or(0, |traffic| traffic.active)
+    }
+
+    /// Records the bytes sent and received by a local client.
+    pub fn transferred(&self, sent: usize, received: usize) {
+        if let Some(traffic) = &mut self.lock().traffic {
+            traffic.bytes_sent += sent as u64;
+            traffic.bytes_received += received as u64;
+        }
+    }
+
+    /// Records whether the traffic on a local port is encrypted with TLS.
+    ///
+    /// The plain ports behind TLS terminating ports are left out.
+    pub fn encryption(&self, port: u16, encryption: Encryption) {
+        if self.terminated_port(port).is_some() {
+            return;
+        }
+
+        let mut status = self.lock();
+        match status
+            .encryption
+            .iter_mut()
+            .find(|known| known.port == port)
+        {
+            Some(known) => known.encryption = encryption,
+            None => status.encryption.push(PortEncryption { port, encryption }),
+        }
+    }
+
+    /// Records the differences between the configuration and the cluster.
+    ///
+    /// Returns `true` if they changed since the last check.
+    pub fn drift(&self, drift: Vec<String>) -> bool {
+        let mut status = self.lock();
+        if status.drift == drift {
+            return false;
+        }
+
+        status.drift = drift;
+        true
+    }
+
+    /// Records that the port-forward was given up.
+    pub fn failed(&self) {
+        let mut status = self.lock();
+        status.state = ForwardState::Failed;
+        status.local_ports.clear();
+    }
+
+    /// Records that the port-forward was stopped, unless it was given up before.
+    pub fn stopped(&self) {
+        let mut status = self.lock();
+        if status.state != ForwardState::Failed {
+            status.state = ForwardState::Stopped;
+        }
+        status.local_ports.clear();
+    }
+
+    fn proxied_port(&self, port: u16) -> Option<u16> {
+        let proxied = self.proxied.lock().expect("lock is not poisoned");
+        proxied.get(&port).copied()
+    }
+
+    fn terminated_port(&self, port: u16) -> Option<u16> {
+        let terminated = self.terminated.lock().expect("lock is not poisoned");
+        terminated.get(&port).copied()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TargetStatus> {
+        self.status.lock().expect("lock is not poisoned")
+    }
+
+    fn attempt(&self) -> std::sync::MutexGuard<'_, Attempt> {
+        self.attempt.lock().expect("lock is not poisoned")
+    }
+}
+
+/// Extracts the local port from a line like `Forwarding from [::1]:5012 -> 80`.
+pub fn parse_forwarding_line(line: &str) -> Option<u16> {
+    let (local, _remote) = line.strip_prefix("Forwarding from ")?.split_once(" -> ")?;
+    let (_address, port) = local.rsplit_once(':')?;
+    port.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forwarding_line() {
+        assert_eq!(
+            parse_forwarding_line("Forwarding from 127.0.0.1:5012 -> 80"),
+            Some(5012)
+        );
+        assert_eq!(
+            parse_forwarding_line("Forwarding from [::1]:46737 -> 8080"),
+            Some(46737)
+        );
+        assert_eq!(parse_forwarding_line("Handling connection for 5012"), None);
+    }
+
+    #[test]
+    fn test_latency() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: foo
+            ports:
+              - "5012:80"
+        "#,
+        )
+        .unwrap();
+
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+        status.spawned();
+        assert_eq!(status.output("Handling connection for 5012"), None);
+        let latency = status.output("Forwarding from 127.0.0.1:5012 -> 80");
+        assert!(latency.is_some());
+        assert_eq!(status.latency(), latency);
+
+        // Listening on another address doesn't make the target ready again.
+        assert_eq!(status.output("Forwarding from [::1]:5012 -> 80"), None);
+        assert!(status.snapshot().latency_ms.is_some());
+
+        status.spawned();
+        assert_eq!(status.latency(), None);
+    }
+
+    #[test]
+    fn test_traffic() {
+        let config =
+            serde_yaml::from_str::<PortForwardConfig>("{ target: foo, ports: [80] }").unwrap();
+        let status = StatusHandle::new(ConfigId::from(0), &config, None);
+
+        // Traffic is only known for targets behind a proxy.
+        status.connection_opened();
+        assert_eq!(status.snapshot().traffic, None);
+
+        status.track_traffic();
+        status.connection_opened();
+        status.transferred(4, 0);
+        status.transferred(0, 16);
+        status.connection_closed();
+        assert_eq!(
+            status.snapshot().traffic,
+            Some(Traffic {
+                connections: 1,
+                active: 0,
+                bytes_sent: 4,
+                bytes_received: 16
+            })
+        );
+    }
+}
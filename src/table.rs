@@ -0,0 +1,244 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Renders aligned tables, e.g. of the targets on startup or in `k8sfwd status`.
+
+use std::env;
+use std::io::{self, IsTerminal};
+
+/// The separator between two columns.
+const SEPARATOR: &str = "  ";
+
+/// The narrowest a column is truncated to, unless its header is narrower.
+const MIN_COLUMN_WIDTH: usize = 8;
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// A table with a header row, rendered with aligned columns.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new<S: Into<String>>(header: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            header: header.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row; missing cells are left empty.
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    /// Prints the table to the standard output.
+    ///
+    /// On a terminal, the header and IDs are highlighted and, unless `wide` is set,
+    /// the widest columns are truncated to fit the width of the terminal.
+    pub fn print(&self, wide: bool) {
+        let terminal = io::stdout().is_terminal();
+        let max_width = if wide || !terminal {
+            None
+        } else {
+            terminal_width()
+        };
+
+        let colored = terminal && env::var_os("NO_COLOR").is_none();
+        for line in self.render(max_width, colored) {
+            println!("{line}");
+        }
+    }
+
+    /// Renders the lines of the table, truncating the widest columns such that the lines
+    /// fit into `max_width` characters, if specified.
+    pub fn render(&self, max_width: Option<usize>, colored: bool) -> Vec<String> {
+        let widths = self.widths(max_width);
+        let last = widths.len().saturating_sub(1);
+
+        std::iter::once(&self.header)
+            .chain(&self.rows)
+            .enumerate()
+            .map(|(index, row)| {
+                let mut line = String::new();
+                for (column, width) in widths.iter().enumerate() {
+                    let cell = truncate(row.get(column).map_or("", String::as_str), *width);
+                    let padding = match column {
+                        column if column == last => 0,
+                        _ => width - display_width(&cell),
+                    };
+
+                    let style = match (index, column, cell.as_str()) {
+                        _ if !colored => None,
+                        (0, _, _) => Some(BOLD),
+                        (_, 0, _) => Some(CYAN),
+                        (_, _, "-") => Some(DIM),
+                        _ => None,
+                    };
+
+                    if column > 0 {
+                        line.push_str(SEPARATOR);
+                    }
+                    match style {
+                        Some(style) => line.push_str(&format!("{style}{cell}{RESET}")),
+                        None => line.push_str(&cell),
+                    }
+                    line.push_str(&" ".repeat(padding));
+                }
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Determines the width of each column, shrinking the widest ones until the table fits.
+    fn widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.header.iter().map(|cell| display_width(cell)).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(display_width(cell));
+            }
+        }
+
+        let Some(max_width) = max_width else {
+            return widths;
+        };
+
+        let separators = SEPARATOR.len() * widths.len().saturating_sub(1);
+        let min_widths: Vec<usize> = self
+            .header
+            .iter()
+            .zip(&widths)
+            .map(|(header, width)| (*width).min(header.chars().count().max(MIN_COLUMN_WIDTH)))
+            .collect();
+
+        while widths.iter().sum::<usize>() + separators > max_width {
+            let widest = widths
+                .iter()
+                .zip(&min_widths)
+                .enumerate()
+                .filter(|(_, (width, min))| width > min)
+                .max_by_key(|(_, (width, _))| **width)
+                .map(|(column, _)| column);
+
+            match widest {
+                Some(column) => widths[column] -= 1,
+                None => break,
+            }
+        }
+
+        widths
+    }
+}
+
+/// Cuts the text short to the specified number of columns, marking the cut with an ellipsis.
+fn truncate(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        used += char_width(c);
+        if used >= width {
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Gets the number of columns the text takes up in a terminal.
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Gets the number of columns a character takes up; emoji, e.g. of the `emoji` theme, take up two.
+fn char_width(c: char) -> usize {
+    match c {
+        '\u{1F300}'..='\u{1FAFF}' => 2,
+        _ => 1,
+    }
+}
+
+/// Gets the number of columns of the terminal, preferring the `COLUMNS` environment variable.
+fn terminal_width() -> Option<usize> {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(terminal_columns)
+}
+
+#[cfg(unix)]
+fn terminal_columns() -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only fills in the zeroed window size.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+fn terminal_columns() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(["ID", "TARGET", "PORTS"]);
+        table.row(vec!["#0".into(), "service/foo".into(), "5012:80".into()]);
+        table.row(vec![
+            "#1".into(),
+            "deployment/a-rather-long-name".into(),
+            "-".into(),
+        ]);
+        table
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            table().render(None, false),
+            vec![
+                "ID  TARGET                         PORTS",
+                "#0  service/foo                    5012:80",
+                "#1  deployment/a-rather-long-name  -",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let lines = table().render(Some(30), false);
+        assert_eq!(lines[2], "#1  deployment/a-rat…  -");
+        assert!(lines.iter().all(|line| line.chars().count() <= 30));
+
+        // Columns aren't truncated below their minimum width.
+        let lines = table().render(Some(10), false);
+        assert_eq!(lines[1], "#0  service…  5012:80");
+    }
+
+    #[test]
+    fn test_colored() {
+        let lines = table().render(None, true);
+        assert!(lines[0].starts_with("\x1b[1mID\x1b[0m"));
+        assert!(lines[2].ends_with("\x1b[2m-\x1b[0m"));
+    }
+
+    #[test]
+    fn test_emoji() {
+        let mut table = Table::new(["ID", "STATE"]);
+        table.row(vec!["#0".into(), "🟢 forwarding".into()]);
+        assert_eq!(truncate("🟢 forwarding", 6), "🟢 fo…");
+        assert_eq!(table.widths(None), vec![2, 13]);
+    }
+}
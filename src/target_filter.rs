@@ -3,19 +3,52 @@
 // SPDX-FileType: SOURCE
 
 use crate::config::PortForwardConfig;
+use regex::{Regex, RegexBuilder};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::Infallible;
 use std::str::FromStr;
 
 /// A filter for selecting a target.
 #[derive(Debug, Clone)]
-pub struct TargetFilter {
-    filter: String,
+pub enum TargetFilter {
+    /// Matches the targets whose resource, name or alias starts with the prefix, ignoring case.
+    Prefix(String),
+    /// Matches the targets whose resource, name or alias matches the regular expression,
+    /// ignoring case unless the expression disables it with `(?-i)`.
+    Regex(Regex),
+    /// Matches the targets whose entire resource, name or alias matches the pattern, ignoring case;
+    /// `*` stands for any number of characters and `?` for a single one, e.g. `*payments*`.
+    Glob(String),
+    /// Matches the targets whose resource, name or alias contains the characters in order,
+    /// ignoring case, e.g. `pgstg` for `postgres-staging`.
+    Fuzzy(String),
 }
 
 impl TargetFilter {
+    /// Creates a filter from a regular expression, e.g. `^api-(staging|prod)$`.
+    ///
+    /// Like the other filters, the expression ignores case.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map(Self::Regex)
+    }
+
+    /// Creates a glob filter if the text contains wildcards, or else a prefix filter.
+    fn from_text(text: String) -> Self {
+        if text.contains(['*', '?']) {
+            Self::Glob(text)
+        } else {
+            Self::Prefix(text)
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.filter.is_empty()
+        matches!(self, Self::Prefix(prefix) if prefix.is_empty())
     }
 }
 
@@ -25,25 +58,16 @@ pub trait MatchesAnyFilter {
 
 impl MatchesAnyFilter for TargetFilter {
     fn matches(&self, config: &PortForwardConfig) -> bool {
-        if self.is_empty() {
-            return true;
-        }
-
-        let filter = self.filter.to_ascii_lowercase();
-
-        if config.target.to_ascii_lowercase().starts_with(&filter) {
-            return true;
-        }
-
-        // TODO: Add alias property
-
-        if let Some(name) = &config.name {
-            if name.to_ascii_lowercase().starts_with(&filter) {
-                return true;
+        match self {
+            Self::Prefix(prefix) if prefix.is_empty() => true,
+            Self::Prefix(prefix) => {
+                let prefix = prefix.to_ascii_lowercase();
+                names(config).any(|name| name.to_ascii_lowercase().starts_with(&prefix))
             }
+            Self::Regex(regex) => names(config).any(|name| regex.is_match(name)),
+            Self::Glob(pattern) => names(config).any(|name| glob_match(pattern, name)),
+            Self::Fuzzy(pattern) => best_fuzzy_score(pattern, config).is_some(),
         }
-
-        false
     }
 }
 
@@ -67,9 +91,254 @@ where
     }
 }
 
+/// Turns the prefix filters matching none of the targets into fuzzy filters, such that e.g.
+/// `pgstg` selects `postgres-staging`.
+pub fn fuzzy_fallback(
+    filters: &[TargetFilter],
+    configs: &[PortForwardConfig],
+) -> Vec<TargetFilter> {
+    filters
+        .iter()
+        .map(|filter| match filter {
+            TargetFilter::Prefix(prefix)
+                if !filter.is_empty() && !configs.iter().any(|config| filter.matches(config)) =>
+            {
+                TargetFilter::Fuzzy(prefix.clone())
+            }
+            filter => filter.clone(),
+        })
+        .collect()
+}
+
+/// Orders the targets by how well they match the fuzzy filters, best first, like fzf;
+/// targets selected by other filters keep their place in front.
+pub fn rank_fuzzy(filters: &[TargetFilter], configs: &mut [PortForwardConfig]) {
+    let patterns: Vec<&str> = filters
+        .iter()
+        .filter_map(|filter| match filter {
+            TargetFilter::Fuzzy(pattern) => Some(pattern.as_str()),
+            _ => None,
+        })
+        .collect();
+    if patterns.is_empty() {
+        return;
+    }
+
+    configs.sort_by_cached_key(|config| {
+        let matched_otherwise = filters
+            .iter()
+            .any(|filter| !matches!(filter, TargetFilter::Fuzzy(_)) && filter.matches(config));
+        let score = patterns
+            .iter()
+            .filter_map(|pattern| best_fuzzy_score(pattern, config))
+            .max();
+        std::cmp::Reverse(if matched_otherwise {
+            Some(i64::MAX)
+        } else {
+            score
+        })
+    });
+}
+
+/// Matches the entire text against the pattern with the wildcards `*` and `?`, ignoring case.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // The positions to continue from if the characters after the last `*` don't match.
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Gets the names the target can be selected by: its resource, name and aliases.
+fn names(config: &PortForwardConfig) -> impl Iterator<Item = &str> {
+    [config.target.as_str()]
+        .into_iter()
+        .chain(config.name.as_deref())
+        .chain(config.aliases.iter().map(String::as_str))
+}
+
+/// Gets the best fuzzy score of the pattern against the names of the target.
+fn best_fuzzy_score(pattern: &str, config: &PortForwardConfig) -> Option<i64> {
+    names(config)
+        .filter_map(|candidate| fuzzy_score(pattern, candidate))
+        .max()
+}
+
+/// Scores how well the characters of the pattern match the candidate in order, ignoring case;
+/// `None` if they don't.
+///
+/// Like with fzf, characters at the start of a word or following the previous match score
+/// higher, while gaps between the matches lower the score.
+fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    const MATCH: i64 = 16;
+    const WORD_START: i64 = 8;
+    const CONSECUTIVE: i64 = 8;
+    const GAP_START: i64 = 3;
+
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let base: Vec<i64> = candidate
+        .iter()
+        .enumerate()
+        .map(|(index, c)| {
+            let word_start = index == 0
+                || !candidate[index - 1].is_alphanumeric()
+                || (candidate[index - 1].is_lowercase() && c.is_uppercase());
+            MATCH + if word_start { WORD_START } else { 0 }
+        })
+        .collect();
+    let lower: Vec<char> = candidate
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // The best score of the pattern up to each character, if it ends at the position.
+    let mut previous: Vec<Option<i64>> = lower
+        .iter()
+        .zip(&base)
+        .map(|(c, base)| (*c == pattern[0]).then_some(*base))
+        .collect();
+    for p in &pattern[1..] {
+        let mut current = vec![None; lower.len()];
+        for (j, c) in lower.iter().enumerate() {
+            if c != p {
+                continue;
+            }
+            current[j] = (0..j)
+                .filter_map(|k| {
+                    let score = previous[k]?;
+                    let link = match j - k - 1 {
+                        0 => CONSECUTIVE,
+                        gap => -(GAP_START + gap as i64 - 1),
+                    };
+                    Some(score + link)
+                })
+                .max()
+                .map(|score| score + base[j]);
+        }
+        previous = current;
+    }
+
+    previous.into_iter().flatten().max()
+}
+
+/// Determines whether any of the exclusion filters matches the target; empty filters
+/// exclude nothing.
+pub fn is_excluded(excludes: &[TargetFilter], config: &PortForwardConfig) -> bool {
+    excludes
+        .iter()
+        .any(|exclude| !exclude.is_empty() && exclude.matches(config))
+}
+
+/// Gets the display names of the targets most similar to the filters, most similar first.
+///
+/// Since filters match by prefix, each filter is compared against the
+/// equally long beginning of the target names as well.
+pub fn suggest_targets<'a>(
+    filters: &[TargetFilter],
+    configs: &'a [PortForwardConfig],
+    max: usize,
+) -> Vec<&'a str> {
+    let mut candidates: Vec<(usize, &str)> = configs
+        .iter()
+        .filter_map(|config| {
+            let display = config.name.as_deref().unwrap_or(&config.target);
+            let distance = filters
+                .iter()
+                .filter_map(|filter| match filter {
+                    TargetFilter::Prefix(prefix) if !prefix.is_empty() => Some(prefix),
+                    _ => None,
+                })
+                .flat_map(|filter| {
+                    let filter = filter.to_ascii_lowercase();
+                    names(config).map(move |candidate| {
+                        let candidate = candidate.to_ascii_lowercase();
+                        let prefix: String =
+                            candidate.chars().take(filter.chars().count()).collect();
+                        let threshold = (filter.chars().count() / 3).max(1);
+                        let distance =
+                            levenshtein(&filter, &prefix).min(levenshtein(&filter, &candidate));
+                        (distance, threshold)
+                    })
+                })
+                .filter(|(distance, threshold)| distance <= threshold)
+                .map(|(distance, _)| distance)
+                .min()?;
+            Some((distance, display))
+        })
+        .collect();
+
+    candidates.sort();
+    candidates.dedup_by(|a, b| a.1 == b.1);
+    candidates
+        .into_iter()
+        .take(max)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Gets the candidate most similar to the name, if any is similar enough to be a likely typo.
+pub fn most_similar<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (levenshtein(name, candidate), candidate.as_str()))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Calculates the edit distance between two strings.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 impl PartialEq for TargetFilter {
     fn eq(&self, other: &Self) -> bool {
-        self.filter == other.filter
+        match (self, other) {
+            (Self::Prefix(a), Self::Prefix(b)) => a == b,
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            (Self::Glob(a), Self::Glob(b)) => a == b,
+            (Self::Fuzzy(a), Self::Fuzzy(b)) => a == b,
+            _ => false,
+        }
     }
 }
 
@@ -77,7 +346,7 @@ impl FromStr for TargetFilter {
     type Err = Infallible;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self { filter: s.into() })
+        Ok(Self::from_text(s.into()))
     }
 }
 
@@ -87,7 +356,7 @@ impl<'de> Deserialize<'de> for TargetFilter {
         D: Deserializer<'de>,
     {
         let filter = String::deserialize(deserializer)?;
-        Ok(Self { filter })
+        Ok(Self::from_text(filter))
     }
 }
 
@@ -96,6 +365,166 @@ impl Serialize for TargetFilter {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.filter)
+        match self {
+            Self::Prefix(text) | Self::Glob(text) | Self::Fuzzy(text) => {
+                serializer.serialize_str(text)
+            }
+            Self::Regex(regex) => serializer.serialize_str(regex.as_str()),
+        }
+    }
+}
+
+/// Filters in configuration files are prefixes.
+impl JsonSchema for TargetFilter {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: Option<&str>, target: &str) -> PortForwardConfig {
+        serde_yaml::from_str(&format!(
+            "name: {name}\ntarget: {target}\nports: [80]",
+            name = name.unwrap_or("null")
+        ))
+        .expect("configuration is valid")
+    }
+
+    #[test]
+    fn test_regex_filter() {
+        let filter = TargetFilter::regex("^api-(staging|prod)$").unwrap();
+        assert!(filter.matches(&config(None, "api-staging")));
+        assert!(filter.matches(&config(Some("api-prod"), "api")));
+        assert!(!filter.matches(&config(None, "api-staging-2")));
+        assert!(filter.matches(&config(None, "API-Staging")));
+        assert!(!TargetFilter::regex("(?-i)^api")
+            .unwrap()
+            .matches(&config(None, "API")));
+        assert!(!filter.is_empty());
+        assert!(TargetFilter::regex("(").is_err());
+    }
+
+    #[test]
+    fn test_alias_filter() {
+        let mut postgres = config(None, "postgres-staging");
+        postgres.aliases = vec!["db".to_string(), "PG".to_string()];
+        let redis = config(None, "redis");
+
+        let filter: TargetFilter = "pg".parse().unwrap();
+        assert!(filter.matches(&postgres));
+        assert!(!filter.matches(&redis));
+        assert!(TargetFilter::regex("^db$").unwrap().matches(&postgres));
+        assert!("d?".parse::<TargetFilter>().unwrap().matches(&postgres));
+        assert!(TargetFilter::Fuzzy("pg".to_string()).matches(&postgres));
+    }
+
+    #[test]
+    fn test_glob_filter() {
+        assert!(glob_match("*payments*", "billing-payments-api"));
+        assert!(glob_match("*-API", "payments-api"));
+        assert!(glob_match("db-?", "db-1"));
+        assert!(!glob_match("db-?", "db-10"));
+        assert!(!glob_match("*payments", "payments-api"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+
+        let filter: TargetFilter = "*payments*".parse().unwrap();
+        assert_eq!(filter, TargetFilter::Glob("*payments*".to_string()));
+        assert!(filter.matches(&config(Some("Billing Payments"), "billing")));
+        assert!(!filter.matches(&config(None, "orders")));
+        assert_eq!(
+            "pay".parse::<TargetFilter>().unwrap(),
+            TargetFilter::Prefix("pay".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_filter() {
+        assert!(fuzzy_score("pgstg", "postgres-staging").is_some());
+        assert!(fuzzy_score("pgstg", "staging-postgres").is_none());
+        assert!(fuzzy_score("api", "api").unwrap() > fuzzy_score("api", "a-pi").unwrap());
+        assert!(
+            fuzzy_score("ps", "postgres-staging").unwrap() > fuzzy_score("ps", "pods").unwrap()
+        );
+
+        let configs = vec![
+            config(None, "pagesettings"),
+            config(None, "postgres-staging"),
+            config(None, "redis"),
+        ];
+        let filters = fuzzy_fallback(
+            &["pgstg".parse().unwrap(), "red".parse().unwrap()],
+            &configs,
+        );
+        assert_eq!(filters[0], TargetFilter::Fuzzy("pgstg".to_string()));
+        assert_eq!(filters[1], TargetFilter::Prefix("red".to_string()));
+
+        let mut selected: Vec<_> = configs
+            .iter()
+            .filter(|config| filters.matches(config))
+            .cloned()
+            .collect();
+        rank_fuzzy(&filters, &mut selected);
+        let targets: Vec<_> = selected
+            .iter()
+            .map(|config| config.target.as_str())
+            .collect();
+        assert_eq!(targets, vec!["redis", "postgres-staging", "pagesettings"]);
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let kafka = config(Some("Kafka"), "kafka-broker");
+        let api = config(None, "api");
+        let excludes: Vec<TargetFilter> = vec!["kaf".parse().unwrap()];
+        assert!(is_excluded(&excludes, &kafka));
+        assert!(!is_excluded(&excludes, &api));
+        assert!(!is_excluded(&["".parse().unwrap()], &api));
+        assert!(!is_excluded(&[], &api));
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", "abc"), 0);
+    }
+
+    #[test]
+    fn test_suggest_targets() {
+        let configs = vec![
+            config(Some("Payments API"), "payments"),
+            config(None, "orders"),
+            config(None, "inventory"),
+        ];
+
+        let filters = vec![TargetFilter::from_str("paymnts").unwrap()];
+        assert_eq!(suggest_targets(&filters, &configs, 3), vec!["Payments API"]);
+
+        let filters = vec![TargetFilter::from_str("ordr").unwrap()];
+        assert_eq!(suggest_targets(&filters, &configs, 3), vec!["orders"]);
+
+        let filters = vec![TargetFilter::from_str("xyz").unwrap()];
+        assert!(suggest_targets(&filters, &configs, 3).is_empty());
+    }
+
+    #[test]
+    fn test_most_similar() {
+        let candidates = vec!["payments-api".to_string(), "orders".to_string()];
+        assert_eq!(
+            most_similar("payment-api", &candidates),
+            Some("payments-api")
+        );
+        assert_eq!(most_similar("inventory", &candidates), None);
     }
 }
@@ -0,0 +1,306 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Terminates TLS on the local ports of a target with `socat`, relaying the decrypted
+//! connections to kubectl on plain loopback ports, such that clients connect with HTTPS even
+//! though the backend speaks plain HTTP, e.g. for OAuth callbacks on `https://localhost`.
+//!
+//! Without a certificate, a self-signed one for `localhost` and the addresses and host names
+//! of the target is generated with `openssl` and kept in the state directory.
+
+use crate::config::{ConfigId, PortForwardConfig, TlsConfig};
+use crate::fnv::fnv1a;
+use crate::kubectl::{ChildEvent, StreamSource};
+use crate::preflight;
+use crate::session;
+use crate::status::StatusHandle;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// The address kubectl listens on behind the TLS terminating ports.
+const PLAIN_ADDRESS: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+/// How long generated certificates are valid, in days; browsers reject longer validities.
+const VALIDITY_DAYS: &str = "825";
+
+/// The `socat` processes terminating TLS on the local ports of a target; stopped when dropped.
+pub struct TlsTerminator {
+    /// The local ports speaking TLS.
+    local_ports: Vec<u16>,
+    children: Vec<process::Child>,
+}
+
+impl TlsTerminator {
+    /// Listens with TLS on the local ports of the target on each of its listen addresses,
+    /// and points the target at plain loopback ports instead.
+    ///
+    /// The ports of the target without a fixed local port are set to the TLS port picked now.
+    pub fn start(
+        id: ConfigId,
+        tls: &TlsConfig,
+        config: &mut PortForwardConfig,
+        current_dir: &Path,
+        status: &StatusHandle,
+        out_tx: Sender<ChildEvent>,
+    ) -> Result<Self, TlsError> {
+        let (cert, key) = match tls.files().map_err(TlsError::Incomplete)? {
+            Some((cert, key)) => (cert.clone(), key.clone()),
+            None => {
+                let (cert, key, generated) = self_signed(config)?;
+                if generated {
+                    let message = format!(
+                        "Generated the self-signed certificate {cert} - trust it to avoid certificate warnings",
+                        cert = cert.display()
+                    );
+                    out_tx
+                        .send(ChildEvent::Output(id, StreamSource::StdOut, message))
+                        .ok();
+                }
+                (cert, key)
+            }
+        };
+
+        let (addresses, localhost) = preflight::listen_addresses(&config.listen_addrs);
+        let mut terminator = Self {
+            local_ports: Vec::new(),
+            children: Vec::new(),
+        };
+
+        let mut terminated = Vec::new();
+        for port in &mut config.ports {
+            let local = match port.local {
+                Some(local) => local,
+                None => free_port(addresses.first().copied())?,
+            };
+
+            let plain = free_port(Some(IpAddr::V4(PLAIN_ADDRESS)))?;
+            for ip in &addresses {
+                let address = SocketAddr::new(*ip, local);
+                let listener = preflight::bind_localhost(address, localhost)
+                    .map_err(|e| TlsError::Listen(address, e))?;
+                if listener.is_none() {
+                    continue;
+                }
+
+                let mut child = Command::new("socat")
+                    .current_dir(current_dir)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .arg(listen_address(address, &cert, &key))
+                    .arg(format!("TCP4:{PLAIN_ADDRESS}:{plain}"))
+                    .spawn()
+                    .map_err(|e| match e.kind() {
+                        io::ErrorKind::NotFound => TlsError::SocatNotFound,
+                        _ => TlsError::Spawn(e),
+                    })?;
+
+                if let Some(stderr) = child.stderr.take() {
+                    let out_tx = out_tx.clone();
+                    thread::spawn(move || {
+                        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                            out_tx
+                                .send(ChildEvent::Output(id, StreamSource::StdErr, line))
+                                .ok();
+                        }
+                    });
+                }
+                terminator.children.push(child);
+            }
+
+            terminator.local_ports.push(local);
+            terminated.push((plain, local));
+            port.local = Some(plain);
+        }
+
+        config.listen_addrs = vec![PLAIN_ADDRESS.to_string()];
+        status.terminated(terminated);
+        Ok(terminator)
+    }
+
+    /// Gets the local ports speaking TLS.
+    pub fn local_ports(&self) -> &[u16] {
+        &self.local_ports
+    }
+
+    /// Gets the process IDs of the `socat` processes.
+    pub fn pids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.children.iter().map(|child| child.id())
+    }
+}
+
+impl Drop for TlsTerminator {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+/// Builds the `socat` address listening with TLS on the address.
+fn listen_address(address: SocketAddr, cert: &Path, key: &Path) -> String {
+    let bind = match address.ip() {
+        IpAddr::V4(ip) => format!("bind={ip}"),
+        IpAddr::V6(ip) => format!("pf=ip6,bind=[{ip}]"),
+    };
+    format!(
+        "OPENSSL-LISTEN:{port},{bind},reuseaddr,fork,cert={cert},key={key},verify=0",
+        port = address.port(),
+        cert = cert.display(),
+        key = key.display()
+    )
+}
+
+/// Picks a free port on the address, or on the loopback address if none is given.
+fn free_port(ip: Option<IpAddr>) -> Result<u16, TlsError> {
+    let address = SocketAddr::new(ip.unwrap_or(IpAddr::V4(PLAIN_ADDRESS)), 0);
+    TcpListener::bind(address)
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .map_err(|e| TlsError::Listen(address, e))
+}
+
+/// Gets the self-signed certificate and key for the target, generating them if needed.
+///
+/// Returns whether they were generated along with their paths.
+fn self_signed(config: &PortForwardConfig) -> Result<(PathBuf, PathBuf, bool), TlsError> {
+    let names = subject_alt_names(config);
+    let dir = session::state_dir()
+        .ok_or(TlsError::NoStateDir)?
+        .join("tls");
+
+    // Targets with the same names share a certificate.
+    let name = format!(
+        "self-signed-{hash:08x}",
+        hash = fnv1a(names.join(",").as_bytes())
+    );
+    let cert = dir.join(format!("{name}.pem"));
+    let key = dir.join(format!("{name}-key.pem"));
+    if cert.exists() && key.exists() {
+        return Ok((cert, key, false));
+    }
+
+    fs::create_dir_all(&dir).map_err(TlsError::Generate)?;
+    let output = Command::new("openssl")
+        .args(["req", "-x509", "-newkey", "rsa:2048", "-nodes", "-sha256"])
+        .args(["-days", VALIDITY_DAYS, "-subj", "/CN=localhost"])
+        .arg("-addext")
+        .arg(format!("subjectAltName={names}", names = names.join(",")))
+        .arg("-keyout")
+        .arg(&key)
+        .arg("-out")
+        .arg(&cert)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => TlsError::OpensslNotFound,
+            _ => TlsError::Generate(e),
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(TlsError::Openssl(stderr.trim().to_string()));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o600)).map_err(TlsError::Generate)?;
+    }
+
+    Ok((cert, key, true))
+}
+
+/// Gets the names a self-signed certificate of the target is valid for: `localhost`, the
+/// loopback addresses, and the listen addresses and host names of the target.
+fn subject_alt_names(config: &PortForwardConfig) -> Vec<String> {
+    let mut names = vec![
+        "DNS:localhost".to_string(),
+        "IP:127.0.0.1".to_string(),
+        "IP:::1".to_string(),
+    ];
+
+    let hosts = config
+        .listen_addrs
+        .iter()
+        .chain(&config.hostnames)
+        .chain(&config.host);
+    for host in hosts {
+        let name = match host.parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => continue,
+            Ok(ip) => format!("IP:{ip}"),
+            Err(_) => format!("DNS:{host}"),
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names[3..].sort();
+    names
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Both `cert` and `key` are needed for TLS - `{0}` is missing")]
+    Incomplete(&'static str),
+    #[error("Unable to listen on {0}: {1}")]
+    Listen(SocketAddr, io::Error),
+    #[error("TLS termination requires socat, which was not found")]
+    SocatNotFound,
+    #[error("Unable to start socat: {0}")]
+    Spawn(io::Error),
+    #[error("Generating a self-signed certificate requires openssl, which was not found")]
+    OpensslNotFound,
+    #[error("Unable to generate a self-signed certificate: {0}")]
+    Generate(io::Error),
+    #[error("Unable to generate a self-signed certificate: {0}")]
+    Openssl(String),
+    #[error("Unable to determine the directory to store the self-signed certificate in")]
+    NoStateDir,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_alt_names() {
+        let config = serde_yaml::from_str::<PortForwardConfig>(
+            r#"
+            target: api
+            listen_addrs: ["127.1.2.3", "0.0.0.0", "127.0.0.1"]
+            hostnames: [api.internal]
+            tls: {}
+            ports: ["8443:80"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            subject_alt_names(&config),
+            vec![
+                "DNS:localhost",
+                "IP:127.0.0.1",
+                "IP:::1",
+                "DNS:api.internal",
+                "IP:127.1.2.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listen_address() {
+        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8443);
+        assert_eq!(
+            listen_address(address, Path::new("c.pem"), Path::new("k.pem")),
+            "OPENSSL-LISTEN:8443,bind=127.0.0.1,reuseaddr,fork,cert=c.pem,key=k.pem,verify=0"
+        );
+    }
+}
@@ -0,0 +1,369 @@
+// SPDX-FileCopyrightText: Copyright 2023 Markus Mayer
+// SPDX-License-Identifier: EUPL-1.2
+// SPDX-FileType: SOURCE
+
+//! Interactively creates a `.k8sfwd` file when no configuration exists yet.
+
+use crate::config::{Port, HIGHEST_SUPPORTED_VERSION};
+use crate::daemon;
+use crate::kubectl::{ContextError, Kubectl, ShellError};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+/// A service offered for forwarding.
+#[derive(Debug, Clone, PartialEq)]
+struct Service {
+    name: String,
+    ports: Vec<u16>,
+}
+
+/// Determines whether the wizard can ask questions, i.e. whether k8sfwd runs in a terminal.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal() && !daemon::is_detached()
+}
+
+/// Asks a question on the terminal, returning the default if the answer is empty.
+pub fn ask(question: &str, default: &str) -> io::Result<String> {
+    let stdin = io::stdin();
+    let mut prompt = Prompt {
+        input: stdin.lock(),
+        output: io::stdout(),
+    };
+    prompt.ask(question, default)
+}
+
+/// Asks for the context, namespace, services and ports to forward and writes
+/// them to the file, e.g. `.k8sfwd`, in the current directory.
+///
+/// Returns the path of the file, or `None` if the user chose not to create one.
+pub fn run(kubectl: &Kubectl, file_name: &str) -> Result<Option<PathBuf>, WizardError> {
+    let stdin = io::stdin();
+    let mut prompt = Prompt {
+        input: stdin.lock(),
+        output: io::stdout(),
+    };
+
+    println!("No configuration file was found.");
+    if !prompt.confirm(&format!(
+        "Create a {file_name} file in the current directory now?"
+    ))? {
+        return Ok(None);
+    }
+
+    // The context; the current one is suggested.
+    let current = kubectl.current_context()?;
+    let mut contexts = kubectl.contexts()?;
+    if contexts.is_empty() {
+        contexts.push(current.clone());
+    }
+    let default = contexts.iter().position(|c| c == &current).unwrap_or(0);
+    let context = prompt.choose("Context", &contexts, default, |c| {
+        if c == &current {
+            format!("{c} (current)")
+        } else {
+            c.clone()
+        }
+    })?;
+
+    // The namespace; listing them may not be permitted.
+    let namespace = match kubectl.get_resource(Some(&context), None, None, "namespaces") {
+        Ok(Some(list)) => {
+            let namespaces = item_names(&list);
+            let default = namespaces.iter().position(|n| n == "default").unwrap_or(0);
+            prompt.choose("Namespace", &namespaces, default, String::clone)?
+        }
+        Ok(None) | Err(_) => prompt.ask("Namespace", "default")?,
+    };
+
+    let services = match kubectl.get_resource(Some(&context), None, Some(&namespace), "services")? {
+        Some(list) => services(&list),
+        None => Vec::new(),
+    };
+    if services.is_empty() {
+        return Err(WizardError::NoServices(namespace));
+    }
+
+    let selected = prompt.select("Services to forward", &services, |s| {
+        let ports: Vec<_> = s.ports.iter().map(u16::to_string).collect();
+        format!("{name} ({ports})", name = s.name, ports = ports.join(", "))
+    })?;
+
+    // The local ports; privileged ports are moved out of the way.
+    let mut targets = Vec::new();
+    for service in selected {
+        let mut ports = Vec::new();
+        for remote in &service.ports {
+            let suggested = suggest_local_port(*remote).to_string();
+            let question = format!("Local port for {name}:{remote}", name = service.name);
+            let local = loop {
+                match prompt.ask(&question, &suggested)?.parse::<u16>() {
+                    Ok(local) if local > 0 => break local,
+                    _ => prompt.say("Please enter a port number between 1 and 65535.")?,
+                }
+            };
+            ports.push(Port::new(Some(local), *remote));
+        }
+        targets.push((service.name.clone(), ports));
+    }
+
+    let path = PathBuf::from(file_name);
+    if !prompt.confirm(&format!(
+        "Write {count} target(s) to {path}?",
+        count = targets.len(),
+        path = path.display()
+    ))? {
+        return Ok(None);
+    }
+
+    fs::write(&path, render(&context, &namespace, &targets))?;
+    Ok(Some(path))
+}
+
+/// Asks questions on the terminal.
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    fn say(&mut self, message: &str) -> io::Result<()> {
+        writeln!(self.output, "{message}")
+    }
+
+    /// Asks a question, returning the default if the answer is empty.
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        if default.is_empty() {
+            write!(self.output, "{question}: ")?;
+        } else {
+            write!(self.output, "{question} [{default}]: ")?;
+        }
+        self.output.flush()?;
+
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let answer = answer.trim();
+        Ok(if answer.is_empty() { default } else { answer }.to_string())
+    }
+
+    fn confirm(&mut self, question: &str) -> io::Result<bool> {
+        let answer = self.ask(&format!("{question} (y/n)"), "y")?;
+        Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+    }
+
+    /// Asks for one of the options, by number or by value.
+    fn choose(
+        &mut self,
+        question: &str,
+        options: &[String],
+        default: usize,
+        display: impl Fn(&String) -> String,
+    ) -> io::Result<String> {
+        for (index, option) in options.iter().enumerate() {
+            self.say(&format!(
+                "  {number}) {option}",
+                number = index + 1,
+                option = display(option)
+            ))?;
+        }
+
+        loop {
+            let answer = self.ask(question, &(default + 1).to_string())?;
+            if let Some(option) = options.iter().find(|option| **option == answer) {
+                return Ok(option.clone());
+            }
+
+            match parse_selection(&answer, options.len()).as_deref() {
+                Some([index]) => return Ok(options[*index].clone()),
+                _ => self.say("Please enter one of the numbers above.")?,
+            }
+        }
+    }
+
+    /// Asks for any number of the options, e.g. `1,3`; all options are selected by default.
+    fn select<'a, T>(
+        &mut self,
+        question: &str,
+        options: &'a [T],
+        display: impl Fn(&T) -> String,
+    ) -> io::Result<Vec<&'a T>> {
+        for (index, option) in options.iter().enumerate() {
+            self.say(&format!(
+                "  {number}) {option}",
+                number = index + 1,
+                option = display(option)
+            ))?;
+        }
+
+        loop {
+            let answer = self.ask(&format!("{question}, e.g. 1,2"), "all")?;
+            if answer == "all" {
+                return Ok(options.iter().collect());
+            }
+
+            match parse_selection(&answer, options.len()) {
+                Some(indexes) => return Ok(indexes.into_iter().map(|i| &options[i]).collect()),
+                None => {
+                    self.say("Please enter the numbers of the options, separated by commas.")?
+                }
+            }
+        }
+    }
+}
+
+/// Parses a comma-separated list of one-based option numbers into indexes.
+fn parse_selection(input: &str, count: usize) -> Option<Vec<usize>> {
+    let mut indexes = Vec::new();
+    for part in input.split([',', ' ']).filter(|part| !part.is_empty()) {
+        let number = part.parse::<usize>().ok()?;
+        if number == 0 || number > count {
+            return None;
+        }
+        if !indexes.contains(&(number - 1)) {
+            indexes.push(number - 1);
+        }
+    }
+
+    (!indexes.is_empty()).then_some(indexes)
+}
+
+/// Suggests a local port for a remote port, avoiding ports that require privileges.
+fn suggest_local_port(remote: u16) -> u16 {
+    if remote < 1024 {
+        remote + 8000
+    } else {
+        remote
+    }
+}
+
+/// Gets the names of the items of a kubectl list.
+fn item_names(list: &Value) -> Vec<String> {
+    list["items"]
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item["metadata"]["name"].as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Gets the services of a kubectl list that expose at least one TCP port.
+fn services(list: &Value) -> Vec<Service> {
+    list["items"]
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| {
+                    let name = item["metadata"]["name"].as_str()?;
+                    let ports: Vec<u16> = item["spec"]["ports"]
+                        .as_array()?
+                        .iter()
+                        .filter(|port| port["protocol"].as_str().unwrap_or("TCP") == "TCP")
+                        .filter_map(|port| port["port"].as_u64())
+                        .filter_map(|port| u16::try_from(port).ok())
+                        .collect();
+                    (!ports.is_empty()).then(|| Service {
+                        name: name.into(),
+                        ports,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Renders the configuration file.
+fn render(context: &str, namespace: &str, targets: &[(String, Vec<Port>)]) -> String {
+    let mut yaml = format!(
+        "---\nversion: {version}\ntargets:\n",
+        version = *HIGHEST_SUPPORTED_VERSION
+    );
+    for (name, ports) in targets {
+        yaml.push_str(&format!(
+            "  - name: {name}\n    target: {name}\n    type: service\n    namespace: {namespace}\n    context: {context}\n    ports:\n",
+            name = serde_json::to_string(name).unwrap_or_default(),
+            namespace = serde_json::to_string(namespace).unwrap_or_default(),
+            context = serde_json::to_string(context).unwrap_or_default(),
+        ));
+        for port in ports {
+            yaml.push_str(&format!("      - \"{port}\"\n"));
+        }
+    }
+    yaml
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WizardError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Kubectl(#[from] ShellError),
+    #[error(transparent)]
+    Context(#[from] ContextError),
+    #[error("No services with TCP ports were found in namespace {0}")]
+    NoServices(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PortForwardConfigs;
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("1,3", 3), Some(vec![0, 2]));
+        assert_eq!(parse_selection("2 2", 3), Some(vec![1]));
+        assert_eq!(parse_selection("4", 3), None);
+        assert_eq!(parse_selection("0", 3), None);
+        assert_eq!(parse_selection("", 3), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let yaml = render(
+            "kind-kind",
+            "test-api",
+            &[(
+                "foo".into(),
+                vec![Port::new(Some(suggest_local_port(80)), 80)],
+            )],
+        );
+
+        let config: PortForwardConfigs = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.is_supported_version());
+        assert_eq!(config.targets.len(), 1);
+        assert_eq!(config.targets[0].namespace, "test-api");
+        assert_eq!(config.targets[0].context.as_deref(), Some("kind-kind"));
+        assert_eq!(config.targets[0].ports[0].local, Some(8080));
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut prompt = Prompt {
+            input: "\n2\nx\n1,2\n".as_bytes(),
+            output: Vec::new(),
+        };
+
+        let options = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(prompt.ask("Namespace", "default").unwrap(), "default");
+        assert_eq!(
+            prompt.choose("Option", &options, 0, String::clone).unwrap(),
+            "b"
+        );
+        assert_eq!(
+            prompt
+                .select("Options", &options, String::clone)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}